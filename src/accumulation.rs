@@ -0,0 +1,77 @@
+use nalgebra::Vector3;
+
+/// Averages the frames rendered while the camera stands still, a cheap way to get a cleaner
+/// interactive preview than a single low sample frame. A camera change starts over.
+#[derive(Debug, Default)]
+pub struct TemporalAccumulation {
+    frames: u32,
+    radiance: Vec<Vector3<f64>>,
+}
+
+impl TemporalAccumulation {
+    pub fn new() -> Self {
+        TemporalAccumulation::default()
+    }
+
+    pub fn frames(&self) -> u32 {
+        self.frames
+    }
+
+    /// The running average of all frames since the last camera change.
+    pub fn radiance(&self) -> &[Vector3<f64>] {
+        &self.radiance
+    }
+
+    pub fn add_frame(&mut self, frame: &[Vector3<f64>], camera_changed: bool) {
+        if camera_changed || self.radiance.len() != frame.len() {
+            self.frames = 0;
+            self.radiance = vec![Vector3::zeros(); frame.len()];
+        }
+
+        self.frames += 1;
+        let weight = 1.0 / self.frames as f64;
+
+        for (average, radiance) in self.radiance.iter_mut().zip(frame) {
+            *average += (radiance - *average) * weight;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::Vector3;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use crate::accumulation::TemporalAccumulation;
+
+    fn noisy_frame(rng: &mut StdRng) -> Vec<Vector3<f64>> {
+        (0..256)
+            .map(|_| Vector3::repeat(0.5 + rng.gen_range(-0.4..0.4)))
+            .collect()
+    }
+
+    fn variance(radiance: &[Vector3<f64>]) -> f64 {
+        radiance.iter().map(|r| (r.y - 0.5).powi(2)).sum::<f64>() / radiance.len() as f64
+    }
+
+    #[test]
+    fn test_static_frames_reduce_noise_until_the_camera_moves() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut accumulation = TemporalAccumulation::new();
+
+        accumulation.add_frame(&noisy_frame(&mut rng), true);
+        let single_frame = variance(accumulation.radiance());
+
+        for _ in 0..15 {
+            accumulation.add_frame(&noisy_frame(&mut rng), false);
+        }
+        assert_eq!(16, accumulation.frames());
+        assert!(variance(accumulation.radiance()) < single_frame / 8.0);
+
+        let frame = noisy_frame(&mut rng);
+        accumulation.add_frame(&frame, true);
+        assert_eq!(1, accumulation.frames());
+        assert_eq!(frame.as_slice(), accumulation.radiance());
+    }
+}
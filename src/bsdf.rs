@@ -1,29 +1,38 @@
 use bitflags::bitflags;
 use nalgebra::{Point3, Vector3};
-use rand::prelude::SliceRandom;
-use rand::{thread_rng, Rng};
 
+use crate::bsdf::blinn_phong::BlinnPhong;
 use crate::bsdf::helpers::{abs_cos_theta, get_cosine_weighted_in_hemisphere, same_hemisphere};
 use crate::bsdf::lambertian::Lambertian;
+use crate::bsdf::lambertian_transmission::LambertianTransmission;
 use crate::bsdf::microfacet_reflection::MicrofacetReflection;
 use crate::bsdf::oren_nayar::OrenNayar;
 use crate::bsdf::specular_reflection::SpecularReflection;
 use crate::bsdf::specular_transmission::SpecularTransmission;
+use crate::bsdf::ward::Ward;
 use crate::renderer::{debug_write_pixel, debug_write_pixel_f64};
 use crate::surface_interaction::SurfaceInteraction;
 
+pub mod blinn_phong;
 pub mod helpers;
 pub mod lambertian;
+pub mod lambertian_transmission;
 pub mod microfacet_reflection;
 pub mod oren_nayar;
 pub mod specular_reflection;
 pub mod specular_transmission;
+pub mod ward;
 
 const MAX_BXDF_COUNT: usize = 5;
+/// Footprints narrower than this leave mirrors sharp, a near delta GGX lobe is numerically worse
+/// than the mirror itself. Same as the lower bound of `TrowbridgeReitzDistribution::regularized`.
+const MIN_FOOTPRINT_ALPHA: f64 = 0.1;
 
 #[derive(Copy, Clone, Debug)]
 pub struct Bsdf {
     bxdfs: [Option<Bxdf>; MAX_BXDF_COUNT],
+    /// Lobes are sampled proportional to these, see `BXDFtrait::sampling_weight`
+    sampling_weights: [f64; MAX_BXDF_COUNT],
     ior: f64,
     geometry_normal: Vector3<f64>,
     shading_normal: Vector3<f64>,
@@ -43,6 +52,7 @@ impl Bsdf {
     pub fn new(surface_interaction: SurfaceInteraction, ior: Option<f64>) -> Bsdf {
         Bsdf {
             bxdfs: [None; MAX_BXDF_COUNT],
+            sampling_weights: [0.0; MAX_BXDF_COUNT],
             ior: ior.unwrap_or(1.0),
             geometry_normal: surface_interaction.geometry_normal,
             shading_normal: surface_interaction.shading_normal,
@@ -52,35 +62,98 @@ impl Bsdf {
     }
 
     pub fn add(&mut self, bxdf: Bxdf) -> &mut Bsdf {
-        let slot = self.bxdfs.iter_mut().find(|x| x.is_none()).unwrap();
+        let index = self.bxdfs.iter().position(|x| x.is_none()).unwrap();
 
-        *slot = Some(bxdf);
+        self.bxdfs[index] = Some(bxdf);
+        self.sampling_weights[index] = bxdf.sampling_weight();
 
         self
     }
 
-    pub fn sample_f(
-        &self,
-        wo_world: Vector3<f64>,
-        bxdf_types_flags: BXDFTYPES,
-    ) -> BsdfSampleResult {
-        let mut rng = thread_rng();
-
-        let bxdfs: Vec<&Bxdf> = self
+    /// The bxdfs matching `bxdf_types_flags` with the chance each one is picked by `sample_f`,
+    /// proportional to their sampling weights. Uniform when none of them reflects anything.
+    fn lobe_probabilities(&self, bxdf_types_flags: BXDFTYPES) -> Vec<(&Bxdf, f64)> {
+        let lobes: Vec<(&Bxdf, f64)> = self
             .bxdfs
             .iter()
-            .filter_map(|bxdf| {
-                if let Some(bxdf) = bxdf {
-                    if bxdf.get_type_flags().intersects(bxdf_types_flags) {
-                        return Some(bxdf);
-                    }
+            .zip(self.sampling_weights)
+            .filter_map(|(bxdf, weight)| match bxdf {
+                Some(bxdf) if bxdf.get_type_flags().intersects(bxdf_types_flags) => {
+                    Some((bxdf, weight))
                 }
-
-                None
+                _ => None,
             })
             .collect();
 
-        if bxdfs.is_empty() {
+        let total: f64 = lobes.iter().map(|(_, weight)| weight).sum();
+        let count = lobes.len() as f64;
+
+        lobes
+            .into_iter()
+            .map(|(bxdf, weight)| match total > 0.0 {
+                true => (bxdf, weight / total),
+                false => (bxdf, 1.0 / count),
+            })
+            .collect()
+    }
+
+    /// Makes mirror and sharp glossy reflection rougher, see `Settings::regularize`. Specular
+    /// transmission has no rough counterpart and is kept.
+    pub fn regularize(&mut self) {
+        for bxdf in self.bxdfs.iter_mut().flatten() {
+            *bxdf = match bxdf {
+                Bxdf::SpecularReflection(x) => Bxdf::MicrofacetReflection(x.regularized()),
+                Bxdf::MicrofacetReflection(x) => Bxdf::MicrofacetReflection(x.regularized()),
+                _ => *bxdf,
+            };
+        }
+    }
+
+    /// Raises the alpha of every microfacet lobe to at least `min_alpha`, see
+    /// `Settings::min_alpha`. Perfect mirrors stay sharp.
+    pub fn clamp_alpha(&mut self, min_alpha: f64) {
+        for bxdf in self.bxdfs.iter_mut().flatten() {
+            *bxdf = match bxdf {
+                Bxdf::MicrofacetReflection(x) => {
+                    Bxdf::MicrofacetReflection(x.with_min_alpha(min_alpha))
+                }
+                Bxdf::Ward(x) => Bxdf::Ward(x.with_min_alpha(min_alpha)),
+                _ => *bxdf,
+            };
+        }
+    }
+
+    /// Blurs mirror and glossy reflections to at least `alpha`, the width of the ray footprint,
+    /// see `Settings::specular_lod`. Mirrors become glossy lobes once the footprint is at least
+    /// `MIN_FOOTPRINT_ALPHA` wide.
+    pub fn blur_for_footprint(&mut self, alpha: f64) {
+        for bxdf in self.bxdfs.iter_mut().flatten() {
+            *bxdf = match bxdf {
+                Bxdf::SpecularReflection(x) if alpha >= MIN_FOOTPRINT_ALPHA => {
+                    Bxdf::MicrofacetReflection(x.with_alpha(alpha))
+                }
+                Bxdf::MicrofacetReflection(x) => {
+                    Bxdf::MicrofacetReflection(x.with_min_alpha(alpha))
+                }
+                Bxdf::Ward(x) => Bxdf::Ward(x.with_min_alpha(alpha)),
+                _ => *bxdf,
+            };
+        }
+    }
+
+    /// Samples an incoming direction. The x component of `sample_u` selects one of the matching
+    /// bxdfs, brighter lobes more often, and is then remapped to [0, 1) so the whole sample can
+    /// drive the selected bxdf. For a non-specular lobe the pdf and f of the other non-specular
+    /// lobes are included, like `pdf` and `f`.
+    pub fn sample_f(
+        &self,
+        wo_world: Vector3<f64>,
+        sample_u: Point3<f64>,
+        bxdf_types_flags: BXDFTYPES,
+    ) -> BsdfSampleResult {
+        let lobes = self.lobe_probabilities(bxdf_types_flags);
+
+        if lobes.is_empty() {
             return BsdfSampleResult {
                 wi: Vector3::zeros(),
                 pdf: 0.0,
@@ -91,16 +164,72 @@ impl Bsdf {
 
         let wo = self.world_to_local(wo_world);
 
-        let bxdf = bxdfs.choose(&mut rng).unwrap();
-        let (wi, pdf, f) = bxdf.sample_f(Point3::new(rng.gen(), rng.gen(), rng.gen()), wo);
+        // the lobe whose share of [0, 1) holds the x component, the last one for rounding errors
+        let mut start = 0.0;
+        let mut component = lobes.len() - 1;
+        for (index, (_, probability)) in lobes.iter().enumerate() {
+            if sample_u.x < start + probability {
+                component = index;
+                break;
+            }
+            start += probability;
+        }
+        let (bxdf, probability) = lobes[component];
+
+        let sample_remapped = Point3::new(
+            ((sample_u.x - start) / probability).clamp(0.0, 1.0 - f64::EPSILON),
+            sample_u.y,
+            sample_u.z,
+        );
+        let (wi, mut pdf, mut f) = bxdf.sample_f(sample_remapped, wo);
 
         let wi_world = self.local_to_world(wi);
+        let flags = bxdf.get_type_flags();
+
+        // The bxdf samples around the shading normal, a direction on the wrong side of the
+        // actual surface would leak light through it. Specular lobes pick their side themselves.
+        if !flags.contains(BXDFTYPES::SPECULAR) {
+            let reflect =
+                wi_world.dot(&self.geometry_normal) * wo_world.dot(&self.geometry_normal) > 0.0;
+            let must_match_type = match reflect {
+                true => BXDFTYPES::REFLECTION,
+                false => BXDFTYPES::TRANSMISSION,
+            };
+
+            if !flags.contains(must_match_type) {
+                return BsdfSampleResult {
+                    wi: wi_world,
+                    pdf: 0.0,
+                    f: Vector3::zeros(),
+                    sampled_flags: flags,
+                };
+            }
+
+            pdf *= probability;
+            for (index, (other, other_probability)) in lobes.iter().enumerate() {
+                let other_flags = other.get_type_flags();
+                if index == component || other_flags.contains(BXDFTYPES::SPECULAR) {
+                    continue;
+                }
+
+                pdf += other_probability * other.pdf(wo, wi);
+                if other_flags.contains(must_match_type) {
+                    f += other.f(wo, wi);
+                }
+            }
+
+            if reflect {
+                f *= bump_shadowing_term(self.geometry_normal, self.shading_normal, wi_world);
+            }
+        } else {
+            pdf *= probability;
+        }
 
         BsdfSampleResult {
             wi: wi_world,
             pdf,
             f,
-            sampled_flags: bxdf.get_type_flags(),
+            sampled_flags: flags,
         }
     }
 
@@ -128,8 +257,12 @@ impl Bsdf {
             }
         }
 
-        // shadow terminator offset
-        f *= shift_cos_in(wi_world.dot(&self.shading_normal), 1.002);
+        // shadow terminator offset, and shadowing of the bumps implied by a shading normal that
+        // differs from the geometry
+        if reflect {
+            f *= shift_cos_in(wi_world.dot(&self.shading_normal), 1.002);
+            f *= bump_shadowing_term(self.geometry_normal, self.shading_normal, wi_world);
+        }
 
         f
     }
@@ -149,16 +282,11 @@ impl Bsdf {
             false => BXDFTYPES::TRANSMISSION,
         };
 
-        let mut pdf = 0.0;
-        for bxdf in &self.bxdfs.iter().filter_map(|x| *x).collect::<Vec<_>>() {
-            if bxdf.get_type_flags().intersects(bxdf_types_flags)
-                && bxdf.get_type_flags().contains(must_match_type)
-            {
-                pdf += bxdf.pdf(wo, wi);
-            }
-        }
-
-        pdf
+        self.lobe_probabilities(bxdf_types_flags)
+            .into_iter()
+            .filter(|(bxdf, _)| bxdf.get_type_flags().contains(must_match_type))
+            .map(|(bxdf, probability)| probability * bxdf.pdf(wo, wi))
+            .sum()
     }
 
     fn world_to_local(&self, v: Vector3<f64>) -> Vector3<f64> {
@@ -178,13 +306,15 @@ impl Bsdf {
     }
 }
 
+/// Smoothly darkens light arriving at a grazing angle on surfaces where the shading normal leans
+/// away from the geometry normal (Chiang et al. 2019), it is one where both normals agree.
 fn bump_shadowing_term(
     normal_geometry: Vector3<f64>,
     normal_shading: Vector3<f64>,
     wi: Vector3<f64>,
 ) -> f64 {
     let g =
-        (normal_geometry.dot(&wi) / normal_shading.dot(&wi)) * normal_geometry.dot(&normal_shading);
+        normal_geometry.dot(&wi) / (normal_shading.dot(&wi) * normal_geometry.dot(&normal_shading));
 
     if g >= 1.0 {
         return 1.0;
@@ -225,10 +355,16 @@ pub enum Bxdf {
     SpecularTransmission(SpecularTransmission),
     OrenNayar(OrenNayar),
     MicrofacetReflection(MicrofacetReflection),
+    BlinnPhong(BlinnPhong),
+    LambertianTransmission(LambertianTransmission),
+    Ward(Ward),
 }
 
 pub trait BXDFtrait {
     fn get_type_flags(&self) -> BXDFTYPES;
+    /// Rough measure of how much light the lobe reflects, a BSDF samples its lobes
+    /// proportional to it. Any scale works as long as it is the same for every lobe.
+    fn sampling_weight(&self) -> f64;
     fn f(&self, wo: Vector3<f64>, wi: Vector3<f64>) -> Vector3<f64>;
     fn pdf(&self, wo: Vector3<f64>, wi: Vector3<f64>) -> f64 {
         if same_hemisphere(wo, wi) {
@@ -237,8 +373,8 @@ pub trait BXDFtrait {
             0.0
         }
     }
-    fn sample_f(&self, _point: Point3<f64>, wo: Vector3<f64>) -> (Vector3<f64>, f64, Vector3<f64>) {
-        let mut wi = get_cosine_weighted_in_hemisphere();
+    fn sample_f(&self, point: Point3<f64>, wo: Vector3<f64>) -> (Vector3<f64>, f64, Vector3<f64>) {
+        let mut wi = get_cosine_weighted_in_hemisphere(point.xy());
         if wo.z < 0.0 {
             wi.z = -wi.z;
         }
@@ -255,6 +391,22 @@ impl BXDFtrait for Bxdf {
             Bxdf::OrenNayar(x) => x.get_type_flags(),
             Bxdf::MicrofacetReflection(x) => x.get_type_flags(),
             Bxdf::SpecularTransmission(x) => x.get_type_flags(),
+            Bxdf::BlinnPhong(x) => x.get_type_flags(),
+            Bxdf::LambertianTransmission(x) => x.get_type_flags(),
+            Bxdf::Ward(x) => x.get_type_flags(),
+        }
+    }
+
+    fn sampling_weight(&self) -> f64 {
+        match self {
+            Bxdf::Lambertian(x) => x.sampling_weight(),
+            Bxdf::SpecularReflection(x) => x.sampling_weight(),
+            Bxdf::OrenNayar(x) => x.sampling_weight(),
+            Bxdf::MicrofacetReflection(x) => x.sampling_weight(),
+            Bxdf::SpecularTransmission(x) => x.sampling_weight(),
+            Bxdf::BlinnPhong(x) => x.sampling_weight(),
+            Bxdf::LambertianTransmission(x) => x.sampling_weight(),
+            Bxdf::Ward(x) => x.sampling_weight(),
         }
     }
 
@@ -265,6 +417,9 @@ impl BXDFtrait for Bxdf {
             Bxdf::OrenNayar(x) => x.f(wo, wi),
             Bxdf::MicrofacetReflection(x) => x.f(wo, wi),
             Bxdf::SpecularTransmission(x) => x.f(wo, wi),
+            Bxdf::BlinnPhong(x) => x.f(wo, wi),
+            Bxdf::LambertianTransmission(x) => x.f(wo, wi),
+            Bxdf::Ward(x) => x.f(wo, wi),
         }
     }
 
@@ -275,6 +430,9 @@ impl BXDFtrait for Bxdf {
             Bxdf::OrenNayar(x) => x.pdf(wo, wi),
             Bxdf::MicrofacetReflection(x) => x.pdf(wo, wi),
             Bxdf::SpecularTransmission(x) => x.pdf(wo, wi),
+            Bxdf::BlinnPhong(x) => x.pdf(wo, wi),
+            Bxdf::LambertianTransmission(x) => x.pdf(wo, wi),
+            Bxdf::Ward(x) => x.pdf(wo, wi),
         }
     }
 
@@ -285,6 +443,192 @@ impl BXDFtrait for Bxdf {
             Bxdf::OrenNayar(x) => x.sample_f(point, wo),
             Bxdf::MicrofacetReflection(x) => x.sample_f(point, wo),
             Bxdf::SpecularTransmission(x) => x.sample_f(point, wo),
+            Bxdf::BlinnPhong(x) => x.sample_f(point, wo),
+            Bxdf::LambertianTransmission(x) => x.sample_f(point, wo),
+            Bxdf::Ward(x) => x.sample_f(point, wo),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+    use nalgebra::{Point3, Vector2, Vector3};
+
+    use crate::bsdf::helpers::fresnel::{Fresnel, FresnelNoop};
+    use crate::bsdf::lambertian::Lambertian;
+    use crate::bsdf::specular_reflection::SpecularReflection;
+    use crate::bsdf::{Bsdf, Bxdf, BXDFTYPES};
+    use crate::surface_interaction::SurfaceInteraction;
+
+    fn diffuse_and_mirror_bsdf() -> Bsdf {
+        let surface_interaction = SurfaceInteraction::new(
+            Point3::origin(),
+            Vector3::z(),
+            Vector3::new(1.0, 0.0, 1.0).normalize(),
+            Vector2::zeros(),
+            Vector3::x(),
+            Vector3::y(),
+            Vector3::x(),
+            Vector3::y(),
+            Vector3::zeros(),
+        );
+
+        let mut bsdf = Bsdf::new(surface_interaction, None);
+        bsdf.add(Bxdf::Lambertian(Lambertian::new(Vector3::repeat(0.5))));
+        bsdf.add(Bxdf::SpecularReflection(SpecularReflection::new(
+            Vector3::repeat(1.0),
+            Fresnel::Noop(FresnelNoop::new()),
+        )));
+
+        bsdf
+    }
+
+    #[test]
+    fn test_larger_footprints_blur_the_mirror_more() {
+        let blurred = |alpha: f64| {
+            let mut bsdf = diffuse_and_mirror_bsdf();
+            bsdf.blur_for_footprint(alpha);
+            bsdf
+        };
+        let (near, far) = (blurred(0.1), blurred(0.3));
+        let wo = Vector3::new(1.0, 0.0, 1.0).normalize();
+        let mirror = Vector3::new(-1.0, 0.0, 1.0).normalize();
+        let off_mirror = Vector3::new(-1.0, 0.0, 3.0).normalize();
+
+        // tiny footprints keep the mirror instead of a near delta glossy lobe
+        let sharp = blurred(1e-5);
+        assert_eq!(1, sharp.lobe_probabilities(BXDFTYPES::SPECULAR).len());
+        assert!(sharp.lobe_probabilities(BXDFTYPES::GLOSSY).is_empty());
+
+        // the mirror turned glossy, the near lobe is narrower and higher
+        assert!(near.f(wo, mirror, BXDFTYPES::GLOSSY).x > far.f(wo, mirror, BXDFTYPES::GLOSSY).x);
+        assert!(
+            far.f(wo, off_mirror, BXDFTYPES::GLOSSY).x
+                > near.f(wo, off_mirror, BXDFTYPES::GLOSSY).x
+        );
+    }
+
+    #[test]
+    fn test_sample_selects_bxdf_from_sample_u() {
+        let bsdf = diffuse_and_mirror_bsdf();
+        let wo = Vector3::new(1.0, 0.0, 1.0).normalize();
+
+        // the mirror reflects twice as much as the lambertian, the upper two thirds of x select it
+        let sample = bsdf.sample_f(wo, Point3::new(0.5, 0.3, 0.6), BXDFTYPES::ALL);
+        assert!(sample.sampled_flags.contains(BXDFTYPES::SPECULAR));
+        assert_relative_eq!(Vector3::new(-1.0, 0.0, 1.0).normalize(), sample.wi);
+
+        // the lower third selects the lambertian, x is remapped to 0.5 so with y at 0.5 the
+        // cosine weighted sample lands in the center of the disk, straight up
+        let sample = bsdf.sample_f(wo, Point3::new(1.0 / 6.0, 0.5, 0.5), BXDFTYPES::ALL);
+        assert!(sample.sampled_flags.contains(BXDFTYPES::DIFFUSE));
+        assert_relative_eq!(Vector3::z(), sample.wi);
+
+        let again = bsdf.sample_f(wo, Point3::new(1.0 / 6.0, 0.5, 0.5), BXDFTYPES::ALL);
+        assert_eq!(sample.wi, again.wi);
+    }
+
+    #[test]
+    fn test_bright_lobes_are_sampled_more_and_the_estimate_stays_unbiased() {
+        let surface_interaction = SurfaceInteraction::new(
+            Point3::origin(),
+            Vector3::z(),
+            Vector3::new(1.0, 0.0, 1.0).normalize(),
+            Vector2::zeros(),
+            Vector3::x(),
+            Vector3::y(),
+            Vector3::x(),
+            Vector3::y(),
+            Vector3::zeros(),
+        );
+        // a dark diffuse base under a bright clear coat
+        let mut bsdf = Bsdf::new(surface_interaction, None);
+        bsdf.add(Bxdf::Lambertian(Lambertian::new(Vector3::repeat(0.1))));
+        bsdf.add(Bxdf::SpecularReflection(SpecularReflection::new(
+            Vector3::repeat(1.0),
+            Fresnel::Noop(FresnelNoop::new()),
+        )));
+        let wo = Vector3::new(1.0, 0.0, 1.0).normalize();
+
+        let n = 64;
+        let mut specular = 0;
+        let mut reflected = 0.0;
+        for i in 0..n {
+            for j in 0..n {
+                let u = Point3::new(
+                    (i as f64 + 0.5) / n as f64,
+                    (j as f64 + 0.5) / n as f64,
+                    0.5,
+                );
+                let sample = bsdf.sample_f(wo, u, BXDFTYPES::ALL);
+                if sample.sampled_flags.contains(BXDFTYPES::SPECULAR) {
+                    specular += 1;
+                }
+                reflected += sample.f.x * sample.wi.z.abs() / sample.pdf;
+            }
+        }
+
+        // the mirror gets 10 of every 11 samples, the lambertian still counts fully
+        assert_relative_eq!(
+            10.0 / 11.0,
+            specular as f64 / (n * n) as f64,
+            epsilon = 0.01
+        );
+        assert_relative_eq!(1.1, reflected / (n * n) as f64, epsilon = 0.02);
+    }
+
+    #[test]
+    fn test_shading_normal_never_leaks_light_below_the_geometry() {
+        // a shading normal tilted towards +x, as on a smoothed low poly mesh
+        let shading_normal = Vector3::new(1.0, 0.0, 1.0).normalize();
+        let mut surface_interaction = SurfaceInteraction::new(
+            Point3::origin(),
+            Vector3::z(),
+            Vector3::z(),
+            Vector2::zeros(),
+            Vector3::x(),
+            Vector3::y(),
+            Vector3::x(),
+            Vector3::y(),
+            Vector3::zeros(),
+        );
+        surface_interaction.shading_normal = shading_normal;
+        surface_interaction.ss = Vector3::y().cross(&shading_normal);
+        surface_interaction.ts = shading_normal.cross(&surface_interaction.ss);
+
+        let mut bsdf = Bsdf::new(surface_interaction, None);
+        bsdf.add(Bxdf::Lambertian(Lambertian::new(Vector3::repeat(0.5))));
+        let wo = Vector3::z();
+
+        // grazing, below the geometry but above the shading normal
+        let below = Vector3::new(1.0, 0.0, -0.1).normalize();
+        assert!(below.dot(&shading_normal) > 0.0);
+        assert_eq!(Vector3::zeros(), bsdf.f(wo, below, BXDFTYPES::ALL));
+
+        // above the geometry but below the shading normal
+        let behind = Vector3::new(-1.0, 0.0, 0.1).normalize();
+        assert!(bsdf.f(wo, behind, BXDFTYPES::ALL).min() >= 0.0);
+
+        // grazing above both is darkened by the bump shadowing, not cut off
+        let grazing = bsdf.f(wo, Vector3::new(1.0, 0.0, 0.05).normalize(), BXDFTYPES::ALL);
+        assert!(grazing.x > 0.0);
+        assert!(grazing.x < bsdf.f(wo, shading_normal, BXDFTYPES::ALL).x);
+
+        // samples that the lambertian takes below the geometry are rejected
+        let mut rejected = 0;
+        for i in 0..16 {
+            for j in 0..16 {
+                let u = Point3::new((i as f64 + 0.5) / 16.0, (j as f64 + 0.5) / 16.0, 0.5);
+                let sample = bsdf.sample_f(wo, u, BXDFTYPES::ALL);
+                if sample.wi.z <= 0.0 {
+                    assert_eq!(0.0, sample.pdf);
+                    rejected += 1;
+                } else {
+                    assert!(sample.f.min() >= 0.0 && sample.pdf > 0.0);
+                }
+            }
         }
+        assert!(rejected > 0);
     }
 }
@@ -0,0 +1,128 @@
+use std::f64::consts::PI;
+
+use nalgebra::{Point3, Vector3};
+use num_traits::Zero;
+
+use crate::bsdf::helpers::{abs_cos_theta, same_hemisphere};
+use crate::bsdf::{BXDFtrait, BXDFTYPES};
+
+/// Normalized Blinn-Phong lobe for materials that come with a Phong exponent (`Ns` in obj
+/// material files), a higher exponent gives a tighter highlight.
+#[derive(Debug, Copy, Clone)]
+pub struct BlinnPhong {
+    specular_color: Vector3<f64>,
+    exponent: f64,
+}
+
+impl BlinnPhong {
+    pub fn new(specular_color: Vector3<f64>, exponent: f64) -> Self {
+        BlinnPhong {
+            specular_color,
+            exponent: exponent.max(0.0),
+        }
+    }
+
+    fn half_vector(wo: Vector3<f64>, wi: Vector3<f64>) -> Option<Vector3<f64>> {
+        let wh = wo + wi;
+
+        if wh.is_zero() {
+            return None;
+        }
+
+        Some(wh.normalize())
+    }
+}
+
+impl BXDFtrait for BlinnPhong {
+    fn get_type_flags(&self) -> BXDFTYPES {
+        BXDFTYPES::REFLECTION | BXDFTYPES::GLOSSY
+    }
+
+    fn sampling_weight(&self) -> f64 {
+        self.specular_color.sum()
+    }
+
+    fn f(&self, wo: Vector3<f64>, wi: Vector3<f64>) -> Vector3<f64> {
+        if !same_hemisphere(wo, wi) {
+            return Vector3::zeros();
+        }
+
+        match BlinnPhong::half_vector(wo, wi) {
+            Some(wh) => {
+                self.specular_color * (self.exponent + 8.0) / (8.0 * PI)
+                    * abs_cos_theta(wh).powf(self.exponent)
+            }
+            None => Vector3::zeros(),
+        }
+    }
+
+    fn pdf(&self, wo: Vector3<f64>, wi: Vector3<f64>) -> f64 {
+        if !same_hemisphere(wo, wi) {
+            return 0.0;
+        }
+
+        match BlinnPhong::half_vector(wo, wi) {
+            Some(wh) => {
+                let pdf_wh =
+                    (self.exponent + 1.0) / (2.0 * PI) * abs_cos_theta(wh).powf(self.exponent);
+
+                pdf_wh / (4.0 * wo.dot(&wh).abs())
+            }
+            None => 0.0,
+        }
+    }
+
+    /// Samples the half vector proportional to cos^n around the normal and reflects `wo`
+    /// around it.
+    fn sample_f(&self, point: Point3<f64>, wo: Vector3<f64>) -> (Vector3<f64>, f64, Vector3<f64>) {
+        let cos_theta = point.x.powf(1.0 / (self.exponent + 1.0));
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * point.y;
+
+        let mut wh = Vector3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta);
+        if wo.z < 0.0 {
+            wh = -wh;
+        }
+
+        let wi = -wo + 2.0 * wo.dot(&wh) * wh;
+        if !same_hemisphere(wo, wi) {
+            return (wi, 0.0, Vector3::zeros());
+        }
+
+        (wi, self.pdf(wo, wi), self.f(wo, wi))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+    use nalgebra::{Point3, Vector3};
+
+    use crate::bsdf::blinn_phong::BlinnPhong;
+    use crate::bsdf::BXDFtrait;
+
+    #[test]
+    fn test_higher_exponent_gives_tighter_lobe() {
+        let wo = Vector3::new(1.0, 0.0, 1.0).normalize();
+        let mirror = Vector3::new(-1.0, 0.0, 1.0).normalize();
+        let off_axis = Vector3::new(-1.0, 0.5, 1.0).normalize();
+
+        let dull = BlinnPhong::new(Vector3::repeat(1.0), 10.0);
+        let shiny = BlinnPhong::new(Vector3::repeat(1.0), 200.0);
+
+        assert!(shiny.f(wo, mirror).x > dull.f(wo, mirror).x);
+        assert!(shiny.f(wo, off_axis).x < dull.f(wo, off_axis).x);
+    }
+
+    #[test]
+    fn test_sample_matches_pdf() {
+        let wo = Vector3::new(0.3, 0.2, 1.0).normalize();
+        let blinn_phong = BlinnPhong::new(Vector3::repeat(1.0), 50.0);
+
+        let (wi, pdf, f) = blinn_phong.sample_f(Point3::new(0.4, 0.7, 0.0), wo);
+
+        assert!(wi.z > 0.0);
+        assert_relative_eq!(blinn_phong.pdf(wo, wi), pdf);
+        assert_relative_eq!(blinn_phong.f(wo, wi), f);
+    }
+}
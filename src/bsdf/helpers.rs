@@ -1,5 +1,6 @@
 pub mod fresnel;
 pub mod microfacet_distribution;
+pub mod multiple_scattering;
 
 use std::f64::consts::{FRAC_PI_2, FRAC_PI_4};
 
@@ -55,8 +56,8 @@ pub fn same_hemisphere(a: Vector3<f64>, b: Vector3<f64>) -> bool {
     a.z * b.z > 0.0
 }
 
-pub fn get_cosine_weighted_in_hemisphere() -> Vector3<f64> {
-    let d = crate::helpers::concentric_sample_disk();
+pub fn get_cosine_weighted_in_hemisphere(u: Point2<f64>) -> Vector3<f64> {
+    let d = crate::helpers::concentric_sample_disk(u);
     let z = f64::max(0.0, 1.0 - d.x * d.x - d.y * d.y).sqrt();
 
     Vector3::new(d.x, d.y, z)
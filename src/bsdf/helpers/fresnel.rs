@@ -2,6 +2,34 @@
 pub enum Fresnel {
     Noop(FresnelNoop),
     Dielectric(FresnelDielectric),
+    Schlick(FresnelSchlick),
+}
+
+/// How materials with a dielectric coating evaluate their Fresnel term.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub enum FresnelModel {
+    /// The full dielectric Fresnel equations
+    #[default]
+    Dielectric,
+    /// Schlick's approximation, cheaper and within a few percent of the full equations
+    Schlick,
+}
+
+impl FresnelModel {
+    pub fn from_str(str: &str) -> Option<FresnelModel> {
+        match str {
+            "dielectric" => Some(FresnelModel::Dielectric),
+            "schlick" => Some(FresnelModel::Schlick),
+            _ => None,
+        }
+    }
+
+    pub fn dielectric(self, eta_i: f64, eta_t: f64) -> Fresnel {
+        match self {
+            FresnelModel::Dielectric => Fresnel::Dielectric(FresnelDielectric::new(eta_i, eta_t)),
+            FresnelModel::Schlick => Fresnel::Schlick(FresnelSchlick::new(eta_i, eta_t)),
+        }
+    }
 }
 
 pub trait FresnelTrait {
@@ -13,6 +41,7 @@ impl FresnelTrait for Fresnel {
         match self {
             Fresnel::Noop(x) => x.evaluate(cos_i),
             Fresnel::Dielectric(x) => x.evaluate(cos_i),
+            Fresnel::Schlick(x) => x.evaluate(cos_i),
         }
     }
 }
@@ -72,3 +101,74 @@ impl FresnelTrait for FresnelDielectric {
         (rpar_l * rpar_l + rper_n * rper_n) / 2.0
     }
 }
+
+#[derive(Copy, Clone, Debug)]
+pub struct FresnelSchlick {
+    eta_i: f64,
+    eta_t: f64,
+    f0: f64,
+}
+
+impl FresnelSchlick {
+    pub fn new(eta_i: f64, eta_t: f64) -> Self {
+        let r0 = (eta_t - eta_i) / (eta_t + eta_i);
+
+        FresnelSchlick {
+            eta_i,
+            eta_t,
+            f0: r0 * r0,
+        }
+    }
+}
+
+impl FresnelTrait for FresnelSchlick {
+    fn evaluate(&self, cos_theta_i: f64) -> f64 {
+        let mut cos_theta = cos_theta_i.clamp(-1.0, 1.0).abs();
+        let (eta_i, eta_t) = if cos_theta_i <= 0.0 {
+            (self.eta_t, self.eta_i)
+        } else {
+            (self.eta_i, self.eta_t)
+        };
+
+        // going into the less dense medium the approximation uses the transmitted angle
+        if eta_i > eta_t {
+            let sin_theta_t = eta_i / eta_t * (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+            if sin_theta_t >= 1.0 {
+                return 1.0;
+            }
+            cos_theta = (1.0 - sin_theta_t * sin_theta_t).sqrt();
+        }
+
+        self.f0 + (1.0 - self.f0) * (1.0 - cos_theta).powi(5)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+
+    use crate::bsdf::helpers::fresnel::{FresnelDielectric, FresnelSchlick, FresnelTrait};
+
+    #[test]
+    fn test_schlick_is_close_to_dielectric() {
+        let dielectric = FresnelDielectric::new(1.0, 1.5);
+        let schlick = FresnelSchlick::new(1.0, 1.5);
+
+        assert_relative_eq!(
+            dielectric.evaluate(1.0),
+            schlick.evaluate(1.0),
+            epsilon = 1e-12
+        );
+        assert_relative_eq!(0.04, schlick.evaluate(1.0), epsilon = 1e-12);
+
+        for i in 0..=20 {
+            let cos_theta = i as f64 / 20.0;
+
+            for cos_theta in [cos_theta, -cos_theta] {
+                let difference =
+                    (dielectric.evaluate(cos_theta) - schlick.evaluate(cos_theta)).abs();
+                assert!(difference < 0.04, "cos {}: {}", cos_theta, difference);
+            }
+        }
+    }
+}
@@ -8,6 +8,38 @@ use crate::bsdf::helpers::{
 };
 use crate::helpers::spherical_direction;
 
+/// How the roughness of a material (0 to 1) maps to the alpha of the microfacet distribution,
+/// different tools use different remaps.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub enum RoughnessRemap {
+    /// alpha = roughness², perceptually linear (0.5 -> 0.25)
+    Square,
+    /// The polynomial fit from PBRT (0.5 -> 1.1308)
+    #[default]
+    Pbrt,
+    /// alpha = roughness (0.5 -> 0.5)
+    Linear,
+}
+
+impl RoughnessRemap {
+    pub fn from_str(str: &str) -> Option<RoughnessRemap> {
+        match str {
+            "square" => Some(RoughnessRemap::Square),
+            "pbrt" => Some(RoughnessRemap::Pbrt),
+            "linear" => Some(RoughnessRemap::Linear),
+            _ => None,
+        }
+    }
+
+    pub fn to_alpha(self, roughness: f64) -> f64 {
+        match self {
+            RoughnessRemap::Square => roughness * roughness,
+            RoughnessRemap::Pbrt => TrowbridgeReitzDistribution::roughness_to_alpha(roughness),
+            RoughnessRemap::Linear => roughness,
+        }
+    }
+}
+
 // todo: create enum
 pub trait MicrofacetDistribution {
     fn roughness_to_alpha(roughness: f64) -> f64;
@@ -53,6 +85,39 @@ impl TrowbridgeReitzDistribution {
         }
     }
 
+    /// Single alpha for anisotropic distributions, the geometric mean of both directions.
+    pub fn alpha(&self) -> f64 {
+        (self.alpha_x * self.alpha_y).sqrt()
+    }
+
+    /// Rougher version of a sharp distribution, for path regularization. Like PBRT v4 alphas
+    /// below 0.3 are doubled and clamped to [0.1, 0.3].
+    pub fn regularized(&self) -> Self {
+        let regularize = |alpha: f64| {
+            if alpha < 0.3 {
+                (2.0 * alpha).clamp(0.1, 0.3)
+            } else {
+                alpha
+            }
+        };
+
+        TrowbridgeReitzDistribution::new(
+            regularize(self.alpha_x),
+            regularize(self.alpha_y),
+            self.sample_visible_area,
+        )
+    }
+
+    /// The distribution with both alphas raised to at least `min_alpha`, see
+    /// `Settings::min_alpha`.
+    pub fn with_min_alpha(&self, min_alpha: f64) -> Self {
+        TrowbridgeReitzDistribution::new(
+            self.alpha_x.max(min_alpha),
+            self.alpha_y.max(min_alpha),
+            self.sample_visible_area,
+        )
+    }
+
     fn trowbridge_reinz_sample_11(cos_theta: f64, u1: f64, u2: f64) -> (f64, f64) {
         if cos_theta > 0.9999 {
             let r = (u1 / (1.0 - u1)).sqrt();
@@ -241,3 +306,31 @@ impl MicrofacetDistribution for TrowbridgeReitzDistribution {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+
+    use crate::bsdf::helpers::microfacet_distribution::{
+        RoughnessRemap, TrowbridgeReitzDistribution,
+    };
+
+    #[test]
+    fn test_roughness_remaps() {
+        assert_relative_eq!(0.25, RoughnessRemap::Square.to_alpha(0.5));
+        assert_relative_eq!(1.1308, RoughnessRemap::Pbrt.to_alpha(0.5), epsilon = 1e-4);
+        assert_relative_eq!(0.5, RoughnessRemap::Linear.to_alpha(0.5));
+        assert_eq!(RoughnessRemap::Pbrt, RoughnessRemap::default());
+    }
+
+    #[test]
+    fn test_min_alpha_raises_only_sharper_distributions() {
+        let sharp = TrowbridgeReitzDistribution::new(0.001, 0.02, true).with_min_alpha(0.05);
+        assert_relative_eq!(0.05, sharp.alpha_x);
+        assert_relative_eq!(0.05, sharp.alpha_y);
+
+        let rough = TrowbridgeReitzDistribution::new(0.3, 0.6, true).with_min_alpha(0.05);
+        assert_relative_eq!(0.3, rough.alpha_x);
+        assert_relative_eq!(0.6, rough.alpha_y);
+    }
+}
@@ -0,0 +1,142 @@
+use std::f64::consts::{PI, TAU};
+
+use lazy_static::lazy_static;
+use nalgebra::Vector3;
+
+use crate::bsdf::helpers::microfacet_distribution::{
+    MicrofacetDistribution, TrowbridgeReitzDistribution,
+};
+
+const TABLE_SIZE: usize = 32;
+const TABLE_SAMPLES: usize = 16;
+const MIN_ALPHA: f64 = 0.02;
+const MIN_COS_THETA: f64 = 0.02;
+
+lazy_static! {
+    static ref ALBEDO_TABLE: AlbedoTable = AlbedoTable::new();
+}
+
+/// Directional albedo of a white single scattering Trowbridge-Reitz lobe, the energy that is
+/// lost because light that bounces between microfacets is not accounted for is 1 - albedo.
+/// Used for the multiple scattering compensation of Kulla and Conty.
+struct AlbedoTable {
+    // indexed by [alpha][cos theta]
+    albedo: Vec<f64>,
+    average_albedo: Vec<f64>,
+}
+
+impl AlbedoTable {
+    fn new() -> Self {
+        let mut albedo = vec![0.0; TABLE_SIZE * TABLE_SIZE];
+        let mut average_albedo = vec![0.0; TABLE_SIZE];
+
+        for alpha_index in 0..TABLE_SIZE {
+            let alpha = table_value(alpha_index, MIN_ALPHA);
+
+            for cos_index in 0..TABLE_SIZE {
+                albedo[alpha_index * TABLE_SIZE + cos_index] =
+                    integrate_albedo(table_value(cos_index, MIN_COS_THETA), alpha);
+            }
+
+            // 2 * integral of albedo(mu) * mu over mu, trapezoid rule
+            let row = &albedo[alpha_index * TABLE_SIZE..(alpha_index + 1) * TABLE_SIZE];
+            average_albedo[alpha_index] = (0..TABLE_SIZE - 1)
+                .map(|i| {
+                    let (mu_0, mu_1) = (
+                        table_value(i, MIN_COS_THETA),
+                        table_value(i + 1, MIN_COS_THETA),
+                    );
+                    (row[i] * mu_0 + row[i + 1] * mu_1) * (mu_1 - mu_0)
+                })
+                .sum();
+        }
+
+        AlbedoTable {
+            albedo,
+            average_albedo,
+        }
+    }
+}
+
+fn table_value(index: usize, min: f64) -> f64 {
+    (index as f64 / (TABLE_SIZE - 1) as f64).max(min)
+}
+
+/// Fractional table index for a value in [0, 1], with the weight of the next entry.
+fn table_lookup(value: f64) -> (usize, f64) {
+    let position = value.clamp(0.0, 1.0) * (TABLE_SIZE - 1) as f64;
+    let index = (position.floor() as usize).min(TABLE_SIZE - 2);
+
+    (index, position - index as f64)
+}
+
+/// Importance samples the microfacet normals on a stratified grid, with the Fresnel term at 1
+/// the weight of a sample is G * (wo . wh) / (cos theta o * cos theta h).
+fn integrate_albedo(cos_theta_o: f64, alpha: f64) -> f64 {
+    let distribution = TrowbridgeReitzDistribution::new(alpha, alpha, false);
+    let wo = Vector3::new((1.0 - cos_theta_o * cos_theta_o).sqrt(), 0.0, cos_theta_o);
+    let mut sum = 0.0;
+
+    for i in 0..TABLE_SAMPLES {
+        for j in 0..TABLE_SAMPLES {
+            let u = (i as f64 + 0.5) / TABLE_SAMPLES as f64;
+            let v = (j as f64 + 0.5) / TABLE_SAMPLES as f64;
+
+            let tan_2_theta = alpha * alpha * u / (1.0 - u);
+            let cos_theta_h = 1.0 / (1.0 + tan_2_theta).sqrt();
+            let sin_theta_h = (1.0 - cos_theta_h * cos_theta_h).max(0.0).sqrt();
+            let phi = TAU * v;
+            let wh = Vector3::new(
+                sin_theta_h * phi.cos(),
+                sin_theta_h * phi.sin(),
+                cos_theta_h,
+            );
+
+            let wo_dot_wh = wo.dot(&wh);
+            let wi = 2.0 * wo_dot_wh * wh - wo;
+            if wo_dot_wh <= 0.0 || wi.z <= 0.0 {
+                continue;
+            }
+
+            sum += distribution.g(wo, wi) * wo_dot_wh / (cos_theta_o * cos_theta_h);
+        }
+    }
+
+    (sum / (TABLE_SAMPLES * TABLE_SAMPLES) as f64).min(1.0)
+}
+
+/// Computes the albedo table, call this before rendering so the first shading point does not
+/// have to wait for it.
+pub fn precompute_albedo_table() {
+    lazy_static::initialize(&ALBEDO_TABLE);
+}
+
+pub fn directional_albedo(cos_theta: f64, alpha: f64) -> f64 {
+    let (alpha_index, alpha_t) = table_lookup(alpha);
+    let (cos_index, cos_t) = table_lookup(cos_theta.abs());
+    let albedo = |a: usize, c: usize| ALBEDO_TABLE.albedo[a * TABLE_SIZE + c];
+
+    let low =
+        albedo(alpha_index, cos_index) * (1.0 - cos_t) + albedo(alpha_index, cos_index + 1) * cos_t;
+    let high = albedo(alpha_index + 1, cos_index) * (1.0 - cos_t)
+        + albedo(alpha_index + 1, cos_index + 1) * cos_t;
+
+    low * (1.0 - alpha_t) + high * alpha_t
+}
+
+pub fn average_albedo(alpha: f64) -> f64 {
+    let (index, t) = table_lookup(alpha);
+
+    ALBEDO_TABLE.average_albedo[index] * (1.0 - t) + ALBEDO_TABLE.average_albedo[index + 1] * t
+}
+
+/// The multiple scattering lobe, without color and Fresnel.
+pub fn multiple_scattering(cos_theta_o: f64, cos_theta_i: f64, alpha: f64) -> f64 {
+    let average = average_albedo(alpha);
+    if average >= 1.0 {
+        return 0.0;
+    }
+
+    (1.0 - directional_albedo(cos_theta_o, alpha)) * (1.0 - directional_albedo(cos_theta_i, alpha))
+        / (PI * (1.0 - average))
+}
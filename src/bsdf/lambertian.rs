@@ -20,6 +20,10 @@ impl BXDFtrait for Lambertian {
         BXDFTYPES::REFLECTION | BXDFTYPES::DIFFUSE
     }
 
+    fn sampling_weight(&self) -> f64 {
+        self.reflectance_color.sum()
+    }
+
     fn f(&self, _wo: Vector3<f64>, _wi: Vector3<f64>) -> Vector3<f64> {
         self.reflectance_color * std::f64::consts::FRAC_1_PI
     }
@@ -0,0 +1,52 @@
+use nalgebra::{Point3, Vector3};
+
+use crate::bsdf::helpers::{abs_cos_theta, get_cosine_weighted_in_hemisphere, same_hemisphere};
+use crate::bsdf::{BXDFtrait, BXDFTYPES};
+
+/// Diffuse transmission, light passes through the surface into a random direction on the other
+/// side.
+#[derive(Debug, Clone, Copy)]
+pub struct LambertianTransmission {
+    transmission_color: Vector3<f64>,
+}
+
+impl LambertianTransmission {
+    pub fn new(transmission_color: Vector3<f64>) -> Self {
+        LambertianTransmission { transmission_color }
+    }
+}
+
+impl BXDFtrait for LambertianTransmission {
+    fn get_type_flags(&self) -> BXDFTYPES {
+        BXDFTYPES::TRANSMISSION | BXDFTYPES::DIFFUSE
+    }
+
+    fn sampling_weight(&self) -> f64 {
+        self.transmission_color.sum()
+    }
+
+    fn f(&self, wo: Vector3<f64>, wi: Vector3<f64>) -> Vector3<f64> {
+        if same_hemisphere(wo, wi) {
+            return Vector3::zeros();
+        }
+
+        self.transmission_color * std::f64::consts::FRAC_1_PI
+    }
+
+    fn pdf(&self, wo: Vector3<f64>, wi: Vector3<f64>) -> f64 {
+        if same_hemisphere(wo, wi) {
+            return 0.0;
+        }
+
+        abs_cos_theta(wi) * std::f64::consts::FRAC_1_PI
+    }
+
+    fn sample_f(&self, point: Point3<f64>, wo: Vector3<f64>) -> (Vector3<f64>, f64, Vector3<f64>) {
+        let mut wi = get_cosine_weighted_in_hemisphere(point.xy());
+        if wo.z > 0.0 {
+            wi.z = -wi.z;
+        }
+
+        (wi, self.pdf(wo, wi), self.f(wo, wi))
+    }
+}
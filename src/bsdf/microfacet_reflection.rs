@@ -6,31 +6,74 @@ use crate::helpers::vector_reflect;
 use crate::renderer::{debug_write_pixel_f64_on_bounce, debug_write_pixel_on_bounce};
 
 use super::helpers::abs_cos_theta;
-use super::helpers::fresnel::{FresnelDielectric, FresnelTrait};
+use super::helpers::fresnel::{Fresnel, FresnelTrait};
 use super::helpers::microfacet_distribution::{
     MicrofacetDistribution, TrowbridgeReitzDistribution,
 };
+use super::helpers::multiple_scattering::{average_albedo, multiple_scattering};
 use super::{BXDFtrait, BXDFTYPES};
 
+const FRESNEL_AVERAGE_STEPS: usize = 16;
+
 #[derive(Debug, Copy, Clone)]
 pub struct MicrofacetReflection {
     reflectance_color: Vector3<f64>,
     distribution: TrowbridgeReitzDistribution,
-    fresnel: FresnelDielectric,
+    fresnel: Fresnel,
+    /// Fresnel weight of the multiple scattering lobe, zero when energy compensation is off
+    multiple_scattering_fresnel: f64,
 }
 
 impl MicrofacetReflection {
+    /// With `energy_compensation` a lobe is added for the light that scatters between
+    /// microfacets more than once (Kulla-Conty), without it rough surfaces lose energy.
     pub fn new(
         reflectance_color: Vector3<f64>,
         distribution: TrowbridgeReitzDistribution,
-        fresnel: FresnelDielectric,
+        fresnel: Fresnel,
+        energy_compensation: bool,
     ) -> Self {
+        let multiple_scattering_fresnel = if energy_compensation {
+            // hemispherical average of the fresnel term, 2 * integral of F(mu) * mu over mu
+            let fresnel_average = (0..FRESNEL_AVERAGE_STEPS)
+                .map(|i| {
+                    let mu = (i as f64 + 0.5) / FRESNEL_AVERAGE_STEPS as f64;
+                    2.0 * fresnel.evaluate(mu) * mu / FRESNEL_AVERAGE_STEPS as f64
+                })
+                .sum::<f64>();
+            let albedo_average = average_albedo(distribution.alpha());
+
+            fresnel_average * fresnel_average * albedo_average
+                / (1.0 - fresnel_average * (1.0 - albedo_average))
+        } else {
+            0.0
+        };
+
         MicrofacetReflection {
             reflectance_color,
             distribution,
             fresnel,
+            multiple_scattering_fresnel,
         }
     }
+
+    pub fn regularized(&self) -> Self {
+        MicrofacetReflection::new(
+            self.reflectance_color,
+            self.distribution.regularized(),
+            self.fresnel,
+            self.multiple_scattering_fresnel > 0.0,
+        )
+    }
+
+    pub fn with_min_alpha(&self, min_alpha: f64) -> Self {
+        MicrofacetReflection::new(
+            self.reflectance_color,
+            self.distribution.with_min_alpha(min_alpha),
+            self.fresnel,
+            self.multiple_scattering_fresnel > 0.0,
+        )
+    }
 }
 
 impl BXDFtrait for MicrofacetReflection {
@@ -38,6 +81,10 @@ impl BXDFtrait for MicrofacetReflection {
         BXDFTYPES::REFLECTION | BXDFTYPES::GLOSSY
     }
 
+    fn sampling_weight(&self) -> f64 {
+        self.reflectance_color.sum()
+    }
+
     fn f(&self, wo: Vector3<f64>, wi: Vector3<f64>) -> Vector3<f64> {
         let cos_theta_o = abs_cos_theta(wo);
         let cos_theta_i = abs_cos_theta(wi);
@@ -53,8 +100,18 @@ impl BXDFtrait for MicrofacetReflection {
 
         let wh = wh.normalize();
         let f = self.fresnel.evaluate(wi.dot(&wh));
-        self.reflectance_color * self.distribution.d(wh) * self.distribution.g(wo, wi) * f
-            / (4.0 * cos_theta_i * cos_theta_o)
+        let single_scattering = self.distribution.d(wh) * self.distribution.g(wo, wi) * f
+            / (4.0 * cos_theta_i * cos_theta_o);
+
+        if self.multiple_scattering_fresnel == 0.0 {
+            return self.reflectance_color * single_scattering;
+        }
+
+        let multiple_scattering =
+            multiple_scattering(cos_theta_o, cos_theta_i, self.distribution.alpha())
+                * self.multiple_scattering_fresnel;
+
+        self.reflectance_color * (single_scattering + multiple_scattering)
     }
 
     fn pdf(&self, wo: Vector3<f64>, wi: Vector3<f64>) -> f64 {
@@ -106,3 +163,54 @@ impl BXDFtrait for MicrofacetReflection {
         (wi, pdf, f)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::{PI, TAU};
+
+    use nalgebra::Vector3;
+
+    use crate::bsdf::helpers::fresnel::{Fresnel, FresnelNoop};
+    use crate::bsdf::helpers::microfacet_distribution::TrowbridgeReitzDistribution;
+    use crate::bsdf::microfacet_reflection::MicrofacetReflection;
+    use crate::bsdf::BXDFtrait;
+
+    /// Integral of f * cos theta i over the hemisphere, midpoint rule in cos theta and phi.
+    fn hemispherical_reflectance(bxdf: &MicrofacetReflection, wo: Vector3<f64>) -> f64 {
+        let steps = 200;
+        let mut sum = 0.0;
+
+        for i in 0..steps {
+            for j in 0..steps {
+                let cos_theta = (i as f64 + 0.5) / steps as f64;
+                let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+                let phi = TAU * (j as f64 + 0.5) / steps as f64;
+                let wi = Vector3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta);
+
+                sum += bxdf.f(wo, wi).x * cos_theta;
+            }
+        }
+
+        sum * 2.0 * PI / (steps * steps) as f64
+    }
+
+    #[test]
+    fn test_energy_compensation_conserves_energy_of_rough_metal() {
+        let rough_metal = |energy_compensation| {
+            MicrofacetReflection::new(
+                Vector3::repeat(1.0),
+                TrowbridgeReitzDistribution::new(0.8, 0.8, true),
+                Fresnel::Noop(FresnelNoop::new()),
+                energy_compensation,
+            )
+        };
+        let wo = Vector3::new(0.6, 0.0, 0.8);
+
+        let single = hemispherical_reflectance(&rough_metal(false), wo);
+        let compensated = hemispherical_reflectance(&rough_metal(true), wo);
+
+        assert!(single < 0.9);
+        assert!((1.0 - compensated).abs() < 0.05);
+        assert!((1.0 - compensated).abs() < (1.0 - single).abs());
+    }
+}
@@ -32,6 +32,10 @@ impl BXDFtrait for OrenNayar {
         BXDFTYPES::REFLECTION | BXDFTYPES::DIFFUSE
     }
 
+    fn sampling_weight(&self) -> f64 {
+        self.reflectance_color.sum()
+    }
+
     fn f(&self, wo: Vector3<f64>, wi: Vector3<f64>) -> Vector3<f64> {
         let sin_theta_i = sin_theta(wi);
         let sin_theta_o = sin_theta(wo);
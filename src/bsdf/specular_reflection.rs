@@ -1,7 +1,9 @@
 use nalgebra::{Point3, Vector3};
 
 use crate::bsdf::helpers::fresnel::{Fresnel, FresnelDielectric, FresnelTrait};
+use crate::bsdf::helpers::microfacet_distribution::TrowbridgeReitzDistribution;
 use crate::bsdf::helpers::{abs_cos_theta, cos_theta};
+use crate::bsdf::microfacet_reflection::MicrofacetReflection;
 use crate::bsdf::{BXDFtrait, BXDFTYPES};
 
 #[derive(Debug, Clone, Copy)]
@@ -17,6 +19,26 @@ impl SpecularReflection {
             fresnel,
         }
     }
+
+    /// Glossy stand-in for the mirror with the given alpha, for reflections far away.
+    pub fn with_alpha(&self, alpha: f64) -> MicrofacetReflection {
+        MicrofacetReflection::new(
+            self.reflectance_color,
+            TrowbridgeReitzDistribution::new(alpha, alpha, true),
+            self.fresnel,
+            false,
+        )
+    }
+
+    /// Glossy stand-in for the mirror, for path regularization.
+    pub fn regularized(&self) -> MicrofacetReflection {
+        MicrofacetReflection::new(
+            self.reflectance_color,
+            TrowbridgeReitzDistribution::new(0.0, 0.0, true).regularized(),
+            self.fresnel,
+            false,
+        )
+    }
 }
 
 impl BXDFtrait for SpecularReflection {
@@ -24,6 +46,10 @@ impl BXDFtrait for SpecularReflection {
         BXDFTYPES::REFLECTION | BXDFTYPES::SPECULAR
     }
 
+    fn sampling_weight(&self) -> f64 {
+        self.reflectance_color.sum()
+    }
+
     fn f(&self, _wo: Vector3<f64>, _wi: Vector3<f64>) -> Vector3<f64> {
         Vector3::zeros()
     }
@@ -1,6 +1,6 @@
 use nalgebra::{Point3, Vector3};
 
-use crate::bsdf::helpers::fresnel::{Fresnel, FresnelDielectric, FresnelTrait};
+use crate::bsdf::helpers::fresnel::{Fresnel, FresnelModel, FresnelTrait};
 use crate::bsdf::helpers::{abs_cos_theta, cos_theta};
 use crate::bsdf::{BXDFtrait, BXDFTYPES};
 use crate::helpers::{face_forward, refract};
@@ -25,11 +25,12 @@ impl SpecularTransmission {
         refraction_color: Vector3<f64>,
         eta_a: f64,
         eta_b: f64,
+        fresnel_model: FresnelModel,
         mode: TransportMode,
     ) -> Self {
         SpecularTransmission {
             refraction_color,
-            fresnel: Fresnel::Dielectric(FresnelDielectric::new(eta_a, eta_b)),
+            fresnel: fresnel_model.dielectric(eta_a, eta_b),
             eta_a,
             eta_b,
             mode,
@@ -42,6 +43,10 @@ impl BXDFtrait for SpecularTransmission {
         BXDFTYPES::REFRACTION | BXDFTYPES::SPECULAR
     }
 
+    fn sampling_weight(&self) -> f64 {
+        self.refraction_color.sum()
+    }
+
     fn f(&self, _wo: Vector3<f64>, _wi: Vector3<f64>) -> Vector3<f64> {
         Vector3::zeros()
     }
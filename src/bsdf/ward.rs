@@ -0,0 +1,205 @@
+use std::f64::consts::PI;
+
+use nalgebra::{Point2, Point3, Vector3};
+
+use crate::bsdf::helpers::{abs_cos_theta, get_cosine_weighted_in_hemisphere, same_hemisphere};
+use crate::bsdf::{BXDFtrait, BXDFTYPES};
+
+/// Anisotropic Ward model, a diffuse lobe plus a specular lobe with a separate roughness along
+/// the tangent (`alpha_x`) and the bitangent (`alpha_y`). Uses the normalization and sampling
+/// from Walter's "Notes on the Ward BRDF".
+#[derive(Debug, Copy, Clone)]
+pub struct Ward {
+    diffuse_color: Vector3<f64>,
+    specular_color: Vector3<f64>,
+    alpha_x: f64,
+    alpha_y: f64,
+}
+
+impl Ward {
+    pub fn new(
+        diffuse_color: Vector3<f64>,
+        specular_color: Vector3<f64>,
+        alpha_x: f64,
+        alpha_y: f64,
+    ) -> Self {
+        Ward {
+            diffuse_color,
+            specular_color,
+            alpha_x: alpha_x.max(1e-3),
+            alpha_y: alpha_y.max(1e-3),
+        }
+    }
+
+    pub fn with_min_alpha(&self, min_alpha: f64) -> Self {
+        Ward::new(
+            self.diffuse_color,
+            self.specular_color,
+            self.alpha_x.max(min_alpha),
+            self.alpha_y.max(min_alpha),
+        )
+    }
+
+    /// Chance of sampling the diffuse lobe instead of the specular one.
+    fn diffuse_probability(&self) -> f64 {
+        let diffuse = self.diffuse_color.sum();
+        let specular = self.specular_color.sum();
+
+        if diffuse + specular == 0.0 {
+            0.5
+        } else {
+            diffuse / (diffuse + specular)
+        }
+    }
+
+    /// exp(-tan²θh (cos²φh / αx² + sin²φh / αy²)), the shape of the specular lobe
+    fn lobe(&self, wh: Vector3<f64>) -> f64 {
+        let x = wh.x / self.alpha_x;
+        let y = wh.y / self.alpha_y;
+
+        (-(x * x + y * y) / (wh.z * wh.z)).exp()
+    }
+
+    fn pdf_wh(&self, wh: Vector3<f64>) -> f64 {
+        let cos_theta = abs_cos_theta(wh);
+
+        self.lobe(wh) / (PI * self.alpha_x * self.alpha_y * cos_theta * cos_theta * cos_theta)
+    }
+}
+
+impl BXDFtrait for Ward {
+    fn get_type_flags(&self) -> BXDFTYPES {
+        BXDFTYPES::REFLECTION | BXDFTYPES::GLOSSY
+    }
+
+    fn sampling_weight(&self) -> f64 {
+        self.diffuse_color.sum() + self.specular_color.sum()
+    }
+
+    fn f(&self, wo: Vector3<f64>, wi: Vector3<f64>) -> Vector3<f64> {
+        if !same_hemisphere(wo, wi) {
+            return Vector3::zeros();
+        }
+
+        let wh = (wo + wi).normalize();
+        let specular = self.lobe(wh)
+            / (4.0
+                * PI
+                * self.alpha_x
+                * self.alpha_y
+                * (abs_cos_theta(wo) * abs_cos_theta(wi)).sqrt());
+
+        self.diffuse_color / PI + self.specular_color * specular
+    }
+
+    fn pdf(&self, wo: Vector3<f64>, wi: Vector3<f64>) -> f64 {
+        if !same_hemisphere(wo, wi) {
+            return 0.0;
+        }
+
+        let wh = (wo + wi).normalize();
+        let diffuse_probability = self.diffuse_probability();
+
+        diffuse_probability * abs_cos_theta(wi) / PI
+            + (1.0 - diffuse_probability) * self.pdf_wh(wh) / (4.0 * wo.dot(&wh).abs())
+    }
+
+    /// The z component picks the lobe, x and y sample the direction within it.
+    fn sample_f(&self, point: Point3<f64>, wo: Vector3<f64>) -> (Vector3<f64>, f64, Vector3<f64>) {
+        let wi = if point.z < self.diffuse_probability() {
+            let mut wi = get_cosine_weighted_in_hemisphere(Point2::new(point.x, point.y));
+            if wo.z < 0.0 {
+                wi.z = -wi.z;
+            }
+
+            wi
+        } else {
+            let angle = 2.0 * PI * point.y;
+            let phi = (self.alpha_y * angle.sin()).atan2(self.alpha_x * angle.cos());
+            let (sin_phi, cos_phi) = phi.sin_cos();
+            let tan_2_theta = -(1.0 - point.x).ln()
+                / (cos_phi * cos_phi / (self.alpha_x * self.alpha_x)
+                    + sin_phi * sin_phi / (self.alpha_y * self.alpha_y));
+            let cos_theta = 1.0 / (1.0 + tan_2_theta).sqrt();
+            let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+
+            let mut wh = Vector3::new(sin_theta * cos_phi, sin_theta * sin_phi, cos_theta);
+            if wo.z < 0.0 {
+                wh = -wh;
+            }
+
+            -wo + 2.0 * wo.dot(&wh) * wh
+        };
+
+        if !same_hemisphere(wo, wi) {
+            return (wi, 0.0, Vector3::zeros());
+        }
+
+        (wi, self.pdf(wo, wi), self.f(wo, wi))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+    use nalgebra::{Point3, Vector3};
+
+    use crate::bsdf::ward::Ward;
+    use crate::bsdf::BXDFtrait;
+
+    fn ward() -> Ward {
+        Ward::new(Vector3::repeat(0.2), Vector3::repeat(0.8), 0.1, 0.3)
+    }
+
+    #[test]
+    fn test_f_is_symmetric() {
+        let ward = ward();
+        let directions = [
+            Vector3::new(0.3, 0.2, 1.0).normalize(),
+            Vector3::new(-0.7, 0.1, 0.4).normalize(),
+            Vector3::new(0.1, -0.9, 0.2).normalize(),
+        ];
+
+        for wo in directions {
+            for wi in directions {
+                assert_relative_eq!(ward.f(wo, wi), ward.f(wi, wo));
+            }
+        }
+    }
+
+    #[test]
+    fn test_f_peaks_around_the_mirror_direction() {
+        let ward = ward();
+        let wo = Vector3::new(1.0, 0.0, 1.0).normalize();
+        let mirror = Vector3::new(-1.0, 0.0, 1.0).normalize();
+        let peak = ward.f(wo, mirror).x;
+
+        for off_axis in [
+            Vector3::new(-1.0, 0.3, 1.0),
+            Vector3::new(-1.0, 0.0, 1.4),
+            Vector3::new(-1.4, 0.0, 1.0),
+            Vector3::new(-0.2, 0.2, 1.0),
+        ] {
+            assert!(ward.f(wo, off_axis.normalize()).x < peak);
+        }
+
+        // the lobe is wider along y (alpha_y) than along x
+        let along_x = ward.f(wo, Vector3::new(-1.2, 0.0, 1.0).normalize()).x;
+        let along_y = ward.f(wo, Vector3::new(-1.0, 0.2, 1.0).normalize()).x;
+        assert!(along_y > along_x);
+    }
+
+    #[test]
+    fn test_sample_matches_pdf() {
+        let ward = ward();
+        let wo = Vector3::new(0.3, 0.2, 1.0).normalize();
+
+        for point in [Point3::new(0.4, 0.7, 0.1), Point3::new(0.4, 0.7, 0.9)] {
+            let (wi, pdf, f) = ward.sample_f(point, wo);
+
+            assert!(wi.z > 0.0);
+            assert_relative_eq!(ward.pdf(wo, wi), pdf);
+            assert_relative_eq!(ward.f(wo, wi), f);
+        }
+    }
+}
@@ -0,0 +1,128 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Mutex;
+
+/// Hands out bucket indices to the render threads. Every thread has its own queue of
+/// neighboring buckets and takes them from the front. A thread whose queue ran empty steals
+/// from the back of the fullest queue, so the threads that got the cheap part of the image help
+/// out with the costly part. Only one queue is locked at a time and only to take an index.
+#[derive(Debug)]
+pub struct BucketQueues {
+    queues: Vec<Mutex<VecDeque<u32>>>,
+    stolen: AtomicU32,
+}
+
+impl BucketQueues {
+    /// Splits the buckets `0..count` into `queue_count` queues of neighboring buckets.
+    pub fn new(count: u32, queue_count: u32) -> Self {
+        Self::from_indices((0..count).collect(), queue_count)
+    }
+
+    fn from_indices(indices: Vec<u32>, queue_count: u32) -> Self {
+        let queue_count = queue_count.max(1) as usize;
+        let chunk_size = indices.len().div_ceil(queue_count).max(1);
+        let mut queues: Vec<Mutex<VecDeque<u32>>> = indices
+            .chunks(chunk_size)
+            .map(|chunk| Mutex::new(chunk.iter().copied().collect()))
+            .collect();
+        queues.resize_with(queue_count, || Mutex::new(VecDeque::new()));
+
+        BucketQueues {
+            queues,
+            stolen: AtomicU32::new(0),
+        }
+    }
+
+    /// Spreads the buckets that were not handed out yet over `queue_count` queues, one for each
+    /// render thread.
+    pub fn split(&mut self, queue_count: u32) {
+        let mut remaining: Vec<u32> = self
+            .queues
+            .iter_mut()
+            .flat_map(|queue| queue.get_mut().unwrap().drain(..))
+            .collect();
+        remaining.sort_unstable();
+
+        *self = Self::from_indices(remaining, queue_count);
+    }
+
+    /// Takes the next bucket of `queue`, or steals one when it is empty. Returns None once
+    /// every queue is empty.
+    pub fn pop(&self, queue: u32) -> Option<u32> {
+        let queue = queue as usize % self.queues.len();
+
+        if let Some(index) = self.queues[queue].lock().unwrap().pop_front() {
+            return Some(index);
+        }
+
+        self.steal(queue)
+    }
+
+    /// Takes a bucket from the back of the fullest other queue, the buckets furthest away from
+    /// the ones its own thread is working on.
+    fn steal(&self, thief: usize) -> Option<u32> {
+        loop {
+            let victim = (0..self.queues.len())
+                .filter(|&queue| queue != thief)
+                .map(|queue| (queue, self.queues[queue].lock().unwrap().len()))
+                .filter(|&(_, len)| len > 0)
+                .max_by_key(|&(_, len)| len)?
+                .0;
+
+            // another thread may have emptied the queue in the meantime, then look again
+            if let Some(index) = self.queues[victim].lock().unwrap().pop_back() {
+                self.stolen.fetch_add(1, Ordering::Relaxed);
+                return Some(index);
+            }
+        }
+    }
+
+    /// Number of buckets taken from another thread's queue.
+    pub fn stolen(&self) -> u32 {
+        self.stolen.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bucket_queue::BucketQueues;
+
+    #[test]
+    fn test_threads_take_their_own_buckets_first_and_then_steal() {
+        let queues = BucketQueues::new(8, 2);
+
+        // thread 0 owns buckets 0 to 3, thread 1 owns 4 to 7
+        assert_eq!(Some(0), queues.pop(0));
+        assert_eq!(Some(4), queues.pop(1));
+        assert_eq!(0, queues.stolen());
+
+        for expected in [5, 6, 7] {
+            assert_eq!(Some(expected), queues.pop(1));
+        }
+        // thread 1 ran out and steals from the back of thread 0's queue
+        assert_eq!(Some(3), queues.pop(1));
+        assert_eq!(Some(1), queues.pop(0));
+        assert_eq!(Some(2), queues.pop(1));
+        assert_eq!(None, queues.pop(0));
+        assert_eq!(None, queues.pop(1));
+        assert_eq!(2, queues.stolen());
+    }
+
+    #[test]
+    fn test_split_only_spreads_the_remaining_buckets() {
+        let mut queues = BucketQueues::new(6, 1);
+        assert_eq!(Some(0), queues.pop(0));
+        assert_eq!(Some(1), queues.pop(0));
+
+        queues.split(2);
+
+        assert_eq!(Some(2), queues.pop(0));
+        assert_eq!(Some(4), queues.pop(1));
+        let mut rest = vec![];
+        while let Some(index) = queues.pop(1) {
+            rest.push(index);
+        }
+        rest.sort_unstable();
+        assert_eq!(vec![3, 5], rest);
+    }
+}
@@ -4,21 +4,35 @@ use std::sync::{Arc, RwLock};
 use ggez::graphics::Transform;
 use nalgebra::{
     Affine2, Affine3, Isometry3, Matrix4, Perspective3, Point2, Point3, Projective3, Quaternion,
-    Rotation3, Scale3, SimdValue, Similarity3, Translation3, UnitQuaternion, Vector3,
+    Rotation3, Scale3, SimdValue, Similarity3, Translation3, Unit, UnitQuaternion, Vector3,
 };
+use yaml_rust::Yaml;
 
-use crate::helpers::Bounds;
+use crate::helpers::{yaml_array_into_point3, yaml_array_into_vector3, yaml_as_f64, Bounds};
 use crate::renderer::Ray;
 use crate::Film;
 
+/// Highest angle above or below the horizon an orbiting camera can reach, in degrees. Right
+/// above the target the view direction would line up with the up axis.
+const MAX_ORBIT_ELEVATION: f64 = 89.0;
+/// Width of the film in scene units, for the focal length of an f-number. A full frame sensor
+/// (36 mm) when the scene is in meters.
+const DEFAULT_SENSOR_SIZE: f64 = 0.036;
+
 #[derive(Clone)]
 pub struct Camera {
     pub position: Point3<f64>,
     pub target: Point3<f64>,
+    pub up: Vector3<f64>,
+    pub roll: f64,
     pub fov: f64,
+    pub near: f64,
+    pub far: f64,
     pub aperture: f64,
     pub focal_distance: f64,
     pub film: Arc<RwLock<Film>>,
+    aspect_ratio: f64,
+    screen_window: Bounds<f64>,
     camera_to_world: Matrix4<f64>,
     camera_to_screen: Matrix4<f64>,
     screen_to_raster: Matrix4<f64>,
@@ -36,11 +50,16 @@ pub struct Camera {
 //  z +backward -forward
 ///
 impl Camera {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         position: Point3<f64>,
         target: Point3<f64>,
+        up: Vector3<f64>,
+        roll: f64,
         aspect_ratio: f64,
         fov: f64,
+        near: f64,
+        far: f64,
         aperture: f64,
         focal_distance: Option<f64>,
         screen_window: Bounds<f64>,
@@ -54,14 +73,18 @@ impl Camera {
         let direction = target - position;
         let focal_distance = focal_distance.unwrap_or_else(|| direction.magnitude());
 
-        let world_up = Vector3::y();
-
-        // Create a rotation and translation matrix from camera space to world space with the Y axis as up direction.
-        let camera_to_world = Rotation3::face_towards(&direction, &world_up)
+        // Create a rotation and translation matrix from camera space to world space, the roll
+        // (in degrees) rotates the camera around its viewing axis.
+        let roll_rotation = Rotation3::from_axis_angle(&Vector3::z_axis(), roll * (PI / 180.0));
+        let camera_to_world = (Rotation3::face_towards(&direction, &up) * roll_rotation)
             .to_homogeneous()
             .append_translation(&position.coords);
 
-        let camera_to_screen = perspective(fov, 0.01, 1000.0);
+        assert!(
+            0.0 < near && near < far,
+            "The camera near plane must lie between 0 and the far plane"
+        );
+        let camera_to_screen = perspective(fov, near, far);
 
         /// To translate from screen space (x -1.0 to 1.0 and y -1.0 to 1.0) to raster space (based on the film resolution)
         /// we apply the following steps (bottom to top):
@@ -88,10 +111,16 @@ impl Camera {
         Camera {
             position,
             target,
+            up,
+            roll,
             fov,
+            near,
+            far,
             aperture,
             focal_distance,
             film,
+            aspect_ratio,
+            screen_window,
             camera_to_world,
             camera_to_screen,
             screen_to_raster,
@@ -100,6 +129,168 @@ impl Camera {
         }
     }
 
+    /// Builds the camera from one camera entry of the render settings. An `f_number` sets the
+    /// aperture instead of `aperture`.
+    pub fn from_yaml(yaml: &Yaml, aspect_ratio: f64, film: Arc<RwLock<Film>>) -> Camera {
+        let fov = yaml["fov"].as_f64().unwrap();
+        let aperture = match yaml_as_f64(&yaml["f_number"]) {
+            Some(f_number) => aperture_from_f_number(
+                f_number,
+                fov,
+                yaml_as_f64(&yaml["sensor_size"]).unwrap_or(DEFAULT_SENSOR_SIZE),
+            ),
+            None => yaml["aperture"].as_f64().unwrap(),
+        };
+
+        Camera::new(
+            yaml_array_into_point3(&yaml["position"]),
+            yaml_array_into_point3(&yaml["target"]),
+            if !yaml["up"].is_badvalue() {
+                yaml_array_into_vector3(&yaml["up"])
+            } else {
+                Vector3::y()
+            },
+            yaml["roll"].as_f64().unwrap_or(0.0),
+            aspect_ratio,
+            fov,
+            yaml["near"].as_f64().unwrap_or(0.01),
+            yaml["far"].as_f64().unwrap_or(1000.0),
+            aperture,
+            yaml["focal_distance"].as_f64(),
+            Bounds {
+                p_min: Point2::new(-1.0, -1.0),
+                p_max: Point2::new(1.0, 1.0),
+            },
+            film,
+        )
+    }
+
+    /// Returns the camera for frame `frame` of a turntable of `frames` frames: the position is
+    /// rotated around the target, about the up axis, by an equal angle per frame.
+    pub fn turntable(&self, frame: u32, frames: u32) -> Camera {
+        let angle = 2.0 * PI * frame as f64 / frames as f64;
+        let rotation = Rotation3::from_axis_angle(&Unit::new_normalize(self.up), angle);
+        let position = self.target + rotation * (self.position - self.target);
+
+        Camera::new(
+            position,
+            self.target,
+            self.up,
+            self.roll,
+            self.aspect_ratio,
+            self.fov,
+            self.near,
+            self.far,
+            self.aperture,
+            Some(self.focal_distance),
+            self.screen_window,
+            self.film.clone(),
+        )
+    }
+
+    /// Returns the camera orbited around its target: `yaw` turns it about the up axis and
+    /// `pitch` raises it (both in degrees), up to just short of straight above or below the
+    /// target. The distance to the target is scaled by `distance_scale`, the focus follows.
+    pub fn orbit(&self, yaw: f64, pitch: f64, distance_scale: f64) -> Camera {
+        let offset = self.position - self.target;
+        let up = self.up.normalize();
+
+        // spherical coordinates around the target, the elevation is measured from the horizon
+        let elevation = offset.normalize().dot(&up).clamp(-1.0, 1.0).asin();
+        let max_elevation = MAX_ORBIT_ELEVATION * (PI / 180.0);
+        let new_elevation = (elevation + pitch * (PI / 180.0)).clamp(-max_elevation, max_elevation);
+
+        let right = Unit::new_normalize(offset.cross(&up));
+        let rotation = Rotation3::from_axis_angle(&Unit::new_normalize(up), -yaw * (PI / 180.0))
+            * Rotation3::from_axis_angle(&right, new_elevation - elevation);
+        let position = self.target + rotation * offset * distance_scale;
+
+        Camera::new(
+            position,
+            self.target,
+            self.up,
+            self.roll,
+            self.aspect_ratio,
+            self.fov,
+            self.near,
+            self.far,
+            self.aperture,
+            Some(self.focal_distance * distance_scale),
+            self.screen_window,
+            self.film.clone(),
+        )
+    }
+
+    /// Returns the camera moved back or forth along its view direction so a sphere at `center`
+    /// with `radius`, the bounds of a scene, just fits in the field of view. The camera looks at
+    /// the center and focuses on it.
+    pub fn framed(&self, center: Point3<f64>, radius: f64) -> Camera {
+        let direction = (self.position - self.target).normalize();
+        let distance = radius / (self.fov * (PI / 180.0) / 2.0).sin();
+
+        Camera::new(
+            center + direction * distance,
+            center,
+            self.up,
+            self.roll,
+            self.aspect_ratio,
+            self.fov,
+            self.near,
+            self.far,
+            self.aperture,
+            Some(distance),
+            self.screen_window,
+            self.film.clone(),
+        )
+    }
+
+    /// Returns the camera moved by `movement` (right, up and forward relative to the view) after
+    /// turning it by `yaw` and `pitch` (in degrees), used to fly through the scene.
+    pub fn fly(&self, movement: Vector3<f64>, yaw: f64, pitch: f64) -> Camera {
+        let view = self.target - self.position;
+        let right = view.cross(&self.up).normalize();
+
+        let rotation =
+            Rotation3::from_axis_angle(&Unit::new_normalize(self.up), -yaw * (PI / 180.0))
+                * Rotation3::from_axis_angle(&Unit::new_normalize(right), -pitch * (PI / 180.0));
+        let view = rotation * view;
+
+        let direction = view.normalize();
+        let right = direction.cross(&self.up).normalize();
+        let up = right.cross(&direction);
+
+        let position =
+            self.position + right * movement.x + up * movement.y + direction * movement.z;
+
+        Camera::new(
+            position,
+            position + view,
+            self.up,
+            self.roll,
+            self.aspect_ratio,
+            self.fov,
+            self.near,
+            self.far,
+            self.aperture,
+            Some(self.focal_distance),
+            self.screen_window,
+            self.film.clone(),
+        )
+    }
+
+    /// Angle between the rays through two neighbouring pixels in the middle of the film, a
+    /// camera ray covers about this angle times the distance it traveled.
+    pub fn pixel_spread_angle(&self) -> f64 {
+        let image_size = self.film.read().unwrap().image_size;
+        let center = Point3::new(image_size.x as f64 / 2.0, image_size.y as f64 / 2.0, 0.0);
+        let below = center + Vector3::y();
+
+        self.raster_to_camera
+            .transform_point(&center)
+            .coords
+            .angle(&self.raster_to_camera.transform_point(&below).coords)
+    }
+
     pub fn generate_ray(&self, sample: CameraSample) -> Ray {
         let mut origin = Point3::origin();
 
@@ -107,7 +298,7 @@ impl Camera {
         let mut direction = self.raster_to_camera.transform_point(&p_film).coords;
 
         if self.aperture > 0.0 {
-            let p_lens = self.aperture * crate::helpers::concentric_sample_disk();
+            let p_lens = self.aperture * crate::helpers::concentric_sample_disk(sample.p_lens);
             let ft = self.focal_distance / direction.z;
 
             let p_focus = ft * direction;
@@ -115,16 +306,50 @@ impl Camera {
             direction = (p_focus - origin.coords).normalize()
         }
 
+        // nothing beyond the far plane is seen, at z = far in camera space
+        let t_max = self.far / direction.normalize().z;
+
         let origin = self.camera_to_world.transform_point(&origin);
         let direction = self.camera_to_world.transform_vector(&direction);
 
         Ray {
             point: origin,
             direction: direction.normalize(),
+            t_max,
         }
     }
 }
 
+/// Lens radius of a lens with this f-number, the focal length divided by the f-number is the
+/// diameter. The focal length follows from the field of view (in degrees) and the size of the
+/// film along it.
+pub fn aperture_from_f_number(f_number: f64, fov: f64, sensor_size: f64) -> f64 {
+    let focal_length = 0.5 * sensor_size / (0.5 * fov.to_radians()).tan();
+
+    focal_length / (2.0 * f_number)
+}
+
+/// Picks the camera to render from the render settings: the camera called `name` in the
+/// `cameras` list, or the single `camera` (the first of the list without one) when no name is
+/// given.
+pub fn select_camera_yaml<'a>(settings_yaml: &'a Yaml, name: Option<&str>) -> &'a Yaml {
+    let cameras = settings_yaml["cameras"]
+        .as_vec()
+        .map(Vec::as_slice)
+        .unwrap_or(&[]);
+
+    match name {
+        Some(name) => cameras
+            .iter()
+            .find(|camera| camera["name"].as_str() == Some(name))
+            .unwrap_or_else(|| panic!("No camera named {name} in the render settings")),
+        None if settings_yaml["camera"].is_badvalue() => cameras
+            .first()
+            .expect("The render settings need a camera or a cameras list"),
+        None => &settings_yaml["camera"],
+    }
+}
+
 pub fn perspective(fov_deg: f64, n: f64, f: f64) -> Matrix4<f64> {
     // Matrix4x4 persp(1, 0,           0,              0,
     //                 0, 1,           0,              0,
@@ -170,8 +395,10 @@ mod tests {
 
     use approx::{assert_relative_eq, relative_eq};
     use nalgebra::{point, Perspective3, Point2, Point3, Vector2, Vector3};
+    use yaml_rust::YamlLoader;
 
-    use crate::camera::{perspective, CameraSample};
+    use crate::camera::{aperture_from_f_number, perspective, select_camera_yaml, CameraSample};
+    use crate::renderer::Ray;
     use crate::{Bounds, Camera, Film, FilterMethod};
 
     #[test]
@@ -181,15 +408,22 @@ mod tests {
             Vector2::new(100, 100),
             None,
             None,
+            None,
             FilterMethod::None,
             1.0,
+            None,
+            None,
         )));
 
         let camera = Camera::new(
             Point3::new(0.0, 0.0, 1.0),
             Point3::new(0.0, 0.0, 0.0),
+            Vector3::y(),
+            0.0,
             1.0,
             90.0,
+            0.01,
+            1000.0,
             0.0,
             None,
             Bounds {
@@ -221,11 +455,18 @@ mod tests {
         let angle = ray_left.direction.angle(&ray_right.direction);
         assert_relative_eq!(90.0, angle * 180.0 / PI, max_relative = 0.00001);
 
+        // 100 pixels span tan(45°) on both sides of the middle
+        assert_relative_eq!(0.02, camera.pixel_spread_angle(), max_relative = 0.001);
+
         let camera = Camera::new(
             Point3::new(0.0, 0.0, 1.0),
             Point3::new(0.0, 1.0, 0.0),
+            Vector3::y(),
+            0.0,
             1.0,
             90.0,
+            0.01,
+            1000.0,
             0.0,
             None,
             Bounds {
@@ -256,4 +497,428 @@ mod tests {
         let angle = ray_left.direction.angle(&ray_right.direction);
         assert_relative_eq!(90.0, angle * 180.0 / PI, max_relative = 0.00001);
     }
+
+    #[test]
+    fn test_roll_swaps_horizontal_and_vertical() {
+        let film = Arc::new(RwLock::new(Film::new(
+            Vector2::new(100, 100),
+            Vector2::new(100, 100),
+            None,
+            None,
+            None,
+            FilterMethod::None,
+            1.0,
+            None,
+            None,
+        )));
+
+        let camera = Camera::new(
+            Point3::new(0.0, 0.0, 1.0),
+            Point3::new(0.0, 0.0, 0.0),
+            Vector3::y(),
+            90.0,
+            1.0,
+            90.0,
+            0.01,
+            1000.0,
+            0.0,
+            None,
+            Bounds {
+                p_min: Point2::new(-1.0, -1.0),
+                p_max: Point2::new(1.0, 1.0),
+            },
+            film,
+        );
+
+        let ray_left = camera.generate_ray(CameraSample {
+            p_film: Point2::new(0.0, 50.0),
+            p_lens: Point2::origin(),
+        });
+
+        let ray_top = camera.generate_ray(CameraSample {
+            p_film: Point2::new(50.0, 0.0),
+            p_lens: Point2::origin(),
+        });
+
+        // the horizontal spread of the film is now vertical in the world and vice versa
+        assert_relative_eq!(0.0, ray_left.direction.x, epsilon = 1.0e-9);
+        assert!(ray_left.direction.y.abs() > 0.5);
+        assert_relative_eq!(0.0, ray_top.direction.y, epsilon = 1.0e-9);
+        assert!(ray_top.direction.x.abs() > 0.5);
+
+        let angle = ray_left.direction.angle(&ray_top.direction);
+        assert_relative_eq!(60.0, angle * 180.0 / PI, max_relative = 0.00001);
+    }
+
+    #[test]
+    fn test_fly_forward_moves_along_view_direction() {
+        let film = Arc::new(RwLock::new(Film::new(
+            Vector2::new(100, 100),
+            Vector2::new(100, 100),
+            None,
+            None,
+            None,
+            FilterMethod::None,
+            1.0,
+            None,
+            None,
+        )));
+
+        let camera = Camera::new(
+            Point3::new(1.0, 0.0, 1.0),
+            Point3::new(0.0, 0.0, 0.0),
+            Vector3::y(),
+            0.0,
+            1.0,
+            90.0,
+            0.01,
+            1000.0,
+            0.0,
+            None,
+            Bounds {
+                p_min: Point2::new(-1.0, -1.0),
+                p_max: Point2::new(1.0, 1.0),
+            },
+            film,
+        );
+
+        let moved = camera.fly(Vector3::new(0.0, 0.0, 0.5), 0.0, 0.0);
+        let direction = Vector3::new(-1.0, 0.0, -1.0).normalize();
+
+        assert_relative_eq!(camera.position + direction * 0.5, moved.position);
+        assert_relative_eq!(
+            direction,
+            (moved.target - moved.position).normalize(),
+            epsilon = 1.0e-9
+        );
+
+        // turning right by 90 degrees looks along the old right vector
+        let turned = camera.fly(Vector3::zeros(), 90.0, 0.0);
+        assert_relative_eq!(
+            Vector3::new(1.0, 0.0, -1.0).normalize(),
+            (turned.target - turned.position).normalize(),
+            epsilon = 1.0e-9
+        );
+    }
+
+    #[test]
+    fn test_near_and_far_change_the_projection() {
+        let film = Arc::new(RwLock::new(Film::new(
+            Vector2::new(100, 100),
+            Vector2::new(100, 100),
+            None,
+            None,
+            None,
+            FilterMethod::None,
+            1.0,
+            None,
+            None,
+        )));
+        let camera = |near, far| {
+            Camera::new(
+                Point3::new(0.0, 0.0, 1.0),
+                Point3::origin(),
+                Vector3::y(),
+                0.0,
+                1.0,
+                60.0,
+                near,
+                far,
+                0.0,
+                None,
+                Bounds {
+                    p_min: Point2::new(-1.0, -1.0),
+                    p_max: Point2::new(1.0, 1.0),
+                },
+                film.clone(),
+            )
+        };
+
+        let small = camera(0.01, 1000.0);
+        let large = camera(1.0, 100000.0);
+
+        assert_relative_eq!(1000.0 / 999.99, small.camera_to_screen[(2, 2)]);
+        assert_relative_eq!(-10.0 / 999.99, small.camera_to_screen[(2, 3)]);
+        assert_relative_eq!(100000.0 / 99999.0, large.camera_to_screen[(2, 2)]);
+        assert_relative_eq!(-100000.0 / 99999.0, large.camera_to_screen[(2, 3)]);
+
+        for p_film in [Point2::new(0.0, 0.0), Point2::new(30.0, 80.0)] {
+            let sample = CameraSample {
+                p_film,
+                p_lens: Point2::origin(),
+            };
+            let small_ray = small.generate_ray(sample);
+            let large_ray = large.generate_ray(sample);
+
+            assert_relative_eq!(1.0, small_ray.direction.magnitude(), epsilon = 1.0e-12);
+            assert_relative_eq!(1.0, large_ray.direction.magnitude(), epsilon = 1.0e-12);
+            assert_relative_eq!(small_ray.direction, large_ray.direction, epsilon = 1.0e-9);
+
+            // the rays end at the far plane, the camera looks down the negative z axis
+            let depth = |ray: Ray| ray.t_max * ray.direction.dot(&-Vector3::z());
+            assert_relative_eq!(1000.0, depth(small_ray), max_relative = 1.0e-9);
+            assert_relative_eq!(100000.0, depth(large_ray), max_relative = 1.0e-9);
+        }
+    }
+
+    #[test]
+    fn test_f_number_sets_the_lens_radius_of_generated_rays() {
+        let settings = &YamlLoader::load_from_str(
+            "
+camera:
+  position: [0.0, 0.0, 4.0]
+  target: [0.0, 0.0, 0.0]
+  fov: 90.0
+  f_number: 2.0
+",
+        )
+        .unwrap()[0];
+        let film = Arc::new(RwLock::new(Film::new(
+            Vector2::new(10, 10),
+            Vector2::new(10, 10),
+            None,
+            None,
+            None,
+            FilterMethod::None,
+            1.0,
+            None,
+            None,
+        )));
+        let camera = Camera::from_yaml(select_camera_yaml(settings, None), 1.0, film);
+
+        // a 36 mm film at 90 degrees has an 18 mm focal length, at f/2 a 9 mm wide lens
+        assert_relative_eq!(0.0045, camera.aperture, epsilon = 1e-12);
+        assert_relative_eq!(
+            camera.aperture,
+            aperture_from_f_number(2.0, 90.0, 0.036),
+            epsilon = 1e-12
+        );
+
+        // a sample on the edge of the lens starts the ray that far from the camera position
+        let ray = camera.generate_ray(CameraSample {
+            p_film: Point2::new(5.0, 5.0),
+            p_lens: Point2::new(1.0, 0.5),
+        });
+        assert_relative_eq!(
+            camera.aperture,
+            (ray.point - camera.position).magnitude(),
+            epsilon = 1e-12
+        );
+    }
+
+    #[test]
+    fn test_cameras_are_selected_by_name() {
+        let settings = &YamlLoader::load_from_str(
+            "
+cameras:
+  - name: front
+    position: [0.0, 0.0, 4.0]
+    target: [0.0, 0.0, 0.0]
+    fov: 40.0
+    aperture: 0.0
+  - name: top
+    position: [0.0, 5.0, 0.0]
+    target: [0.0, 1.0, 0.0]
+    up: [0.0, 0.0, -1.0]
+    fov: 60.0
+    aperture: 0.0
+",
+        )
+        .unwrap()[0];
+        let film = Arc::new(RwLock::new(Film::new(
+            Vector2::new(10, 10),
+            Vector2::new(10, 10),
+            None,
+            None,
+            None,
+            FilterMethod::None,
+            1.0,
+            None,
+            None,
+        )));
+
+        for (name, position, target) in [
+            ("front", Point3::new(0.0, 0.0, 4.0), Point3::origin()),
+            (
+                "top",
+                Point3::new(0.0, 5.0, 0.0),
+                Point3::new(0.0, 1.0, 0.0),
+            ),
+        ] {
+            let camera =
+                Camera::from_yaml(select_camera_yaml(settings, Some(name)), 1.0, film.clone());
+
+            assert_eq!(position, camera.position);
+            assert_eq!(target, camera.target);
+        }
+
+        // without a name the first camera of the list renders
+        let camera = Camera::from_yaml(select_camera_yaml(settings, None), 1.0, film);
+        assert_eq!(Point3::new(0.0, 0.0, 4.0), camera.position);
+    }
+
+    #[test]
+    fn test_turntable_frames_orbit_the_target() {
+        let film = Arc::new(RwLock::new(Film::new(
+            Vector2::new(10, 10),
+            Vector2::new(10, 10),
+            None,
+            None,
+            None,
+            FilterMethod::None,
+            1.0,
+            None,
+            None,
+        )));
+        let target = Point3::new(1.0, 2.0, 0.0);
+        let camera = Camera::new(
+            Point3::new(1.0, 3.0, 4.0),
+            target,
+            Vector3::y(),
+            0.0,
+            1.0,
+            40.0,
+            0.01,
+            1000.0,
+            0.0,
+            None,
+            Bounds {
+                p_min: Point2::new(-1.0, -1.0),
+                p_max: Point2::new(1.0, 1.0),
+            },
+            film,
+        );
+
+        // a quarter turn per frame, counterclockwise seen from above
+        for (frame, position) in [
+            (0, Point3::new(1.0, 3.0, 4.0)),
+            (1, Point3::new(5.0, 3.0, 0.0)),
+            (2, Point3::new(1.0, 3.0, -4.0)),
+            (3, Point3::new(-3.0, 3.0, 0.0)),
+        ] {
+            let orbit = camera.turntable(frame, 4);
+
+            assert_relative_eq!(position, orbit.position, epsilon = 1.0e-12);
+            assert_eq!(target, orbit.target);
+            assert_relative_eq!(camera.focal_distance, orbit.focal_distance);
+        }
+    }
+
+    #[test]
+    fn test_orbit_keeps_the_target_and_the_radius() {
+        let film = Arc::new(RwLock::new(Film::new(
+            Vector2::new(10, 10),
+            Vector2::new(10, 10),
+            None,
+            None,
+            None,
+            FilterMethod::None,
+            1.0,
+            None,
+            None,
+        )));
+        let target = Point3::new(1.0, 2.0, 0.0);
+        let camera = Camera::new(
+            Point3::new(1.0, 2.0, 4.0),
+            target,
+            Vector3::y(),
+            0.0,
+            1.0,
+            40.0,
+            0.01,
+            1000.0,
+            0.0,
+            None,
+            Bounds {
+                p_min: Point2::new(-1.0, -1.0),
+                p_max: Point2::new(1.0, 1.0),
+            },
+            film,
+        );
+
+        // a quarter turn to the right, then up to 30 degrees above the horizon
+        let orbit = camera.orbit(90.0, 0.0, 1.0);
+        assert_relative_eq!(
+            Point3::new(-3.0, 2.0, 0.0),
+            orbit.position,
+            epsilon = 1.0e-12
+        );
+        let orbit = orbit.orbit(0.0, 30.0, 1.0);
+        assert_relative_eq!(
+            Point3::new(1.0 - 4.0 * 30f64.to_radians().cos(), 4.0, 0.0),
+            orbit.position,
+            epsilon = 1.0e-12
+        );
+        assert_eq!(target, orbit.target);
+        assert_relative_eq!(
+            4.0,
+            nalgebra::distance(&orbit.position, &target),
+            epsilon = 1.0e-12
+        );
+
+        // dragging far up stops short of the top, scrolling moves in
+        let top = camera.orbit(0.0, 120.0, 0.5);
+        assert_relative_eq!(
+            2.0,
+            nalgebra::distance(&top.position, &target),
+            epsilon = 1.0e-12
+        );
+        assert_relative_eq!(
+            2.0 + 2.0 * 89f64.to_radians().sin(),
+            top.position.y,
+            epsilon = 1.0e-12
+        );
+        assert_relative_eq!(camera.focal_distance / 2.0, top.focal_distance);
+    }
+
+    #[test]
+    fn test_framing_fits_the_bounds_in_the_field_of_view() {
+        let film = Arc::new(RwLock::new(Film::new(
+            Vector2::new(10, 10),
+            Vector2::new(10, 10),
+            None,
+            None,
+            None,
+            FilterMethod::None,
+            1.0,
+            None,
+            None,
+        )));
+        let camera = Camera::new(
+            Point3::new(0.0, 0.0, 1.0),
+            Point3::origin(),
+            Vector3::y(),
+            0.0,
+            1.0,
+            40.0,
+            0.01,
+            1000.0,
+            0.0,
+            None,
+            Bounds {
+                p_min: Point2::new(-1.0, -1.0),
+                p_max: Point2::new(1.0, 1.0),
+            },
+            film,
+        );
+
+        // a scene with a radius of 2, seen from the same direction
+        let center = Point3::new(1.0, 2.0, -3.0);
+        let framed = camera.framed(center, 2.0);
+        let distance = 2.0 / 20f64.to_radians().sin();
+        assert_relative_eq!(center + Vector3::z() * distance, framed.position);
+        assert_eq!(center, framed.target);
+        assert_relative_eq!(distance, framed.focal_distance);
+
+        // rays through the middle of the film edges just touch the bounds
+        for p_film in [Point2::new(0.0, 5.0), Point2::new(5.0, 10.0)] {
+            let ray = framed.generate_ray(CameraSample {
+                p_film,
+                p_lens: Point2::origin(),
+            });
+            let to_center = center - ray.point;
+            let closest = to_center - ray.direction * to_center.dot(&ray.direction);
+            assert_relative_eq!(2.0, closest.magnitude(), epsilon = 1.0e-9);
+        }
+    }
 }
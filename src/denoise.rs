@@ -1,26 +1,24 @@
-use image::ImageBuffer;
+use image::{ImageBuffer, RgbImage};
 
+use crate::film::DenoiseAux;
 use crate::Film;
 
-pub fn denoise(film: &mut Film) -> &mut Film {
+/// Largest difference of a channel (out of 255) between the previous and the current frame for
+/// which the previous frame is blended in, larger differences are changes in the image (or
+/// motion) instead of flicker
+const HISTORY_TOLERANCE: u8 = 24;
+
+/// Denoises the displayed image. With `Film::temporal_denoise` set the `previous` denoised frame
+/// of a sequence is blended into the result, which reduces flicker between frames.
+pub fn denoise<'a>(film: &'a mut Film, previous: Option<&RgbImage>) -> &'a mut Film {
+    if let Some(threshold) = film.prefilter_fireflies {
+        film.remove_fireflies(threshold);
+    }
+
     let image_width = film.image_size.x;
     let image_height = film.image_size.y;
 
-    let mut normal_map = vec![0f32; image_width as usize * image_height as usize * 3];
-    let mut albedo_map = vec![0f32; image_width as usize * image_height as usize * 3];
-    film.pixels
-        .clone()
-        .iter()
-        .enumerate()
-        .for_each(|(i, pixel)| {
-            normal_map[i * 3] = pixel.normal.x as f32;
-            normal_map[i * 3 + 1] = pixel.normal.y as f32;
-            normal_map[i * 3 + 2] = pixel.normal.z as f32;
-
-            albedo_map[i * 3] = pixel.albedo.x as f32;
-            albedo_map[i * 3 + 1] = pixel.albedo.y as f32;
-            albedo_map[i * 3 + 2] = pixel.albedo.z as f32;
-        });
+    let (normal_map, albedo_map) = aux_maps(film);
 
     let temp = film.image_buffer.clone();
     let input_img: Vec<f32> = temp
@@ -54,5 +52,131 @@ pub fn denoise(film: &mut Film) -> &mut Film {
     )
     .unwrap();
 
+    if let (Some(weight), Some(previous)) = (film.temporal_denoise, previous) {
+        blend_with_previous(&mut film.image_buffer, previous, weight);
+    }
+
     film
 }
+
+/// Blends `weight` of the previous frame into pixels that are close to it. There is no motion
+/// compensation, pixels that moved differ too much and keep the current frame.
+fn blend_with_previous(image: &mut RgbImage, previous: &RgbImage, weight: f64) {
+    if image.dimensions() != previous.dimensions() {
+        return;
+    }
+
+    for (pixel, previous) in image.pixels_mut().zip(previous.pixels()) {
+        let close = pixel
+            .0
+            .iter()
+            .zip(previous.0)
+            .all(|(current, previous)| current.abs_diff(previous) <= HISTORY_TOLERANCE);
+
+        if close {
+            for (current, previous) in pixel.0.iter_mut().zip(previous.0) {
+                *current =
+                    (*current as f64 * (1.0 - weight) + previous as f64 * weight).round() as u8;
+            }
+        }
+    }
+}
+
+/// The normal and albedo guides for the denoiser, taken from the source set by
+/// `Film::denoise_aux`.
+fn aux_maps(film: &Film) -> (Vec<f32>, Vec<f32>) {
+    let mut normal_map = vec![0f32; film.pixels.len() * 3];
+    let mut albedo_map = vec![0f32; film.pixels.len() * 3];
+
+    for (i, pixel) in film.pixels.iter().enumerate() {
+        let (normal, albedo) = match film.denoise_aux {
+            DenoiseAux::LastSample => (pixel.normal, pixel.albedo),
+            DenoiseAux::Aov => (pixel.normal_aov(), pixel.albedo_aov()),
+        };
+
+        normal_map[i * 3] = normal.x as f32;
+        normal_map[i * 3 + 1] = normal.y as f32;
+        normal_map[i * 3 + 2] = normal.z as f32;
+
+        albedo_map[i * 3] = albedo.x as f32;
+        albedo_map[i * 3 + 1] = albedo.y as f32;
+        albedo_map[i * 3 + 2] = albedo.z as f32;
+    }
+
+    (normal_map, albedo_map)
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgb, RgbImage};
+    use nalgebra::{Vector2, Vector3};
+
+    use crate::denoise::{aux_maps, blend_with_previous, HISTORY_TOLERANCE};
+    use crate::film::{DenoiseAux, Film, FilterMethod};
+
+    #[test]
+    fn test_aov_mode_hands_the_averaged_passes_to_the_denoiser() {
+        let mut film = Film::new(
+            Vector2::new(1, 1),
+            Vector2::new(1, 1),
+            None,
+            None,
+            None,
+            FilterMethod::None,
+            1.0,
+            None,
+            None,
+        );
+        // the last sample hit a red wall facing x, most samples a gray floor facing y
+        let pixel = &mut film.pixels[0];
+        pixel.normal = Vector3::x();
+        pixel.albedo = Vector3::new(0.9, 0.1, 0.1);
+        pixel.sum_weight = 4.0;
+        pixel.sum_normal = Vector3::x() + Vector3::y() * 3.0;
+        pixel.sum_albedo = Vector3::new(0.9, 0.1, 0.1) + Vector3::repeat(0.5) * 3.0;
+
+        let (normal_map, albedo_map) = aux_maps(&film);
+        assert_eq!(vec![1.0, 0.0, 0.0], normal_map);
+        assert_eq!(vec![0.9, 0.1, 0.1], albedo_map);
+
+        film.denoise_aux = DenoiseAux::Aov;
+        let (normal_map, albedo_map) = aux_maps(&film);
+        let normal = Vector3::new(1.0, 3.0, 0.0).normalize();
+        assert_eq!(vec![normal.x as f32, normal.y as f32, 0.0], normal_map);
+        assert_eq!(vec![0.6, 0.4, 0.4], albedo_map);
+    }
+
+    #[test]
+    fn test_previous_frame_is_only_blended_into_pixels_close_to_it() {
+        let previous = RgbImage::from_fn(3, 1, |_, _| Rgb([100, 100, 100]));
+        // flicker within the tolerance, a change in one channel just over it and a change far
+        // beyond it
+        let mut image = RgbImage::from_vec(
+            3,
+            1,
+            vec![
+                120,
+                90,
+                100,
+                100 + HISTORY_TOLERANCE + 1,
+                100,
+                100,
+                250,
+                20,
+                20,
+            ],
+        )
+        .unwrap();
+
+        blend_with_previous(&mut image, &previous, 0.25);
+
+        assert_eq!(Rgb([115, 93, 100]), image[(0, 0)]);
+        assert_eq!(Rgb([100 + HISTORY_TOLERANCE + 1, 100, 100]), image[(1, 0)]);
+        assert_eq!(Rgb([250, 20, 20]), image[(2, 0)]);
+
+        // a previous frame of another size is ignored
+        let mut resized = RgbImage::from_pixel(2, 1, Rgb([110, 110, 110]));
+        blend_with_previous(&mut resized, &previous, 0.25);
+        assert_eq!(RgbImage::from_pixel(2, 1, Rgb([110, 110, 110])), resized);
+    }
+}
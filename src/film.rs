@@ -1,11 +1,19 @@
 use std::cmp;
-use std::sync::{Arc, Mutex};
-
-use image::{ImageBuffer, Rgb};
+use std::fs;
+use std::io;
+use std::mem;
+use std::path::Path;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use image::{GrayImage, ImageBuffer, ImageResult, Luma, Rgb, Rgba};
+use indicatif::{ProgressBar, ProgressStyle};
 use nalgebra::{Point2, Vector2, Vector3};
+use yaml_rust::Yaml;
 
+use crate::bucket_queue::BucketQueues;
 use crate::helpers::Bounds;
-use crate::renderer::SampleResult;
+use crate::light_path::{LightPathExpression, MAX_LIGHT_PATH_EXPRESSIONS};
+use crate::renderer::{DebugBuffer, SampleResult};
 
 #[derive(Eq, PartialEq)]
 pub enum FilterMethod {
@@ -15,6 +23,16 @@ pub enum FilterMethod {
 }
 
 const GAUSSIAN_ALPHA: f64 = 1.5;
+const ADAPTIVE_CLAMP_MIN_SAMPLES: u32 = 8;
+const DEFAULT_BUCKET_SIZE: u32 = 32;
+const CHECKPOINT_MAGIC: &[u8] = b"RTCHECK1";
+// the weight, 8 vectors, the light paths, coverage, sample count and the two luminance sums
+const CHECKPOINT_PIXEL_VALUES: usize = 1 + 8 * 3 + MAX_LIGHT_PATH_EXPRESSIONS * 3 + 4;
+pub const DEFAULT_MAX_MEGAPIXELS: f64 = 50.0;
+/// Camera settings with an exposure of 1, see `Film::exposure_from_triangle`
+pub const REFERENCE_SHUTTER: f64 = 1.0 / 60.0;
+pub const REFERENCE_F_NUMBER: f64 = 2.8;
+pub const REFERENCE_ISO: f64 = 100.0;
 
 impl FilterMethod {
     pub fn from_str(str: &str) -> Option<FilterMethod> {
@@ -26,8 +44,122 @@ impl FilterMethod {
     }
 }
 
-#[derive(Debug)]
+/// Where the denoiser takes its normal and albedo guides from.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub enum DenoiseAux {
+    /// The normal and albedo of the last sample that reached the pixel
+    #[default]
+    LastSample,
+    /// The filtered averages of the first hit normal and albedo of all samples
+    Aov,
+}
+
+impl DenoiseAux {
+    pub fn from_str(str: &str) -> Option<DenoiseAux> {
+        match str {
+            "last_sample" => Some(DenoiseAux::LastSample),
+            "aov" => Some(DenoiseAux::Aov),
+            _ => None,
+        }
+    }
+}
+
+/// Transfer function applied when the film is turned into 8 bit images.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub enum OutputColorSpace {
+    #[default]
+    Srgb,
+    Linear,
+    /// Plain power law with the given gamma, e.g. 2.2
+    Gamma(f64),
+    Rec709,
+}
+
+impl OutputColorSpace {
+    pub fn from_str(str: &str, gamma: Option<f64>) -> Option<OutputColorSpace> {
+        match str {
+            "srgb" => Some(OutputColorSpace::Srgb),
+            "linear" => Some(OutputColorSpace::Linear),
+            "gamma" => Some(OutputColorSpace::Gamma(gamma.unwrap_or(2.2))),
+            "rec709" => Some(OutputColorSpace::Rec709),
+            _ => None,
+        }
+    }
+
+    /// Encodes a linear value, clamped to [0, 1].
+    pub fn encode(self, value: f64) -> f64 {
+        let value = value.clamp(0.0, 1.0);
+
+        match self {
+            OutputColorSpace::Srgb => gamma_correct_srgb(value),
+            OutputColorSpace::Linear => value,
+            OutputColorSpace::Gamma(gamma) => value.powf(1.0 / gamma),
+            OutputColorSpace::Rec709 => {
+                if value < 0.018 {
+                    value * 4.5
+                } else {
+                    1.099 * value.powf(0.45) - 0.099
+                }
+            }
+        }
+    }
+}
+
+/// An image written after the render, from the `outputs` list of the render settings.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Pass {
+    Beauty,
+    /// The beauty pass after denoising, the denoiser only works on the 8 bit image so this pass
+    /// should not be written as `.exr`
+    Denoised,
+    Normal,
+    Albedo,
+    Alpha,
+    SampleCount,
+}
+
+impl Pass {
+    pub fn from_str(str: &str) -> Option<Pass> {
+        match str {
+            "beauty" => Some(Pass::Beauty),
+            "denoised" => Some(Pass::Denoised),
+            "normal" => Some(Pass::Normal),
+            "albedo" => Some(Pass::Albedo),
+            "alpha" => Some(Pass::Alpha),
+            "sample_count" => Some(Pass::SampleCount),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Output {
+    pub pass: Pass,
+    pub file: String,
+}
+
+impl Output {
+    /// Reads a list of `{pass, file}` entries, a missing list means no outputs.
+    pub fn list_from_yaml(yaml: &Yaml) -> Vec<Output> {
+        yaml.as_vec()
+            .map(Vec::as_slice)
+            .unwrap_or(&[])
+            .iter()
+            .map(|output| Output {
+                pass: Pass::from_str(output["pass"].as_str().expect("Output without a pass"))
+                    .expect("Unknown output pass"),
+                file: output["file"]
+                    .as_str()
+                    .expect("Output without a file")
+                    .to_string(),
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Bucket {
+    pub index: u32,
     pub sample_bounds: Bounds<u32>,
     pub pixel_bounds: Bounds<u32>,
     pub samples: Vec<SampleResult>,
@@ -46,21 +178,269 @@ pub struct Pixel {
     pub sum_radiance: Vector3<f64>,
     pub normal: Vector3<f64>,
     pub albedo: Vector3<f64>,
+    pub sum_normal: Vector3<f64>,
+    pub sum_albedo: Vector3<f64>,
+    pub sum_diffuse: Vector3<f64>,
+    pub sum_glossy: Vector3<f64>,
+    pub sum_transmission: Vector3<f64>,
+    pub sum_coverage: f64,
+    pub sum_light_paths: [Vector3<f64>; MAX_LIGHT_PATH_EXPRESSIONS],
+    pub sample_count: u32,
+    pub sum_luminance: f64,
+    pub sum_luminance_squared: f64,
+}
+
+impl Pixel {
+    fn new() -> Pixel {
+        Pixel {
+            sum_weight: 0.0,
+            sum_radiance: Vector3::new(0.0, 0.0, 0.0),
+            normal: Vector3::new(0.0, 0.0, 0.0),
+            albedo: Vector3::new(0.0, 0.0, 0.0),
+            sum_normal: Vector3::new(0.0, 0.0, 0.0),
+            sum_albedo: Vector3::new(0.0, 0.0, 0.0),
+            sum_diffuse: Vector3::new(0.0, 0.0, 0.0),
+            sum_glossy: Vector3::new(0.0, 0.0, 0.0),
+            sum_transmission: Vector3::new(0.0, 0.0, 0.0),
+            sum_coverage: 0.0,
+            sum_light_paths: [Vector3::zeros(); MAX_LIGHT_PATH_EXPRESSIONS],
+            sample_count: 0,
+            sum_luminance: 0.0,
+            sum_luminance_squared: 0.0,
+        }
+    }
+
+    /// All fields as `CHECKPOINT_PIXEL_VALUES` numbers, in the order read back by
+    /// `from_checkpoint_values`.
+    fn checkpoint_values(&self) -> Vec<f64> {
+        let mut values = vec![self.sum_weight];
+        for vector in [
+            self.sum_radiance,
+            self.normal,
+            self.albedo,
+            self.sum_normal,
+            self.sum_albedo,
+            self.sum_diffuse,
+            self.sum_glossy,
+            self.sum_transmission,
+        ]
+        .iter()
+        .chain(&self.sum_light_paths)
+        {
+            values.extend_from_slice(vector.as_slice());
+        }
+        values.extend_from_slice(&[
+            self.sum_coverage,
+            self.sample_count as f64,
+            self.sum_luminance,
+            self.sum_luminance_squared,
+        ]);
+
+        values
+    }
+
+    fn from_checkpoint_values(values: &[f64]) -> Pixel {
+        let mut vectors = values[1..].chunks_exact(3).map(Vector3::from_column_slice);
+        let mut pixel = Pixel::new();
+
+        pixel.sum_weight = values[0];
+        for field in [
+            &mut pixel.sum_radiance,
+            &mut pixel.normal,
+            &mut pixel.albedo,
+            &mut pixel.sum_normal,
+            &mut pixel.sum_albedo,
+            &mut pixel.sum_diffuse,
+            &mut pixel.sum_glossy,
+            &mut pixel.sum_transmission,
+        ]
+        .into_iter()
+        .chain(pixel.sum_light_paths.iter_mut())
+        {
+            *field = vectors.next().unwrap();
+        }
+        let [sum_coverage, sample_count, sum_luminance, sum_luminance_squared] =
+            values[values.len() - 4..].try_into().unwrap();
+        pixel.sum_coverage = sum_coverage;
+        pixel.sample_count = sample_count as u32;
+        pixel.sum_luminance = sum_luminance;
+        pixel.sum_luminance_squared = sum_luminance_squared;
+
+        pixel
+    }
+
+    pub fn radiance(&self) -> Vector3<f64> {
+        if self.sum_weight < f64::EPSILON {
+            return Vector3::zeros();
+        }
+
+        self.sum_radiance / self.sum_weight
+    }
+
+    /// Average first hit normal of the samples, normalized. Zero where no sample hit anything.
+    pub fn normal_aov(&self) -> Vector3<f64> {
+        self.sum_normal
+            .try_normalize(f64::EPSILON)
+            .unwrap_or_else(Vector3::zeros)
+    }
+
+    pub fn albedo_aov(&self) -> Vector3<f64> {
+        if self.sum_weight < f64::EPSILON {
+            return Vector3::zeros();
+        }
+
+        self.sum_albedo / self.sum_weight
+    }
+
+    /// Fraction of the camera rays that hit an object, used as alpha channel.
+    pub fn alpha(&self) -> f64 {
+        if self.sum_weight < f64::EPSILON {
+            return 0.0;
+        }
+
+        self.sum_coverage / self.sum_weight
+    }
+
+    /// Whether `luminance` lies more than `k` standard deviations above the mean of the samples
+    /// added so far. The first few samples are always accepted, the estimate is too rough.
+    fn is_above_deviations(&self, luminance: f64, k: f64) -> bool {
+        if self.sample_count < ADAPTIVE_CLAMP_MIN_SAMPLES {
+            return false;
+        }
+
+        let mean = self.sum_luminance / self.sample_count as f64;
+
+        luminance > mean + k * self.variance().sqrt()
+    }
+
+    fn add_variance_sample(&mut self, luminance: f64) {
+        self.sample_count += 1;
+        self.sum_luminance += luminance;
+        self.sum_luminance_squared += luminance * luminance;
+    }
+
+    /// Sample variance of the luminance of all samples that landed in this pixel.
+    pub fn variance(&self) -> f64 {
+        if self.sample_count < 2 {
+            return 0.0;
+        }
+
+        let n = self.sample_count as f64;
+        ((self.sum_luminance_squared - self.sum_luminance * self.sum_luminance / n) / (n - 1.0))
+            .max(0.0)
+    }
+
+    /// Standard error of the pixel mean relative to the mean, so dark and bright pixels with
+    /// the same amount of visible noise are comparable.
+    pub fn relative_error(&self) -> f64 {
+        if self.sample_count == 0 {
+            return 0.0;
+        }
+
+        let n = self.sample_count as f64;
+        let mean = self.sum_luminance / n;
+
+        (self.variance() / n).sqrt() / mean.max(1.0e-4)
+    }
+}
+
+/// Samples film offsets proportional to the reconstruction filter, so samples cluster where the
+/// filter weight is high instead of being filtered into every pixel around them. The filter
+/// divided by the pdf is constant up to its sign, which becomes the weight of the sample (the
+/// Mitchell filter has negative lobes).
+#[derive(Debug, Clone)]
+pub struct FilterSampler {
+    radius: f64,
+    // over the distance to the pixel center, from 0 to the radius
+    cdf: Vec<f64>,
+    signs: Vec<f64>,
+}
+
+impl FilterSampler {
+    fn new(radius: f64, filter: &[f64]) -> Self {
+        let total: f64 = filter.iter().map(|f| f.abs()).sum();
+        let cdf = filter
+            .iter()
+            .scan(0.0, |sum, f| {
+                *sum += f.abs() / total;
+                Some(*sum)
+            })
+            .collect();
+
+        FilterSampler {
+            radius,
+            cdf,
+            signs: filter.iter().map(|f| f.signum()).collect(),
+        }
+    }
+
+    /// Offset from the pixel center and weight for a uniform sample in [0, 1)².
+    pub fn sample(&self, u: Vector2<f64>) -> (Vector2<f64>, f64) {
+        let (x, sign_x) = self.sample_1d(u.x);
+        let (y, sign_y) = self.sample_1d(u.y);
+
+        (Vector2::new(x, y), sign_x * sign_y)
+    }
+
+    /// The lower half of `u` samples the negative side, the upper half the positive side.
+    fn sample_1d(&self, u: f64) -> (f64, f64) {
+        let (side, u) = if u < 0.5 {
+            (-1.0, 2.0 * u)
+        } else {
+            (1.0, 2.0 * u - 1.0)
+        };
+
+        let bin = self
+            .cdf
+            .partition_point(|&c| c <= u)
+            .min(self.cdf.len() - 1);
+        let start = if bin == 0 { 0.0 } else { self.cdf[bin - 1] };
+        let fraction = ((u - start) / (self.cdf[bin] - start)).clamp(0.0, 1.0);
+        let distance = (bin as f64 + fraction) / self.cdf.len() as f64 * self.radius;
+
+        (side * distance, self.signs[bin])
+    }
 }
 
 pub struct Film {
     pub image_size: Vector2<u32>,
     crop_start: Option<Point2<u32>>,
     crop_end: Option<Point2<u32>>,
+    render_region: Option<Bounds<u32>>,
     pub pixels: Vec<Pixel>,
     pub image_buffer: ImageBuffer<Rgb<u8>, Vec<u8>>,
     filter_radius: f64,
     filter_method: FilterMethod,
     filter_table: Vec<f64>,
     filter_table_size: usize,
+    outlier_rejection: Option<f64>,
+    adaptive_clamp: Option<f64>,
+    pub color_space: OutputColorSpace,
+    /// Multiplies the radiance before tone mapping, from the shutter, f-number and ISO of the
+    /// camera
+    pub exposure: f64,
+    /// Luminance that maps to white with the extended Reinhard operator, no tone mapping when
+    /// not set
+    pub white_point: Option<f64>,
+    /// Add a fixed noise pattern before quantizing the displayed and written 8 bit image, smooth
+    /// gradients then show fine noise instead of banding
+    pub dither: bool,
+    /// Before denoising, pixels this many times brighter than the median of their neighbors
+    /// are replaced by that median, not filtered when not set
+    pub prefilter_fireflies: Option<f64>,
+    /// Weight of the previous denoised frame of a turntable in the next one, less flicker
+    /// between frames. Frames are denoised independently when not set
+    pub temporal_denoise: Option<f64>,
+    /// Place samples following the filter instead of filtering them when they are added, see
+    /// `FilterSampler`
+    pub filter_importance_sampling: bool,
+    pub denoise_aux: DenoiseAux,
     bucket_size: Vector2<u32>,
-    current_bucket: u32,
-    buckets: Vec<Arc<Mutex<Bucket>>>,
+    completed_buckets: AtomicU32,
+    progress: ProgressBar,
+    /// The buckets without pixels, a render thread gets a copy with its own pixels
+    buckets: Vec<Bucket>,
+    queues: BucketQueues,
 }
 
 impl Film {
@@ -69,19 +449,17 @@ impl Film {
         bucket_size: Vector2<u32>,
         crop_start: Option<Point2<u32>>,
         crop_end: Option<Point2<u32>>,
+        render_region: Option<Bounds<u32>>,
         filter_method: FilterMethod,
         filter_radius: f64,
+        outlier_rejection: Option<f64>,
+        adaptive_clamp: Option<f64>,
     ) -> Film {
         let mut filter_radius = filter_radius;
         let mut pixels = vec![];
 
         for _ in 0..(image_size.x * image_size.y) {
-            pixels.push(Pixel {
-                sum_weight: 0.0,
-                sum_radiance: Vector3::new(0.0, 0.0, 0.0),
-                normal: Vector3::new(0.0, 0.0, 0.0),
-                albedo: Vector3::new(0.0, 0.0, 0.0),
-            });
+            pixels.push(Pixel::new());
         }
 
         let mut filter_table = vec![];
@@ -115,15 +493,28 @@ impl Film {
             image_size,
             crop_start,
             crop_end,
+            render_region,
             pixels,
             image_buffer: ImageBuffer::new(image_size.x, image_size.y),
             filter_radius,
             filter_method,
             filter_table,
             filter_table_size,
-            current_bucket: 0,
+            outlier_rejection,
+            adaptive_clamp,
+            color_space: OutputColorSpace::default(),
+            exposure: 1.0,
+            white_point: None,
+            dither: false,
+            prefilter_fireflies: None,
+            temporal_denoise: None,
+            filter_importance_sampling: false,
+            denoise_aux: DenoiseAux::default(),
+            completed_buckets: AtomicU32::new(0),
+            progress: ProgressBar::hidden(),
             bucket_size,
             buckets: vec![],
+            queues: BucketQueues::new(0, 1),
         };
 
         film.init_buckets();
@@ -131,35 +522,111 @@ impl Film {
         film
     }
 
-    pub fn get_bucket(&mut self) -> Option<Arc<Mutex<Bucket>>> {
-        let len = self.buckets.len() as u32;
-
-        if self.current_bucket >= len {
-            println!("No buckets left.");
-            return None;
-        }
+    /// Throws away everything rendered so far so all buckets can be rendered again.
+    pub fn clear(&mut self) {
+        self.pixels = vec![Pixel::new(); self.pixels.len()];
+        self.image_buffer = ImageBuffer::new(self.image_size.x, self.image_size.y);
+        self.completed_buckets = AtomicU32::new(0);
+        self.init_buckets();
+    }
 
-        let bucket = self.buckets[self.current_bucket as usize].clone();
-        self.current_bucket += 1;
+    /// Gives each of `thread_count` render threads its own queue of the buckets that are left,
+    /// see `BucketQueues`.
+    pub fn split_buckets(&mut self, thread_count: u32) {
+        self.queues.split(thread_count);
+    }
 
-        println!("Handing out bucket {}", self.current_bucket);
+    /// Hands out the next bucket for the render thread `thread_id`, stolen from another thread
+    /// when its own queue is empty. Only a read lock on the film is needed. The bucket is a copy
+    /// owned by the thread, it is sampled without any locks and only merged into the film with
+    /// `merge_bucket_pixels_to_image_buffer`.
+    pub fn get_bucket(&self, thread_id: u32) -> Option<Bucket> {
+        let index = self.queues.pop(thread_id)?;
+        let mut bucket = self.buckets[index as usize].clone();
+        bucket.pixels = vec![Pixel::new(); bucket.pixel_bounds.area() as usize];
 
         Some(bucket)
     }
 
+    /// Number of buckets a render thread took from the queue of another thread.
+    pub fn stolen_buckets(&self) -> u32 {
+        self.queues.stolen()
+    }
+
+    pub fn completed_buckets(&self) -> u32 {
+        self.completed_buckets.load(Ordering::Relaxed)
+    }
+
+    /// Marks a bucket as rendered and advances the progress bar, returns the number of buckets
+    /// completed so far.
+    pub fn complete_bucket(&self) -> u32 {
+        let completed = self.completed_buckets.fetch_add(1, Ordering::Relaxed) + 1;
+
+        self.progress.inc(1);
+        if completed as usize == self.buckets.len() {
+            self.progress.finish();
+        }
+
+        completed
+    }
+
     pub fn write_bucket_pixels(&self, bucket: &mut Bucket) {
         let samples = &bucket.samples;
+        let rejection_thresholds = self
+            .outlier_rejection
+            .map(|k| outlier_thresholds(bucket, k));
 
         for sample in samples.iter() {
             // compute pixel influence raster
             let pixel_discrete = sample.p_film; // - Point2::new(0.5, 0.5);
 
-            if self.filter_method == FilterMethod::None {
+            // the variance is tracked for the pixel the sample was taken in, regardless of the filter
+            let bucket_x = pixel_discrete.x as u32 - bucket.pixel_bounds.p_min.x;
+            let bucket_y = pixel_discrete.y as u32 - bucket.pixel_bounds.p_min.y;
+            let pixel_index = (bucket_x + bucket.pixel_bounds.vector().x * bucket_y) as usize;
+
+            if let Some(thresholds) = &rejection_thresholds {
+                if sample.radiance.y > thresholds[pixel_index] {
+                    continue;
+                }
+            }
+
+            if let Some(k) = self.adaptive_clamp {
+                if bucket.pixels[pixel_index].is_above_deviations(sample.radiance.y, k) {
+                    continue;
+                }
+            }
+
+            bucket.pixels[pixel_index].add_variance_sample(sample.radiance.y);
+
+            // shadows caught in front of the background are kept in the alpha channel
+            let coverage = if sample.hit_background {
+                sample.shadow
+            } else {
+                1.0
+            };
+
+            // importance sampled filters already placed the sample, it only counts for its pixel
+            if self.filter_method == FilterMethod::None || self.filter_importance_sampling {
+                let weight = sample.filter_weight;
                 let bucket_x = pixel_discrete.x as u32 - bucket.pixel_bounds.p_min.x;
                 let bucket_y = pixel_discrete.y as u32 - bucket.pixel_bounds.p_min.y;
                 let pixel_index = (bucket_x + bucket.pixel_bounds.vector().x * bucket_y) as usize;
-                bucket.pixels[pixel_index].sum_radiance += sample.radiance;
-                bucket.pixels[pixel_index].sum_weight += 1.0;
+                bucket.pixels[pixel_index].sum_radiance += sample.radiance * weight;
+                bucket.pixels[pixel_index].sum_weight += weight;
+                bucket.pixels[pixel_index].sum_normal += sample.normal * weight;
+                bucket.pixels[pixel_index].sum_albedo += sample.albedo * weight;
+                bucket.pixels[pixel_index].sum_diffuse += sample.diffuse * weight;
+                bucket.pixels[pixel_index].sum_glossy += sample.glossy * weight;
+                bucket.pixels[pixel_index].sum_transmission += sample.transmission * weight;
+                bucket.pixels[pixel_index].sum_coverage += coverage * weight;
+                for (sum, radiance) in bucket.pixels[pixel_index]
+                    .sum_light_paths
+                    .iter_mut()
+                    .zip(sample.light_paths.0)
+                {
+                    *sum += radiance * weight;
+                }
                 // todo: average or throw away?
                 bucket.pixels[pixel_index].normal = sample.normal;
                 bucket.pixels[pixel_index].albedo = sample.albedo;
@@ -208,6 +675,20 @@ impl Film {
 
                     bucket.pixels[pixel_index].sum_radiance += sample.radiance * filter_weight;
                     bucket.pixels[pixel_index].sum_weight += filter_weight;
+                    bucket.pixels[pixel_index].sum_normal += sample.normal * filter_weight;
+                    bucket.pixels[pixel_index].sum_albedo += sample.albedo * filter_weight;
+                    bucket.pixels[pixel_index].sum_diffuse += sample.diffuse * filter_weight;
+                    bucket.pixels[pixel_index].sum_glossy += sample.glossy * filter_weight;
+                    bucket.pixels[pixel_index].sum_transmission +=
+                        sample.transmission * filter_weight;
+                    bucket.pixels[pixel_index].sum_coverage += coverage * filter_weight;
+                    for (sum, radiance) in bucket.pixels[pixel_index]
+                        .sum_light_paths
+                        .iter_mut()
+                        .zip(sample.light_paths.0)
+                    {
+                        *sum += radiance * filter_weight;
+                    }
                     // todo: average or throw away?
                     bucket.pixels[pixel_index].normal = sample.normal;
                     bucket.pixels[pixel_index].albedo = sample.albedo;
@@ -227,29 +708,415 @@ impl Film {
 
             self.pixels[film_pixel_index].sum_weight += pixel.sum_weight;
             self.pixels[film_pixel_index].sum_radiance += pixel.sum_radiance;
-            self.pixels[film_pixel_index].normal += pixel.normal;
-            self.pixels[film_pixel_index].albedo += pixel.albedo;
+            self.pixels[film_pixel_index].sum_normal += pixel.sum_normal;
+            self.pixels[film_pixel_index].sum_albedo += pixel.sum_albedo;
+            self.pixels[film_pixel_index].sum_diffuse += pixel.sum_diffuse;
+            self.pixels[film_pixel_index].sum_glossy += pixel.sum_glossy;
+            self.pixels[film_pixel_index].sum_transmission += pixel.sum_transmission;
+            self.pixels[film_pixel_index].sum_coverage += pixel.sum_coverage;
+            for (sum, radiance) in self.pixels[film_pixel_index]
+                .sum_light_paths
+                .iter_mut()
+                .zip(pixel.sum_light_paths)
+            {
+                *sum += radiance;
+            }
+            self.pixels[film_pixel_index].sample_count += pixel.sample_count;
+            self.pixels[film_pixel_index].sum_luminance += pixel.sum_luminance;
+            self.pixels[film_pixel_index].sum_luminance_squared += pixel.sum_luminance_squared;
+
+            // Buckets overlap by the filter radius. The sums add up over the overlap, but the
+            // normal and albedo of the last sample are only taken from the bucket that owns
+            // the pixel, adding them would count them once for every bucket touching it.
+            let sample_bounds = bucket.sample_bounds;
+            if (sample_bounds.p_min.x..sample_bounds.p_max.x).contains(&x)
+                && (sample_bounds.p_min.y..sample_bounds.p_max.y).contains(&y)
+            {
+                self.pixels[film_pixel_index].normal = pixel.normal;
+                self.pixels[film_pixel_index].albedo = pixel.albedo;
+            }
 
-            if self.pixels[film_pixel_index].sum_weight < f64::EPSILON {
-                self.image_buffer.put_pixel(x, y, image::Rgb([0, 0, 0]));
-                continue;
+            self.update_image_pixel(x, y);
+        }
+    }
+
+    fn update_image_pixel(&mut self, x: u32, y: u32) {
+        let pixel = &self.pixels[self.get_pixel_index(x, y)];
+
+        if pixel.sum_weight < f64::EPSILON {
+            self.image_buffer.put_pixel(x, y, image::Rgb([0, 0, 0]));
+            return;
+        }
+
+        let radiance = pixel.sum_radiance / pixel.sum_weight;
+
+        let rgb = match self.dither {
+            true => self.tone_map_and_quantize(radiance, dither_offset(x, y)),
+            false => self.radiance_to_rgb(radiance),
+        };
+        self.image_buffer.put_pixel(x, y, rgb);
+    }
+
+    /// Replaces the radiance of every pixel by `radiance`, one value per pixel row by row, and
+    /// updates the image. Navigation writes the frames averaged while the camera stood still
+    /// this way, the other passes keep the last frame.
+    pub fn set_radiance(&mut self, radiance: &[Vector3<f64>]) {
+        for (pixel, radiance) in self.pixels.iter_mut().zip(radiance) {
+            if pixel.sum_weight < f64::EPSILON {
+                pixel.sum_weight = 1.0;
             }
+            pixel.sum_radiance = radiance * pixel.sum_weight;
+        }
+
+        for y in 0..self.image_size.y {
+            for x in 0..self.image_size.x {
+                self.update_image_pixel(x, y);
+            }
+        }
+    }
+
+    /// Saves the sums of every pixel to `path`, so a later render can continue from them with
+    /// `load_checkpoint`.
+    pub fn write_checkpoint(&self, path: &Path) -> io::Result<()> {
+        let mut bytes = CHECKPOINT_MAGIC.to_vec();
+        bytes.extend_from_slice(&self.image_size.x.to_le_bytes());
+        bytes.extend_from_slice(&self.image_size.y.to_le_bytes());
+
+        for pixel in &self.pixels {
+            for value in pixel.checkpoint_values() {
+                bytes.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+
+        fs::write(path, bytes)
+    }
+
+    /// Replaces the pixels with the ones saved by `write_checkpoint`. The buckets rendered
+    /// afterwards add their samples on top, so a render (or a region of it) continues where
+    /// the checkpoint left off. The checkpoint has to be of an image of the same size.
+    pub fn load_checkpoint(&mut self, path: &Path) -> io::Result<()> {
+        let bytes = fs::read(path)?;
+        let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message);
+
+        let header = bytes
+            .get(..CHECKPOINT_MAGIC.len() + 8)
+            .filter(|header| header.starts_with(CHECKPOINT_MAGIC))
+            .ok_or_else(|| invalid("not a checkpoint file"))?;
+        let size = &header[CHECKPOINT_MAGIC.len()..];
+        let width = u32::from_le_bytes(size[..4].try_into().unwrap());
+        let height = u32::from_le_bytes(size[4..].try_into().unwrap());
+        if width != self.image_size.x || height != self.image_size.y {
+            return Err(invalid(&format!(
+                "checkpoint is {}x{}, the image is {}x{}",
+                width, height, self.image_size.x, self.image_size.y
+            )));
+        }
 
-            let radiance = self.pixels[film_pixel_index].sum_radiance
-                / self.pixels[film_pixel_index].sum_weight;
+        let values: Vec<f64> = bytes[header.len()..]
+            .chunks_exact(8)
+            .map(|value| f64::from_le_bytes(value.try_into().unwrap()))
+            .collect();
+        if values.len() != self.pixels.len() * CHECKPOINT_PIXEL_VALUES {
+            return Err(invalid("checkpoint file is truncated"));
+        }
 
-            let rgb = xyz_to_srgb(radiance);
+        for (pixel, values) in self
+            .pixels
+            .iter_mut()
+            .zip(values.chunks_exact(CHECKPOINT_PIXEL_VALUES))
+        {
+            *pixel = Pixel::from_checkpoint_values(values);
+        }
 
-            let pixel_color_rgb = image::Rgb([
-                ((gamma_correct_srgb(rgb.x)) * 255.0) as u8,
-                ((gamma_correct_srgb(rgb.y)) * 255.0) as u8,
-                ((gamma_correct_srgb(rgb.z)) * 255.0) as u8,
-            ]);
+        for y in 0..self.image_size.y {
+            for x in 0..self.image_size.x {
+                self.update_image_pixel(x, y);
+            }
+        }
 
-            self.image_buffer.put_pixel(x, y, pixel_color_rgb);
+        Ok(())
+    }
+
+    /// Fills the pixels in `bounds` that were skipped by a preview stride (see
+    /// `Settings::preview_stride`) with the color of the closest rendered pixel in the same row.
+    /// Only the displayed image is filled, the pixels and outputs keep the gaps.
+    pub fn fill_preview_gaps(&mut self, bounds: Bounds<u32>, stride: u32) {
+        for y in bounds.p_min.y..bounds.p_max.y {
+            for x in bounds.p_min.x..bounds.p_max.x {
+                if self.pixels[self.get_pixel_index(x, y)].sample_count > 0 {
+                    continue;
+                }
+
+                let nearest = (1..stride)
+                    .flat_map(|distance| [x.checked_sub(distance), Some(x + distance)])
+                    .flatten()
+                    .find(|&x| {
+                        x < self.image_size.x
+                            && self.pixels[self.get_pixel_index(x, y)].sample_count > 0
+                    });
+
+                if let Some(nearest) = nearest {
+                    let color = *self.image_buffer.get_pixel(nearest, y);
+                    self.image_buffer.put_pixel(x, y, color);
+                }
+            }
         }
     }
 
+    /// Replaces fireflies in the displayed image by the per channel median of their (up to 8)
+    /// neighbors. A pixel is a firefly when its luminance is more than `threshold` times the
+    /// median luminance of its neighbors, a ratio so the result does not depend on exposure.
+    /// Pixels with a black median are kept, like in `outlier_thresholds`, so lit edges and thin
+    /// lines against a black background survive.
+    pub fn remove_fireflies(&mut self, threshold: f64) {
+        let image = self.image_buffer.clone();
+        let (width, height) = (self.image_size.x as i64, self.image_size.y as i64);
+
+        for y in 0..height {
+            for x in 0..width {
+                let neighbors: Vec<(u32, u32)> = (-1..=1)
+                    .flat_map(|dy| (-1..=1).map(move |dx| (x + dx, y + dy)))
+                    .filter(|&(nx, ny)| {
+                        (nx, ny) != (x, y) && nx >= 0 && ny >= 0 && nx < width && ny < height
+                    })
+                    .map(|(nx, ny)| (nx as u32, ny as u32))
+                    .collect();
+
+                let mut luminances: Vec<f64> = neighbors
+                    .iter()
+                    .map(|&(nx, ny)| self.pixels[self.get_pixel_index(nx, ny)].radiance().y)
+                    .collect();
+                let luminance = self.pixels[self.get_pixel_index(x as u32, y as u32)]
+                    .radiance()
+                    .y;
+                let median = median(&mut luminances);
+                if median == 0.0 || luminance <= threshold * median {
+                    continue;
+                }
+
+                let mut color = [0; 3];
+                for (channel, value) in color.iter_mut().enumerate() {
+                    let mut values: Vec<u8> = neighbors
+                        .iter()
+                        .map(|&(nx, ny)| image.get_pixel(nx, ny)[channel])
+                        .collect();
+                    values.sort_unstable();
+                    *value = values[values.len() / 2];
+                }
+                self.image_buffer.put_pixel(x as u32, y as u32, Rgb(color));
+            }
+        }
+    }
+
+    /// Writes the image with the coverage of each pixel as alpha channel. An `.exr` path is
+    /// written as linear float data, every other extension as 8 bit sRGB.
+    pub fn write_image(&self, path: &str) -> ImageResult<()> {
+        if path.ends_with(".exr") {
+            let image: ImageBuffer<Rgba<f32>, Vec<f32>> =
+                ImageBuffer::from_fn(self.image_size.x, self.image_size.y, |x, y| {
+                    let pixel = &self.pixels[self.get_pixel_index(x, y)];
+                    if pixel.sum_weight < f64::EPSILON {
+                        return Rgba([0.0, 0.0, 0.0, 0.0]);
+                    }
+
+                    let rgb = xyz_to_srgb(pixel.sum_radiance / pixel.sum_weight * self.exposure);
+                    Rgba([
+                        rgb.x as f32,
+                        rgb.y as f32,
+                        rgb.z as f32,
+                        pixel.alpha() as f32,
+                    ])
+                });
+
+            return image.save(path);
+        }
+
+        ImageBuffer::from_fn(self.image_size.x, self.image_size.y, |x, y| {
+            let Rgb([r, g, b]) = *self.image_buffer.get_pixel(x, y);
+            let alpha = self.pixels[self.get_pixel_index(x, y)].alpha();
+
+            Rgba([r, g, b, (alpha * 255.0).round() as u8])
+        })
+        .save(path)
+    }
+
+    /// Writes the display image to `path` and the linear radiance of the same pixels next to it
+    /// as `.exr`. Only the display image is tone mapped (and denoised, when the film is). An
+    /// `.exr` path gets its display image as `.png`.
+    pub fn write_display_and_linear(&self, path: &str) -> ImageResult<()> {
+        let path = Path::new(path);
+        let linear = path.with_extension("exr");
+        let display = if path == linear {
+            path.with_extension("png")
+        } else {
+            path.to_path_buf()
+        };
+
+        self.write_image(&display.to_string_lossy())?;
+        self.write_image(&linear.to_string_lossy())
+    }
+
+    /// Writes every output to its file. The image buffer is written for both the beauty and the
+    /// denoised pass, denoising is up to the caller.
+    pub fn write_outputs(&self, outputs: &[Output]) -> ImageResult<()> {
+        for output in outputs {
+            match output.pass {
+                Pass::Beauty | Pass::Denoised => self.write_image(&output.file)?,
+                Pass::Normal => self
+                    .pixel_image(|pixel| {
+                        let color = (pixel.normal * 0.5).add_scalar(0.5) * 255.0;
+                        Rgb([color.x as u8, color.y as u8, color.z as u8])
+                    })
+                    .save(&output.file)?,
+                Pass::Albedo => self
+                    .pixel_image(|pixel| radiance_to_rgb(pixel.albedo, self.color_space))
+                    .save(&output.file)?,
+                Pass::Alpha => self
+                    .pixel_image(|pixel| {
+                        let alpha = (pixel.alpha() * 255.0).round() as u8;
+                        Rgb([alpha, alpha, alpha])
+                    })
+                    .save(&output.file)?,
+                Pass::SampleCount => self.write_sample_count(&output.file)?,
+            }
+        }
+
+        Ok(())
+    }
+
+    fn pixel_image<F>(&self, color: F) -> ImageBuffer<Rgb<u8>, Vec<u8>>
+    where
+        F: Fn(&Pixel) -> Rgb<u8>,
+    {
+        ImageBuffer::from_fn(self.image_size.x, self.image_size.y, |x, y| {
+            color(&self.pixels[self.get_pixel_index(x, y)])
+        })
+    }
+
+    /// Writes the diffuse, glossy and transmission light groups as separate images, named
+    /// `{prefix}_diffuse.png`, `{prefix}_glossy.png` and `{prefix}_transmission.png`.
+    pub fn write_lobe_aovs(&self, prefix: &str) -> ImageResult<()> {
+        self.lobe_aov_image(|pixel| pixel.sum_diffuse)
+            .save(format!("{prefix}_diffuse.png"))?;
+        self.lobe_aov_image(|pixel| pixel.sum_glossy)
+            .save(format!("{prefix}_glossy.png"))?;
+        self.lobe_aov_image(|pixel| pixel.sum_transmission)
+            .save(format!("{prefix}_transmission.png"))?;
+
+        Ok(())
+    }
+
+    /// Writes the radiance of each light path expression as `{name}.png`.
+    pub fn write_light_path_aovs(&self, expressions: &[LightPathExpression]) -> ImageResult<()> {
+        for (index, expression) in expressions.iter().enumerate() {
+            self.lobe_aov_image(|pixel| pixel.sum_light_paths[index])
+                .save(format!("{}.png", expression.name))?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes the number of samples taken in each pixel as a grayscale image, the pixel with
+    /// the most samples is white.
+    pub fn write_sample_count(&self, path: &str) -> ImageResult<()> {
+        self.sample_count_image().save(path)
+    }
+
+    /// Writes the relative error of every pixel as a heatmap to the debug buffer, blue where the
+    /// pixel has converged and red where noise remains. See `Pixel::relative_error`.
+    pub fn write_variance_heatmap(&self, debug_buffer: &mut DebugBuffer) {
+        for y in 0..self.image_size.y {
+            for x in 0..self.image_size.x {
+                let pixel = &self.pixels[self.get_pixel_index(x, y)];
+                debug_buffer.write(x, y, heatmap(pixel.relative_error()));
+            }
+        }
+    }
+
+    fn sample_count_image(&self) -> GrayImage {
+        let max_count = self
+            .pixels
+            .iter()
+            .map(|pixel| pixel.sample_count)
+            .max()
+            .unwrap_or(0)
+            .max(1);
+
+        ImageBuffer::from_fn(self.image_size.x, self.image_size.y, |x, y| {
+            let count = self.pixels[self.get_pixel_index(x, y)].sample_count;
+
+            Luma([(count as f64 / max_count as f64 * 255.0).round() as u8])
+        })
+    }
+
+    fn lobe_aov_image<F>(&self, channel: F) -> ImageBuffer<Rgb<u8>, Vec<u8>>
+    where
+        F: Fn(&Pixel) -> Vector3<f64>,
+    {
+        ImageBuffer::from_fn(self.image_size.x, self.image_size.y, |x, y| {
+            let pixel = &self.pixels[self.get_pixel_index(x, y)];
+
+            if pixel.sum_weight < f64::EPSILON {
+                return image::Rgb([0, 0, 0]);
+            }
+
+            self.radiance_to_rgb(channel(pixel) / pixel.sum_weight)
+        })
+    }
+
+    /// Sampler for filter importance sampling, when it is enabled and there is a filter.
+    pub fn filter_sampler(&self) -> Option<FilterSampler> {
+        if !self.filter_importance_sampling || self.filter_method == FilterMethod::None {
+            return None;
+        }
+
+        // the filters are separable, the first row of the table has the shape along one axis
+        Some(FilterSampler::new(
+            self.filter_radius,
+            &self.filter_table[..self.filter_table_size],
+        ))
+    }
+
+    /// Tone maps (when a white point is set) and encodes radiance for 8 bit output.
+    pub fn radiance_to_rgb(&self, radiance: Vector3<f64>) -> Rgb<u8> {
+        self.tone_map_and_quantize(radiance, 0.0)
+    }
+
+    /// `offset`, in [0, 1) of an 8 bit level, is added before the value is rounded down.
+    fn tone_map_and_quantize(&self, radiance: Vector3<f64>, offset: f64) -> Rgb<u8> {
+        let radiance = radiance * self.exposure;
+        let radiance = match self.white_point {
+            Some(white_point) => Film::reinhard_extended(radiance, white_point),
+            None => radiance,
+        };
+
+        quantize(radiance, self.color_space, offset)
+    }
+
+    /// Exposure of a photograph taken with these camera settings, proportional to the shutter
+    /// time (in seconds) and the ISO and inversely to the area of the lens opening (the f-number
+    /// squared). Scene radiance is not in physical units, so the exposure is relative to a
+    /// typical photograph: 1/60 s at f/2.8 and ISO 100 is an exposure of 1.
+    pub fn exposure_from_triangle(shutter: f64, f_number: f64, iso: f64) -> f64 {
+        (shutter / REFERENCE_SHUTTER)
+            * (iso / REFERENCE_ISO)
+            * (REFERENCE_F_NUMBER * REFERENCE_F_NUMBER / (f_number * f_number))
+    }
+
+    /// Extended Reinhard, L (1 + L / Lw²) / (1 + L) on the luminance L. The white point Lw maps
+    /// to exactly 1, brighter values saturate and dark values are barely changed.
+    pub fn reinhard_extended(radiance: Vector3<f64>, white_point: f64) -> Vector3<f64> {
+        let luminance = radiance.y;
+        if luminance <= 0.0 {
+            return radiance;
+        }
+
+        let mapped =
+            luminance * (1.0 + luminance / (white_point * white_point)) / (1.0 + luminance);
+
+        radiance * (mapped / luminance)
+    }
+
     fn get_pixel_index(&self, x: u32, y: u32) -> usize {
         (x + self.image_size.x * y) as usize
     }
@@ -279,7 +1146,19 @@ impl Film {
                 let x_end = cmp::min(start.x + bucket_size.x, image_size.x);
                 let y_end = cmp::min(start.y + bucket_size.y, image_size.y);
 
-                let end = Point2::new(x_end, y_end);
+                let mut end = Point2::new(x_end, y_end);
+                let mut start = start;
+
+                // A render region only renders the buckets overlapping it and only the samples
+                // inside it, the rest of the image keeps what was there before.
+                if let Some(region) = self.render_region {
+                    start = start.sup(&region.p_min);
+                    end = end.inf(&region.p_max);
+
+                    if start.x >= end.x || start.y >= end.y {
+                        continue;
+                    }
+                }
 
                 let sample_bounds = Bounds {
                     p_min: start,
@@ -299,30 +1178,70 @@ impl Film {
                     p_max: Point2::new(pixel_bounds_end_x, pixel_bounds_end_y),
                 };
 
-                let mut pixels = vec![];
-
-                for _ in 0..pixel_bounds.area() {
-                    pixels.push(Pixel {
-                        sum_weight: 0.0,
-                        sum_radiance: Vector3::new(0.0, 0.0, 0.0),
-                        normal: Vector3::new(0.0, 0.0, 0.0),
-                        albedo: Vector3::new(0.0, 0.0, 0.0),
-                    });
-                }
-
-                buckets.push(Arc::new(Mutex::new(Bucket {
+                buckets.push(Bucket {
+                    index: buckets.len() as u32,
                     sample_bounds,
                     pixel_bounds,
                     samples: vec![],
-                    pixels,
-                })));
+                    pixels: vec![],
+                });
             }
         }
 
+        self.progress = ProgressBar::new(buckets.len() as u64).with_style(
+            ProgressStyle::with_template("{wide_bar} {percent}% ETA {eta}")
+                .expect("Invalid progress bar template"),
+        );
+        self.queues = BucketQueues::new(buckets.len() as u32, 1);
         self.buckets = buckets;
     }
 }
 
+/// Guards against a typo in the resolution allocating more memory than the machine has, images
+/// over `max_megapixels` are rejected. Returns an estimate of the memory the film allocates in
+/// bytes: the pixels of the film and the preview buffer. Buckets only hold pixels while a
+/// thread renders them, those are left out.
+pub fn check_image_size(image_size: Vector2<u32>, max_megapixels: f64) -> Result<usize, String> {
+    let pixel_count = image_size.x as usize * image_size.y as usize;
+    let megapixels = pixel_count as f64 / 1e6;
+
+    if pixel_count == 0 {
+        return Err(format!(
+            "Image size must be larger than 0, got {}x{}",
+            image_size.x, image_size.y
+        ));
+    }
+
+    if megapixels > max_megapixels {
+        return Err(format!(
+            "Image size {}x{} ({megapixels:.1} megapixels) is over the maximum of \
+             {max_megapixels} megapixels, raise film.max_megapixels to render it",
+            image_size.x, image_size.y
+        ));
+    }
+
+    Ok(pixel_count * (mem::size_of::<Pixel>() + 3))
+}
+
+/// Validates the bucket size from the render settings. A side that is not set defaults to 32
+/// pixels, sides are capped at the image size so a huge bucket covers the image once.
+pub fn bucket_size(
+    width: Option<i64>,
+    height: Option<i64>,
+    image_size: Vector2<u32>,
+) -> Result<Vector2<u32>, String> {
+    let side = |name: &str, value: Option<i64>, image_side: u32| match value {
+        None => Ok(DEFAULT_BUCKET_SIZE.min(image_side)),
+        Some(value) if value <= 0 => Err(format!("{name} must be larger than 0, got {value}")),
+        Some(value) => Ok(value.min(image_side as i64) as u32),
+    };
+
+    Ok(Vector2::new(
+        side("bucket_width", width, image_size.x)?,
+        side("bucket_height", height, image_size.y)?,
+    ))
+}
+
 fn evaluate_gaussian(point: Point2<f64>, radius: f64, alpha: f64) -> f64 {
     let expv = (-alpha * radius * radius).exp();
 
@@ -357,6 +1276,76 @@ fn evaluate_mitchell_1d(input: f64) -> f64 {
         * (1.0 / 6.0)
 }
 
+/// The middle value, the upper one of the two middle values for an even count.
+fn median(values: &mut [f64]) -> f64 {
+    if values.is_empty() {
+        return 0.0;
+    }
+
+    values.sort_by(f64::total_cmp);
+    values[values.len() / 2]
+}
+
+/// Per pixel luminance above which samples are rejected as fireflies: k times the median
+/// luminance of the samples taken in that pixel. The median is not pulled up by the outliers
+/// themselves, pixels with a black median keep all their samples.
+fn outlier_thresholds(bucket: &Bucket, k: f64) -> Vec<f64> {
+    let mut luminances = vec![vec![]; bucket.pixels.len()];
+
+    for sample in &bucket.samples {
+        let bucket_x = sample.p_film.x as u32 - bucket.pixel_bounds.p_min.x;
+        let bucket_y = sample.p_film.y as u32 - bucket.pixel_bounds.p_min.y;
+        let pixel_index = (bucket_x + bucket.pixel_bounds.vector().x * bucket_y) as usize;
+        luminances[pixel_index].push(sample.radiance.y);
+    }
+
+    luminances
+        .into_iter()
+        .map(|mut pixel_luminances| {
+            if pixel_luminances.is_empty() {
+                return f64::INFINITY;
+            }
+
+            let median = median(&mut pixel_luminances);
+
+            if median > 0.0 {
+                k * median
+            } else {
+                f64::INFINITY
+            }
+        })
+        .collect()
+}
+
+/// Maps a value in [0, 1] onto a blue -> green -> red heatmap.
+pub fn heatmap(value: f64) -> Vector3<f64> {
+    let value = value.clamp(0.0, 1.0);
+
+    if value < 0.5 {
+        Vector3::new(0.0, value * 2.0, 1.0 - value * 2.0)
+    } else {
+        Vector3::new((value - 0.5) * 2.0, 1.0 - (value - 0.5) * 2.0, 0.0)
+    }
+}
+
+pub fn radiance_to_rgb(radiance: Vector3<f64>, color_space: OutputColorSpace) -> Rgb<u8> {
+    quantize(radiance, color_space, 0.0)
+}
+
+fn quantize(radiance: Vector3<f64>, color_space: OutputColorSpace, offset: f64) -> Rgb<u8> {
+    let rgb = xyz_to_srgb(radiance);
+    let level = |value: f64| (color_space.encode(value) * 255.0 + offset).min(255.0) as u8;
+
+    image::Rgb([level(rgb.x), level(rgb.y), level(rgb.z)])
+}
+
+/// Dither offset of a pixel in [0, 1), interleaved gradient noise. Neighbouring pixels get very
+/// different offsets, so the dither looks like fine noise without low frequency blotches, and a
+/// pixel always gets the same offset, renders stay identical for a seed.
+fn dither_offset(x: u32, y: u32) -> f64 {
+    (52.982_918_9 * (0.067_110_56 * x as f64 + 0.005_837_15 * y as f64).fract()).fract()
+}
+
 fn xyz_to_srgb(xyz: Vector3<f64>) -> Vector3<f64> {
     let x = xyz.x;
     let y = xyz.y;
@@ -369,6 +1358,19 @@ fn xyz_to_srgb(xyz: Vector3<f64>) -> Vector3<f64> {
     Vector3::new(r, g, b)
 }
 
+/// Decodes an sRGB encoded value to linear, the inverse of `gamma_correct_srgb`.
+pub fn inverse_gamma_correct_srgb(val: f64) -> f64 {
+    if val <= 0.0 {
+        0.0
+    } else if val < 0.040_45 {
+        val / 12.92
+    } else if val < 1.0 {
+        ((val + 0.055) / 1.055).powf(2.4)
+    } else {
+        1.0
+    }
+}
+
 fn gamma_correct_srgb(val: f64) -> f64 {
     if val <= 0.0 {
         0.0
@@ -380,3 +1382,851 @@ fn gamma_correct_srgb(val: f64) -> f64 {
         1.0
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::sync::Arc;
+    use std::thread;
+
+    use nalgebra::{Point2, Vector2, Vector3};
+    use yaml_rust::YamlLoader;
+
+    use approx::assert_relative_eq;
+
+    use crate::accumulation::TemporalAccumulation;
+    use crate::film::{
+        bucket_size, check_image_size, quantize, Film, FilterMethod, Output, OutputColorSpace,
+        Pass, Pixel,
+    };
+    use crate::helpers::Bounds;
+    use crate::light_path::LightPathRadiance;
+    use crate::renderer::{DebugBuffer, SampleResult};
+
+    fn sample(p_film: Point2<f64>, radiance: f64) -> SampleResult {
+        SampleResult {
+            radiance: Vector3::repeat(radiance),
+            p_film,
+            normal: Vector3::zeros(),
+            albedo: Vector3::zeros(),
+            diffuse: Vector3::zeros(),
+            glossy: Vector3::zeros(),
+            transmission: Vector3::zeros(),
+            hit_background: false,
+            shadow: 0.0,
+            light_paths: LightPathRadiance::default(),
+            path_length: 1,
+            filter_weight: 1.0,
+            max_throughput: 1.0,
+        }
+    }
+
+    #[test]
+    fn test_noisy_pixel_is_hotter_in_the_variance_heatmap() {
+        let mut film = Film::new(
+            Vector2::new(2, 1),
+            Vector2::new(2, 1),
+            None,
+            None,
+            None,
+            FilterMethod::None,
+            1.0,
+            None,
+            None,
+        );
+        for i in 0..16 {
+            film.pixels[0].add_variance_sample(0.5);
+            // a dark pixel with a single bright sample
+            film.pixels[1].add_variance_sample(if i == 0 { 1.0 } else { 0.0 });
+        }
+        let mut debug_buffer = DebugBuffer {
+            width: 2,
+            height: 1,
+            buffer: vec![0.0; 6],
+        };
+
+        film.write_variance_heatmap(&mut debug_buffer);
+
+        // the constant pixel is blue, the noisy one red
+        assert_eq!(vec![0.0, 0.0, 1.0], debug_buffer.buffer[0..3]);
+        assert_relative_eq!(1.0, debug_buffer.buffer[3], epsilon = 1e-6);
+        assert_eq!(0.0, debug_buffer.buffer[5]);
+    }
+
+    #[test]
+    fn test_dithered_mid_gray_mixes_the_two_closest_levels() {
+        let green_levels = |dither: bool| {
+            let mut film = Film::new(
+                Vector2::new(8, 8),
+                Vector2::new(8, 8),
+                None,
+                None,
+                None,
+                FilterMethod::None,
+                1.0,
+                None,
+                None,
+            );
+            film.color_space = OutputColorSpace::Linear;
+            film.dither = dither;
+            let mut bucket = film.get_bucket(0).unwrap();
+
+            // halfway between two 8 bit levels, the D65 white point gives equal rgb channels
+            let gray = Vector3::new(0.9505, 1.0, 1.089) * (127.5 / 255.0);
+            let samples: Vec<SampleResult> = (0..64)
+                .map(|i| SampleResult {
+                    radiance: gray,
+                    ..sample(Point2::new((i % 8) as f64 + 0.5, (i / 8) as f64 + 0.5), 0.0)
+                })
+                .collect();
+            bucket.add_samples(&samples);
+            film.write_bucket_pixels(&mut bucket);
+            film.merge_bucket_pixels_to_image_buffer(&bucket);
+
+            film.image_buffer
+                .pixels()
+                .map(|pixel| pixel[1])
+                .collect::<Vec<u8>>()
+        };
+
+        assert!(green_levels(false).iter().all(|&level| level == 127));
+
+        let dithered = green_levels(true);
+        let high = dithered.iter().filter(|&&level| level == 128).count();
+        assert!(dithered.iter().all(|&level| level == 127 || level == 128));
+        // about half of the pixels round up, the average stays at the value
+        assert!((24..=40).contains(&high), "{} of 64 pixels at 128", high);
+    }
+
+    /// An 8x8 film with gray pixels of the given luminance, the image buffer filled in.
+    fn luminance_film(luminance: impl Fn(u32, u32) -> f64) -> Film {
+        let mut film = Film::new(
+            Vector2::new(8, 8),
+            Vector2::new(8, 8),
+            None,
+            None,
+            None,
+            FilterMethod::None,
+            1.0,
+            None,
+            None,
+        );
+        for y in 0..8 {
+            for x in 0..8 {
+                let radiance = Vector3::new(0.9505, 1.0, 1.089) * luminance(x, y);
+                let pixel = &mut film.pixels[(y * 8 + x) as usize];
+                pixel.sum_weight = 1.0;
+                pixel.sum_radiance = radiance;
+                let rgb = film.radiance_to_rgb(radiance);
+                film.image_buffer.put_pixel(x, y, rgb);
+            }
+        }
+
+        film
+    }
+
+    #[test]
+    fn test_firefly_is_replaced_by_the_median_and_a_bright_region_kept() {
+        // a dim left half with one firefly and a bright right half
+        let mut film = luminance_film(|x, y| match (x, y) {
+            (2, 3) => 50.0,
+            (x, _) if x >= 4 => 0.9,
+            _ => 0.1,
+        });
+        let dim = *film.image_buffer.get_pixel(0, 0);
+        let before = film.image_buffer.clone();
+
+        film.remove_fireflies(5.0);
+
+        assert_eq!(dim, *film.image_buffer.get_pixel(2, 3));
+        for (x, y, pixel) in film.image_buffer.enumerate_pixels() {
+            if (x, y) != (2, 3) {
+                assert_eq!(before.get_pixel(x, y), pixel);
+            }
+        }
+    }
+
+    #[test]
+    fn test_lit_line_on_black_is_not_a_firefly() {
+        // most neighbors of a one pixel wide line are black, their median is zero
+        let mut film = luminance_film(|x, _| if x == 3 { 1.0 } else { 0.0 });
+        let before = film.image_buffer.clone();
+
+        film.remove_fireflies(5.0);
+
+        assert_eq!(before, film.image_buffer);
+    }
+
+    #[test]
+    fn test_outlier_sample_is_rejected() {
+        let film = Film::new(
+            Vector2::new(4, 4),
+            Vector2::new(4, 4),
+            None,
+            None,
+            None,
+            FilterMethod::None,
+            1.0,
+            Some(10.0),
+            None,
+        );
+        let mut bucket = film.get_bucket(0).unwrap();
+
+        let mut samples = vec![];
+        for i in 0..15 {
+            samples.push(sample(Point2::new(1.5, 1.5), 0.9 + 0.2 * (i % 2) as f64));
+        }
+        samples.push(sample(Point2::new(1.5, 1.5), 1000.0));
+        bucket.add_samples(&samples);
+
+        film.write_bucket_pixels(&mut bucket);
+
+        let pixel = &bucket.pixels[1 + 4];
+        assert_eq!(15.0, pixel.sum_weight);
+        let mean = pixel.sum_radiance.y / pixel.sum_weight;
+        assert!((mean - 1.0).abs() < 0.1);
+    }
+
+    #[test]
+    fn test_adaptive_clamp_rejects_samples_far_above_the_running_mean() {
+        // mean 1.0 with a standard deviation of about 0.1, the last sample is 10 deviations up
+        let mut samples = vec![];
+        for i in 0..15 {
+            samples.push(sample(Point2::new(1.5, 1.5), 0.9 + 0.2 * (i % 2) as f64));
+        }
+        samples.push(sample(Point2::new(1.5, 1.5), 2.0));
+
+        for (k, expected_weight) in [(3.0, 15.0), (20.0, 16.0)] {
+            let film = Film::new(
+                Vector2::new(4, 4),
+                Vector2::new(4, 4),
+                None,
+                None,
+                None,
+                FilterMethod::None,
+                1.0,
+                None,
+                Some(k),
+            );
+            let mut bucket = film.get_bucket(0).unwrap();
+            bucket.add_samples(&samples);
+
+            film.write_bucket_pixels(&mut bucket);
+
+            assert_eq!(expected_weight, bucket.pixels[1 + 4].sum_weight);
+        }
+    }
+
+    #[test]
+    fn test_sample_count_map_is_brighter_where_more_samples_were_taken() {
+        let mut film = Film::new(
+            Vector2::new(4, 4),
+            Vector2::new(4, 4),
+            None,
+            None,
+            None,
+            FilterMethod::None,
+            1.0,
+            None,
+            None,
+        );
+        let mut bucket = film.get_bucket(0).unwrap();
+
+        // the noisy left half got four times the samples of the flat right half
+        let mut samples = vec![];
+        for y in 0..4 {
+            for x in 0..4 {
+                let count = if x < 2 { 16 } else { 4 };
+                for i in 0..count {
+                    let radiance = if x < 2 { (i % 2) as f64 } else { 0.5 };
+                    samples.push(sample(
+                        Point2::new(x as f64 + 0.5, y as f64 + 0.5),
+                        radiance,
+                    ));
+                }
+            }
+        }
+        bucket.add_samples(&samples);
+        film.write_bucket_pixels(&mut bucket);
+        film.merge_bucket_pixels_to_image_buffer(&bucket);
+
+        let image = film.sample_count_image();
+        assert_eq!(255, image.get_pixel(0, 2)[0]);
+        assert_eq!(64, image.get_pixel(3, 2)[0]);
+    }
+
+    #[test]
+    fn test_resolutions_over_the_cap_are_rejected() {
+        let memory = check_image_size(Vector2::new(1920, 1080), 50.0).unwrap();
+        assert!(memory > 1920 * 1080 * 3);
+
+        // a typo, one zero too many on both sides
+        assert!(check_image_size(Vector2::new(19200, 10800), 50.0).is_err());
+        assert!(check_image_size(Vector2::new(0, 1080), 50.0).is_err());
+    }
+
+    #[test]
+    fn test_buckets_of_any_valid_size_cover_every_pixel_once() {
+        let image_size = Vector2::new(7, 5);
+
+        for (width, height, bucket_count) in [(7, 5, 1), (100, 80, 1), (1, 1, 35), (3, 2, 9)] {
+            let film = Film::new(
+                image_size,
+                bucket_size(Some(width), Some(height), image_size).unwrap(),
+                None,
+                None,
+                None,
+                FilterMethod::None,
+                1.0,
+                None,
+                None,
+            );
+            assert_eq!(bucket_count, film.buckets.len());
+
+            let mut covered = vec![0; 35];
+            for bucket in &film.buckets {
+                let bounds = bucket.sample_bounds;
+                for y in bounds.p_min.y..bounds.p_max.y {
+                    for x in bounds.p_min.x..bounds.p_max.x {
+                        covered[(y * 7 + x) as usize] += 1;
+                    }
+                }
+            }
+            assert!(covered.iter().all(|count| *count == 1));
+        }
+
+        assert_eq!(
+            Vector2::new(7, 5),
+            bucket_size(None, None, image_size).unwrap()
+        );
+        assert!(bucket_size(Some(0), Some(5), image_size).is_err());
+        assert!(bucket_size(Some(7), Some(-1), image_size).is_err());
+    }
+
+    #[test]
+    fn test_buckets_are_handed_out_once_under_concurrent_access() {
+        let mut film = Film::new(
+            Vector2::new(64, 64),
+            Vector2::new(4, 4),
+            None,
+            None,
+            None,
+            FilterMethod::None,
+            1.0,
+            None,
+            None,
+        );
+        // more threads than queues, so some threads share a queue and all of them steal
+        film.split_buckets(6);
+        let film = Arc::new(film);
+
+        let threads: Vec<_> = (0..8)
+            .map(|thread_id| {
+                let film = film.clone();
+                thread::spawn(move || {
+                    let mut indices = vec![];
+                    while let Some(bucket) = film.get_bucket(thread_id) {
+                        indices.push(bucket.index);
+                    }
+                    indices
+                })
+            })
+            .collect();
+
+        let indices: Vec<u32> = threads
+            .into_iter()
+            .flat_map(|thread| thread.join().unwrap())
+            .collect();
+        let unique: HashSet<u32> = indices.iter().copied().collect();
+
+        assert_eq!(film.buckets.len(), indices.len());
+        assert_eq!(indices.len(), unique.len());
+    }
+
+    #[test]
+    fn test_progress_counts_every_completed_bucket_once() {
+        let film = Arc::new(Film::new(
+            Vector2::new(16, 16),
+            Vector2::new(4, 4),
+            None,
+            None,
+            None,
+            FilterMethod::None,
+            1.0,
+            None,
+            None,
+        ));
+
+        let threads: Vec<_> = (0..4)
+            .map(|thread_id| {
+                let film = film.clone();
+                thread::spawn(move || {
+                    while film.get_bucket(thread_id).is_some() {
+                        film.complete_bucket();
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        assert_eq!(16, film.progress.position());
+        assert!(film.progress.is_finished());
+    }
+
+    #[test]
+    fn test_render_region_only_renders_overlapping_buckets() {
+        let mut film = Film::new(
+            Vector2::new(16, 16),
+            Vector2::new(4, 4),
+            None,
+            None,
+            Some(Bounds {
+                p_min: Point2::new(5, 5),
+                p_max: Point2::new(9, 9),
+            }),
+            FilterMethod::None,
+            1.0,
+            None,
+            None,
+        );
+
+        // the region overlaps 2 by 2 buckets, their sample bounds are clipped to the region
+        assert_eq!(4, film.buckets.len());
+        for bucket in &film.buckets {
+            assert!(bucket.sample_bounds.p_min.x >= 5 && bucket.sample_bounds.p_max.x <= 9);
+            assert!(bucket.sample_bounds.p_min.y >= 5 && bucket.sample_bounds.p_max.y <= 9);
+        }
+
+        // a previous render of the whole image
+        for pixel in film.pixels.iter_mut() {
+            pixel.sum_radiance = Vector3::repeat(0.25);
+            pixel.sum_weight = 1.0;
+        }
+
+        while let Some(mut bucket) = film.get_bucket(0) {
+            let mut samples = vec![];
+            for y in bucket.sample_bounds.p_min.y..bucket.sample_bounds.p_max.y {
+                for x in bucket.sample_bounds.p_min.x..bucket.sample_bounds.p_max.x {
+                    samples.push(sample(Point2::new(x as f64 + 0.5, y as f64 + 0.5), 1.0));
+                }
+            }
+            bucket.add_samples(&samples);
+
+            film.write_bucket_pixels(&mut bucket);
+            film.merge_bucket_pixels_to_image_buffer(&bucket);
+        }
+
+        let inside = &film.pixels[film.get_pixel_index(6, 6)];
+        assert_eq!(2.0, inside.sum_weight);
+
+        for (x, y) in [(4, 6), (9, 6), (6, 4), (6, 9), (0, 0)] {
+            let outside = &film.pixels[film.get_pixel_index(x, y)];
+            assert_eq!(1.0, outside.sum_weight);
+            assert_eq!(Vector3::repeat(0.25), outside.sum_radiance);
+        }
+    }
+
+    #[test]
+    fn test_navigation_writes_the_accumulated_frames() {
+        let mut film = Film::new(
+            Vector2::new(8, 8),
+            Vector2::new(4, 4),
+            None,
+            None,
+            None,
+            FilterMethod::None,
+            1.0,
+            None,
+            None,
+        );
+        let mut accumulation = TemporalAccumulation::new();
+
+        for frame in 0..4 {
+            film.clear();
+            while let Some(mut bucket) = film.get_bucket(0) {
+                let mut samples = vec![];
+                for y in bucket.sample_bounds.p_min.y..bucket.sample_bounds.p_max.y {
+                    for x in bucket.sample_bounds.p_min.x..bucket.sample_bounds.p_max.x {
+                        let value = 0.1 * (frame + 1) as f64 + 0.01 * (x + 8 * y) as f64;
+                        samples.push(sample(Point2::new(x as f64 + 0.5, y as f64 + 0.5), value));
+                    }
+                }
+                bucket.add_samples(&samples);
+
+                film.write_bucket_pixels(&mut bucket);
+                film.merge_bucket_pixels_to_image_buffer(&bucket);
+            }
+
+            let radiance: Vec<_> = film.pixels.iter().map(Pixel::radiance).collect();
+            accumulation.add_frame(&radiance, frame == 0);
+        }
+        film.set_radiance(accumulation.radiance());
+
+        for (index, average) in accumulation.radiance().iter().enumerate() {
+            let (x, y) = (index as u32 % 8, index as u32 / 8);
+            assert_relative_eq!(*average, film.pixels[index].radiance(), epsilon = 1e-12);
+            assert_eq!(
+                film.radiance_to_rgb(*average),
+                *film.image_buffer.get_pixel(x, y)
+            );
+        }
+        // the average of the frames, not the last one
+        assert_relative_eq!(0.25, film.pixels[0].radiance().y, epsilon = 1e-12);
+    }
+
+    #[test]
+    fn test_region_render_continues_from_a_loaded_checkpoint() {
+        let new_film = |render_region| {
+            Film::new(
+                Vector2::new(8, 8),
+                Vector2::new(4, 4),
+                None,
+                None,
+                render_region,
+                FilterMethod::None,
+                1.0,
+                None,
+                None,
+            )
+        };
+        let render = |film: &mut Film, value: f64| {
+            while let Some(mut bucket) = film.get_bucket(0) {
+                let mut samples = vec![];
+                for y in bucket.sample_bounds.p_min.y..bucket.sample_bounds.p_max.y {
+                    for x in bucket.sample_bounds.p_min.x..bucket.sample_bounds.p_max.x {
+                        samples.push(sample(Point2::new(x as f64 + 0.5, y as f64 + 0.5), value));
+                    }
+                }
+                bucket.add_samples(&samples);
+
+                film.write_bucket_pixels(&mut bucket);
+                film.merge_bucket_pixels_to_image_buffer(&bucket);
+            }
+        };
+        let checkpoint = std::env::temp_dir().join("rust_raytracer_checkpoint_test.bin");
+
+        let mut film = new_film(None);
+        render(&mut film, 0.25);
+        film.write_checkpoint(&checkpoint).unwrap();
+
+        let region = Bounds {
+            p_min: Point2::new(2, 2),
+            p_max: Point2::new(4, 4),
+        };
+        let mut resumed = new_film(Some(region));
+        resumed.load_checkpoint(&checkpoint).unwrap();
+        for (loaded, saved) in resumed.pixels.iter().zip(&film.pixels) {
+            assert_eq!(saved.checkpoint_values(), loaded.checkpoint_values());
+        }
+        assert_eq!(film.image_buffer, resumed.image_buffer);
+
+        render(&mut resumed, 1.0);
+
+        let inside = &resumed.pixels[resumed.get_pixel_index(3, 3)];
+        assert_eq!(2, inside.sample_count);
+        assert_relative_eq!(0.625, inside.radiance().y);
+        let outside = &resumed.pixels[resumed.get_pixel_index(5, 3)];
+        assert_eq!(1, outside.sample_count);
+        assert_relative_eq!(0.25, outside.radiance().y);
+        assert_eq!(
+            film.image_buffer.get_pixel(5, 3),
+            resumed.image_buffer.get_pixel(5, 3)
+        );
+
+        // a checkpoint only fits an image of the same size
+        let mut other_size = Film::new(
+            Vector2::new(4, 4),
+            Vector2::new(4, 4),
+            None,
+            None,
+            None,
+            FilterMethod::None,
+            1.0,
+            None,
+            None,
+        );
+        assert!(other_size.load_checkpoint(&checkpoint).is_err());
+
+        std::fs::remove_file(checkpoint).unwrap();
+    }
+
+    #[test]
+    fn test_alpha_is_object_coverage() {
+        let mut film = Film::new(
+            Vector2::new(2, 1),
+            Vector2::new(2, 1),
+            None,
+            None,
+            None,
+            FilterMethod::None,
+            1.0,
+            None,
+            None,
+        );
+        let mut bucket = film.get_bucket(0).unwrap();
+
+        let mut samples = vec![];
+        for _ in 0..4 {
+            samples.push(sample(Point2::new(0.5, 0.5), 1.0));
+            samples.push(SampleResult {
+                hit_background: true,
+                ..sample(Point2::new(1.5, 0.5), 0.0)
+            });
+        }
+        bucket.add_samples(&samples);
+
+        film.write_bucket_pixels(&mut bucket);
+        film.merge_bucket_pixels_to_image_buffer(&bucket);
+
+        assert_eq!(1.0, film.pixels[film.get_pixel_index(0, 0)].alpha());
+        assert_eq!(0.0, film.pixels[film.get_pixel_index(1, 0)].alpha());
+    }
+
+    #[test]
+    fn test_samples_at_a_bucket_border_are_splatted_once() {
+        let render = |bucket_size| {
+            let mut film = Film::new(
+                Vector2::new(8, 4),
+                bucket_size,
+                None,
+                None,
+                None,
+                FilterMethod::Gaussian,
+                1.5,
+                None,
+                None,
+            );
+
+            while let Some(mut bucket) = film.get_bucket(0) {
+                // a sample on either side of the border between the buckets at x = 4
+                let samples: Vec<_> = [3.9, 4.1]
+                    .into_iter()
+                    .map(|x| Point2::new(x, 2.0))
+                    .filter(|p| {
+                        (bucket.sample_bounds.p_min.x..bucket.sample_bounds.p_max.x)
+                            .contains(&(p.x as u32))
+                    })
+                    .map(|p| SampleResult {
+                        normal: Vector3::y(),
+                        ..sample(p, 1.0)
+                    })
+                    .collect();
+                bucket.add_samples(&samples);
+                film.write_bucket_pixels(&mut bucket);
+                film.merge_bucket_pixels_to_image_buffer(&bucket);
+            }
+
+            film
+        };
+
+        let single = render(Vector2::new(8, 4));
+        let split = render(Vector2::new(4, 4));
+        assert_eq!(1, single.buckets.len());
+        assert_eq!(2, split.buckets.len());
+
+        // the filter weight of the two samples ends up in the film once, whether or not the
+        // border between them is also a border between buckets
+        let total_weight = |film: &Film| film.pixels.iter().map(|p| p.sum_weight).sum::<f64>();
+        assert!(total_weight(&single) > 0.0);
+        assert_relative_eq!(total_weight(&single), total_weight(&split));
+
+        // the pixels both samples are splatted to
+        for x in 3..6 {
+            let index = split.get_pixel_index(x, 2);
+            assert!(split.pixels[index].sum_weight > 0.0);
+            assert_relative_eq!(
+                single.pixels[index].sum_weight,
+                split.pixels[index].sum_weight
+            );
+            assert_eq!(Vector3::y(), split.pixels[index].normal);
+        }
+    }
+
+    #[test]
+    fn test_filter_importance_sampling_clusters_samples_near_the_center() {
+        let mut film = Film::new(
+            Vector2::new(4, 4),
+            Vector2::new(4, 4),
+            None,
+            None,
+            None,
+            FilterMethod::Gaussian,
+            2.0,
+            None,
+            None,
+        );
+        assert!(film.filter_sampler().is_none());
+        film.filter_importance_sampling = true;
+        let filter_sampler = film.filter_sampler().unwrap();
+
+        let mut near_center = 0;
+        for i in 0..32 {
+            for j in 0..32 {
+                let u = Vector2::new((i as f64 + 0.5) / 32.0, (j as f64 + 0.5) / 32.0);
+                let (offset, weight) = filter_sampler.sample(u);
+
+                assert!(offset.x.abs() <= 2.0 && offset.y.abs() <= 2.0);
+                assert_eq!(1.0, weight);
+                if offset.x.abs() < 0.5 && offset.y.abs() < 0.5 {
+                    near_center += 1;
+                }
+            }
+        }
+
+        // uniform offsets over the filter would put 1 in 16 samples there
+        assert!(near_center > 3 * 1024 / 16);
+    }
+
+    #[test]
+    fn test_outputs_write_every_listed_pass() {
+        let folder = std::env::temp_dir().join("rust_raytracer_outputs");
+        std::fs::create_dir_all(&folder).unwrap();
+        let beauty = folder.join("beauty.png");
+        let alpha = folder.join("alpha.png");
+        for file in [&beauty, &alpha] {
+            let _ = std::fs::remove_file(file);
+        }
+
+        let yaml = &YamlLoader::load_from_str(&format!(
+            "
+- pass: beauty
+  file: {}
+- pass: alpha
+  file: {}
+",
+            beauty.display(),
+            alpha.display()
+        ))
+        .unwrap()[0];
+        let outputs = Output::list_from_yaml(yaml);
+        assert_eq!(Pass::Beauty, outputs[0].pass);
+        assert_eq!(Pass::Alpha, outputs[1].pass);
+
+        let film = Film::new(
+            Vector2::new(4, 4),
+            Vector2::new(4, 4),
+            None,
+            None,
+            None,
+            FilterMethod::None,
+            1.0,
+            None,
+            None,
+        );
+        film.write_outputs(&outputs).unwrap();
+
+        assert!(beauty.exists());
+        assert!(alpha.exists());
+    }
+
+    #[test]
+    fn test_save_both_writes_tone_mapped_display_and_linear_exr() {
+        let folder = std::env::temp_dir().join("rust_raytracer_save_both");
+        std::fs::create_dir_all(&folder).unwrap();
+        let display = folder.join("render.png");
+        let linear = folder.join("render.exr");
+        for file in [&display, &linear] {
+            let _ = std::fs::remove_file(file);
+        }
+
+        let mut film = Film::new(
+            Vector2::new(2, 2),
+            Vector2::new(2, 2),
+            None,
+            None,
+            None,
+            FilterMethod::None,
+            1.0,
+            None,
+            None,
+        );
+        film.white_point = Some(4.0);
+        // gray above 1, clipped without the tone map
+        let radiance = Vector3::new(0.9505, 1.0, 1.089) * 2.0;
+        for y in 0..2 {
+            for x in 0..2 {
+                let pixel = &mut film.pixels[(y * 2 + x) as usize];
+                pixel.sum_weight = 1.0;
+                pixel.sum_radiance = radiance;
+                pixel.sum_coverage = 1.0;
+                let rgb = film.radiance_to_rgb(radiance);
+                film.image_buffer.put_pixel(x, y, rgb);
+            }
+        }
+
+        film.write_display_and_linear(&display.to_string_lossy())
+            .unwrap();
+
+        let linear_pixel = *image::open(&linear).unwrap().to_rgba32f().get_pixel(1, 1);
+        for channel in &linear_pixel.0[..3] {
+            assert_relative_eq!(2.0, *channel, epsilon = 1e-3);
+        }
+
+        let display_pixel = *image::open(&display).unwrap().to_rgba8().get_pixel(1, 1);
+        let tone_mapped = Film::reinhard_extended(radiance, 4.0);
+        assert_eq!(
+            quantize(tone_mapped, OutputColorSpace::Srgb, 0.0).0,
+            display_pixel.0[..3]
+        );
+        // 2 (1 + 2 / 16) / 3 = 0.75 before the sRGB curve, not clipped to white
+        assert!(display_pixel.0[1] < 255);
+    }
+
+    #[test]
+    fn test_exposure_triangle() {
+        let exposure = Film::exposure_from_triangle(1.0 / 60.0, 8.0, 100.0);
+
+        assert_relative_eq!(
+            2.0 * exposure,
+            Film::exposure_from_triangle(1.0 / 60.0, 8.0, 200.0)
+        );
+        assert_relative_eq!(
+            2.0 * exposure,
+            Film::exposure_from_triangle(1.0 / 30.0, 8.0, 100.0)
+        );
+        // a full stop of aperture doubles the area of the opening
+        assert_relative_eq!(
+            2.0 * exposure,
+            Film::exposure_from_triangle(1.0 / 60.0, 8.0 / 2f64.sqrt(), 100.0)
+        );
+        assert_relative_eq!(1.0, Film::exposure_from_triangle(1.0 / 60.0, 2.8, 100.0));
+        assert_relative_eq!(4.0, Film::exposure_from_triangle(1.0 / 60.0, 2.8, 400.0));
+    }
+
+    #[test]
+    fn test_reinhard_extended_maps_the_white_point_to_white() {
+        let white_point = 4.0;
+
+        let white = Film::reinhard_extended(Vector3::repeat(white_point), white_point);
+        assert_relative_eq!(1.0, white.y, epsilon = 1e-12);
+
+        // 2 * (1 + 2 / 16) / (1 + 2) = 0.75
+        let half = Film::reinhard_extended(Vector3::repeat(white_point / 2.0), white_point);
+        assert_relative_eq!(0.75, half.y, epsilon = 1e-12);
+
+        // the color is scaled, not clipped per channel
+        let color = Film::reinhard_extended(Vector3::new(1.0, 2.0, 4.0), white_point);
+        assert_relative_eq!(color / color.y, Vector3::new(0.5, 1.0, 2.0));
+    }
+
+    #[test]
+    fn test_linear_output_is_unchanged() {
+        for value in [0.0, 0.01, 0.25, 0.5, 1.0] {
+            assert_relative_eq!(value, OutputColorSpace::Linear.encode(value));
+        }
+    }
+
+    #[test]
+    fn test_srgb_output_follows_the_srgb_curve() {
+        let srgb = OutputColorSpace::Srgb;
+
+        assert_relative_eq!(0.0, srgb.encode(0.0));
+        assert_relative_eq!(0.040_45, srgb.encode(0.003_130_8), epsilon = 1e-4);
+        assert_relative_eq!(0.461_4, srgb.encode(0.18), epsilon = 1e-4);
+        assert_relative_eq!(0.735_4, srgb.encode(0.5), epsilon = 1e-4);
+        assert_relative_eq!(1.0, srgb.encode(1.0));
+        assert_relative_eq!(1.0, srgb.encode(4.0));
+    }
+}
@@ -3,10 +3,10 @@ use std::ops::Mul;
 
 use nalgebra::indexing::MatrixIndex;
 use nalgebra::{ArrayStorage, ClosedSub, Point2, Point3, Scalar, Vector2, Vector3, U1, U3};
-use rand::{thread_rng, Rng};
+use rand::Rng;
 use yaml_rust::Yaml;
 
-#[derive(Debug)]
+#[derive(Debug, Copy, Clone)]
 pub struct Bounds<T: Copy + Scalar + ClosedSub + Mul> {
     pub p_min: Point2<T>,
     pub p_max: Point2<T>,
@@ -28,9 +28,7 @@ pub fn vector_reflect(vec: Vector3<f64>, normal: Vector3<f64>) -> Vector3<f64> {
     vec - 2.0 * vec.dot(&normal) * normal
 }
 
-pub fn get_random_in_unit_sphere() -> Vector3<f64> {
-    let mut rng = thread_rng();
-
+pub fn get_random_in_unit_sphere<R: Rng>(rng: &mut R) -> Vector3<f64> {
     let mut vec: Vector3<f64>;
 
     while {
@@ -80,6 +78,21 @@ pub fn face_forward(n: Vector3<f64>, v: Vector3<f64>) -> Vector3<f64> {
     n
 }
 
+/// Moves the origin of a ray leaving a surface off it along the geometry normal, to the side
+/// `direction` leaves on. The offset is at least the rounding error of the point, so the ray
+/// neither hits the surface it starts on nor starts behind it and slips through.
+pub fn offset_ray_origin(
+    point: Point3<f64>,
+    p_error: Vector3<f64>,
+    normal: Vector3<f64>,
+    direction: Vector3<f64>,
+) -> Point3<f64> {
+    // the same minimum as the offset of plane hits, for objects that track no error
+    let distance = normal.abs().dot(&p_error) + 1e-9 * point.coords.abs().max().max(1.0);
+
+    point + face_forward(normal, direction) * distance
+}
+
 pub fn get_fresnel_ratio(normal: Vector3<f64>, angle_of_incidence: Vector3<f64>, ior: f64) -> f64 {
     let fresnel_ratio: f64;
 
@@ -165,6 +178,11 @@ pub fn yaml_into_u32(yaml: &Yaml) -> u32 {
     yaml.as_i64().unwrap() as u32
 }
 
+/// Reads a number that may be written as an integer, yaml-rust only gives `as_f64` for reals.
+pub fn yaml_as_f64(yaml: &Yaml) -> Option<f64> {
+    yaml.as_f64().or_else(|| yaml.as_i64().map(|i| i as f64))
+}
+
 pub fn max_dimension_vec_3<T: Scalar + PartialOrd>(v: Vector3<T>) -> usize {
     if v.x > v.y {
         if v.x > v.z {
@@ -183,10 +201,8 @@ pub fn permute<T: Copy>(v: Vector3<T>, x: usize, y: usize, z: usize) -> Vector3<
     Vector3::new(v[x], v[y], v[z])
 }
 
-pub fn concentric_sample_disk() -> Point2<f64> {
-    let mut rng = thread_rng();
-
-    let u_offset = Point2::new(rng.gen::<f64>(), rng.gen::<f64>()) * 2.0 - Vector2::new(1.0, 1.0);
+pub fn concentric_sample_disk(u: Point2<f64>) -> Point2<f64> {
+    let u_offset = u * 2.0 - Vector2::new(1.0, 1.0);
 
     if u_offset.x == 0.0 && u_offset.y == 0.0 {
         return Point2::new(0.0, 0.0);
@@ -277,4 +293,14 @@ mod tests {
 
         assert_eq!(Vector3::new(3, 2, 1), res);
     }
+
+    #[test]
+    fn test_yaml_numbers_can_be_integers() {
+        let yaml = &yaml_rust::YamlLoader::load_from_str("{a: 400, b: 2.8, c: fast}").unwrap()[0];
+
+        assert_eq!(Some(400.0), yaml_as_f64(&yaml["a"]));
+        assert_eq!(Some(2.8), yaml_as_f64(&yaml["b"]));
+        assert_eq!(None, yaml_as_f64(&yaml["c"]));
+        assert_eq!(None, yaml_as_f64(&yaml["d"]));
+    }
 }
@@ -0,0 +1,124 @@
+use nalgebra::Vector3;
+
+/// Samples carry the radiance of every expression in a fixed size array, so they stay `Copy`.
+pub const MAX_LIGHT_PATH_EXPRESSIONS: usize = 4;
+
+/// Event along a light path, written as E, L, D or S in an expression.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum PathEvent {
+    Eye,
+    Light,
+    /// Any scattering that is not a perfect mirror or refraction, glossy included
+    Diffuse,
+    Specular,
+}
+
+impl PathEvent {
+    fn from_char(char: char) -> Option<PathEvent> {
+        match char {
+            'E' => Some(PathEvent::Eye),
+            'L' => Some(PathEvent::Light),
+            'D' => Some(PathEvent::Diffuse),
+            'S' => Some(PathEvent::Specular),
+            _ => None,
+        }
+    }
+}
+
+/// Selects paths by the events along them, like `LDE` for diffuse direct light or `LSS*DE`
+/// for caustics. Expressions are written from the light to the eye and `*` repeats the event
+/// before it zero or more times.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LightPathExpression {
+    pub name: String,
+    // (event, repeated) pairs in the order of the path from the eye
+    events: Vec<(PathEvent, bool)>,
+}
+
+impl LightPathExpression {
+    pub fn parse(name: &str, expression: &str) -> Option<LightPathExpression> {
+        let mut events = vec![];
+
+        for char in expression.chars().filter(|c| !c.is_whitespace()) {
+            if char == '*' {
+                let (_, repeated) = events.last_mut()?;
+                *repeated = true;
+            } else {
+                events.push((PathEvent::from_char(char)?, false));
+            }
+        }
+
+        events.reverse();
+
+        Some(LightPathExpression {
+            name: name.to_string(),
+            events,
+        })
+    }
+
+    /// Tests a path given in the order it was traced, starting at the eye.
+    pub fn matches(&self, path: &[PathEvent]) -> bool {
+        matches_from(&self.events, path)
+    }
+}
+
+fn matches_from(events: &[(PathEvent, bool)], path: &[PathEvent]) -> bool {
+    match events.split_first() {
+        None => path.is_empty(),
+        Some((&(event, true), rest)) => {
+            matches_from(rest, path)
+                || (path.first() == Some(&event) && matches_from(events, &path[1..]))
+        }
+        Some((&(event, false), rest)) => {
+            path.first() == Some(&event) && matches_from(rest, &path[1..])
+        }
+    }
+}
+
+/// Radiance of one sample split over the requested light path expressions.
+#[derive(Debug, Copy, Clone)]
+pub struct LightPathRadiance(pub [Vector3<f64>; MAX_LIGHT_PATH_EXPRESSIONS]);
+
+impl Default for LightPathRadiance {
+    fn default() -> Self {
+        LightPathRadiance([Vector3::zeros(); MAX_LIGHT_PATH_EXPRESSIONS])
+    }
+}
+
+impl LightPathRadiance {
+    pub fn add(
+        &mut self,
+        expressions: &[LightPathExpression],
+        path: &[PathEvent],
+        radiance: Vector3<f64>,
+    ) {
+        for (sum, expression) in self.0.iter_mut().zip(expressions) {
+            if expression.matches(path) {
+                *sum += radiance;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::light_path::LightPathExpression;
+    use crate::light_path::PathEvent::{Diffuse, Eye, Light, Specular};
+
+    #[test]
+    fn test_expressions_match_paths_from_the_light() {
+        let caustics = LightPathExpression::parse("caustics", "LSS*DE").unwrap();
+
+        assert!(caustics.matches(&[Eye, Diffuse, Specular, Light]));
+        assert!(caustics.matches(&[Eye, Diffuse, Specular, Specular, Specular, Light]));
+        assert!(!caustics.matches(&[Eye, Diffuse, Light]));
+        assert!(!caustics.matches(&[Eye, Specular, Diffuse, Light]));
+
+        let all = LightPathExpression::parse("all", "LD*S*E").unwrap();
+        assert!(all.matches(&[Eye, Light]));
+        assert!(all.matches(&[Eye, Specular, Diffuse, Diffuse, Light]));
+
+        assert!(LightPathExpression::parse("invalid", "LXE").is_none());
+        assert!(LightPathExpression::parse("invalid", "*LE").is_none());
+    }
+}
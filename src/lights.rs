@@ -4,13 +4,19 @@ use crate::lights::area::AreaLight;
 use crate::lights::distant::DistantLight;
 use crate::lights::infinite_area::InfiniteAreaLight;
 use crate::lights::point::PointLight;
+use crate::lights::portal::PortalLight;
+use crate::lights::sky::SkyLight;
 use crate::renderer::Ray;
 use crate::surface_interaction::{Interaction, SurfaceInteraction};
 
 pub mod area;
 pub mod distant;
+pub mod distribution;
+pub mod hierarchical_warp;
 pub mod infinite_area;
 pub mod point;
+pub mod portal;
+pub mod sky;
 
 #[derive(Debug)]
 pub enum Light {
@@ -18,6 +24,8 @@ pub enum Light {
     Area(AreaLight),
     Distant(DistantLight),
     InfiniteArea(InfiniteAreaLight),
+    Sky(SkyLight),
+    Portal(PortalLight),
 }
 
 pub trait LightTrait {
@@ -46,7 +54,10 @@ pub trait LightTrait {
         Vector3::zeros()
     }
 
-    fn power(&self) -> Vector3<f64>;
+    /// Emitted power (flux) in watts. Lights at infinity count the power that reaches the scene,
+    /// through a disk with the radius of the scene, `scene_radius`, facing the light. Their
+    /// power then compares to the lights in the scene.
+    fn power(&self, scene_radius: f64) -> Vector3<f64>;
 }
 
 pub struct LightIrradianceSample {
@@ -75,6 +86,8 @@ impl LightTrait for Light {
             Light::Area(x) => x.is_delta(),
             Light::Distant(x) => x.is_delta(),
             Light::InfiniteArea(x) => x.is_delta(),
+            Light::Sky(x) => x.is_delta(),
+            Light::Portal(x) => x.is_delta(),
         }
     }
 
@@ -84,6 +97,8 @@ impl LightTrait for Light {
             Light::Area(x) => x.emitting(interaction, w),
             Light::Distant(x) => x.emitting(interaction, w),
             Light::InfiniteArea(x) => x.emitting(interaction, w),
+            Light::Sky(x) => x.emitting(interaction, w),
+            Light::Portal(x) => x.emitting(interaction, w),
         }
     }
 
@@ -98,6 +113,8 @@ impl LightTrait for Light {
             Light::Area(x) => x.sample_irradiance(interaction, sample),
             Light::Distant(x) => x.sample_irradiance(interaction, sample),
             Light::InfiniteArea(x) => x.sample_irradiance(interaction, sample),
+            Light::Sky(x) => x.sample_irradiance(interaction, sample),
+            Light::Portal(x) => x.sample_irradiance(interaction, sample),
         }
     }
 
@@ -108,6 +125,8 @@ impl LightTrait for Light {
             Light::Area(x) => x.sample_emitting(),
             Light::Distant(x) => x.sample_emitting(),
             Light::InfiniteArea(x) => x.sample_emitting(),
+            Light::Sky(x) => x.sample_emitting(),
+            Light::Portal(x) => x.sample_emitting(),
         }
     }
 
@@ -118,6 +137,8 @@ impl LightTrait for Light {
             Light::Area(x) => x.pdf_incidence(interaction, wi),
             Light::Distant(x) => x.pdf_incidence(interaction, wi),
             Light::InfiniteArea(x) => x.pdf_incidence(interaction, wi),
+            Light::Sky(x) => x.pdf_incidence(interaction, wi),
+            Light::Portal(x) => x.pdf_incidence(interaction, wi),
         }
     }
 
@@ -128,6 +149,8 @@ impl LightTrait for Light {
             Light::Area(x) => x.pdf_emitting(ray, light_normal),
             Light::Distant(x) => x.pdf_emitting(ray, light_normal),
             Light::InfiniteArea(x) => x.pdf_emitting(ray, light_normal),
+            Light::Sky(x) => x.pdf_emitting(ray, light_normal),
+            Light::Portal(x) => x.pdf_emitting(ray, light_normal),
         }
     }
 
@@ -138,15 +161,19 @@ impl LightTrait for Light {
             Light::Area(x) => x.environment_emitting(ray),
             Light::Distant(x) => x.environment_emitting(ray),
             Light::InfiniteArea(x) => x.environment_emitting(ray),
+            Light::Sky(x) => x.environment_emitting(ray),
+            Light::Portal(x) => x.environment_emitting(ray),
         }
     }
 
-    fn power(&self) -> Vector3<f64> {
+    fn power(&self, scene_radius: f64) -> Vector3<f64> {
         match self {
-            Light::Point(x) => x.power(),
-            Light::Area(x) => x.power(),
-            Light::Distant(x) => x.power(),
-            Light::InfiniteArea(x) => x.power(),
+            Light::Point(x) => x.power(scene_radius),
+            Light::Area(x) => x.power(scene_radius),
+            Light::Distant(x) => x.power(scene_radius),
+            Light::InfiniteArea(x) => x.power(scene_radius),
+            Light::Sky(x) => x.power(scene_radius),
+            Light::Portal(x) => x.power(scene_radius),
         }
     }
 }
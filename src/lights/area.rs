@@ -1,18 +1,22 @@
 use std::f64::consts::PI;
 use std::sync::Arc;
 
-use nalgebra::Vector3;
+use nalgebra::{Point2, Vector2, Vector3};
 
 use crate::lights::{LightEmittingPdf, LightEmittingSample, LightIrradianceSample, LightTrait};
 use crate::objects::{ArcObject, ObjectTrait};
 use crate::renderer::{debug_write_pixel_f64, Ray};
 use crate::surface_interaction::{Interaction, SurfaceInteraction};
+use crate::textures::mip_map::MipMap;
 use crate::Object;
 
 #[derive(Debug)]
 pub struct AreaLight {
     object: ArcObject,
     intensity: Vector3<f64>,
+    /// Scales the intensity by the color at the texture coordinates of the surface, for screens
+    /// or stained glass
+    pub emission_map: Option<Arc<MipMap>>,
 }
 
 impl LightTrait for AreaLight {
@@ -22,7 +26,7 @@ impl LightTrait for AreaLight {
 
     fn emitting(&self, interaction: &SurfaceInteraction, w: Vector3<f64>) -> Vector3<f64> {
         if interaction.shading_normal.dot(&w) > 0.0 {
-            self.intensity
+            self.radiance(interaction.uv, texture_width(interaction))
         } else {
             Vector3::zeros()
         }
@@ -66,14 +70,37 @@ impl LightTrait for AreaLight {
         }
     }
 
-    fn power(&self) -> Vector3<f64> {
-        self.intensity * self.area() * PI
+    /// The surface emits radiance on one side only, into a hemisphere: π·L·A.
+    fn power(&self, _scene_radius: f64) -> Vector3<f64> {
+        let radiance = match &self.emission_map {
+            Some(emission_map) => self.intensity.component_mul(&emission_map.average()),
+            None => self.intensity,
+        };
+
+        radiance * self.area() * PI
     }
 }
 
 impl AreaLight {
     pub fn new(object: ArcObject, intensity: Vector3<f64>) -> Self {
-        Self { object, intensity }
+        Self {
+            object,
+            intensity,
+            emission_map: None,
+        }
+    }
+
+    /// Radiance emitted at the texture coordinates `uv`, averaged over `width` of the emission
+    /// map by trilinear filtering.
+    fn radiance(&self, uv: Vector2<f64>, width: f64) -> Vector3<f64> {
+        match &self.emission_map {
+            Some(emission_map) => {
+                let color = emission_map.lookup(Point2::from(uv), width);
+                self.intensity
+                    .component_mul(&Vector3::new(color[0], color[1], color[2]))
+            }
+            None => self.intensity,
+        }
     }
 
     fn area(&self) -> f64 {
@@ -83,9 +110,204 @@ impl AreaLight {
     /// L()
     pub fn irradiance_at_point(&self, interaction: &Interaction, wo: Vector3<f64>) -> Vector3<f64> {
         if interaction.normal.dot(&wo) > 0.0 {
-            self.intensity
+            self.radiance(interaction.uv, 0.0)
         } else {
             Vector3::zeros()
         }
     }
 }
+
+/// Part of the texture coordinates the footprint of the interaction covers, along the texture
+/// direction it stretches over the most.
+fn texture_width(interaction: &SurfaceInteraction) -> f64 {
+    let length_u = interaction.delta_p_delta_u.magnitude();
+    let length_v = interaction.delta_p_delta_v.magnitude();
+    if length_u == 0.0 || length_v == 0.0 {
+        return 0.0;
+    }
+
+    (interaction.footprint / length_u).max(interaction.footprint / length_v)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use approx::assert_relative_eq;
+    use image::{ImageBuffer, Rgb};
+    use nalgebra::{Point3, Vector2, Vector3};
+
+    use crate::lights::area::AreaLight;
+    use crate::lights::point::PointLight;
+    use crate::lights::LightTrait;
+    use crate::objects::rectangle::Rectangle;
+    use crate::objects::{ArcObject, Object, ObjectTrait};
+    use crate::renderer::Ray;
+    use crate::surface_interaction::SurfaceInteraction;
+    use crate::textures::mip_map::{FilterMode, ImageWrapMethod, MipMap};
+
+    fn area_light(width: f64, radiance: f64) -> AreaLight {
+        let rectangle = Rectangle::new(
+            Point3::origin(),
+            Vector3::x() * width,
+            Vector3::z(),
+            vec![],
+            None,
+        );
+
+        AreaLight::new(
+            ArcObject(Arc::new(Object::Rectangle(rectangle))),
+            Vector3::repeat(radiance),
+        )
+    }
+
+    /// A point below the center of the light of width 2, which faces down.
+    fn receiver() -> SurfaceInteraction {
+        SurfaceInteraction::new(
+            Point3::new(1.0, -2.0, 0.5),
+            Vector3::y(),
+            Vector3::y(),
+            Vector2::zeros(),
+            Vector3::x(),
+            Vector3::z(),
+            Vector3::x(),
+            Vector3::z(),
+            Vector3::zeros(),
+        )
+    }
+
+    #[test]
+    fn test_power_follows_the_area_and_matches_a_point_light_of_the_same_flux() {
+        let small = area_light(1.0, 2.0);
+        let square = Rectangle::new(
+            Point3::origin(),
+            Vector3::x() * 3.0,
+            Vector3::z() * 3.0,
+            vec![],
+            None,
+        );
+        let large = AreaLight::new(
+            ArcObject(Arc::new(Object::Rectangle(square))),
+            Vector3::repeat(2.0),
+        );
+        assert_relative_eq!(large.power(1.0), small.power(1.0) * 9.0);
+
+        // a one sided emitter sends π·L·A into its hemisphere, a point light 4π·I all around
+        let point = PointLight::new(Point3::origin(), Vector3::repeat(2.0 / 4.0));
+        assert_relative_eq!(small.power(1.0), point.power(1.0));
+
+        // lights in the scene do not depend on its size
+        assert_eq!(small.power(1.0), small.power(100.0));
+    }
+
+    #[test]
+    fn test_textured_emitter_returns_the_texture_color_at_the_uv() {
+        // red on the first half of side_a, blue on the second
+        let texture = ImageBuffer::from_fn(2, 1, |x, _| {
+            if x == 0 {
+                Rgb([1.0, 0.0, 0.0])
+            } else {
+                Rgb([0.0, 0.0, 1.0])
+            }
+        });
+        let mut light = area_light(2.0, 3.0);
+        light.emission_map = Some(Arc::new(MipMap::new(
+            texture,
+            ImageWrapMethod::Clamp,
+            FilterMode::Nearest,
+            false,
+        )));
+        let down = Vector3::new(0.0, -1.0, 0.0);
+        let hit = |x| {
+            light
+                .object
+                .test_intersect(Ray {
+                    point: Point3::new(x, -1.0, 0.5),
+                    direction: -down,
+                    t_max: f64::INFINITY,
+                })
+                .unwrap()
+                .1
+        };
+
+        assert_eq!(Vector3::new(3.0, 0.0, 0.0), light.emitting(&hit(0.5), down));
+        assert_eq!(Vector3::new(0.0, 0.0, 3.0), light.emitting(&hit(1.5), down));
+
+        // light samples see the same colors
+        let interaction = receiver();
+        let red = light.sample_irradiance(&interaction, vec![0.25, 0.5]);
+        let blue = light.sample_irradiance(&interaction, vec![0.75, 0.5]);
+        assert_eq!(Vector3::new(3.0, 0.0, 0.0), red.irradiance);
+        assert_eq!(Vector3::new(0.0, 0.0, 3.0), blue.irradiance);
+
+        // half the light is red and half blue
+        assert_relative_eq!(
+            Vector3::new(0.5, 0.0, 0.5) * 3.0 * 2.0 * std::f64::consts::PI,
+            light.power(1.0)
+        );
+    }
+
+    #[test]
+    fn test_trilinear_emitter_blurs_with_the_footprint_of_the_hit() {
+        // columns alternate between black and white along side_a
+        let texture = ImageBuffer::from_fn(4, 1, |x, _| {
+            let value = (x % 2) as f32;
+            Rgb([value, value, value])
+        });
+        let mut light = area_light(2.0, 1.0);
+        light.emission_map = Some(Arc::new(MipMap::new(
+            texture,
+            ImageWrapMethod::Clamp,
+            FilterMode::Trilinear,
+            false,
+        )));
+        let down = Vector3::new(0.0, -1.0, 0.0);
+        let hit = |column: u32, footprint| {
+            let mut interaction = light
+                .object
+                .test_intersect(Ray {
+                    point: Point3::new((column as f64 + 0.5) / 2.0, -1.0, 0.5),
+                    direction: -down,
+                    t_max: f64::INFINITY,
+                })
+                .unwrap()
+                .1;
+            interaction.footprint = footprint;
+            interaction
+        };
+
+        // a sharp footprint sees every column
+        for column in 0..4 {
+            assert_relative_eq!(
+                Vector3::repeat((column % 2) as f64),
+                light.emitting(&hit(column, 0.0), down),
+                epsilon = 1e-9
+            );
+        }
+
+        // a footprint as large as the light sees the average
+        for column in 0..4 {
+            assert_relative_eq!(
+                Vector3::repeat(0.5),
+                light.emitting(&hit(column, 2.0), down),
+                epsilon = 1e-9
+            );
+        }
+    }
+
+    #[test]
+    fn test_the_sample_picks_the_point_on_the_light() {
+        let light = area_light(2.0, 1.0);
+        let interaction = receiver();
+
+        let first = light.sample_irradiance(&interaction, vec![0.25, 0.75]);
+        let again = light.sample_irradiance(&interaction, vec![0.25, 0.75]);
+        let other = light.sample_irradiance(&interaction, vec![0.75, 0.25]);
+
+        assert_relative_eq!(Point3::new(0.5, 0.0, 0.75), first.point);
+        assert_eq!(first.point, again.point);
+        assert_relative_eq!(Point3::new(1.5, 0.0, 0.25), other.point);
+        assert!(first.pdf > 0.0);
+        assert_eq!(Vector3::repeat(1.0), first.irradiance);
+    }
+}
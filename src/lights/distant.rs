@@ -63,8 +63,8 @@ impl LightTrait for DistantLight {
         self.intensity
     }
 
-    fn power(&self) -> Vector3<f64> {
-        self.world_radius * self.world_radius * PI * self.intensity
+    fn power(&self, scene_radius: f64) -> Vector3<f64> {
+        scene_radius * scene_radius * PI * self.intensity
     }
 }
 
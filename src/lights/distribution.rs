@@ -0,0 +1,85 @@
+/// What lights are picked proportional to for direct lighting.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub enum LightSampling {
+    /// The importance set in the scene file
+    #[default]
+    Importance,
+    /// The power of a light times its importance, brighter lights get more of the samples
+    Power,
+}
+
+impl LightSampling {
+    pub fn from_str(str: &str) -> Option<LightSampling> {
+        match str {
+            "importance" => Some(LightSampling::Importance),
+            "power" => Some(LightSampling::Power),
+            _ => None,
+        }
+    }
+}
+
+/// Probabilities of picking each light for direct lighting. Lights are picked proportional to
+/// their importance, which only changes how often a light is sampled and not how bright it is:
+/// the contribution of a picked light is divided by the probability of picking it.
+#[derive(Debug, Clone)]
+pub struct LightDistribution {
+    cdf: Vec<f64>,
+}
+
+impl LightDistribution {
+    pub fn new(importance: &[f64]) -> Self {
+        let mut weights: Vec<f64> = importance.iter().map(|i| i.max(0.0)).collect();
+        if weights.iter().sum::<f64>() <= 0.0 {
+            weights.fill(1.0);
+        }
+        let total: f64 = weights.iter().sum();
+
+        let cdf = weights
+            .iter()
+            .scan(0.0, |sum, weight| {
+                *sum += weight / total;
+                Some(*sum)
+            })
+            .collect();
+
+        LightDistribution { cdf }
+    }
+
+    pub fn uniform(light_count: usize) -> Self {
+        LightDistribution::new(&vec![1.0; light_count])
+    }
+
+    /// Picks a light with a uniform sample in [0, 1).
+    pub fn sample(&self, u: f64) -> usize {
+        self.cdf
+            .partition_point(|&c| c <= u)
+            .min(self.cdf.len() - 1)
+    }
+
+    pub fn pdf(&self, index: usize) -> f64 {
+        if index == 0 {
+            self.cdf[0]
+        } else {
+            self.cdf[index] - self.cdf[index - 1]
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+
+    use crate::lights::distribution::LightDistribution;
+
+    #[test]
+    fn test_lights_are_picked_proportional_to_importance() {
+        let distribution = LightDistribution::new(&[1.0, 3.0]);
+        let picks = (0..1000)
+            .filter(|i| distribution.sample((*i as f64 + 0.5) / 1000.0) == 1)
+            .count();
+
+        assert_eq!(750, picks);
+        assert_relative_eq!(0.25, distribution.pdf(0));
+        assert_relative_eq!(0.75, distribution.pdf(1));
+    }
+}
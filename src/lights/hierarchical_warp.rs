@@ -0,0 +1,199 @@
+use nalgebra::Point2;
+
+/// Samples the unit square proportional to a square grid of weights with hierarchical sample
+/// warping. The weights are summed into a quadtree, from the top down a sample picks one of the
+/// four children proportional to their sums and is rescaled to the picked child. Building it
+/// only takes the sums, a third more than the grid itself, which keeps very large environment
+/// maps cheap to importance sample.
+#[derive(Debug, Clone)]
+pub struct HierarchicalWarp {
+    // sums of the weights, from the full resolution grid up to the single total
+    levels: Vec<Vec<f64>>,
+}
+
+impl HierarchicalWarp {
+    /// The `weights` are a grid of `size` by `size` cells in rows, `size` has to be a power of
+    /// two. A grid without any weight is sampled uniformly.
+    pub fn new(size: usize, mut weights: Vec<f64>) -> Self {
+        assert!(size.is_power_of_two(), "Warp size must be a power of two");
+        assert_eq!(size * size, weights.len());
+
+        if weights.iter().sum::<f64>() <= 0.0 {
+            weights.fill(1.0);
+        }
+
+        let mut levels = vec![weights];
+        while levels.last().unwrap().len() > 1 {
+            let child = levels.last().unwrap();
+            let child_size = level_size(child);
+            let size = child_size / 2;
+            let level = (0..size * size)
+                .map(|i| {
+                    let (x, y) = (2 * (i % size), 2 * (i / size));
+                    child[y * child_size + x]
+                        + child[y * child_size + x + 1]
+                        + child[(y + 1) * child_size + x]
+                        + child[(y + 1) * child_size + x + 1]
+                })
+                .collect();
+            levels.push(level);
+        }
+
+        HierarchicalWarp { levels }
+    }
+
+    fn size(&self) -> usize {
+        level_size(&self.levels[0])
+    }
+
+    /// Warps a uniform sample to a point in the unit square, returns the point and its pdf.
+    pub fn sample(&self, u: Point2<f64>) -> (Point2<f64>, f64) {
+        let mut u = u;
+        let (mut x, mut y) = (0, 0);
+
+        for level in self.levels.iter().rev().skip(1) {
+            let size = level_size(level);
+            (x, y) = (2 * x, 2 * y);
+            let weight = |x: usize, y: usize| level[y * size + x];
+
+            let left = weight(x, y) + weight(x, y + 1);
+            let right = weight(x + 1, y) + weight(x + 1, y + 1);
+            let (column, u_x) = pick(u.x, left, right);
+            x += column;
+
+            let (row, u_y) = pick(u.y, weight(x, y), weight(x, y + 1));
+            y += row;
+            u = Point2::new(u_x, u_y);
+        }
+
+        let point = Point2::new(
+            (x as f64 + u.x) / self.size() as f64,
+            (y as f64 + u.y) / self.size() as f64,
+        );
+
+        (point, self.pdf(point))
+    }
+
+    /// Maps a point back to the uniform sample `sample` warps to it.
+    pub fn invert(&self, point: Point2<f64>) -> Point2<f64> {
+        let size = self.size();
+        let (mut x, mut y) = (
+            ((point.x * size as f64) as usize).min(size - 1),
+            ((point.y * size as f64) as usize).min(size - 1),
+        );
+        let mut u = Point2::new(
+            point.x * size as f64 - x as f64,
+            point.y * size as f64 - y as f64,
+        );
+
+        for level in self.levels.iter().take(self.levels.len() - 1) {
+            let size = level_size(level);
+            let weight = |x: usize, y: usize| level[y * size + x];
+            let (column, row) = (x % 2, y % 2);
+            let (left_x, top_y) = (x - column, y - row);
+
+            u.y = unpick(u.y, row, weight(x, top_y), weight(x, top_y + 1));
+
+            let left = weight(left_x, top_y) + weight(left_x, top_y + 1);
+            let right = weight(left_x + 1, top_y) + weight(left_x + 1, top_y + 1);
+            u.x = unpick(u.x, column, left, right);
+
+            (x, y) = (x / 2, y / 2);
+        }
+
+        u
+    }
+
+    /// Density of `sample` at `point`, with respect to area in the unit square.
+    pub fn pdf(&self, point: Point2<f64>) -> f64 {
+        let size = self.size();
+        let x = ((point.x * size as f64) as usize).min(size - 1);
+        let y = ((point.y * size as f64) as usize).min(size - 1);
+        let total = self.levels.last().unwrap()[0];
+
+        self.levels[0][y * size + x] / total * (size * size) as f64
+    }
+}
+
+fn level_size(level: &[f64]) -> usize {
+    (level.len() as f64).sqrt() as usize
+}
+
+/// Picks the first (0) or second (1) half proportional to their weights and rescales `u` to
+/// [0, 1) within the picked half.
+fn pick(u: f64, first: f64, second: f64) -> (usize, f64) {
+    let split = first / (first + second);
+
+    if u < split {
+        (0, (u / split).min(1.0 - f64::EPSILON))
+    } else {
+        (1, ((u - split) / (1.0 - split)).min(1.0 - f64::EPSILON))
+    }
+}
+
+/// The inverse of `pick`.
+fn unpick(u: f64, half: usize, first: f64, second: f64) -> f64 {
+    let split = first / (first + second);
+
+    if half == 0 {
+        u * split
+    } else {
+        split + u * (1.0 - split)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+    use nalgebra::Point2;
+
+    use crate::lights::hierarchical_warp::HierarchicalWarp;
+
+    fn warp() -> HierarchicalWarp {
+        // a bright cell, a black cell and a dim rest
+        let mut weights = vec![1.0; 16];
+        weights[5] = 20.0;
+        weights[10] = 0.0;
+
+        HierarchicalWarp::new(4, weights)
+    }
+
+    #[test]
+    fn test_inverse_maps_warped_samples_back_and_pdf_follows_the_weights() {
+        let warp = warp();
+        let total = 20.0 + 14.0;
+
+        let mut counts = [0; 16];
+        let n = 64;
+        for i in 0..n {
+            for j in 0..n {
+                let u = Point2::new((i as f64 + 0.5) / n as f64, (j as f64 + 0.5) / n as f64);
+                let (point, pdf) = warp.sample(u);
+
+                assert_relative_eq!(u, warp.invert(point), epsilon = 1e-9);
+                assert_relative_eq!(pdf, warp.pdf(point));
+
+                counts[(point.y * 4.0) as usize * 4 + (point.x * 4.0) as usize] += 1;
+            }
+        }
+
+        // samples land in each cell proportional to its weight, up to the spacing of the
+        // sample grid, and never in a black one
+        assert_eq!(0, counts[10]);
+        assert_relative_eq!(
+            20.0 / total,
+            counts[5] as f64 / (n * n) as f64,
+            epsilon = 1.0 / n as f64
+        );
+        assert_relative_eq!(16.0 * 20.0 / total, warp.pdf(Point2::new(0.3, 0.3)));
+
+        // the pdf integrates to one over the unit square
+        let integral: f64 = (0..16)
+            .map(|i| {
+                let center = Point2::new((i % 4) as f64 + 0.5, (i / 4) as f64 + 0.5);
+                warp.pdf(center / 4.0) / 16.0
+            })
+            .sum();
+        assert_relative_eq!(1.0, integral, epsilon = 1e-12);
+    }
+}
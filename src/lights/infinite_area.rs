@@ -3,14 +3,15 @@ use std::path::PathBuf;
 
 use ggez::graphics::Image;
 use image::Pixel;
-use image::{ImageBuffer, Rgb, RgbImage};
+use image::{ImageBuffer, Rgb, Rgb32FImage};
 use nalgebra::{Matrix3, Matrix4, Point2, Point3, Transform, Vector3};
 
 use crate::helpers::{get_random_in_unit_sphere, spherical_phi, spherical_theta};
+use crate::lights::hierarchical_warp::HierarchicalWarp;
 use crate::lights::{LightEmittingPdf, LightEmittingSample, LightIrradianceSample, LightTrait};
 use crate::renderer::Ray;
 use crate::surface_interaction::{Interaction, SurfaceInteraction};
-use crate::textures::mip_map::MipMap;
+use crate::textures::mip_map::{FilterMode, ImageWrapMethod, MipMap};
 
 #[derive(Debug)]
 pub struct InfiniteAreaLight {
@@ -19,6 +20,11 @@ pub struct InfiniteAreaLight {
     world_to_light: Matrix4<f64>,
     world_center: Point3<f64>,
     world_radius: f64,
+    clamp: Option<(f64, f64)>,
+    // picks directions proportional to the brightness of the map
+    warp: HierarchicalWarp,
+    // average over the sphere, texels near the poles count less
+    average_radiance: Vector3<f64>,
 }
 
 impl LightTrait for InfiniteAreaLight {
@@ -35,8 +41,9 @@ impl LightTrait for InfiniteAreaLight {
         interaction: &SurfaceInteraction,
         sample: Vec<f64>,
     ) -> LightIrradianceSample {
-        let theta = sample[1] * PI;
-        let phi = sample[0] * 2.0 * PI;
+        let (point, _) = self.warp.sample(Point2::new(sample[0], sample[1]));
+        let theta = point.y * PI;
+        let phi = point.x * 2.0 * PI;
         let cos_theta = theta.cos();
         let sin_theta = theta.sin();
         let cos_phi = phi.cos();
@@ -51,12 +58,7 @@ impl LightTrait for InfiniteAreaLight {
         let ray = Ray {
             point: interaction.point,
             direction: wi.normalize(),
-        };
-
-        let pdf = if sin_theta != 0.0 {
-            self.environment_emitting(ray).max() / (2.0 * PI * PI * sin_theta)
-        } else {
-            0.0
+            t_max: f64::INFINITY,
         };
 
         let point_outside = interaction.point + wi * (2.0 * self.world_radius);
@@ -64,7 +66,7 @@ impl LightTrait for InfiniteAreaLight {
         LightIrradianceSample {
             point: point_outside,
             wi,
-            pdf,
+            pdf: self.pdf(wi),
             irradiance: self.environment_emitting(ray),
         }
     }
@@ -73,8 +75,8 @@ impl LightTrait for InfiniteAreaLight {
         todo!()
     }
 
-    fn pdf_incidence(&self, interaction: &Interaction, wi: Vector3<f64>) -> f64 {
-        1.0
+    fn pdf_incidence(&self, _interaction: &Interaction, wi: Vector3<f64>) -> f64 {
+        self.pdf(wi)
     }
 
     fn pdf_emitting(&self, ray: Ray, light_normal: Vector3<f64>) -> LightEmittingPdf {
@@ -88,31 +90,53 @@ impl LightTrait for InfiniteAreaLight {
             spherical_theta(w) * FRAC_1_PI,
         );
 
-        let lookup = self.mip_map.lookup(point, 0.5);
+        // an escaped ray has no footprint to pick a coarser level with, trilinear filtering
+        // reads the full resolution like bilinear
+        let lookup = self.mip_map.lookup(point, 0.0);
+        let radiance = Vector3::new(lookup[0], lookup[1], lookup[2]);
 
-        Vector3::new(lookup[0], lookup[1], lookup[2])
+        match self.clamp {
+            Some((min, max)) => radiance.map(|x| x.clamp(min, max)),
+            None => radiance,
+        }
     }
 
-    fn power(&self) -> Vector3<f64> {
-        let lookup = self.mip_map.lookup(Point2::new(0.5, 0.5), 0.5);
-        Vector3::new(lookup[0], lookup[1], lookup[2]) * PI * self.world_radius * self.world_radius
+    /// The irradiance of a uniform environment with the average radiance of the map, π·L,
+    /// through the disk of the scene.
+    fn power(&self, scene_radius: f64) -> Vector3<f64> {
+        self.average_radiance * PI * PI * scene_radius * scene_radius
     }
 }
 
 impl InfiniteAreaLight {
-    pub fn new(intensity: &Vector3<f64>, image: RgbImage, light_to_world: Matrix4<f64>) -> Self {
+    /// The optional `clamp` limits every channel of the looked up radiance to `(min, max)`.
+    /// Clamping the environment biases the result, but tames the fireflies caused by very bright
+    /// spots like the sun. Directions are importance sampled on a grid of `warp_resolution` cells
+    /// square (rounded up to a power of two), a coarser grid than the map saves memory on very
+    /// large maps.
+    pub fn new(
+        intensity: &Vector3<f64>,
+        image: Rgb32FImage,
+        light_to_world: Matrix4<f64>,
+        clamp: Option<(f64, f64)>,
+        filter: FilterMode,
+        warp_resolution: u32,
+    ) -> Self {
         let mut buffer = ImageBuffer::new(image.width(), image.height());
         for (x, y, pixel) in image.enumerate_pixels() {
             let adjusted_pixel = Rgb([
-                (pixel[0] as f64 * intensity.x) as u8,
-                (pixel[1] as f64 * intensity.y) as u8,
-                (pixel[2] as f64 * intensity.z) as u8,
+                (pixel[0] as f64 * intensity.x) as f32,
+                (pixel[1] as f64 * intensity.y) as f32,
+                (pixel[2] as f64 * intensity.z) as f32,
             ]);
 
             buffer.put_pixel(x, y, adjusted_pixel)
         }
 
-        let mip_map = MipMap::new(buffer);
+        let warp = InfiniteAreaLight::build_warp(&buffer, clamp, warp_resolution);
+        let average_radiance = InfiniteAreaLight::average_radiance(&buffer, clamp);
+        // HDR environment maps are linear
+        let mip_map = MipMap::new(buffer, ImageWrapMethod::Equirectangular, filter, false);
 
         InfiniteAreaLight {
             mip_map,
@@ -120,6 +144,218 @@ impl InfiniteAreaLight {
             world_to_light: light_to_world.try_inverse().unwrap(),
             world_center: Point3::origin(),
             world_radius: 1e20,
+            clamp,
+            warp,
+            average_radiance,
+        }
+    }
+
+    fn average_radiance(image: &Rgb32FImage, clamp: Option<(f64, f64)>) -> Vector3<f64> {
+        let mut sum = Vector3::zeros();
+        let mut sum_weight = 0.0;
+
+        for (_, y, pixel) in image.enumerate_pixels() {
+            let sin_theta = ((y as f64 + 0.5) / image.height() as f64 * PI).sin();
+            let radiance = Vector3::new(pixel[0] as f64, pixel[1] as f64, pixel[2] as f64);
+            let radiance = match clamp {
+                Some((min, max)) => radiance.map(|x| x.clamp(min, max)),
+                None => radiance,
+            };
+
+            sum += radiance * sin_theta;
+            sum_weight += sin_theta;
+        }
+
+        sum / sum_weight
+    }
+
+    /// The weight of a cell is the brightest channel of the texels it covers, times sin(θ) as
+    /// rows near the poles cover less of the sphere.
+    fn build_warp(
+        image: &Rgb32FImage,
+        clamp: Option<(f64, f64)>,
+        warp_resolution: u32,
+    ) -> HierarchicalWarp {
+        let size = warp_resolution.max(1).next_power_of_two() as usize;
+        let (width, height) = (image.width() as usize, image.height() as usize);
+        // texels covered by cell `i` of `size` cells over `texels`, at least one
+        let texel_range = |i: usize, texels: usize| {
+            let start = i * texels / size;
+            start..((i + 1) * texels).div_ceil(size).max(start + 1)
+        };
+
+        let weights = (0..size * size)
+            .map(|i| {
+                let (cell_x, cell_y) = (i % size, i / size);
+                let mut weight = 0.0;
+                for y in texel_range(cell_y, height) {
+                    let sin_theta = ((y as f64 + 0.5) / height as f64 * PI).sin();
+                    for x in texel_range(cell_x, width) {
+                        let brightest = image
+                            .get_pixel(x as u32, y as u32)
+                            .channels()
+                            .iter()
+                            .map(|&channel| match clamp {
+                                Some((min, max)) => (channel as f64).clamp(min, max),
+                                None => channel as f64,
+                            })
+                            .fold(0.0, f64::max);
+                        weight += brightest * sin_theta;
+                    }
+                }
+
+                weight
+            })
+            .collect();
+
+        HierarchicalWarp::new(size, weights)
+    }
+
+    /// Solid angle pdf of sampling `wi`.
+    fn pdf(&self, wi: Vector3<f64>) -> f64 {
+        let w = self.world_to_light.transform_vector(&wi).normalize();
+        let sin_theta = spherical_theta(w).sin();
+        if sin_theta == 0.0 {
+            return 0.0;
+        }
+
+        let point = Point2::new(
+            spherical_phi(w) / (2.0 * PI),
+            spherical_theta(w) * FRAC_1_PI,
+        );
+
+        self.warp.pdf(point) / (2.0 * PI * PI * sin_theta)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+
+    use approx::assert_relative_eq;
+    use image::{ImageBuffer, Rgb};
+    use nalgebra::{Matrix4, Point3, Vector2, Vector3};
+
+    use crate::lights::infinite_area::InfiniteAreaLight;
+    use crate::lights::LightTrait;
+    use crate::renderer::Ray;
+    use crate::surface_interaction::SurfaceInteraction;
+    use crate::textures::mip_map::FilterMode;
+
+    #[test]
+    fn test_hdr_values_are_not_clamped() {
+        let light = InfiniteAreaLight::new(
+            &Vector3::repeat(1.0),
+            ImageBuffer::from_pixel(1, 1, Rgb([50.0, 50.0, 50.0])),
+            Matrix4::identity(),
+            None,
+            FilterMode::Nearest,
+            1,
+        );
+
+        assert_eq!(Vector3::repeat(50.0), light.environment_emitting(up_ray()));
+    }
+
+    #[test]
+    fn test_clamp_limits_bright_values_only() {
+        let bright = InfiniteAreaLight::new(
+            &Vector3::repeat(1.0),
+            ImageBuffer::from_pixel(1, 1, Rgb([50.0, 5.0, 0.0])),
+            Matrix4::identity(),
+            Some((0.5, 10.0)),
+            FilterMode::Nearest,
+            1,
+        );
+
+        assert_eq!(
+            Vector3::new(10.0, 5.0, 0.5),
+            bright.environment_emitting(up_ray())
+        );
+    }
+
+    #[test]
+    fn test_trilinear_environment_keeps_the_full_resolution_detail() {
+        // rows alternate between black and white, every coarser mip level is gray
+        let light = InfiniteAreaLight::new(
+            &Vector3::repeat(1.0),
+            ImageBuffer::from_fn(1, 8, |_, y| {
+                let value = (y % 2) as f32;
+                Rgb([value, value, value])
+            }),
+            Matrix4::identity(),
+            None,
+            FilterMode::Trilinear,
+            1,
+        );
+        let row = |y: f64| {
+            let theta = PI * (y + 0.5) / 8.0;
+            light.environment_emitting(Ray {
+                point: Point3::origin(),
+                direction: Vector3::new(theta.sin(), 0.0, theta.cos()),
+                t_max: f64::INFINITY,
+            })
+        };
+
+        for y in 0..8 {
+            assert_relative_eq!(
+                Vector3::repeat((y % 2) as f64),
+                row(y as f64),
+                epsilon = 1e-9
+            );
+        }
+    }
+
+    #[test]
+    fn test_samples_favor_the_bright_half_with_a_matching_pdf() {
+        // the left half of the map is a hundred times brighter
+        let light = InfiniteAreaLight::new(
+            &Vector3::repeat(1.0),
+            ImageBuffer::from_fn(8, 4, |x, _| {
+                let value = if x < 4 { 100.0 } else { 1.0 };
+                Rgb([value, value, value])
+            }),
+            Matrix4::identity(),
+            None,
+            FilterMode::Nearest,
+            8,
+        );
+        let interaction = SurfaceInteraction::new(
+            Point3::origin(),
+            Vector3::y(),
+            Vector3::y(),
+            Vector2::zeros(),
+            Vector3::x(),
+            Vector3::z(),
+            Vector3::x(),
+            Vector3::z(),
+            Vector3::zeros(),
+        );
+
+        let mut bright = 0;
+        for i in 0..16 {
+            for j in 0..16 {
+                let sample = vec![(i as f64 + 0.5) / 16.0, (j as f64 + 0.5) / 16.0];
+                let light_sample = light.sample_irradiance(&interaction, sample);
+
+                assert_relative_eq!(
+                    light_sample.pdf,
+                    light.pdf_incidence(&(&interaction).into(), light_sample.wi),
+                    max_relative = 1e-9
+                );
+                if light_sample.irradiance.x == 100.0 {
+                    bright += 1;
+                }
+            }
+        }
+
+        assert!(bright > 16 * 16 * 9 / 10);
+    }
+
+    fn up_ray() -> Ray {
+        Ray {
+            point: Point3::origin(),
+            direction: Vector3::new(0.0, 1.0, 0.0),
+            t_max: f64::INFINITY,
         }
     }
 }
@@ -58,7 +58,7 @@ impl LightTrait for PointLight {
         }
     }
 
-    fn power(&self) -> Vector3<f64> {
+    fn power(&self, _scene_radius: f64) -> Vector3<f64> {
         4.0 * PI * self.intensity
     }
 }
@@ -0,0 +1,216 @@
+use std::sync::Arc;
+
+use nalgebra::{Point3, Vector3};
+
+use crate::lights::{
+    Light, LightEmittingPdf, LightEmittingSample, LightIrradianceSample, LightTrait,
+};
+use crate::objects::rectangle::Rectangle;
+use crate::objects::ObjectTrait;
+use crate::renderer::Ray;
+use crate::surface_interaction::{Interaction, SurfaceInteraction};
+
+/// An environment light (map or sky) that is only seen through portals, rectangles placed in
+/// the window openings of an interior. Light samples are taken on the portals instead of over
+/// the whole sphere, most of which is blocked by walls. The environment is not visible in any
+/// other direction, so the portals should cover every opening.
+#[derive(Debug)]
+pub struct PortalLight {
+    environment: Arc<Light>,
+    portals: Vec<Rectangle>,
+    // area of each portal, the portal to sample is picked proportional to its area
+    areas: Vec<f64>,
+    world_radius: f64,
+}
+
+impl PortalLight {
+    pub fn new(environment: Arc<Light>, portals: Vec<Rectangle>) -> Self {
+        let areas = portals
+            .iter()
+            .map(|portal| portal.side_a.cross(&portal.side_b).magnitude())
+            .collect();
+
+        PortalLight {
+            environment,
+            portals,
+            areas,
+            world_radius: 1e20,
+        }
+    }
+
+    fn total_area(&self) -> f64 {
+        self.areas.iter().sum()
+    }
+
+    /// Solid angle pdf of sampling `wi` from `point`, summed over every portal the direction
+    /// passes through.
+    fn pdf_from_point(&self, point: Point3<f64>, wi: Vector3<f64>) -> f64 {
+        let ray = Ray {
+            point,
+            direction: wi,
+            t_max: f64::INFINITY,
+        };
+        let total_area = self.total_area();
+
+        self.portals
+            .iter()
+            .filter_map(|portal| portal.test_intersect(ray))
+            .map(|(distance, interaction)| {
+                let cos = interaction.shading_normal.dot(&wi).abs();
+                if cos == 0.0 {
+                    0.0
+                } else {
+                    distance * distance / (cos * total_area)
+                }
+            })
+            .sum()
+    }
+
+    fn through_portal(&self, ray: Ray) -> bool {
+        self.portals
+            .iter()
+            .any(|portal| portal.test_intersect(ray).is_some())
+    }
+}
+
+impl LightTrait for PortalLight {
+    fn is_delta(&self) -> bool {
+        false
+    }
+
+    fn emitting(&self, _interaction: &SurfaceInteraction, _w: Vector3<f64>) -> Vector3<f64> {
+        Vector3::zeros()
+    }
+
+    fn sample_irradiance(
+        &self,
+        interaction: &SurfaceInteraction,
+        sample: Vec<f64>,
+    ) -> LightIrradianceSample {
+        // the third dimension picks the portal, the first two the point on it
+        let mut u = sample[2] * self.total_area();
+        let mut index = self.portals.len() - 1;
+        for (i, area) in self.areas.iter().enumerate() {
+            if u < *area {
+                index = i;
+                break;
+            }
+            u -= area;
+        }
+
+        let portal_point = self.portals[index].sample_point(sample).point;
+        let wi = (portal_point - interaction.point).normalize();
+        let ray = Ray {
+            point: interaction.point,
+            direction: wi,
+            t_max: f64::INFINITY,
+        };
+
+        LightIrradianceSample {
+            irradiance: self.environment.environment_emitting(ray),
+            point: interaction.point + wi * (2.0 * self.world_radius),
+            wi,
+            pdf: self.pdf_from_point(interaction.point, wi),
+        }
+    }
+
+    fn sample_emitting(&self) -> LightEmittingSample {
+        todo!()
+    }
+
+    fn pdf_incidence(&self, interaction: &Interaction, wi: Vector3<f64>) -> f64 {
+        self.pdf_from_point(interaction.point, wi)
+    }
+
+    fn pdf_emitting(&self, _ray: Ray, _light_normal: Vector3<f64>) -> LightEmittingPdf {
+        todo!()
+    }
+
+    fn environment_emitting(&self, ray: Ray) -> Vector3<f64> {
+        if self.through_portal(ray) {
+            self.environment.environment_emitting(ray)
+        } else {
+            Vector3::zeros()
+        }
+    }
+
+    fn power(&self, scene_radius: f64) -> Vector3<f64> {
+        self.environment.power(scene_radius)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use nalgebra::{Point3, Vector2, Vector3};
+
+    use crate::lights::portal::PortalLight;
+    use crate::lights::sky::SkyLight;
+    use crate::lights::{Light, LightTrait};
+    use crate::objects::rectangle::Rectangle;
+    use crate::objects::ObjectTrait;
+    use crate::renderer::Ray;
+    use crate::surface_interaction::SurfaceInteraction;
+
+    #[test]
+    fn test_samples_only_pass_through_the_portal() {
+        let sky = Arc::new(Light::Sky(SkyLight::new(
+            Vector3::new(0.3, 1.0, 0.2),
+            3.0,
+            Vector3::repeat(0.2),
+            5800.0,
+            20.0,
+            1.0,
+        )));
+        // a window in the wall at z = -2
+        let window = Rectangle::new(
+            Point3::new(-0.5, 0.5, -2.0),
+            Vector3::x() * 2.0,
+            Vector3::y(),
+            vec![],
+            None,
+        );
+        let portal = PortalLight::new(sky, vec![window.clone()]);
+
+        let interaction = SurfaceInteraction::new(
+            Point3::origin(),
+            Vector3::y(),
+            Vector3::y(),
+            Vector2::zeros(),
+            Vector3::x(),
+            Vector3::z(),
+            Vector3::x(),
+            Vector3::z(),
+            Vector3::zeros(),
+        );
+
+        for i in 0..8 {
+            for j in 0..8 {
+                let sample = vec![(i as f64 + 0.5) / 8.0, (j as f64 + 0.5) / 8.0, 0.5];
+                let light_sample = portal.sample_irradiance(&interaction, sample);
+                let ray = Ray {
+                    point: interaction.point,
+                    direction: light_sample.wi,
+                    t_max: f64::INFINITY,
+                };
+
+                assert!(window.test_intersect(ray).is_some());
+                assert!(light_sample.pdf > 0.0);
+                assert!(light_sample.irradiance.max() > 0.0);
+            }
+        }
+
+        // away from the window the environment is neither seen nor sampled
+        let up = Ray {
+            point: interaction.point,
+            direction: Vector3::y(),
+            t_max: f64::INFINITY,
+        };
+        assert_eq!(Vector3::zeros(), portal.environment_emitting(up));
+        assert_eq!(
+            0.0,
+            portal.pdf_incidence(&(&interaction).into(), Vector3::y())
+        );
+    }
+}
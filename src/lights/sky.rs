@@ -0,0 +1,309 @@
+use std::f64::consts::PI;
+
+use nalgebra::Vector3;
+
+use crate::helpers::coordinate_system;
+use crate::lights::{LightEmittingPdf, LightEmittingSample, LightIrradianceSample, LightTrait};
+use crate::renderer::Ray;
+use crate::surface_interaction::{Interaction, SurfaceInteraction};
+
+/// Angular radius of the sun disk as seen from earth, in radians
+const SUN_ANGULAR_RADIUS: f64 = 0.00465;
+/// Fraction of the light samples that go towards the sun disk
+const SUN_SAMPLE_PROBABILITY: f64 = 0.5;
+
+/// Analytic daylight sky (Preetham et al. 1999) with a sun disk. The sky is scaled so the
+/// zenith has a luminance of `intensity`, the color of the sun follows from its temperature
+/// in Kelvin and `sun_irradiance` is the light it casts on a surface facing it. The world
+/// is Y up, below the horizon the ground reflects the sky with `ground_albedo`.
+///
+/// Radiance is returned as XYZ, like the rest of the renderer.
+#[derive(Debug)]
+pub struct SkyLight {
+    sun_direction: Vector3<f64>,
+    ground_albedo: Vector3<f64>,
+    sun_radiance: Vector3<f64>,
+    intensity: f64,
+    zenith_chromaticity: (f64, f64),
+    // Perez coefficients for luminance and the x and y chromaticity, divided by their value at
+    // the zenith
+    perez: [PerezCoefficients; 3],
+    perez_zenith: [f64; 3],
+    world_radius: f64,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct PerezCoefficients {
+    a: f64,
+    b: f64,
+    c: f64,
+    d: f64,
+    e: f64,
+}
+
+impl PerezCoefficients {
+    fn evaluate(&self, cos_theta: f64, gamma: f64) -> f64 {
+        (1.0 + self.a * (self.b / cos_theta.max(0.01)).exp())
+            * (1.0 + self.c * (self.d * gamma).exp() + self.e * gamma.cos().powi(2))
+    }
+}
+
+impl SkyLight {
+    pub fn new(
+        sun_direction: Vector3<f64>,
+        turbidity: f64,
+        ground_albedo: Vector3<f64>,
+        sun_temperature: f64,
+        sun_irradiance: f64,
+        intensity: f64,
+    ) -> Self {
+        let sun_direction = sun_direction.normalize();
+        let t = turbidity.clamp(1.7, 10.0);
+        let theta_s = sun_direction.y.clamp(-1.0, 1.0).acos().min(PI / 2.0);
+
+        let perez = [
+            PerezCoefficients {
+                a: 0.1787 * t - 1.4630,
+                b: -0.3554 * t + 0.4275,
+                c: -0.0227 * t + 5.3251,
+                d: 0.1206 * t - 2.5771,
+                e: -0.0670 * t + 0.3703,
+            },
+            PerezCoefficients {
+                a: -0.0193 * t - 0.2592,
+                b: -0.0665 * t + 0.0008,
+                c: -0.0004 * t + 0.2125,
+                d: -0.0641 * t - 0.8989,
+                e: -0.0033 * t + 0.0452,
+            },
+            PerezCoefficients {
+                a: -0.0167 * t - 0.2608,
+                b: -0.0950 * t + 0.0092,
+                c: -0.0079 * t + 0.2102,
+                d: -0.0441 * t - 1.6537,
+                e: -0.0109 * t + 0.0529,
+            },
+        ];
+        let perez_zenith = perez.map(|coefficients| coefficients.evaluate(1.0, theta_s));
+
+        let (theta, theta_2, theta_3) = (theta_s, theta_s * theta_s, theta_s.powi(3));
+        let zenith_x = t * t * (0.00166 * theta_3 - 0.00375 * theta_2 + 0.00209 * theta)
+            + t * (-0.02903 * theta_3 + 0.06377 * theta_2 - 0.03202 * theta + 0.00394)
+            + (0.11693 * theta_3 - 0.21196 * theta_2 + 0.06052 * theta + 0.25886);
+        let zenith_y = t * t * (0.00275 * theta_3 - 0.00610 * theta_2 + 0.00317 * theta)
+            + t * (-0.04214 * theta_3 + 0.08970 * theta_2 - 0.04153 * theta + 0.00516)
+            + (0.15346 * theta_3 - 0.26756 * theta_2 + 0.06670 * theta + 0.26688);
+
+        let (x, y) = blackbody_chromaticity(sun_temperature);
+        let sun_solid_angle = 2.0 * PI * (1.0 - SUN_ANGULAR_RADIUS.cos());
+
+        SkyLight {
+            sun_direction,
+            ground_albedo,
+            sun_radiance: xyy_to_xyz(x, y, sun_irradiance / sun_solid_angle),
+            intensity,
+            zenith_chromaticity: (zenith_x, zenith_y),
+            perez,
+            perez_zenith,
+            world_radius: 1e20,
+        }
+    }
+
+    fn sky_radiance(&self, direction: Vector3<f64>) -> Vector3<f64> {
+        let cos_theta = direction.y;
+        let gamma = direction.dot(&self.sun_direction).clamp(-1.0, 1.0).acos();
+
+        let relative = |i: usize| self.perez[i].evaluate(cos_theta, gamma) / self.perez_zenith[i];
+        let luminance = self.intensity * relative(0);
+        let x = self.zenith_chromaticity.0 * relative(1);
+        let y = self.zenith_chromaticity.1 * relative(2);
+
+        xyy_to_xyz(x, y, luminance.max(0.0))
+    }
+
+    fn is_sun_up(&self) -> bool {
+        self.sun_direction.y > 0.0
+    }
+
+    fn sun_cos_max(&self) -> f64 {
+        SUN_ANGULAR_RADIUS.cos()
+    }
+
+    fn pdf(&self, wi: Vector3<f64>) -> f64 {
+        let sphere_pdf = 1.0 / (4.0 * PI);
+
+        if !self.is_sun_up() {
+            return sphere_pdf;
+        }
+
+        let sun_pdf = if wi.dot(&self.sun_direction) >= self.sun_cos_max() {
+            1.0 / (2.0 * PI * (1.0 - self.sun_cos_max()))
+        } else {
+            0.0
+        };
+
+        SUN_SAMPLE_PROBABILITY * sun_pdf + (1.0 - SUN_SAMPLE_PROBABILITY) * sphere_pdf
+    }
+}
+
+impl LightTrait for SkyLight {
+    fn is_delta(&self) -> bool {
+        false
+    }
+
+    fn emitting(&self, _interaction: &SurfaceInteraction, _w: Vector3<f64>) -> Vector3<f64> {
+        unimplemented!()
+    }
+
+    /// Samples the sun disk or the whole sphere, the pdf is that of both strategies combined.
+    fn sample_irradiance(
+        &self,
+        interaction: &SurfaceInteraction,
+        sample: Vec<f64>,
+    ) -> LightIrradianceSample {
+        let wi = if self.is_sun_up() && sample[0] < SUN_SAMPLE_PROBABILITY {
+            let cos_theta = 1.0 - sample[1] * (1.0 - self.sun_cos_max());
+            let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+            let phi = 2.0 * PI * sample[2];
+            let (_, u, v) = coordinate_system(self.sun_direction);
+
+            (self.sun_direction * cos_theta
+                + u * (sin_theta * phi.cos())
+                + v * (sin_theta * phi.sin()))
+            .normalize()
+        } else {
+            let y = 1.0 - 2.0 * sample[1];
+            let r = (1.0 - y * y).max(0.0).sqrt();
+            let phi = 2.0 * PI * sample[2];
+
+            Vector3::new(r * phi.cos(), y, r * phi.sin())
+        };
+
+        let ray = Ray {
+            point: interaction.point,
+            direction: wi,
+            t_max: f64::INFINITY,
+        };
+
+        LightIrradianceSample {
+            point: interaction.point + wi * (2.0 * self.world_radius),
+            wi,
+            pdf: self.pdf(wi),
+            irradiance: self.environment_emitting(ray),
+        }
+    }
+
+    fn sample_emitting(&self) -> LightEmittingSample {
+        unimplemented!()
+    }
+
+    fn pdf_incidence(&self, _interaction: &Interaction, wi: Vector3<f64>) -> f64 {
+        self.pdf(wi)
+    }
+
+    fn pdf_emitting(&self, _ray: Ray, _light_normal: Vector3<f64>) -> LightEmittingPdf {
+        unimplemented!()
+    }
+
+    fn environment_emitting(&self, ray: Ray) -> Vector3<f64> {
+        let direction = ray.direction.normalize();
+
+        if direction.y < 0.0 {
+            // the ground reflects the sky above it
+            let mirrored = Vector3::new(direction.x, -direction.y, direction.z);
+            return self
+                .ground_albedo
+                .component_mul(&self.sky_radiance(mirrored));
+        }
+
+        let mut radiance = self.sky_radiance(direction);
+
+        if self.is_sun_up() && direction.dot(&self.sun_direction) >= self.sun_cos_max() {
+            radiance += self.sun_radiance;
+        }
+
+        radiance
+    }
+
+    fn power(&self, scene_radius: f64) -> Vector3<f64> {
+        let sun_irradiance = self.sun_radiance * 2.0 * PI * (1.0 - self.sun_cos_max());
+
+        (sun_irradiance + self.sky_radiance(Vector3::y()) * PI) * PI * scene_radius * scene_radius
+    }
+}
+
+fn xyy_to_xyz(x: f64, y: f64, luminance: f64) -> Vector3<f64> {
+    if y <= 0.0 {
+        return Vector3::zeros();
+    }
+
+    Vector3::new(x / y * luminance, luminance, (1.0 - x - y) / y * luminance)
+}
+
+/// Chromaticity of a black body at the temperature in Kelvin (Kim et al. 2002), valid from
+/// 1667K to 25000K.
+fn blackbody_chromaticity(temperature: f64) -> (f64, f64) {
+    let t = temperature.clamp(1667.0, 25000.0);
+
+    let x = if t <= 4000.0 {
+        -0.2661239e9 / t.powi(3) - 0.2343589e6 / t.powi(2) + 0.8776956e3 / t + 0.179910
+    } else {
+        -3.0258469e9 / t.powi(3) + 2.1070379e6 / t.powi(2) + 0.2226347e3 / t + 0.240390
+    };
+
+    let y = if t <= 2222.0 {
+        -1.1063814 * x.powi(3) - 1.34811020 * x.powi(2) + 2.18555832 * x - 0.20219683
+    } else if t <= 4000.0 {
+        -0.9549476 * x.powi(3) - 1.37418593 * x.powi(2) + 2.09137015 * x - 0.16748867
+    } else {
+        3.0817580 * x.powi(3) - 5.87338670 * x.powi(2) + 3.75112997 * x - 0.37001483
+    };
+
+    (x, y)
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::{Point3, Vector2, Vector3};
+
+    use crate::lights::sky::SkyLight;
+    use crate::lights::LightTrait;
+    use crate::renderer::Ray;
+    use crate::surface_interaction::{Interaction, SurfaceInteraction};
+
+    #[test]
+    fn test_sun_is_much_brighter_than_opposite_horizon() {
+        let sun_direction = Vector3::new(1.0, 1.0, 0.0).normalize();
+        let sky = SkyLight::new(sun_direction, 3.0, Vector3::repeat(0.2), 5800.0, 20.0, 1.0);
+        let interaction = SurfaceInteraction::new(
+            Point3::origin(),
+            Vector3::y(),
+            Vector3::y(),
+            Vector2::zeros(),
+            Vector3::x(),
+            Vector3::z(),
+            Vector3::x(),
+            Vector3::z(),
+            Vector3::zeros(),
+        );
+
+        // the first sample dimension picks the sun
+        let sample = sky.sample_irradiance(&interaction, vec![0.1, 0.5, 0.5]);
+        assert!(sample.wi.dot(&sun_direction) > 0.999);
+
+        let opposite_horizon = sky.environment_emitting(Ray {
+            point: Point3::origin(),
+            direction: Vector3::new(-1.0, 0.01, 0.0).normalize(),
+            t_max: f64::INFINITY,
+        });
+
+        assert!(opposite_horizon.y > 0.0);
+        assert!(sample.irradiance.y > 1000.0 * opposite_horizon.y);
+        let interaction = Interaction {
+            point: Point3::origin(),
+            normal: Vector3::y(),
+            uv: Vector2::zeros(),
+        };
+        assert_eq!(sky.pdf_incidence(&interaction, sample.wi), sample.pdf);
+    }
+}
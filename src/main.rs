@@ -6,6 +6,7 @@ extern crate core;
 use std::fs::File;
 use std::io::Read;
 use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::Receiver;
 use std::sync::{Arc, RwLock};
 use std::thread::JoinHandle;
@@ -18,28 +19,42 @@ use ggez::event::run;
 use ggez::graphics::{self, Color, DrawParam, ImageEncodingFormat, ImageFormat};
 use ggez::input::keyboard;
 use ggez::input::keyboard::KeyCode;
+use ggez::input::mouse::MouseButton;
 use ggez::winit::dpi::LogicalSize;
 use ggez::{event, GameError};
 use ggez::{Context, GameResult};
-use nalgebra::{Point2, Vector2};
+use image::{ImageResult, RgbImage};
+use nalgebra::{Point2, Point3, Vector2};
 use yaml_rust::YamlLoader;
 
+use accumulation::TemporalAccumulation;
 use denoise::denoise;
-use film::{Film, FilterMethod};
-use helpers::{yaml_array_into_point2, yaml_array_into_point3, yaml_into_u32};
+use film::{DenoiseAux, Film, FilterMethod, Output, OutputColorSpace, Pass, Pixel};
+use helpers::{
+    yaml_array_into_point2, yaml_array_into_point3, yaml_array_into_vector3, yaml_as_f64,
+    yaml_into_u32,
+};
 use objects::Object;
 use renderer::{DebugBuffer, ThreadMessage, DEBUG_BUFFER};
 
+use crate::bsdf::helpers::multiple_scattering;
 use crate::camera::Camera;
 use crate::helpers::Bounds;
-use crate::renderer::{debug_write_pixel_f64, Settings};
+use crate::light_path::{LightPathExpression, MAX_LIGHT_PATH_EXPRESSIONS};
+use crate::renderer::{
+    debug_write_pixel_f64, resolve_thread_count, DirectStrategy, Integrator, Settings,
+};
 use crate::sampler::SobolSampler;
+use crate::scene::Scene;
 
+mod accumulation;
 mod bsdf;
+mod bucket_queue;
 mod camera;
 mod denoise;
 mod film;
 mod helpers;
+mod light_path;
 mod lights;
 mod materials;
 mod normal;
@@ -54,6 +69,114 @@ mod tracer;
 #[derive(Parser, Debug)]
 struct Args {
     scene_folder: Option<String>,
+    /// Override all non-emissive materials with a neutral gray matte
+    #[clap(long)]
+    clay: bool,
+    /// Seed for all random decisions, renders with the same seed are identical
+    #[clap(long)]
+    seed: Option<u64>,
+    /// Only render the pixels in this rectangle of the image
+    #[clap(long, number_of_values = 4, value_names = &["X0", "Y0", "X1", "Y1"])]
+    region: Option<Vec<u32>>,
+    /// Continue from the pixels saved in this file if it exists, they are saved to it again
+    /// when the render is done
+    #[clap(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = &["navigate", "orbit", "turntable"]
+    )]
+    checkpoint: Option<String>,
+    /// Fly through the scene with WASD (Q and E for down and up) and by dragging the mouse
+    #[clap(long)]
+    navigate: bool,
+    /// Orbit the camera around its target by dragging the mouse, scroll to move in and out
+    #[clap(long, conflicts_with = "navigate")]
+    orbit: bool,
+    /// Number of render threads, overrides the render settings (0 uses every core)
+    #[clap(long)]
+    threads: Option<u32>,
+    /// Write the radiance and path length of every sample of this pixel to a CSV file
+    #[clap(long, number_of_values = 2, value_names = &["X", "Y"])]
+    debug_pixel: Option<Vec<u32>>,
+    /// Only render the object with this name, the other objects are hidden
+    #[clap(long, value_name = "NAME")]
+    isolate: Option<String>,
+    /// Objects hidden by --isolate still cast shadows and show up in reflections
+    #[clap(long)]
+    isolate_shadows: bool,
+    /// Render this many frames with the camera orbiting the target, saved as numbered images
+    #[clap(long, value_name = "FRAMES", conflicts_with_all = &["navigate", "orbit"])]
+    turntable: Option<u32>,
+    /// Render from the camera with this name in the cameras list of the render settings
+    #[clap(long, value_name = "NAME")]
+    camera: Option<String>,
+    /// Draw the edges of triangles in black over the render
+    #[clap(long)]
+    wireframe: bool,
+    /// Move the camera along its view direction until the whole scene fits in view
+    #[clap(long)]
+    auto_frame: bool,
+    /// Render one in every N pixels for a quick preview, gaps show the closest rendered pixel
+    #[clap(long, value_name = "N")]
+    preview_stride: Option<u32>,
+    /// Also write the image as linear `.exr` next to the tone mapped output image
+    #[clap(long)]
+    save_both: bool,
+}
+
+/// Distance the camera moves per update while a movement key is held
+const NAVIGATION_SPEED: f64 = 0.05;
+/// Degrees the camera turns per pixel the mouse is dragged
+const MOUSE_SENSITIVITY: f64 = 0.2;
+/// Factor the orbit radius shrinks by per step of the mouse wheel
+const DOLLY_SPEED: f64 = 0.9;
+/// Frames averaged in the preview while the camera stands still, after that the render is done
+const MAX_ACCUMULATED_FRAMES: u32 = 64;
+
+/// Everything needed to restart the render, when the camera is moved or for the next turntable
+/// frame
+struct RenderJob {
+    scene: Arc<Scene>,
+    settings: Settings,
+    sampler: SobolSampler,
+    camera: Arc<Camera>,
+}
+
+struct Navigation {
+    /// Orbit around the target instead of flying
+    orbit: bool,
+    mouse_delta: (f64, f64),
+    scroll: f64,
+}
+
+/// Progress of a turntable render, the camera orbits its starting position around the target
+struct Turntable {
+    start: Camera,
+    frames: u32,
+    frame: u32,
+}
+
+impl Turntable {
+    /// `{stem}_0001.png` for the first frame, the stem is taken from the output file
+    fn frame_file(&self, output_file: Option<&str>) -> String {
+        let stem = output_file
+            .and_then(|file| Path::new(file).file_stem())
+            .and_then(|stem| stem.to_str())
+            .unwrap_or("turntable");
+
+        format!("{stem}_{:04}.png", self.frame + 1)
+    }
+}
+
+/// Writes the rendered image, with `--save-both` also as linear `.exr`
+fn write_image(film: &RwLock<Film>, file: &str, save_both: bool) -> ImageResult<()> {
+    let film = film.read().unwrap();
+
+    if save_both {
+        film.write_display_and_linear(file)
+    } else {
+        film.write_image(file)
+    }
 }
 
 struct MainState {
@@ -62,21 +185,49 @@ struct MainState {
     threads: Vec<JoinHandle<()>>,
     receiver: Receiver<ThreadMessage>,
     running_threads: usize,
+    stop: Arc<AtomicBool>,
+    job: RenderJob,
+    navigation: Option<Navigation>,
+    turntable: Option<Turntable>,
     finished: bool,
     denoised: bool,
     should_denoise: bool,
+    /// The last denoised turntable frame, blended into the next with temporal denoising
+    previous_denoised: Option<RgbImage>,
+    lobe_aov_prefix: Option<String>,
+    sample_count_file: Option<String>,
+    output_file: Option<String>,
+    checkpoint_file: Option<String>,
+    save_both: bool,
+    outputs: Vec<Output>,
+    light_path_expressions: Vec<LightPathExpression>,
+    accumulation: TemporalAccumulation,
+    camera_changed: bool,
     debug_normals: bool,
     debug_albedo: bool,
     debug_buffer: bool,
+    debug_variance: bool,
 }
 
 impl MainState {
+    #[allow(clippy::too_many_arguments)]
     fn new(
         film: Arc<RwLock<Film>>,
         threads: Vec<JoinHandle<()>>,
         receiver: Receiver<ThreadMessage>,
         running_threads: usize,
+        stop: Arc<AtomicBool>,
+        job: RenderJob,
+        navigation: Option<Navigation>,
+        turntable: Option<Turntable>,
         should_denoise: bool,
+        lobe_aov_prefix: Option<String>,
+        sample_count_file: Option<String>,
+        output_file: Option<String>,
+        checkpoint_file: Option<String>,
+        save_both: bool,
+        outputs: Vec<Output>,
+        light_path_expressions: Vec<LightPathExpression>,
     ) -> GameResult<MainState> {
         Ok(MainState {
             redraw: true,
@@ -84,14 +235,62 @@ impl MainState {
             threads,
             receiver,
             running_threads,
+            stop,
+            job,
+            navigation,
+            turntable,
             finished: false,
             should_denoise,
+            previous_denoised: None,
+            lobe_aov_prefix,
+            sample_count_file,
+            output_file,
+            checkpoint_file,
+            save_both,
+            outputs,
+            light_path_expressions,
+            accumulation: TemporalAccumulation::new(),
+            camera_changed: true,
             denoised: false,
             debug_normals: false,
             debug_buffer: false,
             debug_albedo: false,
+            debug_variance: false,
         })
     }
+
+    /// Stops the running render and starts over with the new camera
+    fn restart_render(&mut self, camera: Camera) {
+        self.job.camera = Arc::new(camera);
+        self.camera_changed = true;
+        self.start_frame(0);
+    }
+
+    /// Stops the running render and renders the view again, with samples that differ per frame
+    fn start_frame(&mut self, frame: u32) {
+        self.stop.store(true, Ordering::Relaxed);
+        for thread in self.threads.drain(..) {
+            thread.join().unwrap();
+        }
+
+        self.film.write().unwrap().clear();
+
+        self.stop = Arc::new(AtomicBool::new(false));
+        let (threads, receiver) = renderer::render(
+            self.job.scene.clone(),
+            self.job.settings.clone(),
+            self.job.sampler.for_frame(frame),
+            self.job.camera.clone(),
+            self.stop.clone(),
+        );
+
+        self.running_threads = threads.len();
+        self.threads = threads;
+        self.receiver = receiver;
+        self.finished = false;
+        self.denoised = false;
+        self.redraw = true;
+    }
 }
 
 impl event::EventHandler for MainState {
@@ -106,8 +305,48 @@ impl event::EventHandler for MainState {
         }
 
         self.debug_normals = ctx.keyboard.is_key_pressed(KeyCode::N);
-        self.debug_albedo = ctx.keyboard.is_key_pressed(KeyCode::A);
-        self.debug_buffer = ctx.keyboard.is_key_pressed(KeyCode::D);
+        self.debug_variance = ctx.keyboard.is_key_pressed(KeyCode::V);
+
+        // Dragging orbits the camera, scrolling moves it in and out
+        if let Some(navigation) = self.navigation.as_mut().filter(|n| n.orbit) {
+            let (yaw, pitch) = navigation.mouse_delta;
+            let scroll = navigation.scroll;
+            navigation.mouse_delta = (0.0, 0.0);
+            navigation.scroll = 0.0;
+
+            if yaw != 0.0 || pitch != 0.0 || scroll != 0.0 {
+                let camera = self.job.camera.orbit(
+                    yaw * MOUSE_SENSITIVITY,
+                    pitch * MOUSE_SENSITIVITY,
+                    DOLLY_SPEED.powf(scroll),
+                );
+                self.restart_render(camera);
+            }
+        }
+
+        // A and D move the camera when navigating
+        if let Some(navigation) = self.navigation.as_mut().filter(|n| !n.orbit) {
+            let key = |key_code| ctx.keyboard.is_key_pressed(key_code) as i32 as f64;
+            let movement = nalgebra::Vector3::new(
+                key(KeyCode::D) - key(KeyCode::A),
+                key(KeyCode::E) - key(KeyCode::Q),
+                key(KeyCode::W) - key(KeyCode::S),
+            ) * NAVIGATION_SPEED;
+            let (yaw, pitch) = navigation.mouse_delta;
+            navigation.mouse_delta = (0.0, 0.0);
+
+            if movement != nalgebra::Vector3::zeros() || yaw != 0.0 || pitch != 0.0 {
+                let camera = self.job.camera.fly(
+                    movement,
+                    yaw * MOUSE_SENSITIVITY,
+                    pitch * MOUSE_SENSITIVITY,
+                );
+                self.restart_render(camera);
+            }
+        } else {
+            self.debug_albedo = ctx.keyboard.is_key_pressed(KeyCode::A);
+            self.debug_buffer = ctx.keyboard.is_key_pressed(KeyCode::D);
+        }
 
         let message = self.receiver.try_recv();
         if let Ok(message) = message {
@@ -116,17 +355,116 @@ impl event::EventHandler for MainState {
             }
         }
 
+        // While navigating, frames of a static camera are averaged into a cleaner preview
+        if self.running_threads == 0
+            && self.navigation.is_some()
+            && (self.camera_changed || self.accumulation.frames() < MAX_ACCUMULATED_FRAMES)
+        {
+            let frame: Vec<_> = self
+                .film
+                .read()
+                .unwrap()
+                .pixels
+                .iter()
+                .map(Pixel::radiance)
+                .collect();
+            self.accumulation.add_frame(&frame, self.camera_changed);
+            self.camera_changed = false;
+
+            if self.accumulation.frames() < MAX_ACCUMULATED_FRAMES {
+                self.start_frame(self.accumulation.frames());
+            }
+        }
+
         if self.running_threads == 0 && !self.finished {
             println!("All work is done.");
             self.finished = true;
 
-            if !self.denoised && self.should_denoise {
+            // the preview shows the average of the frames, write that instead of the last frame
+            if self.navigation.is_some() && self.accumulation.frames() > 0 {
+                let mut film = self.film.write().unwrap();
+                film.set_radiance(self.accumulation.radiance());
+            }
+
+            if let Some(prefix) = &self.lobe_aov_prefix {
+                if let Err(e) = self.film.read().unwrap().write_lobe_aovs(prefix) {
+                    println!("Error writing lobe AOVs: {e}");
+                }
+            }
+
+            if let Some(file) = &self.sample_count_file {
+                if let Err(e) = self.film.read().unwrap().write_sample_count(file) {
+                    println!("Error writing sample count: {e}");
+                }
+            }
+
+            if let Err(e) = self
+                .film
+                .read()
+                .unwrap()
+                .write_light_path_aovs(&self.light_path_expressions)
+            {
+                println!("Error writing light path AOVs: {e}");
+            }
+
+            if let Err(e) = renderer::write_debug_pixel_samples() {
+                println!("Error writing debug pixel samples: {e}");
+            }
+
+            // the denoised passes are written once the image is denoised, the others before
+            let (denoised_outputs, outputs): (Vec<_>, Vec<_>) = self
+                .outputs
+                .iter()
+                .cloned()
+                .partition(|output| output.pass == Pass::Denoised);
+
+            if let Err(e) = self.film.read().unwrap().write_outputs(&outputs) {
+                println!("Error writing outputs: {e}");
+            }
+
+            if let Some(checkpoint_file) = &self.checkpoint_file {
+                let film = self.film.read().unwrap();
+                if let Err(e) = film.write_checkpoint(Path::new(checkpoint_file)) {
+                    println!("Error writing checkpoint: {e}");
+                }
+            }
+
+            if !self.denoised && (self.should_denoise || !denoised_outputs.is_empty()) {
                 print!("Denoising...");
                 let mut film = self.film.write().unwrap();
-                denoise(&mut film);
+                denoise(&mut film, self.previous_denoised.as_ref());
+                if self.turntable.is_some() && film.temporal_denoise.is_some() {
+                    self.previous_denoised = Some(film.image_buffer.clone());
+                }
                 self.denoised = true;
                 println!(" done!");
             }
+
+            if let Err(e) = self.film.read().unwrap().write_outputs(&denoised_outputs) {
+                println!("Error writing denoised outputs: {e}");
+            }
+
+            if let Some(turntable) = &mut self.turntable {
+                let file = turntable.frame_file(self.output_file.as_deref());
+                if let Err(e) = write_image(&self.film, &file, self.save_both) {
+                    println!("Error writing turntable frame: {e}");
+                }
+
+                turntable.frame += 1;
+                if turntable.frame < turntable.frames {
+                    println!(
+                        "Turntable frame {}/{}",
+                        turntable.frame + 1,
+                        turntable.frames
+                    );
+                    let camera = turntable.start.turntable(turntable.frame, turntable.frames);
+                    self.restart_render(camera);
+                }
+            } else if let Some(output_file) = &self.output_file {
+                if let Err(e) = write_image(&self.film, output_file, self.save_both) {
+                    println!("Error writing image: {e}");
+                }
+            }
         }
 
         Ok(())
@@ -163,7 +501,12 @@ impl event::EventHandler for MainState {
                 output[i + 3] = 255;
                 i += 4;
             });
-        } else if self.debug_buffer {
+        } else if self.debug_buffer || self.debug_variance {
+            // the noise heatmap replaces what the render wrote to the debug buffer
+            if self.debug_variance {
+                film.write_variance_heatmap(&mut DEBUG_BUFFER.write().unwrap());
+            }
+
             let mut i = 0;
             DEBUG_BUFFER
                 .read()
@@ -177,6 +520,16 @@ impl event::EventHandler for MainState {
                     output[i + 3] = 255;
                     i += 4;
                 });
+        } else if !self.camera_changed && self.accumulation.frames() > 1 {
+            let mut i = 0;
+            for radiance in self.accumulation.radiance() {
+                let image::Rgb([r, g, b]) = film.radiance_to_rgb(*radiance);
+                output[i] = r;
+                output[i + 1] = g;
+                output[i + 2] = b;
+                output[i + 3] = 255;
+                i += 4;
+            }
         } else {
             let mut i = 0;
             for chunk in film.image_buffer.clone().into_raw().chunks(3) {
@@ -218,6 +571,32 @@ impl event::EventHandler for MainState {
         Ok(())
     }
 
+    fn mouse_motion_event(
+        &mut self,
+        ctx: &mut Context,
+        _x: f32,
+        _y: f32,
+        dx: f32,
+        dy: f32,
+    ) -> Result<(), GameError> {
+        if let Some(navigation) = &mut self.navigation {
+            if ctx.mouse.button_pressed(MouseButton::Left) {
+                navigation.mouse_delta.0 += dx as f64;
+                navigation.mouse_delta.1 -= dy as f64;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn mouse_wheel_event(&mut self, _ctx: &mut Context, _x: f32, y: f32) -> GameResult {
+        if let Some(navigation) = &mut self.navigation {
+            navigation.scroll += y as f64;
+        }
+
+        Ok(())
+    }
+
     fn resize_event(
         &mut self,
         ctx: &mut Context,
@@ -238,7 +617,13 @@ fn main() -> GameResult {
     // Load scene from yaml file
     let scene_folder_param = args.scene_folder.unwrap();
     let scene_folder = Path::new(&scene_folder_param);
-    let scene = scene::Scene::load_from_folder(scene_folder);
+    let mut scene = scene::Scene::load_from_folder(scene_folder).unwrap_or_else(|error| {
+        eprintln!("{}", error);
+        std::process::exit(1)
+    });
+    if let Some(name) = &args.isolate {
+        scene.isolate(name, args.isolate_shadows);
+    }
 
     // Get settings from yaml file
     let mut file = File::open(scene_folder.join("render_settings.yaml"))
@@ -248,15 +633,105 @@ fn main() -> GameResult {
         .expect("Unable to read file");
     let settings_yaml = &YamlLoader::load_from_str(&contents).unwrap()[0];
 
-    let settings = Settings {
-        thread_count: yaml_into_u32(&settings_yaml["renderer"]["threads"]),
+    if !settings_yaml["scene"]["background_color"].is_badvalue() {
+        scene.bg_color = yaml_array_into_vector3(&settings_yaml["scene"]["background_color"]);
+    }
+    scene.bg_lights_scene = settings_yaml["scene"]["background_lights_scene"]
+        .as_bool()
+        .unwrap_or(false);
+
+    let mut settings = Settings {
+        thread_count: resolve_thread_count(
+            args.threads.unwrap_or_else(|| {
+                settings_yaml["renderer"]["threads"].as_i64().unwrap_or(0) as u32
+            }),
+            std::thread::available_parallelism()
+                .map(|cores| cores.get() as u32)
+                .unwrap_or(1),
+        ),
+        integrator: settings_yaml["renderer"]["integrator"]
+            .as_str()
+            .map(|integrator| Integrator::from_str(integrator).expect("Unknown integrator"))
+            .unwrap_or_default(),
         depth_limit: yaml_into_u32(&settings_yaml["renderer"]["depth_limit"]),
+        rr_start_depth: settings_yaml["renderer"]["rr_start_depth"]
+            .as_i64()
+            .unwrap_or(3) as u32,
+        rr_min_probability: settings_yaml["renderer"]["rr_min_probability"]
+            .as_f64()
+            .unwrap_or(0.05),
+        light_samples: settings_yaml["renderer"]["light_samples"]
+            .as_i64()
+            .unwrap_or(1) as u32,
+        bsdf_samples: settings_yaml["renderer"]["bsdf_samples"]
+            .as_i64()
+            .unwrap_or(1) as u32,
+        direct_strategy: settings_yaml["renderer"]["direct_strategy"]
+            .as_str()
+            .map(|strategy| DirectStrategy::from_str(strategy).expect("Unknown direct strategy"))
+            .unwrap_or_default(),
+        regularize: settings_yaml["renderer"]["regularize"]
+            .as_bool()
+            .unwrap_or(false),
+        min_alpha: settings_yaml["renderer"]["min_alpha"]
+            .as_f64()
+            .unwrap_or(0.0),
+        specular_lod: settings_yaml["renderer"]["specular_lod"]
+            .as_f64()
+            .unwrap_or(0.0),
+        // known once the camera is built
+        pixel_spread_angle: 0.0,
+        first_bounce_clamp: settings_yaml["renderer"]["first_bounce_clamp"].as_f64(),
+        highlight_clamp: settings_yaml["renderer"]["highlight_clamp"].as_f64(),
+        max_gi_distance: settings_yaml["renderer"]["max_gi_distance"].as_f64(),
+        debug_throughput: settings_yaml["renderer"]["debug_throughput"]
+            .as_bool()
+            .unwrap_or(false),
+        wireframe: args.wireframe
+            || settings_yaml["renderer"]["wireframe"]
+                .as_bool()
+                .unwrap_or(false),
         max_samples: yaml_into_u32(&settings_yaml["sampler"]["max_samples"]),
+        clay: args.clay || settings_yaml["renderer"]["clay"].as_bool().unwrap_or(false),
+        light_path_expressions: settings_yaml["film"]["light_path_expressions"]
+            .as_hash()
+            .map(|expressions| {
+                expressions
+                    .iter()
+                    .map(|(name, expression)| {
+                        LightPathExpression::parse(
+                            name.as_str().unwrap(),
+                            expression.as_str().unwrap(),
+                        )
+                        .expect("Invalid light path expression")
+                    })
+                    .collect()
+            })
+            .unwrap_or_default(),
+        debug_pixel: args
+            .debug_pixel
+            .as_ref()
+            .map(|pixel| Point2::new(pixel[0], pixel[1])),
+        preview_stride: args.preview_stride.unwrap_or(1),
     };
+    assert!(
+        settings.light_path_expressions.len() <= MAX_LIGHT_PATH_EXPRESSIONS,
+        "At most {MAX_LIGHT_PATH_EXPRESSIONS} light path expressions are supported"
+    );
 
     let image_width = settings_yaml["film"]["image_width"].as_i64().unwrap() as u32;
     let image_height = settings_yaml["film"]["image_height"].as_i64().unwrap() as u32;
     let aspect_ratio = image_width as f64 / image_height as f64;
+    let film_memory = film::check_image_size(
+        Vector2::new(image_width, image_height),
+        yaml_as_f64(&settings_yaml["film"]["max_megapixels"])
+            .unwrap_or(film::DEFAULT_MAX_MEGAPIXELS),
+    )
+    .unwrap_or_else(|error| {
+        eprintln!("{}", error);
+        std::process::exit(1)
+    });
+    println!("Film memory: {} MB", film_memory / (1024 * 1024));
     let window_scale = settings_yaml["window"]["scale"].as_f64().unwrap_or(1.5) as f32;
     let crop_start = if !settings_yaml["film"]["crop"]["start"].is_badvalue() {
         yaml_array_into_point2(&settings_yaml["film"]["crop"]["start"])
@@ -272,34 +747,107 @@ fn main() -> GameResult {
         )
     };
     let should_denoise = settings_yaml["film"]["denoise"].as_bool().unwrap_or(false);
+    let lobe_aov_prefix = settings_yaml["film"]["lobe_aovs"]
+        .as_str()
+        .map(|prefix| prefix.to_string());
+    let sample_count_file = settings_yaml["film"]["sample_count"]
+        .as_str()
+        .map(|file| file.to_string());
+    let output_file = settings_yaml["film"]["output"]
+        .as_str()
+        .map(|file| file.to_string());
+    let outputs = Output::list_from_yaml(&settings_yaml["film"]["outputs"]);
+    let bucket_size = film::bucket_size(
+        settings_yaml["film"]["bucket_width"].as_i64(),
+        settings_yaml["film"]["bucket_height"].as_i64(),
+        Vector2::new(image_width, image_height),
+    )
+    .unwrap_or_else(|error| {
+        eprintln!("{}", error);
+        std::process::exit(1)
+    });
 
-    let film = Arc::new(RwLock::new(Film::new(
+    let mut film = Film::new(
         Vector2::new(image_width, image_height),
-        Vector2::new(
-            settings_yaml["film"]["bucket_width"].as_i64().unwrap() as u32,
-            settings_yaml["film"]["bucket_height"].as_i64().unwrap() as u32,
-        ),
+        bucket_size,
         Some(crop_start),
         Some(crop_end),
+        args.region.map(|region| Bounds {
+            p_min: Point2::new(region[0], region[1]),
+            p_max: Point2::new(region[2], region[3]),
+        }),
         FilterMethod::from_str(settings_yaml["film"]["filter_method"].as_str().unwrap()).unwrap(),
         settings_yaml["film"]["filter_radius"].as_f64().unwrap(),
-    )));
-
-    let camera = camera::Camera::new(
-        yaml_array_into_point3(&settings_yaml["camera"]["position"]),
-        yaml_array_into_point3(&settings_yaml["camera"]["target"]),
-        aspect_ratio,
-        settings_yaml["camera"]["fov"].as_f64().unwrap(),
-        settings_yaml["camera"]["aperture"].as_f64().unwrap(),
-        settings_yaml["camera"]["focal_distance"].as_f64(),
-        Bounds {
-            p_min: Point2::new(-1.0, -1.0),
-            p_max: Point2::new(1.0, 1.0),
-        },
-        film.clone(),
+        settings_yaml["film"]["outlier_rejection"].as_f64(),
+        settings_yaml["film"]["adaptive_clamp_k"].as_f64(),
     );
+    if let Some(color_space) = settings_yaml["film"]["color_space"].as_str() {
+        film.color_space =
+            OutputColorSpace::from_str(color_space, settings_yaml["film"]["gamma"].as_f64())
+                .expect("Unknown color space");
+    }
+    let camera_yaml = camera::select_camera_yaml(settings_yaml, args.camera.as_deref());
+    if ["shutter", "f_number", "iso"]
+        .iter()
+        .any(|key| !camera_yaml[*key].is_badvalue())
+    {
+        film.exposure = Film::exposure_from_triangle(
+            yaml_as_f64(&camera_yaml["shutter"]).unwrap_or(film::REFERENCE_SHUTTER),
+            yaml_as_f64(&camera_yaml["f_number"]).unwrap_or(film::REFERENCE_F_NUMBER),
+            yaml_as_f64(&camera_yaml["iso"]).unwrap_or(film::REFERENCE_ISO),
+        );
+    }
+    film.white_point = settings_yaml["film"]["white_point"].as_f64();
+    film.dither = settings_yaml["film"]["dither"].as_bool().unwrap_or(false);
+    film.prefilter_fireflies = settings_yaml["film"]["prefilter_fireflies"].as_f64();
+    film.temporal_denoise = settings_yaml["film"]["temporal_denoise"]
+        .as_f64()
+        .map(|weight| weight.clamp(0.0, 1.0));
+    film.filter_importance_sampling = settings_yaml["film"]["filter_importance_sampling"]
+        .as_bool()
+        .unwrap_or(false);
+    if let Some(denoise_aux) = settings_yaml["film"]["denoise_aux"].as_str() {
+        film.denoise_aux = DenoiseAux::from_str(denoise_aux).expect("Unknown denoise aux source");
+    }
+    if let Some(checkpoint_file) = args.checkpoint.as_deref().map(Path::new) {
+        if checkpoint_file.exists() {
+            film.load_checkpoint(checkpoint_file)
+                .unwrap_or_else(|error| {
+                    eprintln!("Could not load checkpoint: {}", error);
+                    std::process::exit(1)
+                });
+            println!("Continuing from checkpoint {}", checkpoint_file.display());
+        }
+    }
+    let film = Arc::new(RwLock::new(film));
+
+    let mut camera = Camera::from_yaml(camera_yaml, aspect_ratio, film.clone());
+    if args.auto_frame {
+        let bounds = scene.bounding_box();
+        if bounds.is_empty() {
+            println!("Nothing to frame, the scene only has planes");
+        } else {
+            let center = bounds.center();
+            camera = camera.framed(
+                Point3::new(center.x as f64, center.y as f64, center.z as f64),
+                bounds.size().length() as f64 / 2.0,
+            );
+            println!(
+                "Auto framed: position {:?}, target {:?}",
+                camera.position, camera.target
+            );
+        }
+    }
+
+    settings.pixel_spread_angle = camera.pixel_spread_angle();
 
-    let sampler = SobolSampler::new();
+    let seed = args.seed.unwrap_or_else(rand::random);
+    println!("Seed: {seed}");
+    println!("Threads: {}", settings.thread_count);
+    let mut sampler = SobolSampler::new(seed);
+    sampler.center_first_sample = settings_yaml["sampler"]["center_first_sample"]
+        .as_bool()
+        .unwrap_or(false);
 
     {
         let mut debug_buffer = DEBUG_BUFFER.write().unwrap();
@@ -309,8 +857,36 @@ fn main() -> GameResult {
     }
 
     // Start the render threads
+    multiple_scattering::precompute_albedo_table();
+
     println!("Start rendering...");
-    let (threads, receiver) = renderer::render(scene, settings, sampler, Arc::new(camera));
+    let scene = Arc::new(scene);
+    let camera = Arc::new(camera);
+    let stop = Arc::new(AtomicBool::new(false));
+    let (threads, receiver) = renderer::render(
+        scene.clone(),
+        settings.clone(),
+        sampler.clone(),
+        camera.clone(),
+        stop.clone(),
+    );
+    let light_path_expressions = settings.light_path_expressions.clone();
+    let turntable = args.turntable.map(|frames| Turntable {
+        start: (*camera).clone(),
+        frames: frames.max(1),
+        frame: 0,
+    });
+    let job = RenderJob {
+        scene,
+        settings,
+        sampler,
+        camera,
+    };
+    let navigation = (args.navigate || args.orbit).then_some(Navigation {
+        orbit: args.orbit,
+        mouse_delta: (0.0, 0.0),
+        scroll: 0.0,
+    });
 
     let cb = ggez::ContextBuilder::new("render_to_image", "ggez")
         .window_setup(WindowSetup {
@@ -339,7 +915,24 @@ fn main() -> GameResult {
 
     let (ctx, event_loop) = cb.build()?;
     let running_threads = threads.len();
-    let state = MainState::new(film, threads, receiver, running_threads, should_denoise)?;
+    let state = MainState::new(
+        film,
+        threads,
+        receiver,
+        running_threads,
+        stop,
+        job,
+        navigation,
+        turntable,
+        should_denoise,
+        lobe_aov_prefix,
+        sample_count_file,
+        output_file,
+        args.checkpoint,
+        args.save_both,
+        outputs,
+        light_path_expressions,
+    )?;
 
     event::run(ctx, event_loop, state)
 }
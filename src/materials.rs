@@ -3,13 +3,21 @@ use nalgebra::Vector3;
 
 use crate::materials::matte::MatteMaterial;
 use crate::materials::mirror::MirrorMaterial;
+use crate::materials::phong::PhongMaterial;
 use crate::materials::plastic::PlasticMaterial;
+use crate::materials::shadow_catcher::ShadowCatcherMaterial;
+use crate::materials::subsurface::{SubsurfaceMaterial, SubsurfaceMedium};
+use crate::materials::unlit::UnlitMaterial;
 use crate::surface_interaction::SurfaceInteraction;
 
 pub mod glass;
 pub mod matte;
 pub mod mirror;
+pub mod phong;
 pub mod plastic;
+pub mod shadow_catcher;
+pub mod subsurface;
+pub mod unlit;
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Material {
@@ -17,11 +25,24 @@ pub enum Material {
     Plastic(PlasticMaterial),
     Mirror(MirrorMaterial),
     Glass(GlassMaterial),
+    Phong(PhongMaterial),
+    Subsurface(SubsurfaceMaterial),
+    ShadowCatcher(ShadowCatcherMaterial),
+    Unlit(UnlitMaterial),
 }
 
 pub trait MaterialTrait {
     fn compute_scattering_functions(&self, si: &mut SurfaceInteraction);
     fn get_albedo(&self) -> Vector3<f64>;
+    /// Absorption coefficient of the medium enclosed by the surface
+    fn get_absorption(&self) -> Vector3<f64> {
+        Vector3::zeros()
+    }
+
+    /// Scattering medium inside the surface, light that is transmitted takes a random walk
+    fn get_subsurface(&self) -> Option<SubsurfaceMedium> {
+        None
+    }
 }
 
 impl MaterialTrait for Material {
@@ -31,6 +52,10 @@ impl MaterialTrait for Material {
             Material::Plastic(x) => x.compute_scattering_functions(si),
             Material::Mirror(x) => x.compute_scattering_functions(si),
             Material::Glass(x) => x.compute_scattering_functions(si),
+            Material::Phong(x) => x.compute_scattering_functions(si),
+            Material::Subsurface(x) => x.compute_scattering_functions(si),
+            Material::ShadowCatcher(x) => x.compute_scattering_functions(si),
+            Material::Unlit(x) => x.compute_scattering_functions(si),
         }
     }
 
@@ -40,6 +65,36 @@ impl MaterialTrait for Material {
             Material::Plastic(x) => x.get_albedo(),
             Material::Mirror(x) => x.get_albedo(),
             Material::Glass(x) => x.get_albedo(),
+            Material::Phong(x) => x.get_albedo(),
+            Material::Subsurface(x) => x.get_albedo(),
+            Material::ShadowCatcher(x) => x.get_albedo(),
+            Material::Unlit(x) => x.get_albedo(),
+        }
+    }
+
+    fn get_absorption(&self) -> Vector3<f64> {
+        match self {
+            Material::Matte(x) => x.get_absorption(),
+            Material::Plastic(x) => x.get_absorption(),
+            Material::Mirror(x) => x.get_absorption(),
+            Material::Glass(x) => x.get_absorption(),
+            Material::Phong(x) => x.get_absorption(),
+            Material::Subsurface(x) => x.get_absorption(),
+            Material::ShadowCatcher(x) => x.get_absorption(),
+            Material::Unlit(x) => x.get_absorption(),
+        }
+    }
+
+    fn get_subsurface(&self) -> Option<SubsurfaceMedium> {
+        match self {
+            Material::Matte(x) => x.get_subsurface(),
+            Material::Plastic(x) => x.get_subsurface(),
+            Material::Mirror(x) => x.get_subsurface(),
+            Material::Glass(x) => x.get_subsurface(),
+            Material::Phong(x) => x.get_subsurface(),
+            Material::Subsurface(x) => x.get_subsurface(),
+            Material::ShadowCatcher(x) => x.get_subsurface(),
+            Material::Unlit(x) => x.get_subsurface(),
         }
     }
 }
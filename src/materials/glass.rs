@@ -1,6 +1,6 @@
 use nalgebra::Vector3;
 
-use crate::bsdf::helpers::fresnel::{Fresnel, FresnelNoop};
+use crate::bsdf::helpers::fresnel::{Fresnel, FresnelModel, FresnelNoop};
 use crate::bsdf::specular_reflection::SpecularReflection;
 use crate::bsdf::specular_transmission::{SpecularTransmission, TransportMode};
 use crate::bsdf::{Bsdf, Bxdf};
@@ -10,11 +10,26 @@ use crate::surface_interaction::SurfaceInteraction;
 #[derive(Debug, Clone, PartialEq)]
 pub struct GlassMaterial {
     refraction_color: Vector3<f64>,
+    absorption: Vector3<f64>,
+    fresnel_model: FresnelModel,
 }
 
 impl GlassMaterial {
-    pub fn new(refraction_color: Vector3<f64>) -> Self {
-        GlassMaterial { refraction_color }
+    /// The absorption coefficient (per unit of distance) attenuates light traveling inside the
+    /// glass, so thick parts of the object are darker and more saturated than thin edges.
+    pub fn new(refraction_color: Vector3<f64>, absorption: Vector3<f64>) -> Self {
+        GlassMaterial {
+            refraction_color,
+            absorption,
+            fresnel_model: FresnelModel::default(),
+        }
+    }
+
+    /// Evaluates the Fresnel term of the surface with `fresnel_model` instead of the full
+    /// dielectric equations.
+    pub fn with_fresnel_model(mut self, fresnel_model: FresnelModel) -> Self {
+        self.fresnel_model = fresnel_model;
+        self
     }
 }
 
@@ -26,6 +41,7 @@ impl MaterialTrait for GlassMaterial {
             self.refraction_color,
             1.0,
             1.5,
+            self.fresnel_model,
             TransportMode::Other,
         )));
 
@@ -35,4 +51,8 @@ impl MaterialTrait for GlassMaterial {
     fn get_albedo(&self) -> Vector3<f64> {
         Vector3::zeros()
     }
+
+    fn get_absorption(&self) -> Vector3<f64> {
+        self.absorption
+    }
 }
@@ -0,0 +1,50 @@
+use nalgebra::Vector3;
+use num_traits::Zero;
+
+use crate::bsdf::blinn_phong::BlinnPhong;
+use crate::bsdf::lambertian::Lambertian;
+use crate::bsdf::{Bsdf, Bxdf};
+use crate::materials::MaterialTrait;
+use crate::surface_interaction::SurfaceInteraction;
+
+/// Diffuse and Blinn-Phong specular, for models that specify their materials with a Phong
+/// exponent.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PhongMaterial {
+    diffuse: Vector3<f64>,
+    specular: Vector3<f64>,
+    exponent: f64,
+}
+
+impl PhongMaterial {
+    pub fn new(diffuse: Vector3<f64>, specular: Vector3<f64>, exponent: f64) -> Self {
+        PhongMaterial {
+            diffuse,
+            specular,
+            exponent,
+        }
+    }
+}
+
+impl MaterialTrait for PhongMaterial {
+    fn compute_scattering_functions(&self, si: &mut SurfaceInteraction) {
+        let mut bsdf = Bsdf::new(*si, None);
+
+        if !self.diffuse.is_zero() {
+            bsdf.add(Bxdf::Lambertian(Lambertian::new(self.diffuse)));
+        }
+
+        if !self.specular.is_zero() {
+            bsdf.add(Bxdf::BlinnPhong(BlinnPhong::new(
+                self.specular,
+                self.exponent,
+            )));
+        }
+
+        si.bsdf = Some(bsdf);
+    }
+
+    fn get_albedo(&self) -> Vector3<f64> {
+        self.diffuse
+    }
+}
@@ -1,57 +1,142 @@
 use nalgebra::Vector3;
 use num_traits::Zero;
 
-use crate::bsdf::helpers::fresnel::FresnelDielectric;
+use crate::bsdf::helpers::fresnel::FresnelModel;
 use crate::bsdf::helpers::microfacet_distribution::{
-    MicrofacetDistribution, TrowbridgeReitzDistribution,
+    MicrofacetDistribution, RoughnessRemap, TrowbridgeReitzDistribution,
 };
 use crate::bsdf::lambertian::Lambertian;
 use crate::bsdf::microfacet_reflection::MicrofacetReflection;
 use crate::bsdf::specular_reflection::SpecularReflection;
+use crate::bsdf::ward::Ward;
 use crate::bsdf::{Bsdf, Bxdf};
 use crate::materials::MaterialTrait;
 use crate::surface_interaction::SurfaceInteraction;
 
+/// Up to this roughness the glossy layer is a perfect mirror, a microfacet lobe that narrow only
+/// adds noise.
+const MIRROR_ROUGHNESS: f64 = 1e-3;
+
+/// Shading model of the glossy layer.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub enum SpecularModel {
+    /// Trowbridge-Reitz microfacets over a lambertian base
+    #[default]
+    Ggx,
+    /// The Ward model, its own diffuse and specular lobes, for comparing with references that
+    /// use it
+    Ward,
+}
+
+impl SpecularModel {
+    pub fn from_str(str: &str) -> Option<SpecularModel> {
+        match str {
+            "ggx" => Some(SpecularModel::Ggx),
+            "ward" => Some(SpecularModel::Ward),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct PlasticMaterial {
     diffuse: Vector3<f64>,
     specular: Vector3<f64>,
     roughness: f64,
+    roughness_remap: RoughnessRemap,
+    // alpha along the tangent and the bitangent, used as is instead of the remapped roughness
+    alpha: Option<(f64, f64)>,
+    fresnel_model: FresnelModel,
+    model: SpecularModel,
+    // add the light that scatters between microfacets more than once, off by default
+    energy_compensation: bool,
+    // use the vertex colors of the mesh as diffuse color where it has them
+    vertex_colors: bool,
 }
 
 impl PlasticMaterial {
-    pub fn new(diffuse: Vector3<f64>, specular: Vector3<f64>, roughness: f64) -> Self {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        diffuse: Vector3<f64>,
+        specular: Vector3<f64>,
+        roughness: f64,
+        roughness_remap: RoughnessRemap,
+        fresnel_model: FresnelModel,
+        model: SpecularModel,
+        energy_compensation: bool,
+        vertex_colors: bool,
+    ) -> Self {
         PlasticMaterial {
             diffuse,
             specular,
             roughness,
+            roughness_remap,
+            alpha: None,
+            fresnel_model,
+            model,
+            energy_compensation,
+            vertex_colors,
         }
     }
+
+    /// Anisotropic highlight, stretched along the tangent when `alpha_x` is larger than
+    /// `alpha_y`.
+    pub fn with_alpha(mut self, alpha_x: f64, alpha_y: f64) -> Self {
+        self.alpha = Some((alpha_x, alpha_y));
+        self
+    }
+
+    fn alpha(&self) -> (f64, f64) {
+        self.alpha.unwrap_or_else(|| {
+            let alpha = self.roughness_remap.to_alpha(self.roughness);
+            (alpha, alpha)
+        })
+    }
 }
 
 impl MaterialTrait for PlasticMaterial {
     fn compute_scattering_functions(&self, si: &mut SurfaceInteraction) {
         let mut bsdf = Bsdf::new(*si, None);
 
-        if !self.diffuse.is_zero() {
-            bsdf.add(Bxdf::Lambertian(Lambertian::new(self.diffuse)));
+        let diffuse = match si.vertex_color {
+            Some(vertex_color) if self.vertex_colors => vertex_color,
+            _ => self.diffuse,
+        };
+
+        let (alpha_x, alpha_y) = self.alpha();
+
+        if self.model == SpecularModel::Ward {
+            bsdf.add(Bxdf::Ward(Ward::new(
+                diffuse,
+                self.specular,
+                alpha_x,
+                alpha_y,
+            )));
+            si.bsdf = Some(bsdf);
+            return;
+        }
+
+        if !diffuse.is_zero() {
+            bsdf.add(Bxdf::Lambertian(Lambertian::new(diffuse)));
         }
 
         // todo: bug in microfacets, creates spots
         if !self.specular.is_zero() {
-            let fresnel = FresnelDielectric::new(1.0, 1.5);
-            let roughness = TrowbridgeReitzDistribution::roughness_to_alpha(self.roughness);
-            let distribution = TrowbridgeReitzDistribution::new(roughness, roughness, true);
-            //
-            // bsdf.add(BXDF::SpecularReflection(SpecularReflection::new(
-            //     self.specular,
-            //     fresnel,
-            // )));
-            bsdf.add(Bxdf::MicrofacetReflection(MicrofacetReflection::new(
-                self.specular,
-                distribution,
-                fresnel,
-            )));
+            let fresnel = self.fresnel_model.dielectric(1.0, 1.5);
+            if alpha_x.max(alpha_y) <= self.roughness_remap.to_alpha(MIRROR_ROUGHNESS) {
+                bsdf.add(Bxdf::SpecularReflection(SpecularReflection::new(
+                    self.specular,
+                    fresnel,
+                )));
+            } else {
+                let distribution = TrowbridgeReitzDistribution::new(alpha_x, alpha_y, true);
+                bsdf.add(Bxdf::MicrofacetReflection(MicrofacetReflection::new(
+                    self.specular,
+                    distribution,
+                    fresnel,
+                    self.energy_compensation,
+                )));
+            }
         }
 
         si.bsdf = Some(bsdf);
@@ -61,3 +146,54 @@ impl MaterialTrait for PlasticMaterial {
         self.diffuse
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::{Point3, Vector2, Vector3};
+
+    use crate::bsdf::helpers::fresnel::FresnelModel;
+    use crate::bsdf::helpers::microfacet_distribution::RoughnessRemap;
+    use crate::bsdf::BXDFTYPES;
+    use crate::materials::plastic::{PlasticMaterial, SpecularModel};
+    use crate::materials::MaterialTrait;
+    use crate::surface_interaction::SurfaceInteraction;
+
+    #[test]
+    fn test_nearly_smooth_plastic_reflects_like_a_mirror() {
+        let sample_specular = |roughness| {
+            let mut si = SurfaceInteraction::new(
+                Point3::origin(),
+                Vector3::z(),
+                Vector3::new(1.0, 0.0, 1.0).normalize(),
+                Vector2::zeros(),
+                Vector3::x(),
+                Vector3::y(),
+                Vector3::x(),
+                Vector3::y(),
+                Vector3::zeros(),
+            );
+            PlasticMaterial::new(
+                Vector3::repeat(0.5),
+                Vector3::repeat(1.0),
+                roughness,
+                RoughnessRemap::default(),
+                FresnelModel::default(),
+                SpecularModel::Ggx,
+                false,
+                false,
+            )
+            .compute_scattering_functions(&mut si);
+
+            si.bsdf.unwrap().sample_f(
+                Vector3::new(1.0, 0.0, 1.0).normalize(),
+                Point3::new(0.5, 0.5, 0.5),
+                BXDFTYPES::SPECULAR,
+            )
+        };
+
+        let smooth = sample_specular(1e-4);
+        assert!(smooth.sampled_flags.contains(BXDFTYPES::SPECULAR));
+        assert_eq!(Vector3::new(-1.0, 0.0, 1.0).normalize(), smooth.wi);
+        assert_eq!(0.0, sample_specular(1e-2).pdf);
+    }
+}
@@ -0,0 +1,33 @@
+use nalgebra::Vector3;
+
+use crate::bsdf::lambertian::Lambertian;
+use crate::bsdf::{Bsdf, Bxdf};
+use crate::materials::MaterialTrait;
+use crate::surface_interaction::SurfaceInteraction;
+
+/// Catches the shadows of the scene to composite it over a background plate. Camera rays pass
+/// through the catcher, the fraction of the light that is blocked where they cross it ends up
+/// in the alpha channel. For other rays it is a diffuse surface so objects still get its bounce
+/// light.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShadowCatcherMaterial {
+    albedo: Vector3<f64>,
+}
+
+impl ShadowCatcherMaterial {
+    pub fn new(albedo: Vector3<f64>) -> Self {
+        ShadowCatcherMaterial { albedo }
+    }
+}
+
+impl MaterialTrait for ShadowCatcherMaterial {
+    fn compute_scattering_functions(&self, si: &mut SurfaceInteraction) {
+        let mut bsdf = Bsdf::new(*si, None);
+        bsdf.add(Bxdf::Lambertian(Lambertian::new(self.albedo)));
+        si.bsdf = Some(bsdf);
+    }
+
+    fn get_albedo(&self) -> Vector3<f64> {
+        self.albedo
+    }
+}
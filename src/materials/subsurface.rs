@@ -0,0 +1,54 @@
+use nalgebra::Vector3;
+
+use crate::bsdf::lambertian_transmission::LambertianTransmission;
+use crate::bsdf::{Bsdf, Bxdf};
+use crate::materials::MaterialTrait;
+use crate::surface_interaction::SurfaceInteraction;
+
+/// The inside of a subsurface object, light travels `mean_free_path` on average between
+/// scattering events and survives each event with probability `albedo`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SubsurfaceMedium {
+    pub albedo: f64,
+    pub mean_free_path: f64,
+}
+
+/// Translucent material for wax, skin or marble. Light enters through the surface and takes a
+/// random walk through the object until it leaves the surface again somewhere else.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SubsurfaceMaterial {
+    color: Vector3<f64>,
+    medium: SubsurfaceMedium,
+}
+
+impl SubsurfaceMaterial {
+    pub fn new(color: Vector3<f64>, albedo: f64, mean_free_path: f64) -> Self {
+        SubsurfaceMaterial {
+            color,
+            medium: SubsurfaceMedium {
+                albedo: albedo.clamp(0.0, 1.0),
+                mean_free_path: mean_free_path.max(1e-6),
+            },
+        }
+    }
+}
+
+impl MaterialTrait for SubsurfaceMaterial {
+    fn compute_scattering_functions(&self, si: &mut SurfaceInteraction) {
+        let mut bsdf = Bsdf::new(*si, None);
+
+        bsdf.add(Bxdf::LambertianTransmission(LambertianTransmission::new(
+            self.color,
+        )));
+
+        si.bsdf = Some(bsdf);
+    }
+
+    fn get_albedo(&self) -> Vector3<f64> {
+        self.color
+    }
+
+    fn get_subsurface(&self) -> Option<SubsurfaceMedium> {
+        Some(self.medium)
+    }
+}
@@ -0,0 +1,25 @@
+use nalgebra::Vector3;
+
+use crate::materials::MaterialTrait;
+use crate::surface_interaction::SurfaceInteraction;
+
+/// A constant color that ignores the lighting, for skydomes and reference cards. Paths that hit
+/// it see the color and end there, it has no BSDF and does not light the scene.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnlitMaterial {
+    color: Vector3<f64>,
+}
+
+impl UnlitMaterial {
+    pub fn new(color: Vector3<f64>) -> Self {
+        UnlitMaterial { color }
+    }
+}
+
+impl MaterialTrait for UnlitMaterial {
+    fn compute_scattering_functions(&self, _si: &mut SurfaceInteraction) {}
+
+    fn get_albedo(&self) -> Vector3<f64> {
+        self.color
+    }
+}
@@ -8,18 +8,26 @@ use nalgebra::{Point3, Vector3};
 use crate::lights::area::AreaLight;
 use crate::lights::Light;
 use crate::materials::Material;
+use crate::objects::cone::Cone;
+use crate::objects::instance::Instance;
 use crate::objects::plane::Plane;
+use crate::objects::quad::Quad;
 use crate::objects::rectangle::Rectangle;
 //use crate::objects::cube::Cube;
 //use crate::objects::rectangle::Rectangle;
 //use crate::objects::sphere::Sphere;
 use crate::objects::triangle::Triangle;
+use crate::objects::triangle_mesh::TriangleMesh;
 use crate::renderer;
 use crate::surface_interaction::{Interaction, SurfaceInteraction};
 
 pub mod triangle;
+pub mod triangle_mesh;
 //pub mod sphere;
+pub mod cone;
+pub mod instance;
 pub mod plane;
+pub mod quad;
 pub mod rectangle;
 //pub mod cube;
 //pub mod rectangle;
@@ -28,8 +36,12 @@ pub mod rectangle;
 pub enum Object {
     //Sphere(Sphere),
     Triangle(Triangle),
+    TriangleMesh(TriangleMesh),
     Plane(Plane),
     Rectangle(Rectangle),
+    Quad(Quad),
+    Cone(Cone),
+    Instance(Instance),
     //Cube(Cube),
 }
 
@@ -40,6 +52,36 @@ pub trait ObjectTrait {
     fn sample_point(&self, sample: Vec<f64>) -> Interaction;
     fn pdf(&self, interaction: &Interaction, wi: Vector3<f64>) -> f64;
     fn area(&self) -> f64;
+
+    /// Whether the ray hits the object closer than `max_dist`, for shadow rays. Shapes with a
+    /// cheaper test than building the full surface interaction override this.
+    fn test_intersect_any(&self, ray: renderer::Ray, max_dist: f64) -> bool {
+        self.test_intersect(ray)
+            .is_some_and(|(distance, _)| distance < max_dist)
+    }
+
+    /// Hidden objects are skipped by camera rays but still cast shadows and show up in
+    /// reflections.
+    fn is_visible_to_camera(&self) -> bool {
+        true
+    }
+
+    /// Name of the asset the object was loaded from, used to isolate it.
+    fn get_name(&self) -> Option<&str> {
+        None
+    }
+
+    /// Thin objects (leaves, paper) are shaded the same from both sides instead of only on the
+    /// side their normals face.
+    fn is_double_sided(&self) -> bool {
+        false
+    }
+
+    /// Objects hidden from reflections are skipped by bounce rays, camera and shadow rays still
+    /// hit them. Used for shadow catchers that should not show up in mirrors.
+    fn is_visible_in_reflections(&self) -> bool {
+        true
+    }
 }
 
 impl ObjectTrait for ArcObject {
@@ -47,8 +89,12 @@ impl ObjectTrait for ArcObject {
         match self.0.as_ref() {
             //Object::Sphere(x) => x.get_materials(),
             Object::Triangle(x) => x.get_materials(),
+            Object::TriangleMesh(x) => x.get_materials(),
             Object::Plane(x) => x.get_materials(),
             Object::Rectangle(x) => x.get_materials(),
+            Object::Quad(x) => x.get_materials(),
+            Object::Cone(x) => x.get_materials(),
+            Object::Instance(x) => x.get_materials(),
             //Object::Cube(x) => x.get_materials(),
         }
     }
@@ -57,8 +103,12 @@ impl ObjectTrait for ArcObject {
         match self.0.as_ref() {
             //Object::Sphere(x) => x.test_intersect(ray),
             Object::Triangle(x) => x.get_light(),
+            Object::TriangleMesh(x) => x.get_light(),
             Object::Plane(x) => x.get_light(),
             Object::Rectangle(x) => x.get_light(),
+            Object::Quad(x) => x.get_light(),
+            Object::Cone(x) => x.get_light(),
+            Object::Instance(x) => x.get_light(),
             //Object::Cube(x) => x.test_intersect(ray),
         }
     }
@@ -67,18 +117,38 @@ impl ObjectTrait for ArcObject {
         match self.0.as_ref() {
             //Object::Sphere(x) => x.test_intersect(ray),
             Object::Triangle(x) => x.test_intersect(ray),
+            Object::TriangleMesh(x) => x.test_intersect(ray),
             Object::Plane(x) => x.test_intersect(ray),
             Object::Rectangle(x) => x.test_intersect(ray),
+            Object::Quad(x) => x.test_intersect(ray),
+            Object::Cone(x) => x.test_intersect(ray),
+            Object::Instance(x) => x.test_intersect(ray),
             //Object::Cube(x) => x.test_intersect(ray),
         }
     }
 
+    fn test_intersect_any(&self, ray: renderer::Ray, max_dist: f64) -> bool {
+        match self.0.as_ref() {
+            Object::Triangle(x) => x.test_intersect_any(ray, max_dist),
+            Object::TriangleMesh(x) => x.test_intersect_any(ray, max_dist),
+            Object::Plane(x) => x.test_intersect_any(ray, max_dist),
+            Object::Rectangle(x) => x.test_intersect_any(ray, max_dist),
+            Object::Quad(x) => x.test_intersect_any(ray, max_dist),
+            Object::Cone(x) => x.test_intersect_any(ray, max_dist),
+            Object::Instance(x) => x.test_intersect_any(ray, max_dist),
+        }
+    }
+
     fn sample_point(&self, sample: Vec<f64>) -> Interaction {
         match self.0.as_ref() {
             //Object::Sphere(x) => x.test_intersect(ray),
             Object::Triangle(x) => x.sample_point(sample),
+            Object::TriangleMesh(x) => x.sample_point(sample),
             Object::Plane(x) => x.sample_point(sample),
             Object::Rectangle(x) => x.sample_point(sample),
+            Object::Quad(x) => x.sample_point(sample),
+            Object::Cone(x) => x.sample_point(sample),
+            Object::Instance(x) => x.sample_point(sample),
             //Object::Cube(x) => x.test_intersect(ray),
         }
     }
@@ -87,8 +157,12 @@ impl ObjectTrait for ArcObject {
         match self.0.as_ref() {
             //Object::Sphere(x) => x.test_intersect(ray),
             Object::Triangle(x) => x.pdf(interaction, wi),
+            Object::TriangleMesh(x) => x.pdf(interaction, wi),
             Object::Plane(x) => x.pdf(interaction, wi),
             Object::Rectangle(x) => x.pdf(interaction, wi),
+            Object::Quad(x) => x.pdf(interaction, wi),
+            Object::Cone(x) => x.pdf(interaction, wi),
+            Object::Instance(x) => x.pdf(interaction, wi),
             //Object::Cube(x) => x.test_intersect(ray),
         }
     }
@@ -97,11 +171,65 @@ impl ObjectTrait for ArcObject {
         match self.0.as_ref() {
             //Object::Sphere(x) => x.test_intersect(ray),
             Object::Triangle(x) => x.area(),
+            Object::TriangleMesh(x) => x.area(),
             Object::Plane(x) => x.area(),
             Object::Rectangle(x) => x.area(),
+            Object::Quad(x) => x.area(),
+            Object::Cone(x) => x.area(),
+            Object::Instance(x) => x.area(),
+            //Object::Cube(x) => x.test_intersect(ray),
+        }
+    }
+
+    fn is_visible_to_camera(&self) -> bool {
+        match self.0.as_ref() {
+            //Object::Sphere(x) => x.test_intersect(ray),
+            Object::Triangle(x) => x.is_visible_to_camera(),
+            Object::TriangleMesh(x) => x.is_visible_to_camera(),
+            Object::Plane(x) => x.is_visible_to_camera(),
+            Object::Rectangle(x) => x.is_visible_to_camera(),
+            Object::Quad(x) => x.is_visible_to_camera(),
+            Object::Cone(x) => x.is_visible_to_camera(),
+            Object::Instance(x) => x.is_visible_to_camera(),
             //Object::Cube(x) => x.test_intersect(ray),
         }
     }
+
+    fn get_name(&self) -> Option<&str> {
+        match self.0.as_ref() {
+            Object::Triangle(x) => x.get_name(),
+            Object::TriangleMesh(x) => x.get_name(),
+            Object::Plane(x) => x.get_name(),
+            Object::Rectangle(x) => x.get_name(),
+            Object::Quad(x) => x.get_name(),
+            Object::Cone(x) => x.get_name(),
+            Object::Instance(x) => x.get_name(),
+        }
+    }
+
+    fn is_double_sided(&self) -> bool {
+        match self.0.as_ref() {
+            Object::Triangle(x) => x.is_double_sided(),
+            Object::TriangleMesh(x) => x.is_double_sided(),
+            Object::Plane(x) => x.is_double_sided(),
+            Object::Rectangle(x) => x.is_double_sided(),
+            Object::Quad(x) => x.is_double_sided(),
+            Object::Cone(x) => x.is_double_sided(),
+            Object::Instance(x) => x.is_double_sided(),
+        }
+    }
+
+    fn is_visible_in_reflections(&self) -> bool {
+        match self.0.as_ref() {
+            Object::Triangle(x) => x.is_visible_in_reflections(),
+            Object::TriangleMesh(x) => x.is_visible_in_reflections(),
+            Object::Plane(x) => x.is_visible_in_reflections(),
+            Object::Rectangle(x) => x.is_visible_in_reflections(),
+            Object::Quad(x) => x.is_visible_in_reflections(),
+            Object::Cone(x) => x.is_visible_in_reflections(),
+            Object::Instance(x) => x.is_visible_in_reflections(),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -112,8 +240,12 @@ impl Bounded for ArcObject {
         match self.0.as_ref() {
             //Object::Sphere(x) => x.aabb(),
             Object::Triangle(x) => x.aabb(),
+            Object::TriangleMesh(x) => x.aabb(),
             Object::Plane(x) => x.aabb(),
             Object::Rectangle(x) => x.aabb(),
+            Object::Quad(x) => x.aabb(),
+            Object::Cone(x) => x.aabb(),
+            Object::Instance(x) => x.aabb(),
             //Object::Cube(x) => x.aabb(),
         }
     }
@@ -124,8 +256,12 @@ impl BHShape for ArcObject {
         match Arc::get_mut(&mut self.0).unwrap() {
             //Object::Sphere(x) => x.set_bh_node_index(index),
             Object::Triangle(x) => x.set_bh_node_index(index),
+            Object::TriangleMesh(x) => x.set_bh_node_index(index),
             Object::Plane(x) => x.set_bh_node_index(index),
             Object::Rectangle(x) => x.set_bh_node_index(index),
+            Object::Quad(x) => x.set_bh_node_index(index),
+            Object::Cone(x) => x.set_bh_node_index(index),
+            Object::Instance(x) => x.set_bh_node_index(index),
             //Object::Cube(x) => x.set_bh_node_index(index),
         }
     }
@@ -134,8 +270,12 @@ impl BHShape for ArcObject {
         match self.0.as_ref() {
             //Object::Sphere(x) => x.bh_node_index(),
             Object::Triangle(x) => x.bh_node_index(),
+            Object::TriangleMesh(x) => x.bh_node_index(),
             Object::Plane(x) => x.bh_node_index(),
             Object::Rectangle(x) => x.bh_node_index(),
+            Object::Quad(x) => x.bh_node_index(),
+            Object::Cone(x) => x.bh_node_index(),
+            Object::Instance(x) => x.bh_node_index(),
             //Object::Cube(x) => x.bh_node_index(),
         }
     }
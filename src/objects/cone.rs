@@ -0,0 +1,325 @@
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+use bvh::aabb::{Bounded, AABB};
+use bvh::bounding_hierarchy::BHShape;
+use nalgebra::{Matrix4, Point3, Vector2, Vector3};
+
+use crate::lights::Light;
+use crate::materials::Material;
+use crate::objects::ObjectTrait;
+use crate::renderer;
+use crate::renderer::Ray;
+use crate::surface_interaction::{Interaction, SurfaceInteraction};
+
+/// An open cone (without a base disk) in object space the base circle lies in the xy plane
+/// around the origin and the apex is at (0, 0, height). The transform places the cone in the
+/// world, phi_max (in radians) cuts off part of the cone around its axis.
+#[derive(Debug, Clone)]
+pub struct Cone {
+    pub radius: f64,
+    pub height: f64,
+    pub phi_max: f64,
+    pub transform: Matrix4<f64>,
+    pub materials: Vec<Material>,
+    pub node_index: usize,
+    pub visible_to_camera: bool,
+    pub double_sided: bool,
+    /// Name from the scene file, objects can be isolated by name
+    pub name: Option<String>,
+    world_to_object: Matrix4<f64>,
+}
+
+impl Cone {
+    pub fn new(
+        radius: f64,
+        height: f64,
+        phi_max: f64,
+        transform: Matrix4<f64>,
+        materials: Vec<Material>,
+    ) -> Self {
+        Cone {
+            radius,
+            height,
+            phi_max: phi_max.clamp(0.0, 360.0) * (PI / 180.0),
+            transform,
+            materials,
+            node_index: 0,
+            visible_to_camera: true,
+            double_sided: false,
+            name: None,
+            world_to_object: transform
+                .try_inverse()
+                .expect("Cone transform is not invertible"),
+        }
+    }
+
+    fn surface_interaction(&self, p: Point3<f64>, wo: Vector3<f64>) -> SurfaceInteraction {
+        let phi = self.phi(p);
+        let v = p.z / self.height;
+
+        let dpdu = Vector3::new(-self.phi_max * p.y, self.phi_max * p.x, 0.0);
+        let dpdv = Vector3::new(-p.x / (1.0 - v), -p.y / (1.0 - v), self.height);
+
+        let dpdu = self.transform.transform_vector(&dpdu);
+        let dpdv = self.transform.transform_vector(&dpdv);
+        let normal = dpdu.cross(&dpdv).normalize();
+
+        let ss = dpdu.normalize();
+        let ts = normal.cross(&ss);
+
+        SurfaceInteraction::new(
+            self.transform.transform_point(&p),
+            normal,
+            wo,
+            Vector2::new(phi / self.phi_max, v),
+            ss,
+            ts,
+            dpdu,
+            dpdv,
+            Vector3::zeros(),
+        )
+    }
+
+    fn phi(&self, p: Point3<f64>) -> f64 {
+        let phi = p.y.atan2(p.x);
+
+        if phi < 0.0 {
+            phi + 2.0 * PI
+        } else {
+            phi
+        }
+    }
+
+    fn is_on_surface(&self, p: Point3<f64>) -> bool {
+        (0.0..=self.height).contains(&p.z) && self.phi(p) <= self.phi_max
+    }
+}
+
+impl ObjectTrait for Cone {
+    fn get_materials(&self) -> &Vec<Material> {
+        &self.materials
+    }
+
+    fn get_light(&self) -> Option<&Arc<Light>> {
+        None
+    }
+
+    fn test_intersect(&self, ray: renderer::Ray) -> Option<(f64, SurfaceInteraction)> {
+        // The direction is not normalized after the transform so the distance along the ray
+        // stays the same in object and world space.
+        let o = self.world_to_object.transform_point(&ray.point);
+        let d = self.world_to_object.transform_vector(&ray.direction);
+
+        let k = (self.radius / self.height).powi(2);
+        let a = d.x * d.x + d.y * d.y - k * d.z * d.z;
+        let b = 2.0 * (d.x * o.x + d.y * o.y - k * d.z * (o.z - self.height));
+        let c = o.x * o.x + o.y * o.y - k * (o.z - self.height) * (o.z - self.height);
+
+        if a.abs() < 1e-12 {
+            return None;
+        }
+
+        let discriminant = b * b - 4.0 * a * c;
+        if discriminant < 0.0 {
+            return None;
+        }
+
+        let root = discriminant.sqrt();
+        let (t0, t1) = {
+            let t0 = (-b - root) / (2.0 * a);
+            let t1 = (-b + root) / (2.0 * a);
+            (t0.min(t1), t0.max(t1))
+        };
+
+        let distance = [t0, t1]
+            .into_iter()
+            .find(|&t| t > 1e-9 && self.is_on_surface(o + d * t))?;
+
+        Some((
+            distance,
+            self.surface_interaction(o + d * distance, -ray.direction),
+        ))
+    }
+
+    fn sample_point(&self, sample: Vec<f64>) -> Interaction {
+        // the area of a slice of the cone falls off linearly towards the apex
+        let v = 1.0 - (1.0 - sample[0]).sqrt();
+        let phi = sample[1] * self.phi_max;
+        let radius = self.radius * (1.0 - v);
+
+        let p = Point3::new(radius * phi.cos(), radius * phi.sin(), v * self.height);
+        let surface_interaction = self.surface_interaction(p, Vector3::zeros());
+
+        Interaction {
+            point: surface_interaction.point,
+            normal: surface_interaction.geometry_normal,
+            uv: surface_interaction.uv,
+        }
+    }
+
+    // todo: duplicate code with triangle and rectangle
+    fn pdf(&self, interaction: &Interaction, wi: Vector3<f64>) -> f64 {
+        let ray = Ray {
+            point: interaction.point + wi * 1e-9,
+            direction: wi,
+            t_max: f64::INFINITY,
+        };
+
+        let intersect_object = self.test_intersect(ray);
+
+        if intersect_object.is_none() {
+            return 0.0;
+        }
+
+        let (_, surface_interaction) = intersect_object.unwrap();
+
+        nalgebra::distance_squared(&interaction.point, &surface_interaction.point)
+            / (surface_interaction.shading_normal.dot(&-wi).abs() * self.area())
+    }
+
+    /// Area in object space, scaling in the transform is not taken into account.
+    fn area(&self) -> f64 {
+        self.radius * (self.height * self.height + self.radius * self.radius).sqrt() * self.phi_max
+            / 2.0
+    }
+
+    fn is_visible_to_camera(&self) -> bool {
+        self.visible_to_camera
+    }
+
+    fn get_name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    fn is_double_sided(&self) -> bool {
+        self.double_sided
+    }
+}
+
+impl Bounded for Cone {
+    fn aabb(&self) -> AABB {
+        let mut min = Point3::from(Vector3::repeat(f64::MAX));
+        let mut max = Point3::from(Vector3::repeat(f64::MIN));
+
+        for corner in 0..8 {
+            let p = Point3::new(
+                if corner & 1 == 0 {
+                    -self.radius
+                } else {
+                    self.radius
+                },
+                if corner & 2 == 0 {
+                    -self.radius
+                } else {
+                    self.radius
+                },
+                if corner & 4 == 0 { 0.0 } else { self.height },
+            );
+            let p = self.transform.transform_point(&p);
+
+            min = min.inf(&p);
+            max = max.sup(&p);
+        }
+
+        AABB::with_bounds(
+            bvh::Point3::new(min.x as f32, min.y as f32, min.z as f32),
+            bvh::Point3::new(max.x as f32, max.y as f32, max.z as f32),
+        )
+    }
+}
+
+impl BHShape for Cone {
+    fn set_bh_node_index(&mut self, index: usize) {
+        self.node_index = index;
+    }
+
+    fn bh_node_index(&self) -> usize {
+        self.node_index
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+    use nalgebra::{Matrix4, Point3, Vector3};
+
+    use crate::objects::cone::Cone;
+    use crate::objects::ObjectTrait;
+    use crate::renderer::Ray;
+
+    fn cone() -> Cone {
+        Cone::new(1.0, 2.0, 360.0, Matrix4::identity(), vec![])
+    }
+
+    #[test]
+    fn test_hit_near_apex() {
+        let ray = Ray {
+            point: Point3::new(-5.0, 0.0, 1.9),
+            direction: Vector3::x(),
+            t_max: f64::INFINITY,
+        };
+
+        let (distance, interaction) = cone().test_intersect(ray).unwrap();
+
+        // radius at z = 1.9 is 0.05
+        assert_relative_eq!(4.95, distance, epsilon = 1e-9);
+        assert_relative_eq!(0.95, interaction.uv.y, epsilon = 1e-9);
+    }
+
+    #[test]
+    fn test_hit_near_base() {
+        let ray = Ray {
+            point: Point3::new(0.0, -5.0, 0.1),
+            direction: Vector3::y(),
+            t_max: f64::INFINITY,
+        };
+
+        let (distance, interaction) = cone().test_intersect(ray).unwrap();
+
+        // radius at z = 0.1 is 0.95
+        assert_relative_eq!(4.05, distance, epsilon = 1e-9);
+        assert_relative_eq!(
+            Point3::new(0.0, -0.95, 0.1),
+            interaction.point,
+            epsilon = 1e-9
+        );
+
+        // below the base and above the apex there is nothing
+        let below = Ray {
+            point: Point3::new(0.0, -5.0, -0.1),
+            direction: Vector3::y(),
+            t_max: f64::INFINITY,
+        };
+        assert!(cone().test_intersect(below).is_none());
+
+        let above = Ray {
+            point: Point3::new(0.0, -5.0, 2.1),
+            direction: Vector3::y(),
+            t_max: f64::INFINITY,
+        };
+        assert!(cone().test_intersect(above).is_none());
+    }
+
+    #[test]
+    fn test_normal_along_slant() {
+        let ray = Ray {
+            point: Point3::new(5.0, 0.0, 1.0),
+            direction: -Vector3::x(),
+            t_max: f64::INFINITY,
+        };
+
+        let (_, interaction) = cone().test_intersect(ray).unwrap();
+
+        // the slant goes from (1, 0, 0) to the apex (0, 0, 2), the normal is perpendicular to it
+        let slant = Vector3::new(-1.0, 0.0, 2.0);
+        let normal = interaction.shading_normal;
+        assert_relative_eq!(0.0, normal.dot(&slant), epsilon = 1e-9);
+        assert_relative_eq!(1.0, normal.magnitude(), epsilon = 1e-9);
+        assert_relative_eq!(
+            Vector3::new(2.0, 0.0, 1.0).normalize(),
+            normal,
+            epsilon = 1e-9
+        );
+    }
+}
@@ -0,0 +1,274 @@
+use std::fmt;
+use std::sync::Arc;
+
+use bvh::aabb::{Bounded, AABB};
+use bvh::bounding_hierarchy::BHShape;
+use bvh::bvh::BVH;
+use nalgebra::{Matrix4, Point3, Vector3};
+
+use crate::lights::Light;
+use crate::materials::Material;
+use crate::objects::{ArcObject, ObjectTrait};
+use crate::renderer;
+use crate::surface_interaction::{Interaction, SurfaceInteraction};
+
+/// Geometry shared by all instances of a model, in its own object space and with its own BVH.
+pub struct InstanceGeometry {
+    objects: Vec<ArcObject>,
+    bvh: BVH,
+}
+
+impl InstanceGeometry {
+    pub fn new(mut objects: Vec<ArcObject>) -> Self {
+        let bvh = BVH::build(&mut objects);
+
+        InstanceGeometry { objects, bvh }
+    }
+
+    fn aabb(&self) -> AABB {
+        self.objects
+            .iter()
+            .fold(AABB::empty(), |aabb, object| aabb.join(&object.aabb()))
+    }
+}
+
+impl fmt::Debug for InstanceGeometry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("InstanceGeometry")
+            .field("objects", &self.objects.len())
+            .finish()
+    }
+}
+
+/// Places shared geometry in the world with a transform, so repeated models are only stored
+/// once. All geometry of an instance is shaded with the materials of the instance.
+#[derive(Debug, Clone)]
+pub struct Instance {
+    pub geometry: Arc<InstanceGeometry>,
+    pub transform: Matrix4<f64>,
+    pub materials: Vec<Material>,
+    pub node_index: usize,
+    pub visible_to_camera: bool,
+    pub double_sided: bool,
+    /// Name from the scene file, objects can be isolated by name
+    pub name: Option<String>,
+    world_to_object: Matrix4<f64>,
+}
+
+impl Instance {
+    pub fn new(
+        geometry: Arc<InstanceGeometry>,
+        transform: Matrix4<f64>,
+        materials: Vec<Material>,
+    ) -> Self {
+        Instance {
+            geometry,
+            transform,
+            materials,
+            node_index: 0,
+            visible_to_camera: true,
+            double_sided: false,
+            name: None,
+            world_to_object: transform
+                .try_inverse()
+                .expect("Instance transform is not invertible"),
+        }
+    }
+
+    /// The ray in object space, also for the BVH, and how much longer the object space
+    /// direction was. Distances along the object space ray are divided by it for world space.
+    fn object_ray(&self, ray: renderer::Ray) -> (renderer::Ray, bvh::ray::Ray, f64) {
+        let direction = self.world_to_object.transform_vector(&ray.direction);
+        let scale = direction.magnitude();
+
+        let object_ray = renderer::Ray {
+            point: self.world_to_object.transform_point(&ray.point),
+            direction: direction / scale,
+            t_max: ray.t_max * scale,
+        };
+
+        let bvh_ray = bvh::ray::Ray::new(
+            bvh::Point3::new(
+                object_ray.point.x as f32,
+                object_ray.point.y as f32,
+                object_ray.point.z as f32,
+            ),
+            bvh::Vector3::new(
+                object_ray.direction.x as f32,
+                object_ray.direction.y as f32,
+                object_ray.direction.z as f32,
+            ),
+        );
+
+        (object_ray, bvh_ray, scale)
+    }
+
+    fn to_world(&self, interaction: SurfaceInteraction, wo: Vector3<f64>) -> SurfaceInteraction {
+        let normal_transform = self.world_to_object.transpose();
+
+        let mut world_interaction = SurfaceInteraction::new(
+            self.transform.transform_point(&interaction.point),
+            normal_transform
+                .transform_vector(&interaction.geometry_normal)
+                .normalize(),
+            wo,
+            interaction.uv,
+            self.transform.transform_vector(&interaction.ss).normalize(),
+            self.transform.transform_vector(&interaction.ts).normalize(),
+            self.transform
+                .transform_vector(&interaction.delta_p_delta_u),
+            self.transform
+                .transform_vector(&interaction.delta_p_delta_v),
+            self.transform.transform_vector(&interaction.p_error).abs(),
+        );
+        world_interaction.bsdf = interaction.bsdf;
+        world_interaction.vertex_color = interaction.vertex_color;
+        world_interaction.barycentric = interaction.barycentric;
+
+        world_interaction
+    }
+}
+
+impl ObjectTrait for Instance {
+    fn get_materials(&self) -> &Vec<Material> {
+        &self.materials
+    }
+
+    fn get_light(&self) -> Option<&Arc<Light>> {
+        None
+    }
+
+    fn test_intersect(&self, ray: renderer::Ray) -> Option<(f64, SurfaceInteraction)> {
+        let (object_ray, bvh_ray, scale) = self.object_ray(ray);
+
+        self.geometry
+            .bvh
+            .traverse_iterator(&bvh_ray, &self.geometry.objects)
+            .filter_map(|object| object.test_intersect(object_ray))
+            .min_by(|(a, _), (b, _)| a.total_cmp(b))
+            .map(|(distance, interaction)| {
+                (distance / scale, self.to_world(interaction, -ray.direction))
+            })
+    }
+
+    fn test_intersect_any(&self, ray: renderer::Ray, max_dist: f64) -> bool {
+        let (object_ray, bvh_ray, scale) = self.object_ray(ray);
+
+        self.geometry
+            .bvh
+            .traverse_iterator(&bvh_ray, &self.geometry.objects)
+            .any(|object| object.test_intersect_any(object_ray, max_dist * scale))
+    }
+
+    fn sample_point(&self, _sample: Vec<f64>) -> Interaction {
+        unimplemented!("Instances cannot be used as area lights");
+    }
+
+    fn pdf(&self, _interaction: &Interaction, _wi: Vector3<f64>) -> f64 {
+        unimplemented!("Instances cannot be used as area lights");
+    }
+
+    fn area(&self) -> f64 {
+        unimplemented!("Instances cannot be used as area lights");
+    }
+
+    fn is_visible_to_camera(&self) -> bool {
+        self.visible_to_camera
+    }
+
+    fn get_name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    fn is_double_sided(&self) -> bool {
+        self.double_sided
+    }
+}
+
+impl Bounded for Instance {
+    fn aabb(&self) -> AABB {
+        let object_aabb = self.geometry.aabb();
+        let bounds = [object_aabb.min, object_aabb.max];
+        let mut aabb = AABB::empty();
+
+        for corner in 0..8 {
+            let p = Point3::new(
+                bounds[corner & 1].x as f64,
+                bounds[(corner >> 1) & 1].y as f64,
+                bounds[corner >> 2].z as f64,
+            );
+            let p = self.transform.transform_point(&p);
+            let p = bvh::Point3::new(p.x as f32, p.y as f32, p.z as f32);
+
+            aabb = aabb.join(&AABB::with_bounds(p, p));
+        }
+
+        aabb
+    }
+}
+
+impl BHShape for Instance {
+    fn set_bh_node_index(&mut self, index: usize) {
+        self.node_index = index;
+    }
+
+    fn bh_node_index(&self) -> usize {
+        self.node_index
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use approx::assert_relative_eq;
+    use nalgebra::{Matrix4, Point3, Vector3};
+    use tobj::Mesh;
+
+    use crate::objects::instance::{Instance, InstanceGeometry};
+    use crate::objects::triangle::Triangle;
+    use crate::objects::{ArcObject, Object, ObjectTrait};
+    use crate::renderer::Ray;
+
+    #[test]
+    fn test_translated_instance_is_hit_where_placed() {
+        let mesh = Mesh {
+            positions: vec![-1.0, -1.0, 0.0, 1.0, -1.0, 0.0, 0.0, 1.0, 0.0],
+            normals: vec![0.0, 0.0, -1.0, 0.0, 0.0, -1.0, 0.0, 0.0, -1.0],
+            indices: vec![0, 1, 2],
+            ..Mesh::default()
+        };
+        let triangle = Triangle::new(Arc::new(mesh), 0, 1, 2, vec![], None);
+        let geometry = Arc::new(InstanceGeometry::new(vec![ArcObject(Arc::new(
+            Object::Triangle(triangle),
+        ))]));
+
+        let instance = Instance::new(
+            geometry,
+            Matrix4::new_translation(&Vector3::new(5.0, 0.0, 0.0)),
+            vec![],
+        );
+
+        let ray = Ray {
+            point: Point3::new(5.0, 0.0, -2.0),
+            direction: Vector3::z(),
+            t_max: f64::INFINITY,
+        };
+        let (distance, interaction) = instance.test_intersect(ray).unwrap();
+
+        assert_relative_eq!(2.0, distance, epsilon = 1e-9);
+        assert_relative_eq!(
+            Point3::new(5.0, 0.0, 0.0),
+            interaction.point,
+            epsilon = 1e-6
+        );
+
+        // the untransformed location is empty
+        let ray = Ray {
+            point: Point3::new(0.0, 0.0, -2.0),
+            direction: Vector3::z(),
+            t_max: f64::INFINITY,
+        };
+        assert!(instance.test_intersect(ray).is_none());
+    }
+}
@@ -20,15 +20,19 @@ pub struct Plane {
     pub normal: Vector3<f64>,
     pub materials: Vec<Material>,
     pub node_index: usize,
+    pub visible_to_camera: bool,
+    pub visible_in_reflections: bool,
 }
 
 impl Plane {
     pub fn new(position: Point3<f64>, normal: Vector3<f64>, materials: Vec<Material>) -> Self {
         Plane {
             position,
-            normal,
+            normal: normal.normalize(),
             materials,
             node_index: 0,
+            visible_to_camera: true,
+            visible_in_reflections: true,
         }
     }
 }
@@ -56,12 +60,13 @@ impl ObjectTrait for Plane {
             return None;
         }
 
-        let p_hit = ray.point + ray.direction * distance + self.normal * 1e-9;
-        //let (sn, ss, ts) = coordinate_system(self.normal);
+        let p_hit = ray.point + ray.direction * distance;
+        // offset towards the side the ray came from, scaled with the distance to the origin so
+        // it stays larger than the rounding error of far away hits
+        let offset = 1e-9 * p_hit.coords.abs().max().max(1.0);
+        let p_hit = p_hit - self.normal * offset * denom.signum();
 
-        // todo: fix?
-        let ss = Vector3::new(1.0, -0.0, 0.0);
-        let ts = Vector3::new(0.0, 0.0, -1.0);
+        let (_, ss, ts) = coordinate_system(self.normal);
 
         Some((
             distance,
@@ -105,6 +110,14 @@ impl ObjectTrait for Plane {
     fn area(&self) -> f64 {
         unimplemented!();
     }
+
+    fn is_visible_to_camera(&self) -> bool {
+        self.visible_to_camera
+    }
+
+    fn is_visible_in_reflections(&self) -> bool {
+        self.visible_in_reflections
+    }
 }
 
 impl Bounded for Plane {
@@ -134,3 +147,33 @@ impl BHShape for Plane {
         self.node_index
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+    use nalgebra::{Point3, Vector3};
+
+    use crate::objects::plane::Plane;
+    use crate::objects::ObjectTrait;
+    use crate::renderer::Ray;
+
+    #[test]
+    fn test_shading_frame_follows_normal() {
+        let normal = Vector3::new(0.3, -0.5, 0.8).normalize();
+        let plane = Plane::new(Point3::new(1.0, 2.0, 3.0), normal, vec![]);
+        let ray = Ray {
+            point: Point3::new(1.0, 2.0, 3.0) + normal * 2.0,
+            direction: Vector3::new(0.1, 0.2, 0.0) - normal,
+            t_max: f64::INFINITY,
+        };
+
+        let (_, interaction) = plane.test_intersect(ray).unwrap();
+
+        assert_relative_eq!(0.0, interaction.ss.dot(&normal), epsilon = 1e-12);
+        assert_relative_eq!(0.0, interaction.ts.dot(&normal), epsilon = 1e-12);
+        assert_relative_eq!(0.0, interaction.ss.dot(&interaction.ts), epsilon = 1e-12);
+        assert_relative_eq!(normal, interaction.shading_normal, epsilon = 1e-12);
+        // the hit point is offset to the side of the ray
+        assert!((interaction.point - plane.position).dot(&normal) > 0.0);
+    }
+}
@@ -0,0 +1,330 @@
+use std::sync::Arc;
+
+use bvh::aabb::{Bounded, AABB};
+use bvh::bounding_hierarchy::BHShape;
+use nalgebra::{Point3, Vector2, Vector3};
+
+use crate::helpers::uniform_sample_triangle;
+use crate::lights::Light;
+use crate::materials::Material;
+use crate::objects::triangle::triangle_aabb;
+use crate::objects::ObjectTrait;
+use crate::renderer;
+use crate::renderer::Ray;
+use crate::surface_interaction::{Interaction, SurfaceInteraction};
+
+/// A planar quad through four coplanar vertices in order around its edge. Unlike a rectangle
+/// the sides don't have to be parallel. Hits are found on two triangles split along the
+/// diagonal that lies inside the quad, so concave quads work as well. The texture coordinates
+/// are bilinear, (0, 0) at the first vertex, (1, 0) at the second and (0, 1) at the last.
+#[derive(Debug, Clone)]
+pub struct Quad {
+    pub vertices: [Point3<f64>; 4],
+    pub materials: Vec<Material>,
+    pub light: Option<Arc<Light>>,
+    pub node_index: usize,
+    pub visible_to_camera: bool,
+    pub double_sided: bool,
+    /// Name from the scene file, objects can be isolated by name
+    pub name: Option<String>,
+    normal: Vector3<f64>,
+    // the two triangles, as indices of their vertices
+    triangles: [[usize; 3]; 2],
+}
+
+impl Quad {
+    pub fn new(
+        vertices: [Point3<f64>; 4],
+        materials: Vec<Material>,
+        light: Option<Arc<Light>>,
+    ) -> Self {
+        let [p0, p1, p2, p3] = vertices;
+        let normal = (p2 - p0).cross(&(p3 - p1)).normalize();
+
+        // the diagonal from p0 to p2 is inside when p1 and p3 lie on opposite sides of it
+        let diagonal = p2 - p0;
+        let side_1 = diagonal.cross(&(p1 - p0)).dot(&normal);
+        let side_3 = diagonal.cross(&(p3 - p0)).dot(&normal);
+        let triangles = if side_1 * side_3 <= 0.0 {
+            [[0, 1, 2], [0, 2, 3]]
+        } else {
+            [[1, 2, 3], [1, 3, 0]]
+        };
+
+        Quad {
+            vertices,
+            materials,
+            light,
+            node_index: 0,
+            visible_to_camera: true,
+            double_sided: false,
+            name: None,
+            normal,
+            triangles,
+        }
+    }
+
+    fn triangle_area(&self, triangle: [usize; 3]) -> f64 {
+        let [a, b, c] = triangle.map(|i| self.vertices[i]);
+
+        0.5 * (b - a).cross(&(c - a)).magnitude()
+    }
+
+    /// Partial derivatives of the bilinear patch through the vertices at (u, v).
+    fn derivatives(&self, uv: Vector2<f64>) -> (Vector3<f64>, Vector3<f64>) {
+        let [p0, p1, p2, p3] = self.vertices;
+        let g = (p0 - p1) + (p2 - p3);
+
+        (p1 - p0 + g * uv.y, p3 - p0 + g * uv.x)
+    }
+
+    /// Inverts the bilinear mapping p0 + u e + v f + u v g for a point on the quad.
+    fn uv(&self, p: Point3<f64>) -> Vector2<f64> {
+        let [p0, p1, p2, p3] = self.vertices;
+        let e = p1 - p0;
+        let f = p3 - p0;
+        let g = (p0 - p1) + (p2 - p3);
+        let h = p - p0;
+        // 2D cross products in the plane of the quad
+        let cross = |a: Vector3<f64>, b: Vector3<f64>| a.cross(&b).dot(&self.normal);
+
+        // quadratic in v: k2 v² + k1 v + k0 = 0
+        let k2 = cross(g, f);
+        let k1 = cross(e, f) + cross(h, g);
+        let k0 = cross(h, e);
+
+        let v = if k2.abs() < 1e-12 * k1.abs().max(1e-12) {
+            -k0 / k1
+        } else {
+            let root = (k1 * k1 - 4.0 * k0 * k2).max(0.0).sqrt();
+            let v = (-k1 + root) / (2.0 * k2);
+            if (-1e-9..=1.0 + 1e-9).contains(&v) {
+                v
+            } else {
+                (-k1 - root) / (2.0 * k2)
+            }
+        };
+
+        let edge = e + g * v;
+        let u = (h - f * v).dot(&edge) / edge.dot(&edge);
+
+        Vector2::new(u.clamp(0.0, 1.0), v.clamp(0.0, 1.0))
+    }
+
+    fn inside(&self, p: Point3<f64>) -> bool {
+        self.triangles.iter().any(|triangle| {
+            let [a, b, c] = triangle.map(|i| self.vertices[i]);
+
+            [(a, b), (b, c), (c, a)]
+                .iter()
+                .all(|(from, to)| (to - from).cross(&(p - from)).dot(&self.normal) >= 0.0)
+        })
+    }
+}
+
+impl ObjectTrait for Quad {
+    fn get_materials(&self) -> &Vec<Material> {
+        &self.materials
+    }
+
+    fn get_light(&self) -> Option<&Arc<Light>> {
+        self.light.as_ref()
+    }
+
+    fn test_intersect(&self, ray: renderer::Ray) -> Option<(f64, SurfaceInteraction)> {
+        let denom = self.normal.dot(&ray.direction);
+
+        if denom.abs() < 1e-9 {
+            return None;
+        }
+
+        let distance = (self.vertices[0] - ray.point).dot(&self.normal) / denom;
+
+        if distance < 1e-9 {
+            return None;
+        }
+
+        let p = ray.point + (ray.direction * distance);
+
+        if !self.inside(p) {
+            return None;
+        }
+
+        let uv = self.uv(p);
+        let (dpdu, dpdv) = self.derivatives(uv);
+        let ss = dpdu.normalize();
+        let ts = self.normal.cross(&ss);
+
+        Some((
+            distance,
+            SurfaceInteraction::new(
+                p,
+                self.normal,
+                -ray.direction,
+                uv,
+                ss,
+                ts,
+                dpdu,
+                dpdv,
+                Vector3::zeros(),
+            ),
+        ))
+    }
+
+    /// The first dimension picks one of the triangles proportional to its area and is reused
+    /// for the point on it.
+    fn sample_point(&self, sample: Vec<f64>) -> Interaction {
+        let first_share = self.triangle_area(self.triangles[0]) / self.area();
+        let (triangle, u) = if sample[0] < first_share {
+            (self.triangles[0], sample[0] / first_share)
+        } else {
+            (
+                self.triangles[1],
+                (sample[0] - first_share) / (1.0 - first_share),
+            )
+        };
+
+        let barycentric = uniform_sample_triangle(vec![u.min(1.0 - f64::EPSILON), sample[1]]);
+        let [a, b, c] = triangle.map(|i| self.vertices[i]);
+        let point = a + (b - a) * barycentric.x + (c - a) * barycentric.y;
+
+        Interaction {
+            point,
+            normal: self.normal,
+            uv: self.uv(point),
+        }
+    }
+
+    fn pdf(&self, interaction: &Interaction, wi: Vector3<f64>) -> f64 {
+        let ray = Ray {
+            point: interaction.point + wi * 1e-9,
+            direction: wi,
+            t_max: f64::INFINITY,
+        };
+
+        match self.test_intersect(ray) {
+            Some((_, surface_interaction)) => {
+                nalgebra::distance_squared(&interaction.point, &surface_interaction.point)
+                    / (surface_interaction.shading_normal.dot(&-wi).abs() * self.area())
+            }
+            None => 0.0,
+        }
+    }
+
+    fn area(&self) -> f64 {
+        self.triangles
+            .iter()
+            .map(|triangle| self.triangle_area(*triangle))
+            .sum()
+    }
+
+    fn is_visible_to_camera(&self) -> bool {
+        self.visible_to_camera
+    }
+
+    fn get_name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    fn is_double_sided(&self) -> bool {
+        self.double_sided
+    }
+}
+
+impl Bounded for Quad {
+    fn aabb(&self) -> AABB {
+        let [p0, p1, p2, p3] = self.vertices;
+
+        triangle_aabb(p0, p1, p2).join(&triangle_aabb(p0, p2, p3))
+    }
+}
+
+impl BHShape for Quad {
+    fn set_bh_node_index(&mut self, index: usize) {
+        self.node_index = index;
+    }
+
+    fn bh_node_index(&self) -> usize {
+        self.node_index
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+    use nalgebra::{Point3, Vector2, Vector3};
+
+    use crate::objects::quad::Quad;
+    use crate::objects::ObjectTrait;
+    use crate::renderer::Ray;
+
+    fn down_at(x: f64, z: f64) -> Ray {
+        Ray {
+            point: Point3::new(x, 1.0, z),
+            direction: -Vector3::y(),
+            t_max: f64::INFINITY,
+        }
+    }
+
+    #[test]
+    fn test_hits_in_every_corner_and_bilinear_uvs() {
+        // a trapezoid in the xz plane, wider at z = 0 than at z = 1
+        let quad = Quad::new(
+            [
+                Point3::new(0.0, 0.0, 0.0),
+                Point3::new(4.0, 0.0, 0.0),
+                Point3::new(3.0, 0.0, 1.0),
+                Point3::new(1.0, 0.0, 1.0),
+            ],
+            vec![],
+            None,
+        );
+
+        for (x, z, uv) in [
+            (0.1, 0.05, Vector2::new(0.0, 0.0)),
+            (3.9, 0.05, Vector2::new(1.0, 0.0)),
+            (2.95, 0.95, Vector2::new(1.0, 1.0)),
+            (1.05, 0.95, Vector2::new(0.0, 1.0)),
+        ] {
+            let (distance, interaction) = quad.test_intersect(down_at(x, z)).unwrap();
+
+            assert_relative_eq!(1.0, distance);
+            assert_relative_eq!(uv, interaction.uv, epsilon = 0.1);
+        }
+
+        // the center maps to the middle of the texture, outside the slanted sides is a miss
+        let (_, center) = quad.test_intersect(down_at(2.0, 0.5)).unwrap();
+        assert_relative_eq!(Vector2::new(0.5, 0.5), center.uv, epsilon = 1e-9);
+        assert!(quad.test_intersect(down_at(0.4, 0.9)).is_none());
+        assert!(quad.test_intersect(down_at(3.6, 0.9)).is_none());
+    }
+
+    #[test]
+    fn test_area_matches_the_polygon_and_samples_stay_on_it() {
+        // a concave arrowhead, the diagonal from the first to the third vertex lies outside
+        let quad = Quad::new(
+            [
+                Point3::new(0.0, 0.0, 0.0),
+                Point3::new(2.0, 0.0, 1.0),
+                Point3::new(4.0, 0.0, 0.0),
+                Point3::new(2.0, 0.0, 3.0),
+            ],
+            vec![],
+            None,
+        );
+
+        // shoelace formula of the outline
+        assert_relative_eq!(4.0, quad.area());
+        assert!(quad.test_intersect(down_at(2.0, 0.5)).is_none());
+        assert!(quad.test_intersect(down_at(2.0, 2.0)).is_some());
+
+        for i in 0..8 {
+            for j in 0..8 {
+                let sample = vec![(i as f64 + 0.5) / 8.0, (j as f64 + 0.5) / 8.0];
+                let point = quad.sample_point(sample).point;
+
+                assert!(quad.test_intersect(down_at(point.x, point.z)).is_some());
+            }
+        }
+    }
+}
@@ -90,11 +90,11 @@ impl ObjectTrait for Rectangle {
                 p,
                 normal,
                 -ray.direction,
-                Vector2::zeros(),
-                ss,
-                ts,
+                Vector2::new(a, b),
                 ss,
                 ts,
+                self.side_a,
+                self.side_b,
                 Vector3::zeros(),
             ),
         ))
@@ -106,6 +106,7 @@ impl ObjectTrait for Rectangle {
         Interaction {
             point,
             normal: self.get_normal(),
+            uv: Vector2::new(sample[0], sample[1]),
         }
     }
 
@@ -114,6 +115,7 @@ impl ObjectTrait for Rectangle {
         let ray = Ray {
             point: interaction.point + wi * 1e-9,
             direction: wi,
+            t_max: f64::INFINITY,
         };
 
         let intersect_object = self.test_intersect(ray);
@@ -129,7 +131,7 @@ impl ObjectTrait for Rectangle {
     }
 
     fn area(&self) -> f64 {
-        self.side_b.magnitude() * self.side_b.magnitude()
+        self.side_a.cross(&self.side_b).magnitude()
     }
 }
 
@@ -155,3 +157,33 @@ impl BHShape for Rectangle {
         self.node_index
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+    use nalgebra::{Point3, Vector3};
+
+    use crate::objects::rectangle::Rectangle;
+    use crate::objects::ObjectTrait;
+
+    #[test]
+    fn test_area_is_the_parallelogram_spanned_by_both_sides() {
+        let rectangle = Rectangle::new(
+            Point3::origin(),
+            Vector3::x() * 2.0,
+            Vector3::z() * 0.5,
+            vec![],
+            None,
+        );
+        assert_relative_eq!(rectangle.area(), 1.0);
+
+        let sheared = Rectangle::new(
+            Point3::origin(),
+            Vector3::new(3.0, 0.0, 0.0),
+            Vector3::new(1.0, 0.0, 2.0),
+            vec![],
+            None,
+        );
+        assert_relative_eq!(sheared.area(), 6.0);
+    }
+}
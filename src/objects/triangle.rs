@@ -25,9 +25,13 @@ pub struct Triangle {
     n0: Vector3<f64>,
     n1: Vector3<f64>,
     n2: Vector3<f64>,
+    colors: Option<[Vector3<f64>; 3]>,
+    uvs: Option<[Point2<f64>; 3]>,
     pub materials: Vec<Material>,
     pub light: Option<Arc<Light>>,
     pub node_index: usize,
+    pub visible_to_camera: bool,
+    pub double_sided: bool,
 }
 
 impl Triangle {
@@ -41,6 +45,8 @@ impl Triangle {
     ) -> Triangle {
         let (p0, p1, p2) = Triangle::get_vertices(&mesh, v0_index, v1_index, v2_index);
         let (n0, n1, n2) = Triangle::get_normals(&mesh, v0_index, v1_index, v2_index);
+        let colors = Triangle::get_vertex_colors(&mesh, v0_index, v1_index, v2_index);
+        let uvs = Triangle::get_texcoords(&mesh, v0_index, v1_index, v2_index);
 
         Triangle {
             mesh,
@@ -50,13 +56,17 @@ impl Triangle {
             n0,
             n1,
             n2,
+            colors,
+            uvs,
             materials,
             light,
             node_index: 0,
+            visible_to_camera: true,
+            double_sided: false,
         }
     }
 
-    fn get_vertices(
+    pub fn get_vertices(
         mesh: &Arc<Mesh>,
         v0_index: usize,
         v1_index: usize,
@@ -81,7 +91,7 @@ impl Triangle {
         )
     }
 
-    fn get_normals(
+    pub fn get_normals(
         mesh: &Arc<Mesh>,
         v0_index: usize,
         v1_index: usize,
@@ -105,6 +115,45 @@ impl Triangle {
             ),
         )
     }
+
+    /// The colors of the three vertices, or None when the mesh has no vertex colors.
+    pub fn get_vertex_colors(
+        mesh: &Arc<Mesh>,
+        v0_index: usize,
+        v1_index: usize,
+        v2_index: usize,
+    ) -> Option<[Vector3<f64>; 3]> {
+        if mesh.vertex_color.is_empty() {
+            return None;
+        }
+
+        Some([v0_index, v1_index, v2_index].map(|index| {
+            Vector3::new(
+                mesh.vertex_color[3 * index] as f64,
+                mesh.vertex_color[3 * index + 1] as f64,
+                mesh.vertex_color[3 * index + 2] as f64,
+            )
+        }))
+    }
+
+    /// The texture coordinates of the three vertices, or None when the mesh has none.
+    pub fn get_texcoords(
+        mesh: &Arc<Mesh>,
+        v0_index: usize,
+        v1_index: usize,
+        v2_index: usize,
+    ) -> Option<[Point2<f64>; 3]> {
+        if mesh.texcoords.is_empty() {
+            return None;
+        }
+
+        Some([v0_index, v1_index, v2_index].map(|index| {
+            Point2::new(
+                mesh.texcoords[2 * index] as f64,
+                mesh.texcoords[2 * index + 1] as f64,
+            )
+        }))
+    }
 }
 
 impl ObjectTrait for Triangle {
@@ -117,141 +166,22 @@ impl ObjectTrait for Triangle {
     }
 
     fn test_intersect(&self, ray: renderer::Ray) -> Option<(f64, SurfaceInteraction)> {
-        let p0 = self.p0;
-        let p1 = self.p1;
-        let p2 = self.p2;
-
-        let mut p0t = p0 - ray.point;
-        let mut p1t = p1 - ray.point;
-        let mut p2t = p2 - ray.point;
-
-        let kz = max_dimension_vec_3(ray.direction.abs());
-        let kx = (kz + 1) % 3;
-        let ky = (kx + 1) % 3;
-
-        let d = permute(ray.direction, kx, ky, kz);
-        p0t = permute(p0t, kx, ky, kz);
-        p1t = permute(p1t, kx, ky, kz);
-        p2t = permute(p2t, kx, ky, kz);
-
-        let s_x = -d.x / d.z;
-        let s_y = -d.y / d.z;
-        let s_z = 1.0 / d.z;
-        p0t.x += s_x * p0t.z;
-        p0t.y += s_y * p0t.z;
-        p1t.x += s_x * p1t.z;
-        p1t.y += s_y * p1t.z;
-        p2t.x += s_x * p2t.z;
-        p2t.y += s_y * p2t.z;
-
-        let e0 = p1t.x * p2t.y - p1t.y * p2t.x;
-        let e1 = p2t.x * p0t.y - p2t.y * p0t.x;
-        let e2 = p0t.x * p1t.y - p0t.y * p1t.x;
-
-        if (e0 < 0.0 || e1 < 0.0 || e2 < 0.0) && (e0 > 0.0 || e1 > 0.0 || e2 > 0.0) {
-            return None;
-        }
-
-        let det = e0 + e1 + e2;
-        if det == 0.0 {
-            return None;
-        }
-
-        p0t.z *= s_z;
-        p1t.z *= s_z;
-        p2t.z *= s_z;
-        let t_scaled = e0 * p0t.z + e1 * p1t.z + e2 * p2t.z;
-        // todo: implement ray.t_max instead of 1000.0
-        if det < 0.0 && (t_scaled >= 0.0 || t_scaled < 1000.0 * det) {
-            return None;
-        }
-
-        if det > 0.0 && (t_scaled <= 0.0 || t_scaled > 1000.0 * det) {
-            return None;
-        }
-
-        let inv_det = 1.0 / det;
-        let b0 = e0 * inv_det;
-        let b1 = e1 * inv_det;
-        let b2 = e2 * inv_det;
-        let t = t_scaled * inv_det;
-
-        if t < f64::EPSILON {
-            return None;
-        }
-
-        let uv = vec![
-            Point2::new(0.0, 0.0),
-            Point2::new(1.0, 0.0),
-            Point2::new(1.0, 1.0),
-        ];
-
-        let duv02: Vector2<f64> = uv[0] - uv[2];
-        let duv12: Vector2<f64> = uv[1] - uv[2];
-        let dp02: Vector3<f64> = p0 - p2;
-        let dp12: Vector3<f64> = p1 - p2;
-
-        let determinant = duv02.x * duv12.y - duv02.y * duv12.x;
-
-        let (dpdu, dpdv) = if determinant == 0.0 {
-            let (_, u, v) = coordinate_system((p2 - p0).cross(&(p1 - p0)).normalize());
-            (u, v)
-        } else {
-            let inv_det = 1.0 / determinant;
-            let dpdu = (duv12[1] * dp02 - duv02[1] * dp12) * inv_det;
-            let dpdv = (-duv12[0] * dp02 + duv02[0] * dp12) * inv_det;
-            (dpdu, dpdv)
-        };
-
-        let p0_normal = self.n0;
-        let p1_normal = self.n1;
-        let p2_normal = self.n2;
-        let shading_normal = (b0 * p0_normal + b1 * p1_normal + b2 * p2_normal).normalize();
-
-        let (ss, ts) = {
-            let mut ss = dpdu.normalize();
-            let mut ts = shading_normal.cross(&ss);
-            if ts.magnitude_squared() > 0.0 {
-                ts = ts.normalize();
-                ss = ts.cross(&shading_normal);
-                (ss, ts)
-            } else {
-                let (_, ss, ts) = coordinate_system(shading_normal);
-                (ss, ts)
-            }
-        };
+        intersect_triangle(
+            ray,
+            self.p0,
+            self.p1,
+            self.p2,
+            self.n0,
+            self.n1,
+            self.n2,
+            self.colors,
+            self.uvs,
+        )
+    }
 
-        let uv_hit = b0 * uv[0].coords + b1 * uv[1].coords + b2 * uv[2].coords;
-
-        let x_abs_sum = (b0 * p0.x).abs() + (b1 * p1.x).abs() + (b2 * p2.x).abs();
-        let y_abs_sum = (b0 * p0.y).abs() + (b1 * p1.y).abs() + (b2 * p2.y).abs();
-        let z_abs_sum = (b0 * p0.z).abs() + (b1 * p1.z).abs() + (b2 * p2.z).abs();
-
-        let p_error: Vector3<f64> = gamma(7.0) * Vector3::new(x_abs_sum, y_abs_sum, z_abs_sum);
-        let mut p_hit: Point3<f64> = (b0 * p0.coords + b1 * p1.coords + b2 * p2.coords).into();
-
-        // p_hit = compute_shading_position(
-        //     p_hit, p0, p1, p2, p0_normal, p1_normal, p2_normal, b0, b1, b2, normal,
-        // );
-        let p1p0 = p1 - p0;
-        let geometry_normal = (p2 - p0).cross(&p1p0).normalize();
-
-        p_hit += shading_normal * 1.0e-9;
-
-        Some((
-            t,
-            SurfaceInteraction::new(
-                p_hit,
-                geometry_normal,
-                -ray.direction,
-                uv_hit,
-                ss,
-                ts,
-                dpdu,
-                dpdv,
-                p_error,
-            ),
-        ))
+    fn test_intersect_any(&self, ray: renderer::Ray, max_dist: f64) -> bool {
+        intersect_triangle_distance(ray, self.p0, self.p1, self.p2)
+            .is_some_and(|distance| distance < max_dist)
     }
 
     fn sample_point(&self, sample: Vec<f64>) -> Interaction {
@@ -265,9 +195,15 @@ impl ObjectTrait for Triangle {
             (sample.x * self.n0 + sample.y * self.n1 + (1.0 - sample.x - sample.y) * self.n2)
                 .normalize();
 
+        let uv = texture_coordinates(self.uvs);
+        let uv = sample.x * uv[0].coords
+            + sample.y * uv[1].coords
+            + (1.0 - sample.x - sample.y) * uv[2].coords;
+
         Interaction {
             point,
             normal: shading_normal,
+            uv,
         }
     }
 
@@ -275,6 +211,7 @@ impl ObjectTrait for Triangle {
         let ray = Ray {
             point: interaction.point + wi * 1e-9,
             direction: wi,
+            t_max: f64::INFINITY,
         };
 
         let intersect_object = self.test_intersect(ray);
@@ -295,6 +232,189 @@ impl ObjectTrait for Triangle {
 
         0.5 * p0p1.cross(&p0p2).magnitude()
     }
+
+    fn is_visible_to_camera(&self) -> bool {
+        self.visible_to_camera
+    }
+
+    fn is_double_sided(&self) -> bool {
+        self.double_sided
+    }
+}
+
+/// The texture coordinates of the corners, (0, 0), (1, 0) and (1, 1) when the mesh has none.
+fn texture_coordinates(uvs: Option<[Point2<f64>; 3]>) -> [Point2<f64>; 3] {
+    uvs.unwrap_or([
+        Point2::new(0.0, 0.0),
+        Point2::new(1.0, 0.0),
+        Point2::new(1.0, 1.0),
+    ])
+}
+
+/// Intersects the triangle with vertices `p0`, `p1` and `p2` and vertex normals `n0`, `n1` and
+/// `n2`, shared by single triangles and triangle meshes. Vertex `colors` and texture coordinates
+/// (`uvs`) are interpolated at the hit point, without texture coordinates the corners get
+/// (0, 0), (1, 0) and (1, 1).
+#[allow(clippy::too_many_arguments)]
+pub fn intersect_triangle(
+    ray: Ray,
+    p0: Point3<f64>,
+    p1: Point3<f64>,
+    p2: Point3<f64>,
+    n0: Vector3<f64>,
+    n1: Vector3<f64>,
+    n2: Vector3<f64>,
+    colors: Option<[Vector3<f64>; 3]>,
+    uvs: Option<[Point2<f64>; 3]>,
+) -> Option<(f64, SurfaceInteraction)> {
+    let (t, [b0, b1, b2]) = triangle_hit(ray, p0, p1, p2)?;
+
+    let uv = texture_coordinates(uvs);
+
+    let duv02: Vector2<f64> = uv[0] - uv[2];
+    let duv12: Vector2<f64> = uv[1] - uv[2];
+    let dp02: Vector3<f64> = p0 - p2;
+    let dp12: Vector3<f64> = p1 - p2;
+
+    let determinant = duv02.x * duv12.y - duv02.y * duv12.x;
+
+    let (dpdu, dpdv) = if determinant == 0.0 {
+        let (_, u, v) = coordinate_system((p2 - p0).cross(&(p1 - p0)).normalize());
+        (u, v)
+    } else {
+        let inv_det = 1.0 / determinant;
+        let dpdu = (duv12[1] * dp02 - duv02[1] * dp12) * inv_det;
+        let dpdv = (-duv12[0] * dp02 + duv02[0] * dp12) * inv_det;
+        (dpdu, dpdv)
+    };
+
+    let shading_normal = (b0 * n0 + b1 * n1 + b2 * n2).normalize();
+
+    let (ss, ts) = {
+        let mut ss = dpdu.normalize();
+        let mut ts = shading_normal.cross(&ss);
+        if ts.magnitude_squared() > 0.0 {
+            ts = ts.normalize();
+            ss = ts.cross(&shading_normal);
+            (ss, ts)
+        } else {
+            let (_, ss, ts) = coordinate_system(shading_normal);
+            (ss, ts)
+        }
+    };
+
+    let uv_hit = b0 * uv[0].coords + b1 * uv[1].coords + b2 * uv[2].coords;
+
+    let x_abs_sum = (b0 * p0.x).abs() + (b1 * p1.x).abs() + (b2 * p2.x).abs();
+    let y_abs_sum = (b0 * p0.y).abs() + (b1 * p1.y).abs() + (b2 * p2.y).abs();
+    let z_abs_sum = (b0 * p0.z).abs() + (b1 * p1.z).abs() + (b2 * p2.z).abs();
+
+    let p_error: Vector3<f64> = gamma(7.0) * Vector3::new(x_abs_sum, y_abs_sum, z_abs_sum);
+    let mut p_hit: Point3<f64> = (b0 * p0.coords + b1 * p1.coords + b2 * p2.coords).into();
+
+    // p_hit = compute_shading_position(
+    //     p_hit, p0, p1, p2, p0_normal, p1_normal, p2_normal, b0, b1, b2, normal,
+    // );
+    let p1p0 = p1 - p0;
+    let geometry_normal = (p2 - p0).cross(&p1p0).normalize();
+
+    p_hit += shading_normal * 1.0e-9;
+
+    let mut interaction = SurfaceInteraction::new(
+        p_hit,
+        geometry_normal,
+        -ray.direction,
+        uv_hit,
+        ss,
+        ts,
+        dpdu,
+        dpdv,
+        p_error,
+    );
+    interaction.vertex_color = colors.map(|[c0, c1, c2]| b0 * c0 + b1 * c1 + b2 * c2);
+    interaction.barycentric = Some(Vector3::new(b0, b1, b2));
+
+    Some((t, interaction))
+}
+
+/// Distance along the ray to the triangle, without the shading information of
+/// `intersect_triangle`. For shadow rays, which only need to know whether anything is hit.
+pub fn intersect_triangle_distance(
+    ray: Ray,
+    p0: Point3<f64>,
+    p1: Point3<f64>,
+    p2: Point3<f64>,
+) -> Option<f64> {
+    triangle_hit(ray, p0, p1, p2).map(|(t, _)| t)
+}
+
+/// Watertight ray triangle test, the distance and the barycentric coordinates of the hit.
+fn triangle_hit(
+    ray: Ray,
+    p0: Point3<f64>,
+    p1: Point3<f64>,
+    p2: Point3<f64>,
+) -> Option<(f64, [f64; 3])> {
+    let mut p0t = p0 - ray.point;
+    let mut p1t = p1 - ray.point;
+    let mut p2t = p2 - ray.point;
+
+    let kz = max_dimension_vec_3(ray.direction.abs());
+    let kx = (kz + 1) % 3;
+    let ky = (kx + 1) % 3;
+
+    let d = permute(ray.direction, kx, ky, kz);
+    p0t = permute(p0t, kx, ky, kz);
+    p1t = permute(p1t, kx, ky, kz);
+    p2t = permute(p2t, kx, ky, kz);
+
+    let s_x = -d.x / d.z;
+    let s_y = -d.y / d.z;
+    let s_z = 1.0 / d.z;
+    p0t.x += s_x * p0t.z;
+    p0t.y += s_y * p0t.z;
+    p1t.x += s_x * p1t.z;
+    p1t.y += s_y * p1t.z;
+    p2t.x += s_x * p2t.z;
+    p2t.y += s_y * p2t.z;
+
+    let e0 = p1t.x * p2t.y - p1t.y * p2t.x;
+    let e1 = p2t.x * p0t.y - p2t.y * p0t.x;
+    let e2 = p0t.x * p1t.y - p0t.y * p1t.x;
+
+    if (e0 < 0.0 || e1 < 0.0 || e2 < 0.0) && (e0 > 0.0 || e1 > 0.0 || e2 > 0.0) {
+        return None;
+    }
+
+    let det = e0 + e1 + e2;
+    if det == 0.0 {
+        return None;
+    }
+
+    p0t.z *= s_z;
+    p1t.z *= s_z;
+    p2t.z *= s_z;
+    let t_scaled = e0 * p0t.z + e1 * p1t.z + e2 * p2t.z;
+    // t_scaled / det is the distance, compared without dividing to skip hits past t_max early
+    if det < 0.0 && (t_scaled >= 0.0 || t_scaled < ray.t_max * det) {
+        return None;
+    }
+
+    if det > 0.0 && (t_scaled <= 0.0 || t_scaled > ray.t_max * det) {
+        return None;
+    }
+
+    let inv_det = 1.0 / det;
+    let b0 = e0 * inv_det;
+    let b1 = e1 * inv_det;
+    let b2 = e2 * inv_det;
+    let t = t_scaled * inv_det;
+
+    if t < f64::EPSILON {
+        return None;
+    }
+
+    Some((t, [b0, b1, b2]))
 }
 
 fn project_on_plane(p: Point3<f64>, origin: Point3<f64>, normal: Vector3<f64>) -> Point3<f64> {
@@ -330,23 +450,27 @@ fn compute_shading_position(
     }
 }
 
+pub fn triangle_aabb(p0: Point3<f64>, p1: Point3<f64>, p2: Point3<f64>) -> AABB {
+    let min_x = p0.x.min(p1.x.min(p2.x));
+    let min_y = p0.y.min(p1.y.min(p2.y));
+    let min_z = p0.z.min(p1.z.min(p2.z));
+    let max_x = p0.x.max(p1.x.max(p2.x));
+    let max_y = p0.y.max(p1.y.max(p2.y));
+    let max_z = p0.z.max(p1.z.max(p2.z));
+
+    AABB::with_bounds(
+        bvh::Point3::new(min_x as f32, min_y as f32, min_z as f32),
+        bvh::Point3::new(
+            (max_x + 0.001) as f32,
+            (max_y + 0.001) as f32,
+            (max_z + 0.001) as f32,
+        ),
+    )
+}
+
 impl Bounded for Triangle {
     fn aabb(&self) -> AABB {
-        let min_x = self.p0.x.min(self.p1.x.min(self.p2.x));
-        let min_y = self.p0.y.min(self.p1.y.min(self.p2.y));
-        let min_z = self.p0.z.min(self.p1.z.min(self.p2.z));
-        let max_x = self.p0.x.max(self.p1.x.max(self.p2.x));
-        let max_y = self.p0.y.max(self.p1.y.max(self.p2.y));
-        let max_z = self.p0.z.max(self.p1.z.max(self.p2.z));
-
-        AABB::with_bounds(
-            bvh::Point3::new(min_x as f32, min_y as f32, min_z as f32),
-            bvh::Point3::new(
-                (max_x + 0.001) as f32,
-                (max_y + 0.001) as f32,
-                (max_z + 0.001) as f32,
-            ),
-        )
+        triangle_aabb(self.p0, self.p1, self.p2)
     }
 }
 
@@ -364,7 +488,8 @@ impl BHShape for Triangle {
 mod tests {
     use std::sync::Arc;
 
-    use nalgebra::{Point3, Vector3};
+    use approx::assert_relative_eq;
+    use nalgebra::{Point3, Vector2, Vector3};
     use tobj::Mesh;
 
     use crate::materials;
@@ -403,6 +528,7 @@ mod tests {
         let ray = Ray {
             point: Point3::new(0.0, 0.0, -2.0),
             direction: Vector3::new(0.0, 0.0, 1.0),
+            t_max: f64::INFINITY,
         };
 
         let option_intersection = triangle.test_intersect(ray);
@@ -421,5 +547,87 @@ mod tests {
         );
 
         assert_eq!(2.0, distance);
+
+        // the triangle lies beyond the end of a shorter ray
+        let short_ray = Ray { t_max: 1.5, ..ray };
+        assert!(triangle.test_intersect(short_ray).is_none());
+    }
+
+    #[test]
+    fn test_vertex_colors_are_interpolated() {
+        let mesh = Mesh {
+            positions: vec![0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0],
+            vertex_color: vec![1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0],
+            normals: vec![0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0],
+            indices: vec![0, 1, 2],
+            ..Mesh::default()
+        };
+        let triangle = Triangle::new(Arc::new(mesh), 0, 1, 2, vec![], None);
+
+        let ray = Ray {
+            point: Point3::new(1.0 / 3.0, 1.0 / 3.0, 1.0),
+            direction: Vector3::new(0.0, 0.0, -1.0),
+            t_max: f64::INFINITY,
+        };
+        let (_, i) = triangle.test_intersect(ray).unwrap();
+
+        assert_relative_eq!(
+            Vector3::repeat(1.0 / 3.0),
+            i.vertex_color.unwrap(),
+            epsilon = 1e-9
+        );
+    }
+
+    #[test]
+    fn test_barycentric_coordinates_tell_how_close_a_hit_is_to_an_edge() {
+        let mesh = Mesh {
+            positions: vec![0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0],
+            normals: vec![0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0],
+            indices: vec![0, 1, 2],
+            ..Mesh::default()
+        };
+        let triangle = Triangle::new(Arc::new(mesh), 0, 1, 2, vec![], None);
+        let barycentric = |x, y| {
+            let ray = Ray {
+                point: Point3::new(x, y, 1.0),
+                direction: Vector3::new(0.0, 0.0, -1.0),
+                t_max: f64::INFINITY,
+            };
+
+            triangle.test_intersect(ray).unwrap().1.barycentric.unwrap()
+        };
+
+        let centroid = barycentric(1.0 / 3.0, 1.0 / 3.0);
+        assert_relative_eq!(Vector3::repeat(1.0 / 3.0), centroid, epsilon = 1e-9);
+
+        // just above the edge along x
+        let near_edge = barycentric(0.5, 0.01);
+        assert!(near_edge.min() < 0.02);
+        assert_relative_eq!(1.0, near_edge.sum(), epsilon = 1e-9);
+    }
+
+    #[test]
+    fn test_texture_coordinates_are_interpolated() {
+        let mesh = Mesh {
+            positions: vec![0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0],
+            normals: vec![0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0],
+            texcoords: vec![0.2, 0.4, 0.8, 0.4, 0.2, 1.0],
+            indices: vec![0, 1, 2],
+            ..Mesh::default()
+        };
+        let triangle = Triangle::new(Arc::new(mesh), 0, 1, 2, vec![], None);
+
+        let ray = Ray {
+            point: Point3::new(1.0 / 3.0, 1.0 / 3.0, 1.0),
+            direction: Vector3::new(0.0, 0.0, -1.0),
+            t_max: f64::INFINITY,
+        };
+        let (_, i) = triangle.test_intersect(ray).unwrap();
+
+        // texture coordinates are stored as f32
+        assert_relative_eq!(Vector2::new(0.4, 0.6), i.uv, epsilon = 1e-6);
+        // u and v both change 0.6 over the unit length sides
+        assert_relative_eq!(Vector3::x() / 0.6, i.delta_p_delta_u, epsilon = 1e-6);
+        assert_relative_eq!(Vector3::y() / 0.6, i.delta_p_delta_v, epsilon = 1e-6);
     }
 }
@@ -0,0 +1,603 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+use bvh::aabb::{Bounded, AABB};
+use bvh::bounding_hierarchy::BHShape;
+use bvh::bvh::BVH;
+use nalgebra::Vector3;
+use tobj::Mesh;
+
+use crate::lights::Light;
+use crate::materials::Material;
+use crate::objects::triangle::{
+    intersect_triangle, intersect_triangle_distance, triangle_aabb, Triangle,
+};
+use crate::objects::ObjectTrait;
+use crate::renderer;
+use crate::surface_interaction::{Interaction, SurfaceInteraction};
+
+/// One triangle of a mesh, only the vertex indices and the bounds the BVH is built from.
+#[derive(Debug, Clone)]
+struct MeshTriangle {
+    indices: [u32; 3],
+    aabb: AABB,
+    node_index: usize,
+}
+
+impl Bounded for MeshTriangle {
+    fn aabb(&self) -> AABB {
+        self.aabb
+    }
+}
+
+impl BHShape for MeshTriangle {
+    fn set_bh_node_index(&mut self, index: usize) {
+        self.node_index = index;
+    }
+
+    fn bh_node_index(&self) -> usize {
+        self.node_index
+    }
+}
+
+/// All triangles of a mesh as a single object with its own BVH. The vertices are read from the
+/// shared mesh, so a triangle costs a few indices instead of an allocated `Triangle` object.
+/// Going by the type sizes that is about 48 bytes per face instead of roughly 400 for the
+/// `Object`, its `Arc` and its cloned materials. This is an estimate, it was not measured.
+#[derive(Clone)]
+pub struct TriangleMesh {
+    pub mesh: Arc<Mesh>,
+    triangles: Arc<Vec<MeshTriangle>>,
+    bvh: Arc<BVH>,
+    pub materials: Vec<Material>,
+    pub node_index: usize,
+    /// Name of the model in the obj file
+    pub name: Option<String>,
+    pub visible_to_camera: bool,
+    pub double_sided: bool,
+}
+
+impl TriangleMesh {
+    pub fn new(mesh: Arc<Mesh>, materials: Vec<Material>) -> Self {
+        let mut triangles: Vec<MeshTriangle> = mesh
+            .indices
+            .chunks_exact(3)
+            .map(|indices| {
+                let (p0, p1, p2) = Triangle::get_vertices(
+                    &mesh,
+                    indices[0] as usize,
+                    indices[1] as usize,
+                    indices[2] as usize,
+                );
+
+                MeshTriangle {
+                    indices: [indices[0], indices[1], indices[2]],
+                    aabb: triangle_aabb(p0, p1, p2),
+                    node_index: 0,
+                }
+            })
+            .collect();
+        let bvh = BVH::build(&mut triangles);
+
+        TriangleMesh {
+            mesh,
+            triangles: Arc::new(triangles),
+            bvh: Arc::new(bvh),
+            materials,
+            node_index: 0,
+            name: None,
+            visible_to_camera: true,
+            double_sided: false,
+        }
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.triangles.len()
+    }
+}
+
+/// Replaces the normals of a mesh by the average of the face normals around each vertex, larger
+/// faces count more. Faces at an angle above `crease_angle` (in degrees) to each other are not
+/// averaged, so sharp edges stay sharp. A vertex that ends up with different normals for its
+/// faces is split.
+pub fn recompute_normals(mesh: &Mesh, crease_angle: f64) -> Mesh {
+    let cos_crease = crease_angle.to_radians().cos();
+    let position = |index: u32| {
+        let index = index as usize;
+        Vector3::new(
+            mesh.positions[3 * index] as f64,
+            mesh.positions[3 * index + 1] as f64,
+            mesh.positions[3 * index + 2] as f64,
+        )
+    };
+    // vertices are only shared when normals or texture coordinates match, so adjacent faces
+    // are found by position
+    let position_key = |index: u32| {
+        let index = index as usize;
+        [
+            mesh.positions[3 * index].to_bits(),
+            mesh.positions[3 * index + 1].to_bits(),
+            mesh.positions[3 * index + 2].to_bits(),
+        ]
+    };
+
+    let faces: Vec<&[u32]> = mesh.indices.chunks_exact(3).collect();
+    // not normalized, the length is twice the area of the face
+    let face_normals: Vec<Vector3<f64>> = faces
+        .iter()
+        .map(|face| {
+            (position(face[1]) - position(face[0])).cross(&(position(face[2]) - position(face[0])))
+        })
+        .collect();
+
+    let mut faces_at_position: HashMap<[u32; 3], Vec<usize>> = HashMap::new();
+    for (face_index, face) in faces.iter().enumerate() {
+        for &index in face.iter() {
+            faces_at_position
+                .entry(position_key(index))
+                .or_default()
+                .push(face_index);
+        }
+    }
+
+    let mut smoothed = Mesh {
+        material_id: mesh.material_id,
+        ..Mesh::default()
+    };
+    let mut new_indices: HashMap<(u32, [u64; 3]), u32> = HashMap::new();
+
+    for (face_index, face) in faces.iter().enumerate() {
+        let face_normal = face_normals[face_index].normalize();
+
+        for &index in face.iter() {
+            let normal = faces_at_position[&position_key(index)]
+                .iter()
+                .map(|&other| face_normals[other])
+                .filter(|other| other.normalize().dot(&face_normal) >= cos_crease)
+                .sum::<Vector3<f64>>()
+                .try_normalize(0.0)
+                .unwrap_or(face_normal);
+
+            let key = (index, normal.map(f64::to_bits).into());
+            let new_index = *new_indices.entry(key).or_insert_with(|| {
+                let i = index as usize;
+                smoothed
+                    .positions
+                    .extend_from_slice(&mesh.positions[3 * i..3 * i + 3]);
+                smoothed.normals.extend(normal.iter().map(|&n| n as f32));
+                if !mesh.texcoords.is_empty() {
+                    smoothed
+                        .texcoords
+                        .extend_from_slice(&mesh.texcoords[2 * i..2 * i + 2]);
+                }
+                if !mesh.vertex_color.is_empty() {
+                    smoothed
+                        .vertex_color
+                        .extend_from_slice(&mesh.vertex_color[3 * i..3 * i + 3]);
+                }
+
+                (smoothed.positions.len() / 3 - 1) as u32
+            });
+            smoothed.indices.push(new_index);
+        }
+    }
+
+    smoothed
+}
+
+/// Negates the vertex normals, for models exported with their normals pointing inwards.
+pub fn flip_normals(mesh: &mut Mesh) {
+    for normal in mesh.normals.iter_mut() {
+        *normal = -*normal;
+    }
+}
+
+/// Swaps two vertices of every triangle, which turns the geometric normal around. For models
+/// exported with the opposite winding order.
+pub fn flip_winding(mesh: &mut Mesh) {
+    for triangle in mesh.indices.chunks_exact_mut(3) {
+        triangle.swap(1, 2);
+    }
+}
+
+/// Splits every triangle into four at the midpoints of its edges. The midpoints are shared
+/// between the triangles on either side of an edge and get the average of the normals, texture
+/// coordinates and vertex colors of the edge ends. Positions are not smoothed, the surface
+/// keeps its shape but smooth normals interpolate over more vertices.
+pub fn subdivide(mesh: &Mesh) -> Mesh {
+    let mut subdivided = mesh.clone();
+    subdivided.indices.clear();
+    let mut midpoints: HashMap<(u32, u32), u32> = HashMap::new();
+
+    let mut midpoint = |subdivided: &mut Mesh, a: u32, b: u32| {
+        *midpoints.entry((a.min(b), a.max(b))).or_insert_with(|| {
+            let (a, b) = (a as usize, b as usize);
+            let average = |values: &[f32], size: usize| {
+                (0..size)
+                    .map(|k| 0.5 * (values[size * a + k] + values[size * b + k]))
+                    .collect::<Vec<f32>>()
+            };
+
+            let positions = average(&mesh.positions, 3);
+            subdivided.positions.extend(positions);
+            if !mesh.normals.is_empty() {
+                let normal = average(&mesh.normals, 3);
+                let length = normal.iter().map(|n| n * n).sum::<f32>().sqrt();
+                let length = if length > 0.0 { length } else { 1.0 };
+                subdivided.normals.extend(normal.iter().map(|n| n / length));
+            }
+            if !mesh.texcoords.is_empty() {
+                subdivided.texcoords.extend(average(&mesh.texcoords, 2));
+            }
+            if !mesh.vertex_color.is_empty() {
+                subdivided
+                    .vertex_color
+                    .extend(average(&mesh.vertex_color, 3));
+            }
+
+            (subdivided.positions.len() / 3 - 1) as u32
+        })
+    };
+
+    for triangle in mesh.indices.chunks_exact(3) {
+        let [a, b, c] = [triangle[0], triangle[1], triangle[2]];
+        let ab = midpoint(&mut subdivided, a, b);
+        let bc = midpoint(&mut subdivided, b, c);
+        let ca = midpoint(&mut subdivided, c, a);
+
+        subdivided
+            .indices
+            .extend([a, ab, ca, ab, b, bc, ca, bc, c, ab, bc, ca]);
+    }
+
+    subdivided
+}
+
+impl fmt::Debug for TriangleMesh {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TriangleMesh")
+            .field("triangles", &self.triangles.len())
+            .field("materials", &self.materials)
+            .finish()
+    }
+}
+
+impl ObjectTrait for TriangleMesh {
+    fn get_materials(&self) -> &Vec<Material> {
+        &self.materials
+    }
+
+    fn get_light(&self) -> Option<&Arc<Light>> {
+        None
+    }
+
+    fn test_intersect(&self, ray: renderer::Ray) -> Option<(f64, SurfaceInteraction)> {
+        let bvh_ray = bvh::ray::Ray::new(
+            bvh::Point3::new(ray.point.x as f32, ray.point.y as f32, ray.point.z as f32),
+            bvh::Vector3::new(
+                ray.direction.x as f32,
+                ray.direction.y as f32,
+                ray.direction.z as f32,
+            ),
+        );
+
+        self.bvh
+            .traverse_iterator(&bvh_ray, &self.triangles)
+            .filter_map(|triangle| {
+                let [v0, v1, v2] = triangle.indices.map(|index| index as usize);
+                let (p0, p1, p2) = Triangle::get_vertices(&self.mesh, v0, v1, v2);
+                let (n0, n1, n2) = Triangle::get_normals(&self.mesh, v0, v1, v2);
+                let colors = Triangle::get_vertex_colors(&self.mesh, v0, v1, v2);
+                let uvs = Triangle::get_texcoords(&self.mesh, v0, v1, v2);
+
+                intersect_triangle(ray, p0, p1, p2, n0, n1, n2, colors, uvs)
+            })
+            .min_by(|(a, _), (b, _)| a.total_cmp(b))
+    }
+
+    fn test_intersect_any(&self, ray: renderer::Ray, max_dist: f64) -> bool {
+        let bvh_ray = bvh::ray::Ray::new(
+            bvh::Point3::new(ray.point.x as f32, ray.point.y as f32, ray.point.z as f32),
+            bvh::Vector3::new(
+                ray.direction.x as f32,
+                ray.direction.y as f32,
+                ray.direction.z as f32,
+            ),
+        );
+
+        self.bvh
+            .traverse_iterator(&bvh_ray, &self.triangles)
+            .any(|triangle| {
+                let [v0, v1, v2] = triangle.indices.map(|index| index as usize);
+                let (p0, p1, p2) = Triangle::get_vertices(&self.mesh, v0, v1, v2);
+
+                intersect_triangle_distance(ray, p0, p1, p2)
+                    .is_some_and(|distance| distance < max_dist)
+            })
+    }
+
+    fn sample_point(&self, _sample: Vec<f64>) -> Interaction {
+        unimplemented!("Triangle meshes cannot be used as area lights");
+    }
+
+    fn pdf(&self, _interaction: &Interaction, _wi: Vector3<f64>) -> f64 {
+        unimplemented!("Triangle meshes cannot be used as area lights");
+    }
+
+    fn area(&self) -> f64 {
+        unimplemented!("Triangle meshes cannot be used as area lights");
+    }
+
+    fn is_visible_to_camera(&self) -> bool {
+        self.visible_to_camera
+    }
+
+    fn get_name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    fn is_double_sided(&self) -> bool {
+        self.double_sided
+    }
+}
+
+impl Bounded for TriangleMesh {
+    fn aabb(&self) -> AABB {
+        self.triangles
+            .iter()
+            .fold(AABB::empty(), |aabb, triangle| aabb.join(&triangle.aabb))
+    }
+}
+
+impl BHShape for TriangleMesh {
+    fn set_bh_node_index(&mut self, index: usize) {
+        self.node_index = index;
+    }
+
+    fn bh_node_index(&self) -> usize {
+        self.node_index
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use approx::assert_relative_eq;
+    use nalgebra::{Point3, Vector3};
+    use tobj::Mesh;
+
+    use crate::objects::triangle::Triangle;
+    use crate::objects::triangle_mesh::{
+        flip_normals, flip_winding, recompute_normals, subdivide, MeshTriangle, TriangleMesh,
+    };
+    use crate::objects::{Object, ObjectTrait};
+    use crate::renderer::Ray;
+
+    #[test]
+    fn test_a_mesh_triangle_is_much_smaller_than_a_triangle_object() {
+        // the estimate in the doc of TriangleMesh, the materials and Arc header come on top
+        assert!(std::mem::size_of::<MeshTriangle>() <= 48);
+        assert!(std::mem::size_of::<Object>() >= 4 * std::mem::size_of::<MeshTriangle>());
+    }
+
+    #[test]
+    fn test_intersections_match_single_triangles() {
+        // two triangles forming a tilted quad and one triangle behind it
+        let mesh = Arc::new(Mesh {
+            positions: vec![
+                -1.0, -1.0, 0.0, 1.0, -1.0, 0.2, 1.0, 1.0, 0.4, -1.0, 1.0, 0.2, -0.5, -0.5, 2.0,
+                0.5, -0.5, 2.0, 0.0, 0.5, 2.0,
+            ],
+            normals: vec![
+                0.0, 0.0, -1.0, 0.1, 0.0, -1.0, 0.0, 0.1, -1.0, 0.1, 0.1, -1.0, 0.0, 0.0, -1.0,
+                0.0, 0.0, -1.0, 0.0, 0.0, -1.0,
+            ],
+            indices: vec![0, 1, 2, 0, 2, 3, 4, 5, 6],
+            ..Mesh::default()
+        });
+
+        let triangle_mesh = TriangleMesh::new(mesh.clone(), vec![]);
+        let triangles: Vec<Triangle> = mesh
+            .indices
+            .chunks_exact(3)
+            .map(|i| {
+                Triangle::new(
+                    mesh.clone(),
+                    i[0] as usize,
+                    i[1] as usize,
+                    i[2] as usize,
+                    vec![],
+                    None,
+                )
+            })
+            .collect();
+
+        assert_eq!(3, triangle_mesh.triangle_count());
+
+        for x in -6..=6 {
+            for y in -6..=6 {
+                let ray = Ray {
+                    point: Point3::new(x as f64 * 0.2, y as f64 * 0.2, -2.0),
+                    direction: Vector3::new(0.05, 0.02, 1.0).normalize(),
+                    t_max: f64::INFINITY,
+                };
+
+                let expected = triangles
+                    .iter()
+                    .filter_map(|triangle| triangle.test_intersect(ray))
+                    .min_by(|(a, _), (b, _)| a.total_cmp(b));
+                let actual = triangle_mesh.test_intersect(ray);
+
+                assert_eq!(expected.is_some(), actual.is_some());
+                if let (Some((expected_t, expected)), Some((actual_t, actual))) = (expected, actual)
+                {
+                    assert_relative_eq!(expected_t, actual_t);
+                    assert_relative_eq!(expected.point, actual.point);
+                    assert_relative_eq!(expected.shading_normal, actual.shading_normal);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_recomputed_normals_respect_the_crease_angle() {
+        // two triangles sharing the edge from vertex 0 to 1, folded 20 degrees
+        let fold = 20.0_f64.to_radians();
+        let (cos, sin) = (fold.cos() as f32, fold.sin() as f32);
+        let mesh = Mesh {
+            positions: vec![0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.5, 1.0, 0.0, 0.5, -cos, -sin],
+            indices: vec![0, 1, 2, 1, 0, 3],
+            ..Mesh::default()
+        };
+        let normal = |mesh: &Mesh, index: u32| {
+            let i = 3 * index as usize;
+            Vector3::new(
+                mesh.normals[i] as f64,
+                mesh.normals[i + 1] as f64,
+                mesh.normals[i + 2] as f64,
+            )
+        };
+
+        let smooth = recompute_normals(&mesh, 30.0);
+        let averaged = Vector3::new(0.0, -(fold / 2.0).sin(), (fold / 2.0).cos());
+        assert_eq!(4, smooth.positions.len() / 3);
+        assert_relative_eq!(averaged, normal(&smooth, smooth.indices[0]), epsilon = 1e-6);
+        assert_relative_eq!(averaged, normal(&smooth, smooth.indices[1]), epsilon = 1e-6);
+        assert_relative_eq!(
+            Vector3::z(),
+            normal(&smooth, smooth.indices[2]),
+            epsilon = 1e-6
+        );
+
+        let sharp = recompute_normals(&mesh, 10.0);
+        let folded = Vector3::new(0.0, -fold.sin(), fold.cos());
+        assert_eq!(6, sharp.positions.len() / 3);
+        for corner in 0..3 {
+            assert_relative_eq!(
+                Vector3::z(),
+                normal(&sharp, sharp.indices[corner]),
+                epsilon = 1e-6
+            );
+            assert_relative_eq!(
+                folded,
+                normal(&sharp, sharp.indices[corner + 3]),
+                epsilon = 1e-6
+            );
+        }
+    }
+
+    #[test]
+    fn test_flip_options_turn_normals_and_faces_around() {
+        let mesh = Mesh {
+            positions: vec![0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0],
+            normals: vec![0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0],
+            indices: vec![0, 1, 2],
+            ..Mesh::default()
+        };
+        // the geometric normal as the triangle intersection computes it
+        let geometry_normal = |mesh: &Mesh| {
+            let indices = &mesh.indices;
+            let (p0, p1, p2) = Triangle::get_vertices(
+                &Arc::new(mesh.clone()),
+                indices[0] as usize,
+                indices[1] as usize,
+                indices[2] as usize,
+            );
+            (p2 - p0).cross(&(p1 - p0)).normalize()
+        };
+
+        let mut flipped_normals = mesh.clone();
+        flip_normals(&mut flipped_normals);
+        assert_eq!(vec![-0.0, -0.0, -1.0], flipped_normals.normals[..3]);
+        assert_eq!(mesh.indices, flipped_normals.indices);
+
+        let mut flipped_winding = mesh.clone();
+        flip_winding(&mut flipped_winding);
+        assert_eq!(mesh.normals, flipped_winding.normals);
+        assert_relative_eq!(-geometry_normal(&mesh), geometry_normal(&flipped_winding));
+    }
+
+    #[test]
+    fn test_any_hit_agrees_with_the_closest_hit_on_occlusion() {
+        // a tilted quad with a triangle behind it, hit from both sides of each
+        let mesh = Arc::new(Mesh {
+            positions: vec![
+                -1.0, -1.0, 0.0, 1.0, -1.0, 0.2, 1.0, 1.0, 0.4, -1.0, 1.0, 0.2, -0.5, -0.5, 2.0,
+                0.5, -0.5, 2.0, 0.0, 0.5, 2.0,
+            ],
+            normals: [0.0, 0.0, -1.0].repeat(7),
+            indices: vec![0, 1, 2, 0, 2, 3, 4, 5, 6],
+            ..Mesh::default()
+        });
+        let triangle_mesh = TriangleMesh::new(mesh.clone(), vec![]);
+        let triangle = Triangle::new(mesh, 4, 5, 6, vec![], None);
+
+        for x in -6..=6 {
+            for y in -6..=6 {
+                let ray = Ray {
+                    point: Point3::new(x as f64 * 0.2, y as f64 * 0.2, -2.0),
+                    direction: Vector3::new(0.05, 0.02, 1.0).normalize(),
+                    t_max: f64::INFINITY,
+                };
+
+                // short of the quad, between the quad and the triangle and beyond both
+                for max_dist in [1.5, 3.0, 10.0] {
+                    let blocks = |hit: Option<(f64, _)>| hit.is_some_and(|(t, _)| t < max_dist);
+
+                    assert_eq!(
+                        blocks(triangle_mesh.test_intersect(ray)),
+                        triangle_mesh.test_intersect_any(ray, max_dist)
+                    );
+                    assert_eq!(
+                        blocks(triangle.test_intersect(ray)),
+                        triangle.test_intersect_any(ray, max_dist)
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_subdivide_splits_a_triangle_at_its_edge_midpoints() {
+        let mesh = Mesh {
+            positions: vec![0.0, 0.0, 0.0, 2.0, 0.0, 0.0, 0.0, 2.0, 0.0],
+            normals: vec![0.0, 0.0, 1.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0],
+            texcoords: vec![0.0, 0.0, 1.0, 0.0, 0.0, 1.0],
+            indices: vec![0, 1, 2],
+            ..Mesh::default()
+        };
+        let subdivided = subdivide(&mesh);
+        let vertex =
+            |values: &[f32], i: u32| Vector3::from_column_slice(&values[3 * i as usize..][..3]);
+
+        assert_eq!(12, subdivided.indices.len());
+        assert_eq!(6, subdivided.positions.len() / 3);
+        // the corner triangle at the first vertex, the others share its midpoints
+        let [_, ab, ca] = [
+            subdivided.indices[0],
+            subdivided.indices[1],
+            subdivided.indices[2],
+        ];
+        assert_eq!(
+            Vector3::new(1.0, 0.0, 0.0),
+            vertex(&subdivided.positions, ab)
+        );
+        assert_eq!(
+            Vector3::new(0.0, 1.0, 0.0),
+            vertex(&subdivided.positions, ca)
+        );
+        assert_eq!(vec![0.5, 0.0], subdivided.texcoords[2 * ab as usize..][..2]);
+        assert_relative_eq!(
+            Vector3::new(0.5, 0.0, 0.5).normalize(),
+            vertex(&subdivided.normals, ab)
+        );
+        let bc = subdivided.indices[10];
+        assert_eq!(
+            Vector3::new(1.0, 1.0, 0.0),
+            vertex(&subdivided.positions, bc)
+        );
+        assert_eq!(vec![ab, bc, ca], subdivided.indices[9..]);
+    }
+}
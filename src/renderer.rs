@@ -1,6 +1,7 @@
 use std::borrow::Borrow;
 use std::cell::RefCell;
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc;
 use std::sync::mpsc::{Receiver, Sender};
 use std::sync::{Arc, RwLock};
@@ -9,10 +10,12 @@ use std::thread::JoinHandle;
 use std::time::SystemTime;
 
 use lazy_static::lazy_static;
-use nalgebra::{Point2, Point3, Vector3};
+use nalgebra::{Point2, Point3, Vector2, Vector3};
 
 use crate::camera::Camera;
-use crate::film::{Bucket, Film};
+use crate::film::{heatmap, Bucket, Film};
+use crate::helpers::offset_ray_origin;
+use crate::light_path::{LightPathExpression, LightPathRadiance};
 use crate::lights::LightIrradianceSample;
 use crate::objects::ObjectTrait;
 use crate::objects::{ArcObject, Object};
@@ -21,11 +24,98 @@ use crate::scene::Scene;
 use crate::surface_interaction::SurfaceInteraction;
 use crate::tracer::trace;
 
-#[derive(Debug, Copy, Clone)]
+/// How the radiance along a camera ray is estimated.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub enum Integrator {
+    /// Light sampling combined with BSDF sampling through MIS
+    #[default]
+    PathTracer,
+    /// Only BSDF sampling, lights are found by hitting them. Slow but simple, a reference to
+    /// compare the path tracer against.
+    BruteForce,
+}
+
+impl Integrator {
+    pub fn from_str(str: &str) -> Option<Integrator> {
+        match str {
+            "path" => Some(Integrator::PathTracer),
+            "brute_force" => Some(Integrator::BruteForce),
+            _ => None,
+        }
+    }
+}
+
+/// Which samples estimate the direct light of the path tracer.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub enum DirectStrategy {
+    /// Light samples and BSDF samples combined through MIS
+    #[default]
+    Mis,
+    /// Only light samples, best for diffuse surfaces under small lights
+    Light,
+    /// Only BSDF samples, best for very glossy surfaces. Delta lights (point, distant) can't be
+    /// hit by BSDF samples, they are still sampled directly.
+    Bsdf,
+}
+
+impl DirectStrategy {
+    pub fn from_str(str: &str) -> Option<DirectStrategy> {
+        match str {
+            "mis" => Some(DirectStrategy::Mis),
+            "light" => Some(DirectStrategy::Light),
+            "bsdf" => Some(DirectStrategy::Bsdf),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Settings {
     pub thread_count: u32,
+    pub integrator: Integrator,
     pub depth_limit: u32,
+    /// Bounce after which russian roulette may end paths
+    pub rr_start_depth: u32,
+    /// Lowest chance of ending a path once russian roulette started
+    pub rr_min_probability: f64,
+    /// Light samples per shading point, more samples give smoother penumbras
+    pub light_samples: u32,
+    /// BSDF samples per shading point, combined with the light samples using MIS
+    pub bsdf_samples: u32,
+    pub direct_strategy: DirectStrategy,
+    /// Blur mirror and sharp glossy surfaces after the first non-specular bounce, trades a
+    /// little bias for less caustic fireflies
+    pub regularize: bool,
+    /// Lower bound for the alpha of microfacet lobes, near mirror gloss is blurred a little to
+    /// get much less noise under area lights. 0 keeps the materials as they are.
+    pub min_alpha: f64,
+    /// Alpha added to reflections per unit of distance the path traveled, so far away mirrors
+    /// and sharp gloss blur a little instead of shimmering. 0 turns it off.
+    pub specular_lod: f64,
+    /// Angle between the camera rays of neighbouring pixels, textured emitters pick their mip
+    /// level from the footprint it gives. 0 reads the emission maps at full resolution.
+    pub pixel_spread_angle: f64,
+    /// Highest luminance of the light picked up at the first indirect bounce, not clamped when
+    /// not set
+    pub first_bounce_clamp: Option<f64>,
+    /// Highest luminance of the glossy and specular part of the direct light at the surface the
+    /// camera sees, small bright highlights then alias less. Not clamped when not set
+    pub highlight_clamp: Option<f64>,
+    /// Secondary rays that travel farther than this see the environment instead of what they
+    /// hit, not limited when not set
+    pub max_gi_distance: Option<f64>,
+    /// Write a heatmap of the largest path throughput of each pixel to `DEBUG_BUFFER`
+    pub debug_throughput: bool,
+    /// Draw the edges of triangles seen by the camera over the render
+    pub wireframe: bool,
     pub max_samples: u32,
+    pub clay: bool,
+    pub light_path_expressions: Vec<LightPathExpression>,
+    /// Every sample of this pixel is recorded in `DEBUG_PIXEL_SAMPLES`
+    pub debug_pixel: Option<Point2<u32>>,
+    /// Only render one in every this many pixels for a quick preview, the gaps are filled with
+    /// the closest rendered pixel. 1 renders every pixel.
+    pub preview_stride: u32,
 }
 
 pub struct DebugBuffer {
@@ -34,6 +124,27 @@ pub struct DebugBuffer {
     pub buffer: Vec<f64>,
 }
 
+impl DebugBuffer {
+    /// Index of the first channel of pixel (x, y), rows are `width` pixels long.
+    fn index(&self, x: u32, y: u32) -> usize {
+        (y * self.width + x) as usize * 3
+    }
+
+    /// Writes `val` to pixel (x, y). Pixels outside the buffer are ignored, it is empty until
+    /// the image size is known.
+    pub fn write(&mut self, x: u32, y: u32, val: Vector3<f64>) {
+        if x >= self.width || y >= self.height {
+            return;
+        }
+
+        let index = self.index(x, y);
+        self.buffer[index..index + 3].copy_from_slice(val.as_slice());
+    }
+}
+
+/// Fraction of their length shadow rays stop short of the light
+const SHADOW_EPSILON: f64 = 1e-4;
+
 lazy_static! {
     pub static ref DEBUG_BUFFER: RwLock<DebugBuffer> = {
         RwLock::new(DebugBuffer {
@@ -44,6 +155,18 @@ lazy_static! {
     };
 }
 
+/// One sample of the debug pixel, to look into fireflies and convergence of a single pixel
+#[derive(Debug, Copy, Clone)]
+pub struct DebugPixelSample {
+    pub pixel: Point2<u32>,
+    pub radiance: Vector3<f64>,
+    pub path_length: u32,
+}
+
+lazy_static! {
+    pub static ref DEBUG_PIXEL_SAMPLES: RwLock<Vec<DebugPixelSample>> = RwLock::new(vec![]);
+}
+
 thread_local! {
     static CURRENT_X: RefCell<u32> = RefCell::new(0);
     static CURRENT_Y: RefCell<u32> = RefCell::new(0);
@@ -51,6 +174,7 @@ thread_local! {
 }
 
 pub struct ThreadMessage {
+    /// The thread was stopped before all buckets were rendered
     pub exit: bool,
     pub finished: bool,
 }
@@ -72,6 +196,9 @@ pub struct StatsThread {
 pub struct Ray {
     pub point: Point3<f64>,
     pub direction: Vector3<f64>,
+    /// Hits farther along the ray than this are ignored, the far plane for camera rays and
+    /// infinity for all other rays.
+    pub t_max: f64,
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -86,24 +213,70 @@ pub struct SampleResult {
     pub p_film: Point2<f64>,
     pub normal: Vector3<f64>,
     pub albedo: Vector3<f64>,
+    pub diffuse: Vector3<f64>,
+    pub glossy: Vector3<f64>,
+    pub transmission: Vector3<f64>,
+    /// The camera ray escaped the scene without hitting an object
+    pub hit_background: bool,
+    /// Fraction of the light blocked at the shadow catcher the camera ray passed through
+    pub shadow: f64,
+    /// Radiance of the paths matching each of the light path expressions in the settings
+    pub light_paths: LightPathRadiance,
+    /// Number of surfaces the path hit
+    pub path_length: u32,
+    /// Weight of the sample in its pixel, the sign of the filter with filter importance
+    /// sampling and 1 otherwise
+    pub filter_weight: f64,
+    /// Largest throughput (luminance) the path reached, for the throughput debug view
+    pub max_throughput: f64,
+}
+
+/// Number of render threads for a requested count, 0 picks one thread per core. More threads than
+/// cores only slow the render down, so the count is capped at the `available` cores.
+pub fn resolve_thread_count(requested: u32, available: u32) -> u32 {
+    match requested {
+        0 => available,
+        requested => requested.min(available),
+    }
+    .max(1)
+}
+
+/// Whether the pixel is rendered with a preview stride, the rendered pixels lie on diagonals
+/// `stride` pixels apart, a checkerboard for a stride of 2.
+pub fn is_preview_pixel(x: u32, y: u32, stride: u32) -> bool {
+    stride <= 1 || (x + y).is_multiple_of(stride)
 }
 
+/// Starts the render threads, setting `stop` makes them return as soon as they finish the
+/// pixel they are working on.
 pub fn render(
-    scene: Scene,
+    scene: Arc<Scene>,
     settings: Settings,
     sampler: SobolSampler,
     camera: Arc<Camera>,
+    stop: Arc<AtomicBool>,
 ) -> (Vec<JoinHandle<()>>, Receiver<ThreadMessage>) {
-    let scene = Arc::new(scene);
     let mut threads: Vec<JoinHandle<()>> = vec![];
 
     let (sender, receiver): (Sender<ThreadMessage>, Receiver<ThreadMessage>) = mpsc::channel();
 
+    if settings.debug_pixel.is_some() {
+        DEBUG_PIXEL_SAMPLES.write().unwrap().clear();
+    }
+
+    camera
+        .film
+        .write()
+        .unwrap()
+        .split_buckets(settings.thread_count);
+
     // thread id is used to divide the work
     for thread_id in 0..settings.thread_count {
         let thread_scene = scene.clone();
         let thread_camera = camera.clone();
-        let mut thread_sampler = sampler.clone();
+        let thread_sampler = sampler.clone();
+        let thread_stop = stop.clone();
+        let thread_settings = settings.clone();
 
         let thread_sender = sender.clone();
 
@@ -121,36 +294,47 @@ pub fn render(
             let mut samples_done = 0;
 
             loop {
-                let bucket = thread_camera.film.write().unwrap().get_bucket();
+                let bucket = thread_camera.film.read().unwrap().get_bucket(thread_id);
 
                 match bucket {
-                    Some(bucket) => {
-                        // this lock should always work so do try_lock
-                        let mut bucket_lock = bucket.try_lock().unwrap();
+                    Some(mut bucket) => {
+                        let mut bucket_sampler = thread_sampler.for_bucket(bucket.index);
 
                         // returns false if thread was requested to stop
                         if !render_work(
-                            &mut bucket_lock,
+                            &mut bucket,
                             &thread_scene,
-                            &settings,
-                            &mut thread_sampler,
+                            &thread_settings,
+                            &mut bucket_sampler,
                             &thread_camera,
+                            &thread_stop,
                         ) {
+                            // the receiver is gone when the render was restarted
+                            let _ = thread_sender.send(ThreadMessage {
+                                exit: true,
+                                finished: false,
+                            });
                             return;
                         }
 
-                        samples_done += bucket_lock.samples.len();
+                        samples_done += bucket.samples.len();
                         thread_camera
                             .film
                             .read()
                             .unwrap()
-                            .write_bucket_pixels(&mut bucket_lock);
+                            .write_bucket_pixels(&mut bucket);
                         // keep the write lock as short as possible
-                        thread_camera
-                            .film
-                            .write()
-                            .unwrap()
-                            .merge_bucket_pixels_to_image_buffer(&bucket_lock);
+                        {
+                            let mut film = thread_camera.film.write().unwrap();
+                            film.merge_bucket_pixels_to_image_buffer(&bucket);
+                            if thread_settings.preview_stride > 1 {
+                                film.fill_preview_gaps(
+                                    bucket.pixel_bounds,
+                                    thread_settings.preview_stride,
+                                );
+                            }
+                        }
+                        thread_camera.film.read().unwrap().complete_bucket();
                     }
                     None => {
                         break;
@@ -180,26 +364,81 @@ pub fn render(
     (threads, receiver)
 }
 
+/// Renders and waits for all render threads. Returns false when the render was cancelled with
+/// `stop`, the film then only holds the buckets that were finished before, the others stay
+/// empty.
+pub fn render_and_wait(
+    scene: Arc<Scene>,
+    settings: Settings,
+    sampler: SobolSampler,
+    camera: Arc<Camera>,
+    stop: Arc<AtomicBool>,
+) -> bool {
+    let (threads, receiver) = render(scene, settings, sampler, camera, stop);
+
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    receiver.try_iter().all(|message| message.finished)
+}
+
 fn render_work(
     bucket: &mut Bucket,
     scene: &Scene,
     settings: &Settings,
     sampler: &mut SobolSampler,
     camera: &Arc<Camera>,
+    stop: &AtomicBool,
 ) -> bool {
+    let filter_sampler = camera.film.read().unwrap().filter_sampler();
+
     for y in bucket.sample_bounds.p_min.y..bucket.sample_bounds.p_max.y {
         for x in bucket.sample_bounds.p_min.x..bucket.sample_bounds.p_max.x {
+            if stop.load(Ordering::Relaxed) {
+                return false;
+            }
+
+            if !is_preview_pixel(x, y, settings.preview_stride) {
+                continue;
+            }
+
             CURRENT_X.with(|current_x| *current_x.borrow_mut() = x);
             CURRENT_Y.with(|current_y| *current_y.borrow_mut() = y);
 
             let mut sample_results: Vec<SampleResult> =
                 Vec::with_capacity(settings.max_samples as usize);
 
-            for _ in 0..settings.max_samples {
-                let camera_sample = sampler.get_camera_sample(Point2::new(x as f64, y as f64));
+            for sample_index in 0..settings.max_samples {
+                sampler.start_pixel_sample(sample_index, settings.max_samples);
+                let pixel = Point2::new(x as f64, y as f64);
+                let mut camera_sample = sampler.get_camera_sample(pixel);
+                let mut p_film = camera_sample.p_film;
+                let mut filter_weight = 1.0;
+
+                // The uniform jitter is replaced by an offset from the pixel center that follows
+                // the filter, the sample then only counts for this pixel.
+                if let Some(filter_sampler) = &filter_sampler {
+                    let (offset, weight) = filter_sampler.sample(camera_sample.p_film - pixel);
+                    p_film = pixel + Vector2::new(0.5, 0.5);
+                    camera_sample.p_film = p_film + offset;
+                    filter_weight = weight;
+                }
+
                 let ray = camera.generate_ray(camera_sample);
 
-                sample_results.push(trace(ray, camera_sample.p_film, settings, scene, sampler));
+                let mut sample_result = trace(ray, p_film, settings, scene, sampler);
+                sample_result.filter_weight = filter_weight;
+                debug_record_sample(settings, &sample_result);
+                sample_results.push(sample_result);
+            }
+
+            if settings.debug_throughput {
+                let max_throughput = sample_results
+                    .iter()
+                    .map(|sample| sample.max_throughput)
+                    .fold(0.0, f64::max);
+                debug_write_pixel(throughput_heatmap(max_throughput));
             }
 
             bucket.add_samples(&sample_results);
@@ -210,8 +449,27 @@ fn render_work(
 }
 
 pub fn check_intersect_scene(ray: Ray, scene: &Scene) -> Option<(SurfaceInteraction, &ArcObject)> {
+    closest_intersection(ray, scene, f64::MAX, false)
+}
+
+/// The closest hit of a bounce ray (not a camera or shadow ray) that is nearer than
+/// `max_distance`. Farther objects and objects hidden from reflections are ignored.
+pub fn check_intersect_bounce(
+    ray: Ray,
+    scene: &Scene,
+    max_distance: f64,
+) -> Option<(SurfaceInteraction, &ArcObject)> {
+    closest_intersection(ray, scene, max_distance, true)
+}
+
+fn closest_intersection(
+    ray: Ray,
+    scene: &Scene,
+    max_distance: f64,
+    bounce: bool,
+) -> Option<(SurfaceInteraction, &ArcObject)> {
     let mut closest_hit: Option<(SurfaceInteraction, &ArcObject)> = None;
-    let mut closest_distance = f64::MAX;
+    let mut closest_distance = max_distance.min(ray.t_max);
 
     let bvh_ray = bvh::ray::Ray::new(
         bvh::Point3::new(ray.point.x as f32, ray.point.y as f32, ray.point.z as f32),
@@ -223,23 +481,18 @@ pub fn check_intersect_scene(ray: Ray, scene: &Scene) -> Option<(SurfaceInteract
     );
 
     let hit_sphere_aabbs = scene.bvh.traverse_iterator(&bvh_ray, &scene.objects);
-    for object in hit_sphere_aabbs {
+    for object in hit_sphere_aabbs.chain(&scene.unbounded_objects) {
+        if bounce && !object.is_visible_in_reflections() {
+            continue;
+        }
+
         if let Some((distance, intersection)) = object.test_intersect(ray) {
             // If we found an intersection we check if the current
-            // closest intersection is farther than the intersection
-            // we found.
-
-            match closest_hit {
-                None => {
-                    closest_hit = Some((intersection, object));
-                    closest_distance = distance;
-                }
-                Some((_, _)) => {
-                    if distance < closest_distance {
-                        closest_hit = Some((intersection, object));
-                        closest_distance = distance;
-                    }
-                }
+            // closest intersection (or the max distance) is farther
+            // than the intersection we found.
+            if distance < closest_distance {
+                closest_hit = Some((intersection, object));
+                closest_distance = distance;
             }
         }
     }
@@ -260,17 +513,8 @@ pub fn check_intersect_scene_simple(ray: Ray, scene: &Scene, max_dist: f64) -> b
     scene
         .bvh
         .traverse_iterator(&bvh_ray, &scene.objects)
-        .any(|object| {
-            if let Some((distance, _)) = object.test_intersect(ray) {
-                // If we found an intersection we check if distance is less
-                // than the max distance we want to check. If so -> exit with true
-                if distance < max_dist {
-                    return true;
-                }
-            }
-
-            false
-        })
+        .chain(&scene.unbounded_objects)
+        .any(|object| object.test_intersect_any(ray, max_dist.min(ray.t_max)))
 }
 
 pub fn check_light_visible(
@@ -278,13 +522,21 @@ pub fn check_light_visible(
     scene: &Scene,
     light_sample: &LightIrradianceSample,
 ) -> bool {
-    let direction = (light_sample.point - interaction.point).normalize();
+    let origin = offset_ray_origin(
+        interaction.point,
+        interaction.p_error,
+        interaction.geometry_normal,
+        light_sample.point - interaction.point,
+    );
+    let to_light = light_sample.point - origin;
     let ray = Ray {
-        point: interaction.point + (direction * 1e-9),
-        direction,
+        point: origin,
+        direction: to_light.normalize(),
+        t_max: f64::INFINITY,
     };
 
-    let distance = nalgebra::distance(&interaction.point, &light_sample.point) - 1e-7;
+    // stop just short of the light, its own surface does not block it
+    let distance = to_light.magnitude() * (1.0 - SHADOW_EPSILON);
 
     if check_intersect_scene_simple(ray, scene, distance) {
         return false;
@@ -304,32 +556,53 @@ lazy_static! {
     };
 }
 
-pub fn debug_write_pixel(val: Vector3<f64>) {
-    let mut buffer = DEBUG_BUFFER.write().unwrap();
-    let mut index = 0;
-    CURRENT_Y.with(|y| {
-        CURRENT_X.with(|x| {
-            index = *y.borrow() * buffer.height + *x.borrow();
-            index *= 3;
+/// Records the sample when the current pixel is the debug pixel of the settings.
+pub fn debug_record_sample(settings: &Settings, sample: &SampleResult) {
+    let pixel = CURRENT_X.with(|x| CURRENT_Y.with(|y| Point2::new(*x.borrow(), *y.borrow())));
+    if settings.debug_pixel == Some(pixel) {
+        DEBUG_PIXEL_SAMPLES.write().unwrap().push(DebugPixelSample {
+            pixel,
+            radiance: sample.radiance,
+            path_length: sample.path_length,
         });
-    });
-    buffer.buffer[index as usize] = val.x;
-    buffer.buffer[(index + 1) as usize] = val.y;
-    buffer.buffer[(index + 2) as usize] = val.z;
+    }
+}
+
+/// Writes the recorded samples of the debug pixel to `debug_pixel_<x>_<y>.csv`, one line with
+/// the radiance and path length per sample.
+pub fn write_debug_pixel_samples() -> std::io::Result<()> {
+    let samples = DEBUG_PIXEL_SAMPLES.read().unwrap();
+    let first = match samples.first() {
+        Some(first) => first,
+        None => return Ok(()),
+    };
+
+    let mut csv = String::from("sample,x,y,z,path_length\n");
+    for (index, sample) in samples.iter().enumerate() {
+        csv.push_str(&format!(
+            "{},{},{},{},{}\n",
+            index, sample.radiance.x, sample.radiance.y, sample.radiance.z, sample.path_length
+        ));
+    }
+
+    std::fs::write(
+        format!("debug_pixel_{}_{}.csv", first.pixel.x, first.pixel.y),
+        csv,
+    )
+}
+
+/// Heatmap color of a path throughput on a log scale, a throughput of 1 or less is blue and
+/// 1000 or more is red. Paths that blow up far past 1 are where fireflies come from.
+pub fn throughput_heatmap(throughput: f64) -> Vector3<f64> {
+    heatmap(throughput.max(1.0).log10() / 3.0)
+}
+
+pub fn debug_write_pixel(val: Vector3<f64>) {
+    debug_write_current_pixel(val);
 }
 
 pub fn debug_write_pixel_f64(val: f64) {
-    let mut buffer = DEBUG_BUFFER.write().unwrap();
-    let mut index = 0;
-    CURRENT_Y.with(|y| {
-        CURRENT_X.with(|x| {
-            index = *y.borrow() * buffer.height + *x.borrow();
-            index *= 3;
-        });
-    });
-    buffer.buffer[index as usize] = val;
-    buffer.buffer[(index + 1) as usize] = val;
-    buffer.buffer[(index + 2) as usize] = val;
+    debug_write_current_pixel(Vector3::repeat(val));
 }
 
 pub fn debug_write_pixel_on_bounce(val: Vector3<f64>, bounce: u32) {
@@ -337,17 +610,7 @@ pub fn debug_write_pixel_on_bounce(val: Vector3<f64>, bounce: u32) {
         return;
     }
 
-    let mut buffer = DEBUG_BUFFER.write().unwrap();
-    let mut index = 0;
-    CURRENT_Y.with(|y| {
-        CURRENT_X.with(|x| {
-            index = *y.borrow() * buffer.height + *x.borrow();
-            index *= 3;
-        });
-    });
-    buffer.buffer[index as usize] = val.x;
-    buffer.buffer[(index + 1) as usize] = val.y;
-    buffer.buffer[(index + 2) as usize] = val.z;
+    debug_write_current_pixel(val);
 }
 
 pub fn debug_write_pixel_f64_on_bounce(val: f64, bounce: u32) {
@@ -355,15 +618,567 @@ pub fn debug_write_pixel_f64_on_bounce(val: f64, bounce: u32) {
         return;
     }
 
-    let mut buffer = DEBUG_BUFFER.write().unwrap();
-    let mut index = 0;
-    CURRENT_Y.with(|y| {
-        CURRENT_X.with(|x| {
-            index = *y.borrow() * buffer.height + *x.borrow();
-            index *= 3;
+    debug_write_current_pixel(Vector3::repeat(val));
+}
+
+/// Writes to the pixel the calling thread is rendering. Each thread renders its own pixels and
+/// the write lock is held for the whole write, so threads never mix up each other's values.
+fn debug_write_current_pixel(val: Vector3<f64>) {
+    let (x, y) = CURRENT_X.with(|x| CURRENT_Y.with(|y| (*x.borrow(), *y.borrow())));
+
+    DEBUG_BUFFER.write().unwrap().write(x, y, val);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, RwLock};
+    use std::thread;
+    use std::time::Instant;
+
+    use bvh::bvh::BVH;
+    use nalgebra::{Point2, Point3, Vector2, Vector3};
+
+    use crate::camera::Camera;
+    use crate::film::{Film, FilterMethod};
+    use crate::helpers::Bounds;
+    use crate::lights::distribution::LightDistribution;
+    use crate::lights::point::PointLight;
+    use crate::lights::{Light, LightIrradianceSample};
+    use crate::materials::matte::MatteMaterial;
+    use crate::materials::mirror::MirrorMaterial;
+    use crate::materials::shadow_catcher::ShadowCatcherMaterial;
+    use crate::materials::Material;
+    use crate::objects::plane::Plane;
+    use crate::objects::rectangle::Rectangle;
+    use crate::objects::{ArcObject, Object};
+    use crate::renderer::{
+        check_intersect_bounce, check_intersect_scene, check_intersect_scene_simple,
+        check_light_visible, is_preview_pixel, render_and_wait, resolve_thread_count,
+        throughput_heatmap, DebugBuffer, DirectStrategy, Integrator, Ray, Settings,
+        DEBUG_PIXEL_SAMPLES,
+    };
+    use crate::sampler::SobolSampler;
+    use crate::scene::{split_unbounded_objects, Scene};
+
+    fn render_with_seed(seed: u64) -> Film {
+        render_with_settings(seed, render_settings())
+    }
+
+    fn render_settings() -> Settings {
+        Settings {
+            thread_count: 2,
+            integrator: Integrator::PathTracer,
+            depth_limit: 4,
+            rr_start_depth: 3,
+            rr_min_probability: 0.05,
+            light_samples: 1,
+            bsdf_samples: 1,
+            direct_strategy: DirectStrategy::Mis,
+            regularize: false,
+            min_alpha: 0.0,
+            specular_lod: 0.0,
+            pixel_spread_angle: 0.0,
+            first_bounce_clamp: None,
+            highlight_clamp: None,
+            max_gi_distance: None,
+            debug_throughput: false,
+            wireframe: false,
+            max_samples: 4,
+            clay: false,
+            light_path_expressions: vec![],
+            debug_pixel: None,
+            preview_stride: 1,
+        }
+    }
+
+    fn render_with_settings(seed: u64, settings: Settings) -> Film {
+        let film = Arc::new(RwLock::new(Film::new(
+            Vector2::new(16, 16),
+            Vector2::new(8, 8),
+            None,
+            None,
+            None,
+            FilterMethod::None,
+            1.0,
+            None,
+            None,
+        )));
+
+        assert!(render_into(
+            seed,
+            settings,
+            film.clone(),
+            Arc::new(AtomicBool::new(false))
+        ));
+
+        Arc::try_unwrap(film).ok().unwrap().into_inner().unwrap()
+    }
+
+    /// Renders a lit plane into `film`, returns whether the render finished.
+    fn render_into(
+        seed: u64,
+        settings: Settings,
+        film: Arc<RwLock<Film>>,
+        stop: Arc<AtomicBool>,
+    ) -> bool {
+        let mut objects = vec![ArcObject(Arc::new(Object::Plane(Plane::new(
+            Point3::origin(),
+            Vector3::y(),
+            vec![Material::Matte(MatteMaterial::new(
+                Vector3::repeat(0.8),
+                0.0,
+            ))],
+        ))))];
+        let bvh = BVH::build(&mut objects);
+        let scene = Scene {
+            bg_color: Vector3::zeros(),
+            bg_lights_scene: false,
+            objects,
+            unbounded_objects: vec![],
+            light_distribution: LightDistribution::uniform(1),
+            lights: vec![Arc::new(Light::Point(PointLight::new(
+                Point3::new(0.0, 2.0, 0.0),
+                Vector3::repeat(10.0),
+            )))],
+            bvh,
+            isolated: None,
+        };
+
+        let camera = Camera::new(
+            Point3::new(0.0, 1.0, 2.0),
+            Point3::origin(),
+            Vector3::y(),
+            0.0,
+            1.0,
+            60.0,
+            0.01,
+            1000.0,
+            0.1,
+            None,
+            Bounds {
+                p_min: Point2::new(-1.0, -1.0),
+                p_max: Point2::new(1.0, 1.0),
+            },
+            film,
+        );
+
+        render_and_wait(
+            Arc::new(scene),
+            settings,
+            SobolSampler::new(seed),
+            Arc::new(camera),
+            stop,
+        )
+    }
+
+    #[test]
+    fn test_preview_stride_renders_half_the_pixels_and_fills_the_rest() {
+        let film = render_with_settings(
+            42,
+            Settings {
+                preview_stride: 2,
+                ..render_settings()
+            },
+        );
+        let sampled = |x: u32, y: u32| film.pixels[(y * 16 + x) as usize].sample_count > 0;
+
+        for y in 0..16 {
+            for x in 0..16 {
+                assert_eq!(is_preview_pixel(x, y, 2), sampled(x, y));
+                // every gap shows one of its rendered neighbours
+                if !sampled(x, y) {
+                    let neighbours = [x.checked_sub(1), Some(x + 1)];
+                    assert!(neighbours.iter().flatten().any(|&n| {
+                        n < 16
+                            && film.image_buffer.get_pixel(n, y)
+                                == film.image_buffer.get_pixel(x, y)
+                    }));
+                }
+            }
+        }
+        assert_eq!(
+            128,
+            film.pixels
+                .iter()
+                .filter(|pixel| pixel.sample_count > 0)
+                .count()
+        );
+    }
+
+    #[test]
+    fn test_same_seed_renders_identical_film() {
+        let film_a = render_with_seed(42);
+        let film_b = render_with_seed(42);
+
+        assert_eq!(film_a.image_buffer.as_raw(), film_b.image_buffer.as_raw());
+        for (pixel_a, pixel_b) in film_a.pixels.iter().zip(film_b.pixels.iter()) {
+            assert_eq!(pixel_a.sum_radiance, pixel_b.sum_radiance);
+        }
+    }
+
+    #[test]
+    fn test_stopping_keeps_only_the_finished_buckets() {
+        let film = Arc::new(RwLock::new(Film::new(
+            Vector2::new(64, 64),
+            Vector2::new(8, 8),
+            None,
+            None,
+            None,
+            FilterMethod::None,
+            1.0,
+            None,
+            None,
+        )));
+        let stop = Arc::new(AtomicBool::new(false));
+
+        // cancel like a UI would, as soon as the first bucket shows up
+        let watcher = {
+            let film = film.clone();
+            let stop = stop.clone();
+            thread::spawn(move || {
+                while film.read().unwrap().completed_buckets() == 0 {
+                    thread::yield_now();
+                }
+                stop.store(true, Ordering::Relaxed);
+            })
+        };
+
+        let finished = render_into(
+            0,
+            Settings {
+                thread_count: 1,
+                max_samples: 8,
+                ..render_settings()
+            },
+            film.clone(),
+            stop,
+        );
+        watcher.join().unwrap();
+
+        assert!(!finished);
+        let film = film.read().unwrap();
+        let completed = film.completed_buckets() as usize;
+        assert!((1..64).contains(&completed));
+
+        // every pixel is either rendered with all of its samples or not at all
+        let rendered = film.pixels.iter().filter(|p| p.sample_count == 8).count();
+        let empty = film.pixels.iter().filter(|p| p.sample_count == 0).count();
+        assert_eq!(completed * 64, rendered);
+        assert_eq!(64 * 64, rendered + empty);
+    }
+
+    #[test]
+    fn test_debug_write_lands_on_its_pixel_in_a_wide_buffer() {
+        let mut buffer = DebugBuffer {
+            width: 4,
+            height: 2,
+            buffer: vec![0.0; 4 * 2 * 3],
+        };
+
+        buffer.write(3, 1, Vector3::new(0.1, 0.2, 0.3));
+        buffer.write(4, 0, Vector3::repeat(1.0));
+
+        assert_eq!(21, buffer.index(3, 1));
+        assert_eq!([0.1, 0.2, 0.3], buffer.buffer[21..24]);
+        // the write outside the buffer is dropped, everything else stays black
+        assert_eq!(
+            3,
+            buffer.buffer.iter().filter(|&&value| value != 0.0).count()
+        );
+    }
+
+    #[test]
+    fn test_large_throughput_shows_hot_in_the_heatmap() {
+        assert_eq!(Vector3::new(0.0, 0.0, 1.0), throughput_heatmap(0.5));
+        assert_eq!(Vector3::new(0.0, 0.0, 1.0), throughput_heatmap(1.0));
+        assert_eq!(Vector3::new(1.0, 0.0, 0.0), throughput_heatmap(1e6));
+        assert!(throughput_heatmap(100.0).x > throughput_heatmap(10.0).x);
+    }
+
+    #[test]
+    fn test_debug_pixel_records_every_sample() {
+        render_with_settings(
+            7,
+            Settings {
+                thread_count: 2,
+                integrator: Integrator::PathTracer,
+                depth_limit: 4,
+                rr_start_depth: 3,
+                rr_min_probability: 0.05,
+                light_samples: 1,
+                bsdf_samples: 1,
+                direct_strategy: DirectStrategy::Mis,
+                regularize: false,
+                min_alpha: 0.0,
+                specular_lod: 0.0,
+                pixel_spread_angle: 0.0,
+                first_bounce_clamp: None,
+                highlight_clamp: None,
+                max_gi_distance: None,
+                debug_throughput: false,
+                wireframe: false,
+                max_samples: 6,
+                clay: false,
+                light_path_expressions: vec![],
+                debug_pixel: Some(Point2::new(5, 11)),
+                preview_stride: 1,
+            },
+        );
+
+        let samples = DEBUG_PIXEL_SAMPLES.read().unwrap();
+        assert_eq!(6, samples.len());
+        assert!(samples
+            .iter()
+            .all(|sample| sample.pixel == Point2::new(5, 11) && sample.path_length <= 4));
+    }
+
+    #[test]
+    fn test_planes_outside_the_bvh_are_intersected() {
+        let material = Material::Matte(MatteMaterial::new(Vector3::repeat(0.8), 0.0));
+        let (mut objects, unbounded_objects) = split_unbounded_objects(vec![
+            ArcObject(Arc::new(Object::Plane(Plane::new(
+                Point3::origin(),
+                Vector3::y(),
+                vec![material.clone()],
+            )))),
+            ArcObject(Arc::new(Object::Rectangle(Rectangle::new(
+                Point3::new(5.0, 1.0, 0.0),
+                Vector3::x(),
+                Vector3::z(),
+                vec![material],
+                None,
+            )))),
+        ]);
+        assert_eq!(1, objects.len());
+        assert_eq!(1, unbounded_objects.len());
+
+        let bvh = BVH::build(&mut objects);
+        let scene = Scene {
+            bg_color: Vector3::zeros(),
+            bg_lights_scene: false,
+            objects,
+            unbounded_objects,
+            light_distribution: LightDistribution::uniform(0),
+            lights: vec![],
+            bvh,
+            isolated: None,
+        };
+
+        let ray = Ray {
+            point: Point3::new(0.0, 2.0, 0.0),
+            direction: -Vector3::y(),
+            t_max: f64::INFINITY,
+        };
+
+        let (interaction, object) = check_intersect_scene(ray, &scene).unwrap();
+        assert!(matches!(object.0.as_ref(), Object::Plane(_)));
+        assert!(interaction.point.y.abs() < 1e-6);
+        assert!(check_intersect_scene_simple(ray, &scene, 3.0));
+        assert!(!check_intersect_scene_simple(ray, &scene, 1.0));
+    }
+
+    #[test]
+    fn test_planes_hidden_from_reflections_only_stop_camera_and_shadow_rays() {
+        let mut catcher = Plane::new(
+            Point3::origin(),
+            Vector3::y(),
+            vec![Material::ShadowCatcher(ShadowCatcherMaterial::new(
+                Vector3::repeat(0.8),
+            ))],
+        );
+        catcher.visible_in_reflections = false;
+        let (mut objects, unbounded_objects) =
+            split_unbounded_objects(vec![ArcObject(Arc::new(Object::Plane(catcher)))]);
+        let bvh = BVH::build(&mut objects);
+        let scene = Scene {
+            bg_color: Vector3::zeros(),
+            bg_lights_scene: false,
+            objects,
+            unbounded_objects,
+            light_distribution: LightDistribution::uniform(0),
+            lights: vec![],
+            bvh,
+            isolated: None,
+        };
+
+        // reflected down off a mirror above the catcher
+        let reflection = Ray {
+            point: Point3::new(0.0, 1.0, 0.0),
+            direction: Vector3::new(0.3, -1.0, 0.0).normalize(),
+            t_max: f64::INFINITY,
+        };
+
+        assert!(check_intersect_bounce(reflection, &scene, f64::MAX).is_none());
+        assert!(check_intersect_scene(reflection, &scene).is_some());
+        assert!(check_intersect_scene_simple(reflection, &scene, 2.0));
+    }
+
+    #[test]
+    fn test_light_outside_a_closed_box_does_not_leak_in() {
+        let material = Material::Matte(MatteMaterial::new(Vector3::repeat(0.8), 0.0));
+        // the six walls of the box from -1 to 1, each as a thin rectangle
+        let wall = |corner: Point3<f64>, side_a: Vector3<f64>, side_b: Vector3<f64>| {
+            ArcObject(Arc::new(Object::Rectangle(Rectangle::new(
+                corner,
+                side_a,
+                side_b,
+                vec![material.clone()],
+                None,
+            ))))
+        };
+        let (x, y, z) = (Vector3::x() * 2.0, Vector3::y() * 2.0, Vector3::z() * 2.0);
+        let low = Point3::new(-1.0, -1.0, -1.0);
+        let high = Point3::new(1.0, 1.0, 1.0);
+        let mut objects = vec![
+            wall(low, x, z),
+            wall(low, y, z),
+            wall(low, x, y),
+            wall(high, -x, -z),
+            wall(high, -y, -z),
+            wall(high, -x, -y),
+        ];
+        let bvh = BVH::build(&mut objects);
+        let scene = Scene {
+            bg_color: Vector3::zeros(),
+            bg_lights_scene: false,
+            objects,
+            unbounded_objects: vec![],
+            light_distribution: LightDistribution::uniform(0),
+            lights: vec![],
+            bvh,
+            isolated: None,
+        };
+
+        // a point on the floor right against the wall at x = 1, seen from inside
+        let ray = Ray {
+            point: Point3::origin(),
+            direction: Vector3::new(1.0 - 1e-7, -1.0, 0.0).normalize(),
+            t_max: f64::INFINITY,
+        };
+        let (receiver, _) = check_intersect_scene(ray, &scene).unwrap();
+        let light_at = |point: Point3<f64>| LightIrradianceSample {
+            irradiance: Vector3::repeat(1.0),
+            point,
+            wi: (point - receiver.point).normalize(),
+            pdf: 1.0,
+        };
+
+        // just behind the wall, grazing along the floor, and further out
+        for outside in [
+            Point3::new(1.0 + 1e-6, -1.0 + 1e-6, 0.0),
+            Point3::new(1.5, -1.0 + 1e-9, 0.0),
+            Point3::new(3.0, 0.5, 0.0),
+        ] {
+            assert!(!check_light_visible(&receiver, &scene, &light_at(outside)));
+        }
+        assert!(check_light_visible(
+            &receiver,
+            &scene,
+            &light_at(Point3::new(0.0, 0.5, 0.0))
+        ));
+    }
+
+    #[test]
+    fn test_thread_count_defaults_to_the_cores_and_is_capped_by_them() {
+        assert_eq!(8, resolve_thread_count(0, 8));
+        assert_eq!(4, resolve_thread_count(4, 8));
+        assert_eq!(8, resolve_thread_count(16, 8));
+        assert_eq!(1, resolve_thread_count(0, 0));
+    }
+
+    /// A benchmark rather than a test, run it with
+    /// `cargo test --release bench_costly_corner -- --ignored --nocapture`. Only the buckets in
+    /// one corner of the image look into a grid of tall mirror walls, where paths bounce until
+    /// the depth limit instead of escaping after a bounce or two.
+    #[test]
+    #[ignore]
+    fn bench_costly_corner() {
+        let mut objects: Vec<ArcObject> = (0..24)
+            .map(|i| {
+                ArcObject(Arc::new(Object::Rectangle(Rectangle::new(
+                    Point3::new(1.0 + i as f64 * 0.06, 0.0, 1.0),
+                    Vector3::y() * 2.0,
+                    Vector3::z() * 1.5,
+                    vec![Material::Mirror(MirrorMaterial::new(Vector3::repeat(0.95)))],
+                    None,
+                ))))
+            })
+            .collect();
+        objects.push(ArcObject(Arc::new(Object::Plane(Plane::new(
+            Point3::origin(),
+            Vector3::y(),
+            vec![Material::Matte(MatteMaterial::new(
+                Vector3::repeat(0.8),
+                0.0,
+            ))],
+        )))));
+        let (mut objects, unbounded_objects) = split_unbounded_objects(objects);
+        let bvh = BVH::build(&mut objects);
+        let scene = Arc::new(Scene {
+            bg_color: Vector3::zeros(),
+            bg_lights_scene: false,
+            objects,
+            unbounded_objects,
+            light_distribution: LightDistribution::uniform(1),
+            lights: vec![Arc::new(Light::Point(PointLight::new(
+                Point3::new(0.0, 3.0, 0.0),
+                Vector3::repeat(10.0),
+            )))],
+            bvh,
+            isolated: None,
         });
-    });
-    buffer.buffer[index as usize] = val;
-    buffer.buffer[(index + 1) as usize] = val;
-    buffer.buffer[(index + 2) as usize] = val;
+
+        for thread_count in [1, 2, 4, 8] {
+            let film = Arc::new(RwLock::new(Film::new(
+                Vector2::new(128, 128),
+                Vector2::new(16, 16),
+                None,
+                None,
+                None,
+                FilterMethod::None,
+                1.0,
+                None,
+                None,
+            )));
+            // looking straight down, the slab covers the top right corner
+            let camera = Camera::new(
+                Point3::new(0.0, 4.0, 0.0),
+                Point3::origin(),
+                Vector3::z(),
+                0.0,
+                1.0,
+                60.0,
+                0.01,
+                1000.0,
+                0.0,
+                None,
+                Bounds {
+                    p_min: Point2::new(-1.0, -1.0),
+                    p_max: Point2::new(1.0, 1.0),
+                },
+                film.clone(),
+            );
+            let settings = Settings {
+                thread_count,
+                depth_limit: 32,
+                rr_start_depth: 32,
+                ..render_settings()
+            };
+
+            let start = Instant::now();
+            assert!(render_and_wait(
+                scene.clone(),
+                settings,
+                SobolSampler::new(0),
+                Arc::new(camera),
+                Arc::new(AtomicBool::new(false)),
+            ));
+            println!(
+                "{thread_count} threads: {:.2?}, {} buckets stolen",
+                start.elapsed(),
+                film.read().unwrap().stolen_buckets()
+            );
+        }
+    }
 }
@@ -2,6 +2,7 @@ use std::f64::consts::PI;
 
 use lazy_static::lazy_static;
 use nalgebra::Point2;
+use rand::rngs::StdRng;
 use rand::*;
 use sobol::params::JoeKuoD6;
 use sobol::Sobol;
@@ -32,15 +33,26 @@ pub struct Sample {
     pub ray: Ray,
 }
 
+/// Up to this many samples per pixel the light picks are stratified over the samples, above it
+/// independent random picks already cover the lights well.
+const STRATIFIED_LIGHT_SAMPLE_LIMIT: u32 = 16;
+
 #[derive(Clone)]
 pub struct SobolSampler {
+    seed: u64,
+    rng: StdRng,
+    offset: [f64; 3],
+    pixel_sample: Option<(u32, u32)>,
+    /// Put the first sample of every pixel at its center, only the later samples are jittered.
+    /// A render with a single sample per pixel then looks sharp instead of offset and noisy.
+    pub center_first_sample: bool,
     sobol_1d: Sobol<f64>,
     sobol_2d: Sobol<f64>,
     sobol_3d: Sobol<f64>,
 }
 
 impl SobolSampler {
-    pub fn new() -> Self {
+    pub fn new(seed: u64) -> Self {
         // let aspect_ratio = image_width as f64 / image_height as f64;
         //
         // // fov = horizontal
@@ -59,30 +71,180 @@ impl SobolSampler {
         let sobol_3d = Sobol::<f64>::new(3, &sobol_params);
 
         SobolSampler {
+            seed,
+            rng: StdRng::seed_from_u64(seed),
+            offset: [0.0; 3],
+            pixel_sample: None,
+            center_first_sample: false,
             sobol_1d,
             sobol_2d,
             sobol_3d,
         }
     }
 
+    /// Returns a sampler for rendering one bucket. Buckets are handed out to the threads in
+    /// whatever order they ask for them, so the random state is derived from the seed and the
+    /// bucket index (not the thread id) to make a render reproducible. The Sobol sequence
+    /// starts over for every bucket and is decorrelated between buckets with a random offset
+    /// (Cranley-Patterson rotation).
+    pub fn for_bucket(&self, bucket_index: u32) -> SobolSampler {
+        let mut sampler = self.clone();
+        sampler.rng = StdRng::seed_from_u64(
+            self.seed
+                .wrapping_mul(0x9e37_79b9_7f4a_7c15)
+                .wrapping_add(bucket_index as u64),
+        );
+        sampler.offset = [sampler.rng.gen(), sampler.rng.gen(), sampler.rng.gen()];
+
+        sampler
+    }
+
+    /// Sampler for another frame of the same view, so repeated frames get different samples.
+    /// Only the first frame puts a sample at the pixel centers, the first samples of later
+    /// frames are jittered so the accumulated frames are antialiased.
+    pub fn for_frame(&self, frame: u32) -> SobolSampler {
+        let mut sampler = SobolSampler::new(self.seed.wrapping_add(frame as u64));
+        sampler.center_first_sample = self.center_first_sample && frame == 0;
+
+        sampler
+    }
+
+    /// Random number generator for the decisions that are not driven by the Sobol sequence,
+    /// seeded so renders are reproducible.
+    pub fn rng(&mut self) -> &mut StdRng {
+        &mut self.rng
+    }
+
     pub fn get_1d(&mut self) -> f64 {
-        self.sobol_1d.next().unwrap().pop().unwrap()
+        let sample = self.sobol_1d.next().unwrap();
+        self.rotate(sample).pop().unwrap()
     }
 
     pub fn get_2d(&mut self) -> Vec<f64> {
-        self.sobol_2d.next().unwrap()
+        let sample = self.sobol_2d.next().unwrap();
+        self.rotate(sample)
     }
 
     pub fn get_3d(&mut self) -> Vec<f64> {
-        self.sobol_3d.next().unwrap()
+        let sample = self.sobol_3d.next().unwrap();
+        self.rotate(sample)
+    }
+
+    /// Marks the start of sample `index` out of `count` samples for the current pixel, used to
+    /// stratify the light selection for the camera ray.
+    pub fn start_pixel_sample(&mut self, index: u32, count: u32) {
+        self.pixel_sample = Some((index, count));
+    }
+
+    /// Sample used to pick a light. The first pick of a pixel sample is stratified over the
+    /// samples of the pixel so every light gets its fair share, picks for later bounces are
+    /// independent.
+    pub fn get_light_sample(&mut self) -> f64 {
+        let u = self.rng.gen::<f64>();
+        match self.pixel_sample.take() {
+            Some((index, count)) if count <= STRATIFIED_LIGHT_SAMPLE_LIMIT => {
+                (index as f64 + u) / count as f64
+            }
+            _ => u,
+        }
     }
 
     pub fn get_camera_sample(&mut self, pixel_pos: Point2<f64>) -> CameraSample {
-        let p_film = pixel_pos + Point2::from_slice(&self.sobol_2d.next().unwrap()).coords;
+        let jitter = Point2::from_slice(&self.get_2d());
+        let jitter = match self.pixel_sample {
+            Some((0, _)) if self.center_first_sample => Point2::new(0.5, 0.5),
+            _ => jitter,
+        };
+        let p_film = pixel_pos + jitter.coords;
 
         CameraSample {
-            p_lens: Point2::from_slice(&self.sobol_2d.next().unwrap()),
+            p_lens: Point2::from_slice(&self.get_2d()),
             p_film,
         }
     }
+
+    fn rotate(&self, mut sample: Vec<f64>) -> Vec<f64> {
+        for (value, offset) in sample.iter_mut().zip(self.offset) {
+            *value = (*value + offset).fract();
+        }
+
+        sample
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::Point2;
+
+    use crate::lights::distribution::LightDistribution;
+    use crate::sampler::SobolSampler;
+
+    #[test]
+    fn test_light_selection_is_stratified_over_pixel_samples() {
+        let mut sampler = SobolSampler::new(0).for_bucket(0);
+        let distribution = LightDistribution::uniform(2);
+
+        for _ in 0..100 {
+            let mut selected = vec![];
+            for sample_index in 0..2 {
+                sampler.start_pixel_sample(sample_index, 2);
+                selected.push(distribution.sample(sampler.get_light_sample()));
+            }
+
+            assert_eq!(vec![0, 1], selected);
+        }
+    }
+
+    #[test]
+    fn test_first_sample_is_centered_and_later_ones_jittered() {
+        let mut sampler = SobolSampler::new(0).for_bucket(0);
+        sampler.center_first_sample = true;
+        let pixel = Point2::new(3.0, 7.0);
+
+        for _ in 0..4 {
+            sampler.start_pixel_sample(0, 4);
+            let first = sampler.get_camera_sample(pixel);
+            assert_eq!(Point2::new(3.5, 7.5), first.p_film);
+
+            let later: Vec<_> = (1..4)
+                .map(|index| {
+                    sampler.start_pixel_sample(index, 4);
+                    sampler.get_camera_sample(pixel).p_film
+                })
+                .collect();
+            for p_film in &later {
+                assert_ne!(Point2::new(3.5, 7.5), *p_film);
+                assert!((3.0..4.0).contains(&p_film.x) && (7.0..8.0).contains(&p_film.y));
+            }
+            assert_ne!(later[0], later[1]);
+        }
+
+        // off by default, the first sample is jittered as well
+        let mut sampler = SobolSampler::new(0).for_bucket(0);
+        sampler.start_pixel_sample(0, 4);
+        sampler.get_camera_sample(pixel);
+        sampler.start_pixel_sample(0, 4);
+        assert_ne!(
+            Point2::new(3.5, 7.5),
+            sampler.get_camera_sample(pixel).p_film
+        );
+    }
+
+    #[test]
+    fn test_only_the_first_frame_centers_its_first_sample() {
+        let mut sampler = SobolSampler::new(0);
+        sampler.center_first_sample = true;
+        let pixel = Point2::new(3.0, 7.0);
+
+        let first_sample_of_frame = |frame| {
+            let mut sampler = sampler.for_frame(frame).for_bucket(0);
+            sampler.start_pixel_sample(0, 4);
+            sampler.get_camera_sample(pixel).p_film
+        };
+
+        assert_eq!(Point2::new(3.5, 7.5), first_sample_of_frame(0));
+        for frame in 1..4 {
+            assert_ne!(Point2::new(3.5, 7.5), first_sample_of_frame(frame));
+        }
+    }
 }
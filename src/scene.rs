@@ -1,43 +1,163 @@
 use std::borrow::BorrowMut;
+use std::collections::{BTreeMap, HashMap};
+use std::f64::consts::PI;
+use std::fmt;
 use std::fs::File;
 use std::io::Read;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
-use bvh::bvh::BVH;
+use bvh::aabb::{Bounded, AABB};
+use bvh::bvh::{BVHNode, BVH};
 use image::io::Reader;
+use image::DynamicImage;
 use indicatif::ProgressBar;
 use nalgebra::{Matrix3, Matrix4, Point3, Rotation3, Translation3, Vector3};
 use tobj::{LoadOptions, Mesh};
-use yaml_rust::YamlLoader;
+use yaml_rust::{Yaml, YamlLoader};
 
-use crate::helpers::yaml_array_into_vector3;
+use crate::bsdf::helpers::fresnel::FresnelModel;
+use crate::bsdf::helpers::microfacet_distribution::RoughnessRemap;
+use crate::helpers::{yaml_array_into_vector3, yaml_as_f64};
 use crate::lights::area::AreaLight;
 use crate::lights::distant::DistantLight;
+use crate::lights::distribution::{LightDistribution, LightSampling};
 use crate::lights::infinite_area::InfiniteAreaLight;
 use crate::lights::point::PointLight;
-use crate::lights::Light;
+use crate::lights::portal::PortalLight;
+use crate::lights::sky::SkyLight;
+use crate::lights::{Light, LightTrait};
 use crate::materials::glass::GlassMaterial;
 use crate::materials::matte::MatteMaterial;
 use crate::materials::mirror::MirrorMaterial;
-use crate::materials::plastic::PlasticMaterial;
+use crate::materials::phong::PhongMaterial;
+use crate::materials::plastic::{PlasticMaterial, SpecularModel};
+use crate::materials::shadow_catcher::ShadowCatcherMaterial;
+use crate::materials::subsurface::SubsurfaceMaterial;
+use crate::materials::unlit::UnlitMaterial;
 use crate::materials::Material;
+use crate::objects::cone::Cone;
+use crate::objects::instance::{Instance, InstanceGeometry};
 use crate::objects::plane::Plane;
+use crate::objects::quad::Quad;
 use crate::objects::rectangle::Rectangle;
 use crate::objects::triangle::Triangle;
-use crate::objects::ArcObject;
+use crate::objects::triangle_mesh::{
+    flip_normals, flip_winding, recompute_normals, subdivide, TriangleMesh,
+};
+use crate::objects::{ArcObject, ObjectTrait};
+use crate::textures::mip_map::{FilterMode, ImageWrapMethod, MipMap};
 use crate::{yaml_array_into_point3, Object};
 
+/// Named materials objects can refer to, looked up like the other files of a scene
+const MATERIAL_LIBRARY_FILE: &str = "materials.yaml";
+/// Library material used by objects without a `material`
+const DEFAULT_MATERIAL: &str = "default";
+/// Roughness of imported materials without a `Pr` roughness or `Ns` exponent
+const DEFAULT_MODEL_ROUGHNESS: f64 = 0.05;
+
+/// Why a scene could not be loaded.
+#[derive(Debug)]
+pub enum SceneError {
+    /// A file the scene refers to is in none of the searched folders
+    MissingFile {
+        file: String,
+        searched: Vec<PathBuf>,
+    },
+    /// A file was found but could not be read
+    InvalidFile { file: PathBuf, message: String },
+}
+
+impl SceneError {
+    fn invalid(file: &Path, error: impl fmt::Display) -> SceneError {
+        SceneError::InvalidFile {
+            file: file.to_path_buf(),
+            message: error.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneError::MissingFile { file, searched } => {
+                let searched: Vec<String> = searched
+                    .iter()
+                    .map(|folder| folder.display().to_string())
+                    .collect();
+                write!(f, "Cannot find {}, searched {}", file, searched.join(", "))
+            }
+            SceneError::InvalidFile { file, message } => {
+                write!(f, "Cannot load {}: {}", file.display(), message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SceneError {}
+
+/// Folders the files of a scene are looked up in, the scene folder first and then the
+/// `asset_paths` listed in the scene (relative to the scene folder).
+struct AssetPaths(Vec<PathBuf>);
+
+impl AssetPaths {
+    fn new(scene_folder: &Path, scene_yaml: &Yaml) -> Self {
+        let mut folders = vec![scene_folder.to_path_buf()];
+        folders.extend(
+            scene_yaml["asset_paths"]
+                .clone()
+                .into_iter()
+                .filter_map(|folder| folder.as_str().map(|folder| scene_folder.join(folder))),
+        );
+
+        AssetPaths(folders)
+    }
+
+    fn resolve(&self, file: &str) -> Result<PathBuf, SceneError> {
+        self.0
+            .iter()
+            .map(|folder| folder.join(file))
+            .find(|path| path.is_file())
+            .ok_or_else(|| SceneError::MissingFile {
+                file: file.to_string(),
+                searched: self.0.clone(),
+            })
+    }
+}
+
+/// Counts of what ended up in a scene, to spot misconfigured scenes.
+#[derive(Debug)]
+pub struct SceneSummary {
+    pub objects: BTreeMap<&'static str, usize>,
+    pub triangles: usize,
+    pub lights: BTreeMap<&'static str, usize>,
+    pub unique_materials: usize,
+    pub bvh_nodes: usize,
+    pub bvh_depth: usize,
+    /// Bounds of all objects except the infinite planes
+    pub bounds: AABB,
+}
+
 pub struct Scene {
+    /// Seen by camera rays that miss everything when there is no environment light
     pub bg_color: Vector3<f64>,
+    /// Let the background color also light the scene, rays that miss at any bounce see it
+    pub bg_lights_scene: bool,
     pub objects: Vec<ArcObject>,
+    /// Objects with infinite bounds (planes), tested for every ray instead of through the BVH
+    pub unbounded_objects: Vec<ArcObject>,
     pub lights: Vec<Arc<Light>>,
+    /// Picks the light to sample for direct lighting, follows the `importance` of the lights
+    pub light_distribution: LightDistribution,
     pub bvh: BVH,
+    /// Name of the only object camera rays stop at, see `isolate`
+    pub isolated: Option<String>,
 }
 
 impl Scene {
     pub fn new(
         bg_color: Vector3<f64>,
+        bg_lights_scene: bool,
         lights: Vec<Arc<Light>>,
         objects: Vec<ArcObject>,
         meshes: Vec<Arc<Mesh>>,
@@ -45,33 +165,105 @@ impl Scene {
     ) -> Scene {
         Scene {
             bg_color,
+            bg_lights_scene,
             objects,
+            unbounded_objects: vec![],
+            light_distribution: LightDistribution::uniform(lights.len()),
             lights,
             bvh,
+            isolated: None,
         }
     }
 
-    pub fn load_from_folder(path: &Path) -> Scene {
+    pub fn load_from_folder(path: &Path) -> Result<Scene, SceneError> {
         println!("Load scene from {:?}", path.display());
-        let mut file = File::open(path.join("scene.yaml")).expect("Unable to open scene.yaml file");
+        let scene_file = AssetPaths(vec![path.to_path_buf()]).resolve("scene.yaml")?;
         let mut contents = String::new();
 
-        file.read_to_string(&mut contents)
-            .expect("Unable to read file");
-        let scene_yaml = &YamlLoader::load_from_str(&contents).unwrap()[0];
-
-        let (mut objects, meshes) = if let Some(filename) = scene_yaml["world"]["file"].as_str() {
-            let world_model_file = path.join(Path::new(filename));
-            let up_axis = scene_yaml["world"]["up_axis"].as_str().unwrap();
-            load_model(world_model_file.as_path(), up_axis)
-        } else {
-            (vec![], vec![])
+        File::open(&scene_file)
+            .and_then(|mut file| file.read_to_string(&mut contents))
+            .map_err(|error| SceneError::invalid(&scene_file, error))?;
+        let scene_yaml = &YamlLoader::load_from_str(&contents)
+            .map_err(|error| SceneError::invalid(&scene_file, error))?[0];
+        let assets = AssetPaths::new(path, scene_yaml);
+        let material_library = match assets.resolve(MATERIAL_LIBRARY_FILE) {
+            Ok(file) => load_material_library(&file)?,
+            Err(_) => HashMap::new(),
         };
 
+        let roughness_remap = scene_yaml["roughness_remap"]
+            .as_str()
+            .map(|remap| RoughnessRemap::from_str(remap).expect("Unknown roughness remap"))
+            .unwrap_or_default();
+        let fresnel_model = scene_yaml["fresnel"]
+            .as_str()
+            .map(|model| FresnelModel::from_str(model).expect("Unknown fresnel model"))
+            .unwrap_or_default();
+        let specular_model = scene_yaml["model"]
+            .as_str()
+            .map(|model| SpecularModel::from_str(model).expect("Unknown specular model"))
+            .unwrap_or_default();
+        let energy_compensation = scene_yaml["energy_compensation"].as_bool().unwrap_or(false);
+
+        let (mut objects, meshes, model_lights) =
+            if let Some(filename) = scene_yaml["world"]["file"].as_str() {
+                let world_model_file = assets.resolve(filename)?;
+                let up_axis = scene_yaml["world"]["up_axis"].as_str().unwrap();
+                // either true or the crease angle in degrees
+                let recompute_normals = &scene_yaml["world"]["recompute_normals"];
+                let crease_angle = match recompute_normals {
+                    Yaml::Boolean(true) => Some(60.0),
+                    Yaml::Integer(angle) => Some(*angle as f64),
+                    Yaml::Real(_) => recompute_normals.as_f64(),
+                    _ => None,
+                };
+                load_model(
+                    world_model_file.as_path(),
+                    up_axis,
+                    model_override_material(&scene_yaml["world"], &material_library),
+                    scene_yaml["world"]["visible_to_camera"]
+                        .as_bool()
+                        .unwrap_or(true),
+                    roughness_remap,
+                    fresnel_model,
+                    specular_model,
+                    energy_compensation,
+                    crease_angle,
+                    scene_yaml["world"]["vertex_colors"]
+                        .as_bool()
+                        .unwrap_or(false),
+                    scene_yaml["world"]["double_sided"]
+                        .as_bool()
+                        .unwrap_or(false),
+                    scene_yaml["world"]["flip_normals"]
+                        .as_bool()
+                        .unwrap_or(false),
+                    scene_yaml["world"]["flip_winding"]
+                        .as_bool()
+                        .unwrap_or(false),
+                    scene_yaml["world"]["subdivide"]
+                        .as_i64()
+                        .unwrap_or(0)
+                        .max(0) as u32,
+                    yaml_as_f64(&scene_yaml["world"]["emission_scale"]).unwrap_or(1.0),
+                )?
+            } else {
+                (vec![], vec![], vec![])
+            };
+
         let mut lights: Vec<Arc<Light>> = vec![];
+        // lights are sampled proportional to their importance, this does not change how much
+        // light they emit
+        let mut light_importance: Vec<f64> = vec![];
+
+        // emissive triangles of the world model
+        let model_importance = yaml_as_f64(&scene_yaml["world"]["importance"]).unwrap_or(1.0);
+        light_importance.extend(model_lights.iter().map(|_| model_importance));
+        lights.extend(model_lights);
 
         for light_config in scene_yaml["lights"].clone() {
             let l_type = light_config["type"].as_str().unwrap();
+            let importance = yaml_as_f64(&light_config["importance"]).unwrap_or(1.0);
 
             if l_type == "area" {
                 let l_pos = yaml_array_into_point3(&light_config["position"]);
@@ -87,7 +279,12 @@ impl Scene {
                     None,
                 ))));
 
-                let light = Arc::new(Light::Area(AreaLight::new(light_rectangle, l_intensity)));
+                let mut light = AreaLight::new(light_rectangle, l_intensity);
+                if let Some(emission_map) = light_config["emission_map"].as_str() {
+                    light.emission_map =
+                        Some(Arc::new(load_emission_map(&assets.resolve(emission_map)?)?));
+                }
+                let light = Arc::new(Light::Area(light));
 
                 let light_rectangle = ArcObject(Arc::new(Object::Rectangle(Rectangle::new(
                     l_pos,
@@ -101,6 +298,7 @@ impl Scene {
                 ))));
 
                 lights.push(light);
+                light_importance.push(importance);
                 objects.push(light_rectangle);
             }
 
@@ -113,46 +311,406 @@ impl Scene {
                 )));
 
                 lights.push(light);
+                light_importance.push(importance);
             }
         }
 
-        if let Some(environment_map) = scene_yaml["environment_map"].as_str() {
-            let image_map = Reader::open(path.join(environment_map))
-                .expect("Environment map not found.")
+        // HDR formats (.hdr, .exr) decode to float images, so keep the map in float to preserve
+        // the dynamic range of bright sources like the sun
+        let environment_map = if scene_yaml["environment_map"]["file"].is_badvalue() {
+            scene_yaml["environment_map"].as_str()
+        } else {
+            scene_yaml["environment_map"]["file"].as_str()
+        };
+
+        if let Some(environment_map) = environment_map {
+            let clamp = if !scene_yaml["environment_map"]["clamp"].is_badvalue() {
+                Some((
+                    scene_yaml["environment_map"]["clamp"][0].as_f64().unwrap(),
+                    scene_yaml["environment_map"]["clamp"][1].as_f64().unwrap(),
+                ))
+            } else {
+                None
+            };
+
+            let environment_map = assets.resolve(environment_map)?;
+            let image_map = Reader::open(&environment_map)
+                .map_err(|error| SceneError::invalid(&environment_map, error))?
                 .decode()
-                .expect("Cannot decode environment map.");
+                .map_err(|error| SceneError::invalid(&environment_map, error))?;
             let infinite_light = Light::InfiniteArea(InfiniteAreaLight::new(
                 &Vector3::repeat(1.0),
-                image_map.to_rgb8(),
+                image_map.to_rgb32f(),
                 Matrix4::new_translation(&Vector3::new(0.0, 1.0, 0.0)),
+                clamp,
+                scene_yaml["environment_map"]["filter"]
+                    .as_str()
+                    .map(|filter| FilterMode::from_str(filter).expect("Unknown filter mode"))
+                    .unwrap_or(FilterMode::Bilinear),
+                scene_yaml["environment_map"]["warp_resolution"]
+                    .as_i64()
+                    .unwrap_or(512) as u32,
             ));
 
             lights.push(Arc::new(infinite_light));
+            light_importance
+                .push(yaml_as_f64(&scene_yaml["environment_map"]["importance"]).unwrap_or(1.0));
+        }
+
+        let sky_config = &scene_yaml["sky"];
+        if !sky_config.is_badvalue() {
+            lights.push(Arc::new(Light::Sky(SkyLight::new(
+                yaml_array_into_vector3(&sky_config["sun_direction"]),
+                sky_config["turbidity"].as_f64().unwrap_or(3.0),
+                if sky_config["ground_albedo"].is_badvalue() {
+                    Vector3::repeat(0.2)
+                } else {
+                    yaml_array_into_vector3(&sky_config["ground_albedo"])
+                },
+                sky_config["sun_temperature"].as_f64().unwrap_or(5800.0),
+                sky_config["sun_irradiance"].as_f64().unwrap_or(20.0),
+                sky_config["intensity"].as_f64().unwrap_or(1.0),
+            ))));
+            light_importance.push(yaml_as_f64(&sky_config["importance"]).unwrap_or(1.0));
+        }
+
+        // With portals the environment is only sampled through the window openings they cover
+        let portals: Vec<Rectangle> = scene_yaml["portals"]
+            .clone()
+            .into_iter()
+            .map(|portal_config| {
+                Rectangle::new(
+                    yaml_array_into_point3(&portal_config["position"]),
+                    yaml_array_into_vector3(&portal_config["side_a"]),
+                    yaml_array_into_vector3(&portal_config["side_b"]),
+                    vec![],
+                    None,
+                )
+            })
+            .collect();
+        if !portals.is_empty() {
+            for light in lights.iter_mut() {
+                if matches!(light.as_ref(), Light::InfiniteArea(_) | Light::Sky(_)) {
+                    *light = Arc::new(Light::Portal(PortalLight::new(
+                        light.clone(),
+                        portals.clone(),
+                    )));
+                }
+            }
+        }
+
+        for object_config in scene_yaml["objects"].clone() {
+            let o_type = object_config["type"].as_str().unwrap();
+
+            let material = entry_material(&object_config, &material_library);
+            let transform = yaml_into_transform(&object_config);
+
+            let visible_to_camera = object_config["visible_to_camera"].as_bool().unwrap_or(true);
+
+            if o_type == "cone" {
+                let mut cone = Cone::new(
+                    object_config["radius"].as_f64().unwrap(),
+                    object_config["height"].as_f64().unwrap(),
+                    object_config["phi_max"].as_f64().unwrap_or(360.0),
+                    transform,
+                    vec![material],
+                );
+                cone.visible_to_camera = visible_to_camera;
+                cone.double_sided = object_config["double_sided"].as_bool().unwrap_or(false);
+                cone.name = object_config["name"].as_str().map(String::from);
+
+                objects.push(ArcObject(Arc::new(Object::Cone(cone))));
+            } else if o_type == "quad" {
+                let vertices = yaml_into_quad_vertices(&object_config["vertices"])
+                    .map(|vertex| transform.transform_point(&vertex));
+                let mut quad = Quad::new(vertices, vec![material], None);
+                quad.visible_to_camera = visible_to_camera;
+                quad.double_sided = object_config["double_sided"].as_bool().unwrap_or(false);
+                quad.name = object_config["name"].as_str().map(String::from);
+
+                objects.push(ArcObject(Arc::new(Object::Quad(quad))));
+            } else if o_type == "plane" {
+                let normal = if object_config["normal"].is_badvalue() {
+                    Vector3::y()
+                } else {
+                    yaml_array_into_vector3(&object_config["normal"])
+                };
+                let mut plane = Plane::new(
+                    transform.transform_point(&Point3::origin()),
+                    transform.transform_vector(&normal),
+                    vec![material],
+                );
+                plane.visible_to_camera = visible_to_camera;
+                plane.visible_in_reflections = object_config["visible_in_reflections"]
+                    .as_bool()
+                    .unwrap_or(true);
+
+                objects.push(ArcObject(Arc::new(Object::Plane(plane))));
+            }
         }
 
-        let floor = ArcObject(Arc::new(Object::Plane(Plane::new(
-            Point3::new(0.0, -0.1, 0.0),
-            Vector3::new(0.0, 1.0, 0.0),
-            vec![Material::Matte(MatteMaterial::new(
-                Vector3::repeat(0.9),
-                1.0,
-            ))],
-        ))));
+        // Every model is loaded once and shared by all of its instances
+        let mut instance_geometries: HashMap<String, Arc<InstanceGeometry>> = HashMap::new();
+
+        for instance_config in scene_yaml["instances"].clone() {
+            let model = instance_config["model"].as_str().unwrap();
 
-        objects.push(floor);
+            let geometry = match instance_geometries.get(model) {
+                Some(geometry) => geometry.clone(),
+                None => {
+                    // emissive materials of instanced models are not sampled as lights
+                    let (model_objects, _, _) = load_model(
+                        assets.resolve(model)?.as_path(),
+                        "y",
+                        None,
+                        true,
+                        roughness_remap,
+                        fresnel_model,
+                        specular_model,
+                        energy_compensation,
+                        None,
+                        false,
+                        false,
+                        false,
+                        false,
+                        0,
+                        1.0,
+                    )?;
+                    let geometry = Arc::new(InstanceGeometry::new(model_objects));
+                    instance_geometries.insert(model.to_string(), geometry.clone());
+                    geometry
+                }
+            };
+
+            // `model` of an instance is its model file, not the specular model of its material
+            let mut material_config = instance_config.clone();
+            if let Yaml::Hash(keys) = &mut material_config {
+                keys.remove(&Yaml::String("model".to_string()));
+            }
+            let material = model_override_material(&material_config, &material_library)
+                .unwrap_or_else(|| {
+                    model_material(
+                        DEFAULT_MODEL_ROUGHNESS,
+                        roughness_remap,
+                        fresnel_model,
+                        specular_model,
+                        energy_compensation,
+                        false,
+                    )
+                });
+            let visible_to_camera = instance_config["visible_to_camera"]
+                .as_bool()
+                .unwrap_or(true);
+            let name = instance_config["name"].as_str().unwrap_or(model);
+            let double_sided = instance_config["double_sided"].as_bool().unwrap_or(false);
+
+            for transform_config in instance_config["transforms"].clone() {
+                let mut instance = Instance::new(
+                    geometry.clone(),
+                    yaml_into_transform(&transform_config),
+                    vec![material.clone()],
+                );
+                instance.visible_to_camera = visible_to_camera;
+                instance.double_sided = double_sided;
+                instance.name = Some(name.to_string());
+
+                objects.push(ArcObject(Arc::new(Object::Instance(instance))));
+            }
+        }
+
+        // a matte floor just below the origin, only when the scene asks for it
+        if scene_yaml["add_default_floor"].as_bool().unwrap_or(false) {
+            let floor = ArcObject(Arc::new(Object::Plane(Plane::new(
+                Point3::new(0.0, -0.1, 0.0),
+                Vector3::new(0.0, 1.0, 0.0),
+                vec![Material::Matte(MatteMaterial::new(
+                    Vector3::repeat(0.9),
+                    1.0,
+                ))],
+            ))));
+
+            objects.push(floor);
+        }
 
         // Build scene
+        let (mut objects, unbounded_objects) = split_unbounded_objects(objects);
+
+        let light_sampling = scene_yaml["light_sampling"]
+            .as_str()
+            .map(|sampling| LightSampling::from_str(sampling).expect("Unknown light sampling"))
+            .unwrap_or_default();
+        if light_sampling == LightSampling::Power {
+            let scene_radius = scene_radius(&objects);
+            for (importance, light) in light_importance.iter_mut().zip(&lights) {
+                *importance *= light.power(scene_radius).y;
+            }
+        }
         println!("Building BVH...");
         let bvh = BVH::build(&mut objects);
         println!("Done!");
 
         println!("Scene loaded.");
 
-        Scene {
-            bg_color: Vector3::new(0.5, 0.5, 0.5),
+        let scene = Scene {
+            bg_color: Vector3::zeros(),
+            bg_lights_scene: false,
             objects,
+            unbounded_objects,
             lights,
+            light_distribution: LightDistribution::new(&light_importance),
             bvh,
+            isolated: None,
+        };
+        scene.print_summary();
+
+        Ok(scene)
+    }
+
+    /// Renders only the objects called `name`, to look at a single asset. The other objects are
+    /// hidden from the camera. With `keep_shadows` they still cast shadows and show up in
+    /// reflections, otherwise they are removed. Objects that emit light are always kept.
+    pub fn isolate(&mut self, name: &str, keep_shadows: bool) {
+        let is_isolated = |object: &ArcObject| object.get_name() == Some(name);
+        assert!(
+            self.objects
+                .iter()
+                .chain(&self.unbounded_objects)
+                .any(is_isolated),
+            "No object named {}",
+            name
+        );
+
+        if !keep_shadows {
+            let objects = std::mem::take(&mut self.objects)
+                .into_iter()
+                .chain(std::mem::take(&mut self.unbounded_objects))
+                .filter(|object| is_isolated(object) || object.get_light().is_some())
+                .collect();
+            let (mut objects, unbounded_objects) = split_unbounded_objects(objects);
+            self.bvh = BVH::build(&mut objects);
+            self.objects = objects;
+            self.unbounded_objects = unbounded_objects;
+        }
+
+        self.isolated = Some(name.to_string());
+    }
+
+    /// Camera rays pass through hidden objects and through everything but the isolated object.
+    pub fn is_visible_to_camera(&self, object: &ArcObject) -> bool {
+        let isolated = match &self.isolated {
+            Some(name) => object.get_name() == Some(name.as_str()),
+            None => true,
+        };
+
+        isolated && object.is_visible_to_camera()
+    }
+
+    pub fn summary(&self) -> SceneSummary {
+        let mut objects = BTreeMap::new();
+        let mut triangles = 0;
+        let mut materials: Vec<&Material> = vec![];
+
+        for object in self.objects.iter().chain(&self.unbounded_objects) {
+            let name = match object.0.as_ref() {
+                Object::Triangle(_) => "triangle",
+                Object::TriangleMesh(_) => "triangle mesh",
+                Object::Plane(_) => "plane",
+                Object::Rectangle(_) => "rectangle",
+                Object::Quad(_) => "quad",
+                Object::Cone(_) => "cone",
+                Object::Instance(_) => "instance",
+            };
+            *objects.entry(name).or_insert(0) += 1;
+
+            match object.0.as_ref() {
+                Object::Triangle(_) => triangles += 1,
+                Object::TriangleMesh(mesh) => triangles += mesh.triangle_count(),
+                _ => {}
+            }
+
+            // materials can't be hashed, the few unique ones are compared one by one
+            for material in object.get_materials() {
+                if !materials.contains(&material) {
+                    materials.push(material);
+                }
+            }
+        }
+
+        let mut lights = BTreeMap::new();
+        for light in &self.lights {
+            let name = match light.as_ref() {
+                Light::Point(_) => "point",
+                Light::Area(_) => "area",
+                Light::Distant(_) => "distant",
+                Light::InfiniteArea(_) => "environment",
+                Light::Sky(_) => "sky",
+                Light::Portal(_) => "portal",
+            };
+            *lights.entry(name).or_insert(0) += 1;
+        }
+
+        let mut bvh_depth = 0;
+        let mut stack = if self.bvh.nodes.is_empty() {
+            vec![]
+        } else {
+            vec![(0, 1)]
+        };
+        while let Some((index, depth)) = stack.pop() {
+            bvh_depth = bvh_depth.max(depth);
+
+            if let BVHNode::Node {
+                child_l_index,
+                child_r_index,
+                ..
+            } = self.bvh.nodes[index]
+            {
+                stack.push((child_l_index, depth + 1));
+                stack.push((child_r_index, depth + 1));
+            }
+        }
+
+        SceneSummary {
+            objects,
+            triangles,
+            lights,
+            unique_materials: materials.len(),
+            bvh_nodes: self.bvh.nodes.len(),
+            bvh_depth,
+            bounds: self.bounding_box(),
+        }
+    }
+
+    /// Bounds of all objects except the infinite planes, empty when there are only planes.
+    pub fn bounding_box(&self) -> AABB {
+        self.objects
+            .iter()
+            .chain(&self.unbounded_objects)
+            // planes are infinite and would swallow the bounds of everything else
+            .filter(|object| !matches!(object.0.as_ref(), Object::Plane(_)))
+            .fold(AABB::empty(), |bounds, object| bounds.join(&object.aabb()))
+    }
+
+    pub fn print_summary(&self) {
+        let summary = self.summary();
+
+        println!("Scene summary:");
+        for (name, count) in &summary.objects {
+            println!("  {} {}", count, name);
+        }
+        println!("  {} triangles", summary.triangles);
+        for (name, count) in &summary.lights {
+            println!("  {} {} lights", count, name);
+        }
+        println!("  {} unique materials", summary.unique_materials);
+        println!(
+            "  BVH: {} nodes, depth {}",
+            summary.bvh_nodes, summary.bvh_depth
+        );
+        if !summary.bounds.is_empty() {
+            println!(
+                "  Bounds: {:?} to {:?}",
+                summary.bounds.min, summary.bounds.max
+            );
         }
     }
 
@@ -161,7 +719,228 @@ impl Scene {
     }
 }
 
-fn load_model(model_file: &Path, _up_axis: &str) -> (Vec<ArcObject>, Vec<Arc<Mesh>>) {
+/// Radius of the sphere around the bounded objects, 1 for a scene without any.
+fn scene_radius(objects: &[ArcObject]) -> f64 {
+    let bounds = objects
+        .iter()
+        .fold(AABB::empty(), |bounds, object| bounds.join(&object.aabb()));
+
+    if bounds.is_empty() {
+        1.0
+    } else {
+        (bounds.max - bounds.min).length() as f64 / 2.0
+    }
+}
+
+/// Splits off the objects with infinite bounds, every ray would hit their box so they make the
+/// BVH useless. Returns the bounded and the unbounded objects. When nothing bounded is left
+/// everything stays in the BVH, it can not be built without objects.
+pub fn split_unbounded_objects(objects: Vec<ArcObject>) -> (Vec<ArcObject>, Vec<ArcObject>) {
+    let (unbounded, bounded): (Vec<_>, Vec<_>) = objects
+        .into_iter()
+        .partition(|object| matches!(object.0.as_ref(), Object::Plane(_)));
+
+    if bounded.is_empty() {
+        return (unbounded, vec![]);
+    }
+
+    (bounded, unbounded)
+}
+
+/// Builds the material of an object entry, or of an entry in the material library, from its
+/// `material` type and parameters.
+fn parse_material(config: &Yaml) -> Material {
+    let color = if config["color"].is_badvalue() {
+        Vector3::repeat(0.8)
+    } else {
+        yaml_array_into_vector3(&config["color"])
+    };
+
+    match config["material"].as_str().unwrap_or("matte") {
+        "glass" => Material::Glass(
+            GlassMaterial::new(
+                color,
+                if config["absorption"].is_badvalue() {
+                    Vector3::zeros()
+                } else {
+                    yaml_array_into_vector3(&config["absorption"])
+                },
+            )
+            .with_fresnel_model(
+                config["fresnel"]
+                    .as_str()
+                    .map(|model| FresnelModel::from_str(model).expect("Unknown fresnel model"))
+                    .unwrap_or_default(),
+            ),
+        ),
+        "subsurface" => Material::Subsurface(SubsurfaceMaterial::new(
+            color,
+            config["albedo"].as_f64().unwrap_or(0.95),
+            config["mean_free_path"].as_f64().unwrap_or(0.05),
+        )),
+        "plastic" => {
+            let plastic = PlasticMaterial::new(
+                color,
+                if config["specular"].is_badvalue() {
+                    Vector3::repeat(1.0)
+                } else {
+                    yaml_array_into_vector3(&config["specular"])
+                },
+                yaml_as_f64(&config["roughness"]).unwrap_or(DEFAULT_MODEL_ROUGHNESS),
+                config["roughness_remap"]
+                    .as_str()
+                    .map(|remap| RoughnessRemap::from_str(remap).expect("Unknown roughness remap"))
+                    .unwrap_or_default(),
+                config["fresnel"]
+                    .as_str()
+                    .map(|model| FresnelModel::from_str(model).expect("Unknown fresnel model"))
+                    .unwrap_or_default(),
+                config["model"]
+                    .as_str()
+                    .map(|model| SpecularModel::from_str(model).expect("Unknown specular model"))
+                    .unwrap_or_default(),
+                config["energy_compensation"].as_bool().unwrap_or(false),
+                false,
+            );
+
+            // a single alpha is used along both directions
+            let alpha_x = yaml_as_f64(&config["alpha_x"]);
+            let alpha_y = yaml_as_f64(&config["alpha_y"]);
+            Material::Plastic(match alpha_x.or(alpha_y) {
+                Some(alpha) => {
+                    plastic.with_alpha(alpha_x.unwrap_or(alpha), alpha_y.unwrap_or(alpha))
+                }
+                None => plastic,
+            })
+        }
+        "shadow_catcher" => Material::ShadowCatcher(ShadowCatcherMaterial::new(color)),
+        "unlit" => Material::Unlit(UnlitMaterial::new(color)),
+        _ => Material::Matte(MatteMaterial::new(color, 0.0)),
+    }
+}
+
+/// Material of an object, world or instance entry. `material` is either the name of a material
+/// in the library or the type of a material described by the keys of the entry itself.
+fn entry_material(config: &Yaml, library: &HashMap<String, Material>) -> Material {
+    match library.get(config["material"].as_str().unwrap_or(DEFAULT_MATERIAL)) {
+        Some(material) => material.clone(),
+        None => parse_material(config),
+    }
+}
+
+/// Material that replaces the materials of a model, only when the world or instance entry sets
+/// one. Models keep the materials of their obj file otherwise.
+fn model_override_material(config: &Yaml, library: &HashMap<String, Material>) -> Option<Material> {
+    (!config["material"].is_badvalue()).then(|| entry_material(config, library))
+}
+
+/// Loads the named materials of a material library, each entry takes the same keys as the
+/// material of an object.
+fn load_material_library(file: &Path) -> Result<HashMap<String, Material>, SceneError> {
+    let mut contents = String::new();
+    File::open(file)
+        .and_then(|mut file| file.read_to_string(&mut contents))
+        .map_err(|error| SceneError::invalid(file, error))?;
+    let library_yaml =
+        YamlLoader::load_from_str(&contents).map_err(|error| SceneError::invalid(file, error))?;
+
+    let mut library = HashMap::new();
+    if let Some(Yaml::Hash(entries)) = library_yaml.first() {
+        for (name, config) in entries {
+            let name = name
+                .as_str()
+                .ok_or_else(|| SceneError::invalid(file, "material names must be strings"))?;
+            library.insert(name.to_string(), parse_material(config));
+        }
+    }
+
+    Ok(library)
+}
+
+fn yaml_into_quad_vertices(yaml: &Yaml) -> [Point3<f64>; 4] {
+    let vertices: Vec<Point3<f64>> = yaml
+        .as_vec()
+        .expect("Quad needs a list of vertices")
+        .iter()
+        .map(yaml_array_into_point3)
+        .collect();
+
+    vertices
+        .try_into()
+        .expect("Quad needs exactly four vertices")
+}
+
+/// Reads `position`, an optional `rotation` (in degrees around the x, y and z axis) and an
+/// optional uniform `scale` into an object to world transform.
+fn yaml_into_transform(yaml: &Yaml) -> Matrix4<f64> {
+    let rotation = if yaml["rotation"].is_badvalue() {
+        Vector3::zeros()
+    } else {
+        yaml_array_into_vector3(&yaml["rotation"]) * (PI / 180.0)
+    };
+
+    Translation3::from(yaml_array_into_vector3(&yaml["position"])).to_homogeneous()
+        * Rotation3::from_euler_angles(rotation.x, rotation.y, rotation.z).to_homogeneous()
+        * Matrix4::new_scaling(yaml["scale"].as_f64().unwrap_or(1.0))
+}
+
+fn model_material(
+    roughness: f64,
+    roughness_remap: RoughnessRemap,
+    fresnel_model: FresnelModel,
+    specular_model: SpecularModel,
+    energy_compensation: bool,
+    vertex_colors: bool,
+) -> Material {
+    Material::Plastic(PlasticMaterial::new(
+        Vector3::new(0.7, 0.7, 0.7),
+        Vector3::repeat(1.0),
+        roughness,
+        roughness_remap,
+        fresnel_model,
+        specular_model,
+        energy_compensation,
+        vertex_colors,
+    ))
+}
+
+/// Roughness of an imported material. A `Pr` roughness (the PBR extension of the mtl format)
+/// is used as is, a Phong exponent `Ns` is converted with sqrt(2 / (Ns + 2)), the roughness of
+/// a microfacet lobe about as wide.
+fn mtl_roughness(material: &tobj::Material) -> Option<f64> {
+    let pr = material
+        .unknown_param
+        .get("Pr")
+        .and_then(|pr| pr.trim().parse::<f64>().ok());
+
+    match pr {
+        Some(roughness) => Some(roughness.clamp(0.0, 1.0)),
+        None if material.shininess > 0.0 => Some((2.0 / (material.shininess as f64 + 2.0)).sqrt()),
+        None => None,
+    }
+}
+
+/// The objects and meshes of a model, and the lights of its emissive triangles
+type LoadedModel = (Vec<ArcObject>, Vec<Arc<Mesh>>, Vec<Arc<Light>>);
+
+#[allow(clippy::too_many_arguments)]
+fn load_model(
+    model_file: &Path,
+    _up_axis: &str,
+    override_material: Option<Material>,
+    visible_to_camera: bool,
+    roughness_remap: RoughnessRemap,
+    fresnel_model: FresnelModel,
+    specular_model: SpecularModel,
+    energy_compensation: bool,
+    crease_angle: Option<f64>,
+    vertex_colors: bool,
+    double_sided: bool,
+    flip_normals_on_import: bool,
+    flip_winding_on_import: bool,
+    subdivisions: u32,
+    emission_scale: f64,
+) -> Result<LoadedModel, SceneError> {
     //dbg!(model_file);
     let (models, materials) = tobj::load_obj(
         model_file,
@@ -172,17 +951,33 @@ fn load_model(model_file: &Path, _up_axis: &str) -> (Vec<ArcObject>, Vec<Arc<Mes
             ignore_lines: true,
         },
     )
-    .expect("Failed to load file");
+    .map_err(|error| SceneError::invalid(model_file, error))?;
 
-    let materials = materials.unwrap();
+    let materials = materials.map_err(|error| SceneError::invalid(model_file, error))?;
 
     //dbg!(&materials);
     let mut triangles: Vec<ArcObject> = vec![];
     let mut meshes = vec![];
+    let mut lights = vec![];
 
-    for (i, m) in models.iter().enumerate() {
-        let mesh = Arc::new(m.mesh.clone());
-        println!("model[{}].name = \'{}\'", i, m.name);
+    for m in models.iter() {
+        // the winding is fixed first, recomputed normals follow it and are then interpolated
+        // over the subdivided triangles
+        let mut mesh = m.mesh.clone();
+        if flip_winding_on_import {
+            flip_winding(&mut mesh);
+        }
+        let mut mesh = match crease_angle {
+            Some(crease_angle) => recompute_normals(&mesh, crease_angle),
+            None => mesh,
+        };
+        for _ in 0..subdivisions {
+            mesh = subdivide(&mesh);
+        }
+        if flip_normals_on_import {
+            flip_normals(&mut mesh);
+        }
+        let mesh = Arc::new(mesh);
         //println!("model[{}].mesh.material_id = {:?}", i, mesh.material_id);
 
         // Normals and texture coordinates are also loaded, but not printed in this example
@@ -200,70 +995,515 @@ fn load_model(model_file: &Path, _up_axis: &str) -> (Vec<ArcObject>, Vec<Arc<Mes
         let bar = ProgressBar::new((mesh.indices.len() / 3) as u64);
 
         let material = mesh.material_id.map(|material_id| &materials[material_id]);
+        let roughness = material
+            .and_then(mtl_roughness)
+            .unwrap_or(DEFAULT_MODEL_ROUGHNESS);
 
-        for v in 0..mesh.indices.len() / 3 {
-            let color = if let Some(material) = material {
-                Vector3::new(
-                    material.diffuse[0] as f64,
-                    material.diffuse[1] as f64,
-                    material.diffuse[2] as f64,
-                )
-            } else {
-                Vector3::repeat(0.8)
-            };
+        // A material set in the scene replaces the material of every mesh. Otherwise vertex
+        // colors replace it when enabled, and materials with a Phong exponent and specular
+        // color keep their highlight.
+        let mesh_material = override_material.clone().unwrap_or_else(|| match material {
+            _ if vertex_colors && !mesh.vertex_color.is_empty() => model_material(
+                roughness,
+                roughness_remap,
+                fresnel_model,
+                specular_model,
+                energy_compensation,
+                true,
+            ),
+            Some(material)
+                if material.shininess > 0.0 && material.specular.iter().any(|&s| s > 0.0) =>
+            {
+                Material::Phong(PhongMaterial::new(
+                    Vector3::new(
+                        material.diffuse[0] as f64,
+                        material.diffuse[1] as f64,
+                        material.diffuse[2] as f64,
+                    ),
+                    Vector3::new(
+                        material.specular[0] as f64,
+                        material.specular[1] as f64,
+                        material.specular[2] as f64,
+                    ),
+                    material.shininess as f64,
+                ))
+            }
+            _ => model_material(
+                roughness,
+                roughness_remap,
+                fresnel_model,
+                specular_model,
+                energy_compensation,
+                false,
+            ),
+        });
 
-            // let specular = Vector3::new(
-            //     material.specular[0] as f64,
-            //     material.specular[1] as f64,
-            //     material.specular[2] as f64,
-            // );
-
-            //let specular = Vector3::new(1.0, 1.0, 1.0);
-
-            // let _reflection = material.specular[0] as f64;
-
-            let triangle = Triangle::new(
-                mesh.clone(),
-                mesh.indices[3 * v] as usize,
-                mesh.indices[3 * v + 1] as usize,
-                mesh.indices[3 * v + 2] as usize,
-                // vec![Material::MatteMaterial(MatteMaterial::new(
-                //     //weight: 1.0,
-                //     color,
-                //     //specular,
-                //     20.0, //(material.shininess / 1000.0) as f64,
-                //          //ior: material.optical_density as f64,
-                //          //refraction: 1.0 - material.dissolve as f64,
-                // ))],
-                // vec![Material::Glass(GlassMaterial::new(
-                //     Vector3::repeat(1.0),
-                //     // Vector3::repeat(1.0),
-                //      //0.03,
-                // ))],
-                // vec![Material::Glass(GlassMaterial::new(
-                //     Vector3::repeat(0.6),
-                //     // Vector3::repeat(1.0),
-                //     //0.03,
-                // ))],
-                vec![Material::Plastic(PlasticMaterial::new(
-                    Vector3::new(0.7, 0.7, 0.7),
-                    Vector3::repeat(1.0),
-                    0.05,
-                ))],
-                None,
-            );
+        // Meshes with an emission texture (map_Ke) are split up in triangles that are each an
+        // area light, like the lights in the scene file. Ke scales the texture.
+        let emission_map = material
+            .and_then(|material| material.unknown_param.get("map_Ke"))
+            .map(|file| load_emission_map(&model_file.with_file_name(file)))
+            .transpose()?
+            .map(Arc::new);
+        if let (Some(emission_map), Some(material)) = (emission_map, material) {
+            let intensity = material
+                .unknown_param
+                .get("Ke")
+                .map(|ke| {
+                    parse_color(ke).ok_or_else(|| {
+                        SceneError::invalid(model_file, format!("Invalid Ke color: {ke}"))
+                    })
+                })
+                .transpose()?
+                .unwrap_or(Vector3::repeat(1.0))
+                * emission_scale;
 
-            triangles.push(ArcObject(Arc::new(Object::Triangle(triangle))));
+            for face in mesh.indices.chunks(3) {
+                let [v0, v1, v2] = [face[0], face[1], face[2]].map(|index| index as usize);
+                let shape = Triangle::new(mesh.clone(), v0, v1, v2, vec![], None);
+                let mut light =
+                    AreaLight::new(ArcObject(Arc::new(Object::Triangle(shape))), intensity);
+                light.emission_map = Some(emission_map.clone());
+                let light = Arc::new(Light::Area(light));
 
-            if v % 1000 == 0 {
-                bar.inc(1000);
+                let mut triangle = Triangle::new(
+                    mesh.clone(),
+                    v0,
+                    v1,
+                    v2,
+                    vec![mesh_material.clone()],
+                    Some(light.clone()),
+                );
+                triangle.visible_to_camera = visible_to_camera;
+                triangle.double_sided = double_sided;
+                triangles.push(ArcObject(Arc::new(Object::Triangle(triangle))));
+                lights.push(light);
             }
+
+            bar.inc((mesh.indices.len() / 3) as u64);
+            meshes.push(mesh.clone());
+            bar.finish();
+            continue;
         }
 
+        let mut triangle_mesh = TriangleMesh::new(mesh.clone(), vec![mesh_material]);
+        triangle_mesh.name = Some(m.name.clone());
+        triangle_mesh.visible_to_camera = visible_to_camera;
+        triangle_mesh.double_sided = double_sided;
+        bar.inc(triangle_mesh.triangle_count() as u64);
+
+        triangles.push(ArcObject(Arc::new(Object::TriangleMesh(triangle_mesh))));
+
         meshes.push(mesh.clone());
 
         bar.finish();
     }
 
-    (triangles, meshes)
+    Ok((triangles, meshes, lights))
+}
+
+/// Loads the texture of a textured emitter. 8 bit images are taken to be sRGB, float images
+/// (.hdr, .exr) are linear.
+/// Emission maps are filtered trilinearly, the camera sees them averaged over its pixels so
+/// small emitters with detailed textures do not alias.
+fn load_emission_map(file: &Path) -> Result<MipMap, SceneError> {
+    let image = Reader::open(file)
+        .map_err(|error| SceneError::invalid(file, error))?
+        .decode()
+        .map_err(|error| SceneError::invalid(file, error))?;
+    let is_srgb = !matches!(
+        image,
+        DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_)
+    );
+
+    Ok(MipMap::new(
+        image.to_rgb32f(),
+        ImageWrapMethod::Repeat,
+        FilterMode::Trilinear,
+        is_srgb,
+    ))
+}
+
+/// Parses a color written as three numbers, like `Ke 1.0 0.8 0.5` in a material library.
+fn parse_color(color: &str) -> Option<Vector3<f64>> {
+    let values: Vec<f64> = color
+        .split_whitespace()
+        .map(|value| value.parse().ok())
+        .collect::<Option<_>>()?;
+
+    match values[..] {
+        [r, g, b] => Some(Vector3::new(r, g, b)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use approx::assert_relative_eq;
+    use bvh::bvh::BVH;
+    use image::{Rgb, RgbImage};
+    use nalgebra::{Point3, Vector3};
+    use tobj::Mesh;
+
+    use crate::bsdf::helpers::fresnel::FresnelModel;
+    use crate::bsdf::helpers::microfacet_distribution::RoughnessRemap;
+    use crate::lights::distant::DistantLight;
+    use crate::lights::point::PointLight;
+    use crate::lights::{Light, LightTrait};
+    use crate::materials::glass::GlassMaterial;
+    use crate::materials::matte::MatteMaterial;
+    use crate::materials::plastic::{PlasticMaterial, SpecularModel};
+    use crate::materials::shadow_catcher::ShadowCatcherMaterial;
+    use crate::materials::Material;
+    use crate::objects::plane::Plane;
+    use crate::objects::rectangle::Rectangle;
+    use crate::objects::triangle_mesh::TriangleMesh;
+    use crate::objects::{ArcObject, Object, ObjectTrait};
+    use crate::scene::{mtl_roughness, Scene, SceneError};
+
+    #[test]
+    fn test_mtl_roughness_from_pr_or_shininess() {
+        let shiny = tobj::Material {
+            shininess: 1000.0,
+            ..tobj::Material::default()
+        };
+        let roughness = mtl_roughness(&shiny).unwrap();
+        assert!(roughness > 0.0 && roughness < 0.05);
+
+        let mut pbr = tobj::Material {
+            shininess: 1000.0,
+            ..tobj::Material::default()
+        };
+        pbr.unknown_param
+            .insert("Pr".to_string(), "0.5".to_string());
+        assert_eq!(Some(0.5), mtl_roughness(&pbr));
+
+        // without either the default roughness of the importer is used
+        assert_eq!(None, mtl_roughness(&tobj::Material::default()));
+    }
+
+    #[test]
+    fn test_summary_counts_scene_contents() {
+        let gray = Material::Matte(MatteMaterial::new(Vector3::repeat(0.5), 0.0));
+        let red = Material::Matte(MatteMaterial::new(Vector3::new(0.8, 0.1, 0.1), 0.0));
+        let mesh = Mesh {
+            positions: vec![0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 1.0, 0.0, 0.0, 1.0, 0.0],
+            normals: vec![0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0],
+            indices: vec![0, 1, 2, 0, 2, 3],
+            ..Mesh::default()
+        };
+        let rectangle = |position: Point3<f64>| {
+            ArcObject(Arc::new(Object::Rectangle(Rectangle::new(
+                position,
+                Vector3::x(),
+                Vector3::z(),
+                vec![gray.clone()],
+                None,
+            ))))
+        };
+
+        let mut objects = vec![
+            ArcObject(Arc::new(Object::Plane(Plane::new(
+                Point3::origin(),
+                Vector3::y(),
+                vec![gray.clone()],
+            )))),
+            rectangle(Point3::new(-2.0, 1.0, 0.0)),
+            rectangle(Point3::new(3.0, 1.0, 0.0)),
+            ArcObject(Arc::new(Object::TriangleMesh(TriangleMesh::new(
+                Arc::new(mesh),
+                vec![red],
+            )))),
+        ];
+        let bvh = BVH::build(&mut objects);
+        let lights = vec![
+            Arc::new(Light::Point(PointLight::new(
+                Point3::new(0.0, 2.0, 0.0),
+                Vector3::repeat(1.0),
+            ))),
+            Arc::new(Light::Point(PointLight::new(
+                Point3::new(1.0, 2.0, 0.0),
+                Vector3::repeat(1.0),
+            ))),
+            Arc::new(Light::Distant(DistantLight::new(
+                Point3::origin(),
+                1e20,
+                -Vector3::y(),
+                Vector3::repeat(1.0),
+            ))),
+        ];
+        let scene = Scene::new(Vector3::zeros(), false, lights, objects, vec![], bvh);
+
+        let summary = scene.summary();
+
+        assert_eq!(Some(&1), summary.objects.get("plane"));
+        assert_eq!(Some(&2), summary.objects.get("rectangle"));
+        assert_eq!(Some(&1), summary.objects.get("triangle mesh"));
+        assert_eq!(2, summary.triangles);
+        assert_eq!(Some(&2), summary.lights.get("point"));
+        assert_eq!(Some(&1), summary.lights.get("distant"));
+        assert_eq!(2, summary.unique_materials);
+        assert_eq!(7, summary.bvh_nodes);
+        assert!(summary.bvh_depth >= 3);
+        // the infinite plane is left out of the bounds
+        assert_eq!(-2.0, summary.bounds.min.x);
+        assert!(summary.bounds.max.x >= 4.0 && summary.bounds.max.x < 4.1);
+    }
+
+    #[test]
+    fn test_missing_model_is_an_error_naming_the_file() {
+        let folder = std::env::temp_dir().join("rust_raytracer_missing_model");
+        std::fs::create_dir_all(&folder).unwrap();
+        std::fs::write(
+            folder.join("scene.yaml"),
+            "world:\n  file: missing.obj\n  up_axis: y\nasset_paths: [ models ]\n",
+        )
+        .unwrap();
+
+        let error = Scene::load_from_folder(&folder).err().unwrap();
+        let message = error.to_string();
+
+        assert!(matches!(error, SceneError::MissingFile { .. }));
+        assert!(message.contains("missing.obj"));
+        assert!(message.contains(&folder.join("models").display().to_string()));
+    }
+
+    #[test]
+    fn test_scene_only_contains_what_it_declares_without_the_default_floor() {
+        let folder = std::env::temp_dir().join("rust_raytracer_default_floor");
+        std::fs::create_dir_all(&folder).unwrap();
+        let scene_yaml = "lights:\n  - type: distant\n    direction: [ 0.0,-1.0,0.0 ]\n    \
+                          intensity: [ 1.0,1.0,1.0 ]\nobjects:\n  - type: cone\n    \
+                          position: [ 0.0,0.0,0.0 ]\n    radius: 0.2\n    height: 0.5\n";
+
+        std::fs::write(folder.join("scene.yaml"), scene_yaml).unwrap();
+        let scene = Scene::load_from_folder(&folder).unwrap();
+        assert_eq!(1, scene.lights.len());
+        assert_eq!(1, scene.objects.len());
+        assert!(scene.unbounded_objects.is_empty());
+
+        std::fs::write(
+            folder.join("scene.yaml"),
+            format!("{scene_yaml}add_default_floor: true\n"),
+        )
+        .unwrap();
+        let scene = Scene::load_from_folder(&folder).unwrap();
+        assert_eq!(1, scene.lights.len());
+        assert_eq!(1, scene.objects.len());
+        assert!(matches!(
+            scene.unbounded_objects[..],
+            [ArcObject(ref floor)] if matches!(**floor, Object::Plane(_))
+        ));
+    }
+
+    #[test]
+    fn test_objects_get_the_parameters_of_their_library_material() {
+        let folder = std::env::temp_dir().join("rust_raytracer_material_library");
+        std::fs::create_dir_all(&folder).unwrap();
+        std::fs::write(
+            folder.join("materials.yaml"),
+            "default:\n  color: [ 0.2,0.3,0.4 ]\n\
+             tinted_glass:\n  material: glass\n  color: [ 0.9,1.0,0.9 ]\n  absorption: [ 0.5,0.1,0.1 ]\n",
+        )
+        .unwrap();
+        let cone =
+            "  - type: cone\n    position: [ 0.0,0.0,0.0 ]\n    radius: 0.2\n    height: 0.5\n";
+        std::fs::write(
+            folder.join("scene.yaml"),
+            format!(
+                "objects:\n{}    material: tinted_glass\n{}    name: plain\n",
+                cone, cone
+            ),
+        )
+        .unwrap();
+
+        let scene = Scene::load_from_folder(&folder).unwrap();
+        let material = |name: Option<&str>| {
+            let cone = scene
+                .objects
+                .iter()
+                .find(|object| object.get_name() == name)
+                .unwrap();
+            cone.get_materials()[0].clone()
+        };
+
+        assert_eq!(
+            Material::Glass(GlassMaterial::new(
+                Vector3::new(0.9, 1.0, 0.9),
+                Vector3::new(0.5, 0.1, 0.1)
+            )),
+            material(None)
+        );
+        // objects without a material get the default of the library
+        assert_eq!(
+            Material::Matte(MatteMaterial::new(Vector3::new(0.2, 0.3, 0.4), 0.0)),
+            material(Some("plain"))
+        );
+    }
+
+    #[test]
+    fn test_models_and_instances_take_the_material_and_visibility_of_their_entry() {
+        let folder = std::env::temp_dir().join("rust_raytracer_model_material");
+        std::fs::create_dir_all(&folder).unwrap();
+        std::fs::write(
+            folder.join("triangle.obj"),
+            "o triangle\nv 0 0 0\nv 1 0 0\nv 0 0 1\nf 1 2 3\n",
+        )
+        .unwrap();
+        std::fs::write(
+            folder.join("scene.yaml"),
+            "world:\n  file: triangle.obj\n  up_axis: y\n  material: shadow_catcher\n  \
+             visible_to_camera: false\n\
+             instances:\n  - model: triangle.obj\n    name: instance\n    material: glass\n    \
+             transforms:\n      - position: [ 0.0,1.0,0.0 ]\n",
+        )
+        .unwrap();
+
+        let scene = Scene::load_from_folder(&folder).unwrap();
+        let object = |name: &str| {
+            scene
+                .objects
+                .iter()
+                .find(|object| object.get_name() == Some(name))
+                .unwrap()
+        };
+
+        let world = object("triangle");
+        assert_eq!(
+            Material::ShadowCatcher(ShadowCatcherMaterial::new(Vector3::repeat(0.8))),
+            world.get_materials()[0]
+        );
+        assert!(!world.is_visible_to_camera());
+
+        let instance = object("instance");
+        assert_eq!(
+            Material::Glass(GlassMaterial::new(Vector3::repeat(0.8), Vector3::zeros())),
+            instance.get_materials()[0]
+        );
+        assert!(instance.is_visible_to_camera());
+    }
+
+    #[test]
+    fn test_emissive_models_and_the_environment_read_their_importance_and_scale() {
+        let folder = std::env::temp_dir().join("rust_raytracer_light_importance");
+        std::fs::create_dir_all(&folder).unwrap();
+        RgbImage::from_pixel(1, 1, Rgb([255, 255, 255]))
+            .save(folder.join("white.png"))
+            .unwrap();
+        std::fs::write(
+            folder.join("screen.mtl"),
+            "newmtl screen\nKe 1.0 1.0 1.0\nmap_Ke white.png\n",
+        )
+        .unwrap();
+        std::fs::write(
+            folder.join("screen.obj"),
+            "mtllib screen.mtl\no screen\nv 0 0 0\nv 1 0 0\nv 0 0 1\nvn 0 1 0\nusemtl screen\nf 1//1 2//1 3//1\n",
+        )
+        .unwrap();
+        std::fs::write(
+            folder.join("scene.yaml"),
+            "world:\n  file: screen.obj\n  up_axis: y\n  emission_scale: 3\n  importance: 4\n\
+             environment_map:\n  file: white.png\n  importance: 2\n  warp_resolution: 4\n\
+             lights:\n  - type: distant\n    direction: [ 0.0,-1.0,0.0 ]\n    \
+             intensity: [ 1.0,1.0,1.0 ]\n",
+        )
+        .unwrap();
+
+        let scene = Scene::load_from_folder(&folder).unwrap();
+
+        // the screen triangle, then the distant light and the environment map
+        assert!(matches!(*scene.lights[0], Light::Area(_)));
+        assert_relative_eq!(
+            Vector3::repeat(0.5 * std::f64::consts::PI * 3.0),
+            scene.lights[0].power(1.0),
+            max_relative = 1e-6
+        );
+        assert_relative_eq!(4.0 / 7.0, scene.light_distribution.pdf(0));
+        assert_relative_eq!(1.0 / 7.0, scene.light_distribution.pdf(1));
+        assert_relative_eq!(2.0 / 7.0, scene.light_distribution.pdf(2));
+    }
+
+    #[test]
+    fn test_plastic_materials_choose_their_specular_model_and_alphas() {
+        let folder = std::env::temp_dir().join("rust_raytracer_plastic_model");
+        std::fs::create_dir_all(&folder).unwrap();
+        std::fs::write(
+            folder.join("triangle.obj"),
+            "o triangle\nv 0 0 0\nv 1 0 0\nv 0 0 1\nf 1 2 3\n",
+        )
+        .unwrap();
+        std::fs::write(
+            folder.join("materials.yaml"),
+            "brushed:\n  material: plastic\n  color: [ 0.2,0.2,0.2 ]\n  model: ward\n  \
+             alpha_x: 0.3\n  alpha_y: 0.05\n",
+        )
+        .unwrap();
+        std::fs::write(
+            folder.join("scene.yaml"),
+            "objects:\n  - type: quad\n    name: brushed\n    material: brushed\n    \
+             position: [ 0.0,0.0,0.0 ]\n    \
+             vertices: [ [ 0.0,0.0,0.0 ],[ 1.0,0.0,0.0 ],[ 1.0,0.0,1.0 ],[ 0.0,0.0,1.0 ] ]\n\
+             instances:\n  - model: triangle.obj\n    name: instance\n    material: plastic\n    \
+             roughness: 0.5\n    transforms:\n      - position: [ 0.0,1.0,0.0 ]\n",
+        )
+        .unwrap();
+
+        let scene = Scene::load_from_folder(&folder).unwrap();
+        let material = |name: &str| {
+            scene
+                .objects
+                .iter()
+                .find(|object| object.get_name() == Some(name))
+                .unwrap()
+                .get_materials()[0]
+                .clone()
+        };
+        let plastic = |color, model, roughness| {
+            PlasticMaterial::new(
+                Vector3::repeat(color),
+                Vector3::repeat(1.0),
+                roughness,
+                RoughnessRemap::default(),
+                FresnelModel::default(),
+                model,
+                false,
+                false,
+            )
+        };
+
+        assert_eq!(
+            Material::Plastic(plastic(0.2, SpecularModel::Ward, 0.05).with_alpha(0.3, 0.05)),
+            material("brushed")
+        );
+        // the model key of an instance names its model file, not the specular model
+        assert_eq!(
+            Material::Plastic(plastic(0.8, SpecularModel::Ggx, 0.5)),
+            material("instance")
+        );
+    }
+
+    #[test]
+    fn test_glass_chooses_its_fresnel_model() {
+        let folder = std::env::temp_dir().join("rust_raytracer_glass_fresnel");
+        std::fs::create_dir_all(&folder).unwrap();
+        std::fs::write(
+            folder.join("scene.yaml"),
+            "objects:\n  - type: quad\n    name: glass\n    material: glass\n    \
+             fresnel: schlick\n    position: [ 0.0,0.0,0.0 ]\n    \
+             vertices: [ [ 0.0,0.0,0.0 ],[ 1.0,0.0,0.0 ],[ 1.0,0.0,1.0 ],[ 0.0,0.0,1.0 ] ]\n",
+        )
+        .unwrap();
+
+        let scene = Scene::load_from_folder(&folder).unwrap();
+
+        assert_eq!(
+            vec![Material::Glass(
+                GlassMaterial::new(Vector3::repeat(0.8), Vector3::zeros())
+                    .with_fresnel_model(FresnelModel::Schlick)
+            )],
+            *scene.objects[0].get_materials()
+        );
+    }
 }
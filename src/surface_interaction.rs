@@ -6,6 +6,8 @@ use crate::helpers::face_forward;
 pub struct Interaction {
     pub point: Point3<f64>,
     pub normal: Vector3<f64>,
+    /// Texture coordinates of the point, used by textured emitters
+    pub uv: Vector2<f64>,
 }
 
 impl From<SurfaceInteraction> for Interaction {
@@ -13,6 +15,7 @@ impl From<SurfaceInteraction> for Interaction {
         Interaction {
             point: s.point,
             normal: s.shading_normal,
+            uv: s.uv,
         }
     }
 }
@@ -22,6 +25,7 @@ impl From<&SurfaceInteraction> for Interaction {
         Interaction {
             point: s.point,
             normal: s.shading_normal,
+            uv: s.uv,
         }
     }
 }
@@ -39,6 +43,12 @@ pub struct SurfaceInteraction {
     pub delta_p_delta_u: Vector3<f64>,
     pub delta_p_delta_v: Vector3<f64>,
     pub p_error: Vector3<f64>,
+    /// Interpolated vertex color of meshes that have them
+    pub vertex_color: Option<Vector3<f64>>,
+    /// Barycentric coordinates of the hit on a triangle, the weights of its three vertices
+    pub barycentric: Option<Vector3<f64>>,
+    /// Width in scene units of the camera pixel the surface is seen through, 0 when unknown
+    pub footprint: f64,
 }
 
 impl SurfaceInteraction {
@@ -68,6 +78,19 @@ impl SurfaceInteraction {
             delta_p_delta_u,
             delta_p_delta_v,
             p_error,
+            vertex_color: None,
+            barycentric: None,
+            footprint: 0.0,
+        }
+    }
+
+    /// Flips the surface to the side of `w` when it is hit from the back, used for double sided
+    /// objects. The tangent is flipped along so the shading frame stays right handed.
+    pub fn face_towards(&mut self, w: Vector3<f64>) {
+        if self.geometry_normal.dot(&w) < 0.0 {
+            self.geometry_normal = -self.geometry_normal;
+            self.shading_normal = -self.shading_normal;
+            self.ts = -self.ts;
         }
     }
 }
@@ -1,39 +1,254 @@
-use ggez::graphics::Image;
-use image::{ImageBuffer, Pixel, Rgb, RgbImage};
-use nalgebra::Point2;
+use image::{ImageBuffer, Pixel, Rgb, Rgb32FImage};
+use nalgebra::{Point2, Vector3};
 
-#[derive(Debug)]
+use crate::film::inverse_gamma_correct_srgb;
+
+#[derive(Debug, Copy, Clone)]
 pub enum ImageWrapMethod {
     Repeat,
     Black,
     Clamp,
+    /// Repeats horizontally and clamps vertically, for latitude-longitude maps where the left
+    /// and right edges meet and the top and bottom rows are the poles
+    Equirectangular,
+}
+
+/// How a lookup between texel centers is filtered.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub enum FilterMode {
+    /// The closest texel
+    #[default]
+    Nearest,
+    /// Interpolates the four closest texels
+    Bilinear,
+    /// Bilinear in the two mip levels closest to the filter width, interpolated between them
+    Trilinear,
+}
+
+impl FilterMode {
+    pub fn from_str(str: &str) -> Option<FilterMode> {
+        match str {
+            "nearest" => Some(FilterMode::Nearest),
+            "bilinear" => Some(FilterMode::Bilinear),
+            "trilinear" => Some(FilterMode::Trilinear),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct MipMap {
-    image: RgbImage,
+    // the image followed by versions of half the resolution, down to a single texel
+    pyramid: Vec<Rgb32FImage>,
     wrap_method: ImageWrapMethod,
+    filter: FilterMode,
 }
 
 impl MipMap {
-    pub fn new(image: RgbImage) -> Self {
+    /// Color maps (albedo) are usually stored as sRGB, pass `is_srgb` to decode them to linear.
+    /// Data maps (roughness, normals) and HDR images are linear already. Texels are decoded
+    /// before the mip levels are built so filtering averages linear values.
+    pub fn new(
+        mut image: Rgb32FImage,
+        wrap_method: ImageWrapMethod,
+        filter: FilterMode,
+        is_srgb: bool,
+    ) -> Self {
+        if is_srgb {
+            for pixel in image.pixels_mut() {
+                pixel.apply(|channel| inverse_gamma_correct_srgb(channel as f64) as f32);
+            }
+        }
+
+        let mut pyramid = vec![image];
+
+        while let Some(level) = pyramid.last().filter(|l| l.width() > 1 || l.height() > 1) {
+            pyramid.push(downsample(level));
+        }
+
         Self {
-            image,
-            wrap_method: ImageWrapMethod::Black,
+            pyramid,
+            wrap_method,
+            filter,
         }
     }
 
+    /// Looks up the color at `point`, in [0, 1] texture coordinates. The `width` of the filter,
+    /// also in texture coordinates, picks the mip level for trilinear filtering.
     pub fn lookup(&self, point: Point2<f64>, width: f64) -> Rgb<f64> {
-        let x = (self.image.dimensions().0 as f64 * point.x * 0.99) as u32;
-        let y = (self.image.dimensions().1 as f64 * point.y * 0.99) as u32;
-        let channels: Vec<f64> = self
-            .image
-            .get_pixel(x, y)
-            .channels()
-            .iter()
-            .map(|x| *x as f64 / 255.0)
-            .collect();
-
-        Rgb(channels.try_into().unwrap())
+        let color = match self.filter {
+            FilterMode::Nearest => self.nearest(0, point),
+            FilterMode::Bilinear => self.bilinear(0, point),
+            FilterMode::Trilinear => {
+                let last_level = self.pyramid.len() - 1;
+                let level = last_level as f64 + width.max(1e-8).log2();
+
+                if level <= 0.0 {
+                    self.bilinear(0, point)
+                } else if level >= last_level as f64 {
+                    self.bilinear(last_level, point)
+                } else {
+                    let fine = level.floor() as usize;
+                    let t = level - fine as f64;
+
+                    self.bilinear(fine, point) * (1.0 - t) + self.bilinear(fine + 1, point) * t
+                }
+            }
+        };
+
+        Rgb([color.x, color.y, color.z])
+    }
+
+    /// The average color of the image, the last mip level.
+    pub fn average(&self) -> Vector3<f64> {
+        self.texel(self.pyramid.len() - 1, 0, 0)
+    }
+
+    fn nearest(&self, level: usize, point: Point2<f64>) -> Vector3<f64> {
+        let (width, height) = self.pyramid[level].dimensions();
+        // a lookup at exactly 1.0 belongs to the last texel, not to the one after it
+        let x = ((point.x * width as f64).floor() as i64).min(width as i64 - 1);
+        let y = ((point.y * height as f64).floor() as i64).min(height as i64 - 1);
+
+        self.texel(level, x, y)
+    }
+
+    fn bilinear(&self, level: usize, point: Point2<f64>) -> Vector3<f64> {
+        let (width, height) = self.pyramid[level].dimensions();
+        // texel centers lie halfway between the integer coordinates
+        let s = point.x * width as f64 - 0.5;
+        let t = point.y * height as f64 - 0.5;
+        let (x, y) = (s.floor(), t.floor());
+        let (dx, dy) = (s - x, t - y);
+        let (x, y) = (x as i64, y as i64);
+
+        self.texel(level, x, y) * (1.0 - dx) * (1.0 - dy)
+            + self.texel(level, x + 1, y) * dx * (1.0 - dy)
+            + self.texel(level, x, y + 1) * (1.0 - dx) * dy
+            + self.texel(level, x + 1, y + 1) * dx * dy
+    }
+
+    fn texel(&self, level: usize, x: i64, y: i64) -> Vector3<f64> {
+        let image = &self.pyramid[level];
+        let (width, height) = (image.width() as i64, image.height() as i64);
+
+        let (x, y) = match self.wrap_method {
+            ImageWrapMethod::Repeat => (x.rem_euclid(width), y.rem_euclid(height)),
+            ImageWrapMethod::Clamp => (x.clamp(0, width - 1), y.clamp(0, height - 1)),
+            ImageWrapMethod::Equirectangular => (x.rem_euclid(width), y.clamp(0, height - 1)),
+            ImageWrapMethod::Black => {
+                if x < 0 || y < 0 || x >= width || y >= height {
+                    return Vector3::zeros();
+                }
+                (x, y)
+            }
+        };
+
+        let pixel = image.get_pixel(x as u32, y as u32).channels();
+        Vector3::new(pixel[0] as f64, pixel[1] as f64, pixel[2] as f64)
+    }
+}
+
+/// Averages blocks of 2x2 texels, an odd last row or column is averaged with itself.
+fn downsample(image: &Rgb32FImage) -> Rgb32FImage {
+    let (width, height) = image.dimensions();
+
+    ImageBuffer::from_fn((width / 2).max(1), (height / 2).max(1), |x, y| {
+        let mut sum = [0.0; 3];
+        for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+            let pixel = image.get_pixel((2 * x + dx).min(width - 1), (2 * y + dy).min(height - 1));
+            for (sum, channel) in sum.iter_mut().zip(pixel.channels()) {
+                *sum += channel / 4.0;
+            }
+        }
+
+        Rgb(sum)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+    use image::{ImageBuffer, Rgb};
+    use nalgebra::Point2;
+
+    use crate::textures::mip_map::{FilterMode, ImageWrapMethod, MipMap};
+
+    fn checker(filter: FilterMode) -> MipMap {
+        let image = ImageBuffer::from_fn(4, 4, |x, y| {
+            let value = ((x + y) % 2) as f32;
+            Rgb([value, value, value])
+        });
+
+        MipMap::new(image, ImageWrapMethod::Clamp, filter, false)
+    }
+
+    #[test]
+    fn test_nearest_and_bilinear_agree_only_at_texel_centers() {
+        let nearest = checker(FilterMode::Nearest);
+        let bilinear = checker(FilterMode::Bilinear);
+
+        for (x, y) in [(0, 0), (1, 2), (3, 3)] {
+            let center = Point2::new((x as f64 + 0.5) / 4.0, (y as f64 + 0.5) / 4.0);
+            assert_relative_eq!(
+                nearest.lookup(center, 0.0)[0],
+                bilinear.lookup(center, 0.0)[0]
+            );
+        }
+
+        let between = Point2::new(0.5, 0.3);
+        assert_eq!(1.0, nearest.lookup(between, 0.0)[0]);
+        assert_relative_eq!(0.5, bilinear.lookup(between, 0.0)[0]);
+    }
+
+    #[test]
+    fn test_trilinear_blurs_with_the_filter_width() {
+        let trilinear = checker(FilterMode::Trilinear);
+        let center = Point2::new(0.125, 0.125);
+
+        assert_relative_eq!(0.0, trilinear.lookup(center, 0.0)[0]);
+        assert_relative_eq!(0.5, trilinear.lookup(center, 1.0)[0]);
+    }
+
+    #[test]
+    fn test_equirectangular_interpolates_across_the_seam_and_clamps_at_the_poles() {
+        // black on the left half, white on the right, the top row is gray
+        let image = ImageBuffer::from_fn(4, 2, |x, y| {
+            let value = match (x, y) {
+                (_, 0) => 0.5,
+                (x, _) if x < 2 => 0.0,
+                _ => 1.0,
+            };
+            Rgb([value, value, value])
+        });
+        let equirectangular = MipMap::new(
+            image.clone(),
+            ImageWrapMethod::Equirectangular,
+            FilterMode::Bilinear,
+            false,
+        );
+        let clamp = MipMap::new(image, ImageWrapMethod::Clamp, FilterMode::Bilinear, false);
+
+        // halfway between the first and the last column of the bottom row
+        for u in [0.0, 1.0] {
+            let seam = Point2::new(u, 0.75);
+            assert_relative_eq!(0.5, equirectangular.lookup(seam, 0.0)[0]);
+        }
+        assert_eq!(0.0, clamp.lookup(Point2::new(0.0, 0.75), 0.0)[0]);
+
+        // the pole does not wrap around to the bottom row
+        assert_relative_eq!(0.5, equirectangular.lookup(Point2::new(0.375, 0.0), 0.0)[0]);
+    }
+
+    #[test]
+    fn test_srgb_texels_are_linearized_and_linear_ones_kept() {
+        let gray = || ImageBuffer::from_pixel(1, 1, Rgb([0.5, 0.5, 0.5]));
+        let center = Point2::new(0.5, 0.5);
+
+        let srgb = MipMap::new(gray(), ImageWrapMethod::Clamp, FilterMode::Nearest, true);
+        let linear = MipMap::new(gray(), ImageWrapMethod::Clamp, FilterMode::Nearest, false);
+
+        assert_relative_eq!(0.214, srgb.lookup(center, 0.0)[0], epsilon = 1e-3);
+        assert_eq!(0.5, linear.lookup(center, 0.0)[0]);
     }
 }
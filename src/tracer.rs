@@ -1,26 +1,45 @@
 use std::borrow::BorrowMut;
+use std::sync::Arc;
 
+use lazy_static::lazy_static;
 use nalgebra::{Point2, Point3, SimdPartialOrd, Vector3};
 use num_traits::identities::Zero;
-use rand::prelude::SliceRandom;
-use rand::{thread_rng, Rng};
+use rand::Rng;
 
 use crate::bsdf::{BsdfSampleResult, BXDFTYPES};
-use crate::helpers::power_heuristic;
+use crate::helpers::{get_random_in_unit_sphere, power_heuristic};
+use crate::light_path::{LightPathRadiance, PathEvent};
 use crate::lights::area::AreaLight;
 use crate::lights::{Light, LightTrait};
-use crate::materials::MaterialTrait;
+use crate::materials::matte::MatteMaterial;
+use crate::materials::subsurface::SubsurfaceMedium;
+use crate::materials::{Material, MaterialTrait};
 use crate::objects::plane::Plane;
 use crate::objects::ObjectTrait;
 use crate::renderer::{
-    check_intersect_scene, check_intersect_scene_simple, check_light_visible, debug_write_pixel,
-    debug_write_pixel_f64, debug_write_pixel_f64_on_bounce, debug_write_pixel_on_bounce, Ray,
-    SampleResult, Settings, CURRENT_BOUNCE,
+    check_intersect_bounce, check_intersect_scene, check_intersect_scene_simple,
+    check_light_visible, debug_write_pixel, debug_write_pixel_f64, debug_write_pixel_f64_on_bounce,
+    debug_write_pixel_on_bounce, DirectStrategy, Integrator, Ray, SampleResult, Settings,
+    CURRENT_BOUNCE,
 };
 use crate::scene::Scene;
 use crate::surface_interaction::{Interaction, SurfaceInteraction};
 use crate::{Object, SobolSampler};
 
+const MAX_RANDOM_WALK_STEPS: u32 = 256;
+/// Camera rays hitting a triangle closer to an edge than this barycentric coordinate are drawn
+/// as wireframe
+const WIREFRAME_WIDTH: f64 = 0.02;
+/// Highest alpha the specular LOD blurs reflections to, far away mirrors stay glossy
+const MAX_LOD_ALPHA: f64 = 0.3;
+/// Lobes limited by `Settings::highlight_clamp`
+const HIGHLIGHT_FLAGS: BXDFTYPES = BXDFTYPES::GLOSSY.union(BXDFTYPES::SPECULAR);
+
+lazy_static! {
+    static ref CLAY_MATERIAL: Material =
+        Material::Matte(MatteMaterial::new(Vector3::repeat(0.5), 0.0));
+}
+
 pub fn trace(
     starting_ray: Ray,
     point_film: Point2<f64>,
@@ -28,63 +47,233 @@ pub fn trace(
     scene: &Scene,
     sampler: &mut SobolSampler,
 ) -> SampleResult {
-    let mut rng = thread_rng();
     let mut l = Vector3::new(0.0, 0.0, 0.0);
     let mut contribution = Vector3::new(1.0, 1.0, 1.0);
     let mut specular_bounce = false;
     let mut ray = starting_ray;
     let mut normal = Vector3::zeros();
     let mut albedo = Vector3::zeros();
+    let mut lobes = LobeRadiance::default();
+    let mut path_lobe = None;
+    let mut transmitted = false;
+    let mut hit_background = false;
+    let mut shadow: f64 = 0.0;
+    let mut path_length = 0;
+    let mut max_throughput: f64 = 1.0;
+    let mut light_paths = LightPathRadiance::default();
+    // events of the path so far, only tracked when light path expressions are requested
+    let track_events = !settings.light_path_expressions.is_empty();
+    let mut events = vec![PathEvent::Eye];
+    let mut add_light_path = |events: &mut Vec<PathEvent>, end: &[PathEvent], radiance| {
+        if track_events {
+            let length = events.len();
+            events.extend_from_slice(end);
+            light_paths.add(&settings.light_path_expressions, events, radiance);
+            events.truncate(length);
+        }
+    };
+    // absorption of the medium the ray is currently traveling through, set after entering glass
+    let mut medium_absorption: Option<Vector3<f64>> = None;
+    // scattering medium the ray is inside of, set after entering a subsurface material
+    let mut subsurface: Option<SubsurfaceMedium> = None;
+    // set after the first non-specular bounce when path regularization is on
+    let mut regularize = false;
+    let brute_force = settings.integrator == Integrator::BruteForce;
+    let mut on_wireframe = false;
+    // distance traveled along the path, the footprint of the ray grows with it
+    let mut path_distance = 0.0;
 
     for bounce in 0..settings.depth_limit {
         CURRENT_BOUNCE.with(|current_bounce| *current_bounce.borrow_mut() = bounce);
 
-        let intersect = check_intersect_scene(ray, scene);
+        if let Some(medium) = subsurface {
+            ray = match random_walk(ray, scene, medium, sampler) {
+                Some(ray) => ray,
+                None => break,
+            };
+        }
+
+        let mut intersect = if bounce > 0 {
+            check_intersect_bounce(ray, scene, settings.max_gi_distance.unwrap_or(f64::MAX))
+        } else {
+            check_intersect_scene(ray, scene)
+        };
+
+        // Camera rays pass through hidden objects (and everything but an isolated object), they
+        // only block light and secondary rays.
+        // Shadow catchers are passed as well, after measuring how much light they miss.
+        if bounce == 0 {
+            while let Some((interaction, object)) = &intersect {
+                if let Some(Material::ShadowCatcher(_)) = object.get_materials().first() {
+                    shadow = shadow.max(blocked_light_fraction(scene, interaction, sampler));
+                } else if scene.is_visible_to_camera(object) {
+                    break;
+                }
 
-        if bounce == 0 || specular_bounce {
-            if let Some((interaction, object)) = intersect {
+                let point = interaction.point + ray.direction * 1e-7;
+                ray.t_max -= nalgebra::distance(&ray.point, &point);
+                ray.point = point;
+                intersect = check_intersect_scene(ray, scene);
+            }
+        }
+
+        // Beer-Lambert attenuation over the distance traveled inside the medium
+        if let (Some(absorption), Some((interaction, _))) = (medium_absorption, &intersect) {
+            let distance = nalgebra::distance(&ray.point, &interaction.point);
+            contribution.component_mul_assign(&absorption.map(|a| (-a * distance).exp()));
+        }
+
+        // Light that is hit is only counted when it could not have been found by light sampling,
+        // the brute force integrator does not sample lights and counts every hit.
+        if bounce == 0 || specular_bounce || brute_force {
+            if let Some((mut interaction, object)) = intersect {
                 if let Some(light) = object.get_light() {
-                    l += contribution.component_mul(&light.emitting(&interaction, -ray.direction));
+                    interaction.footprint = settings.pixel_spread_angle
+                        * (path_distance + nalgebra::distance(&ray.point, &interaction.point));
+                    let emitted = clamp_first_bounce(
+                        bounce,
+                        contribution.component_mul(&light.emitting(&interaction, -ray.direction)),
+                        settings,
+                    );
+                    l += emitted;
+                    lobes.add(path_lobe, emitted);
+                    add_light_path(&mut events, &[PathEvent::Light], emitted);
                 }
             } else {
                 for light in &scene.lights {
-                    l += contribution.component_mul(&light.environment_emitting(ray));
+                    let emitted = clamp_first_bounce(
+                        bounce,
+                        contribution.component_mul(&light.environment_emitting(ray)),
+                        settings,
+                    );
+                    l += emitted;
+                    lobes.add(path_lobe, emitted);
+                    add_light_path(&mut events, &[PathEvent::Light], emitted);
                 }
             }
         }
 
+        // Without an environment light, rays that miss see the background color. Only camera
+        // rays unless the background is set to light the scene.
+        if intersect.is_none()
+            && (bounce == 0 || scene.bg_lights_scene)
+            && !scene.lights.iter().any(|light| {
+                matches!(
+                    light.as_ref(),
+                    Light::InfiniteArea(_) | Light::Sky(_) | Light::Portal(_)
+                )
+            })
+        {
+            let background = contribution.component_mul(&scene.bg_color);
+            l += background;
+            lobes.add(path_lobe, background);
+        }
+
         // Check for an intersection
         let (mut surface_interaction, object) = match intersect {
             Some(intersection) => intersection,
             None => {
+                hit_background = bounce == 0;
                 break;
             }
         };
+        path_length += 1;
+        path_distance += nalgebra::distance(&ray.point, &surface_interaction.point);
+
+        if object.is_double_sided() {
+            surface_interaction.face_towards(-ray.direction);
+        }
+
+        if bounce == 0 && settings.wireframe {
+            on_wireframe = surface_interaction
+                .barycentric
+                .is_some_and(|barycentric| barycentric.min() < WIREFRAME_WIDTH);
+        }
+
+        // In clay mode every non-emissive object is shaded with the same neutral material
+        let materials = if settings.clay && object.get_light().is_none() {
+            std::slice::from_ref(&*CLAY_MATERIAL)
+        } else {
+            object.get_materials().as_slice()
+        };
 
         if bounce == 0 {
             normal = surface_interaction.shading_normal;
-            albedo = object.get_materials()[0].get_albedo()
+            albedo = materials[0].get_albedo()
+        }
+
+        // Unlit objects show their color as is and end the path
+        if let Material::Unlit(unlit) = &materials[0] {
+            let color = contribution.component_mul(&unlit.get_albedo());
+            l += color;
+            lobes.add(path_lobe, color);
+            add_light_path(&mut events, &[PathEvent::Light], color);
+            break;
         }
 
-        for material in object.get_materials() {
+        for material in materials {
             material.compute_scattering_functions(&mut surface_interaction);
         }
 
-        let mut light_irradiance = uniform_sample_light(scene, &surface_interaction, sampler);
+        if regularize {
+            if let Some(bsdf) = surface_interaction.bsdf.as_mut() {
+                bsdf.regularize();
+            }
+        }
 
-        // clamp indirect light?
-        // if bounce > 0 {
-        //     light_irradiance = light_irradiance.simd_clamp(Vector3::zeros(), Vector3::repeat(10.0));
-        // }
+        if settings.min_alpha > 0.0 {
+            if let Some(bsdf) = surface_interaction.bsdf.as_mut() {
+                bsdf.clamp_alpha(settings.min_alpha);
+            }
+        }
 
-        l += contribution.component_mul(&light_irradiance);
+        if settings.specular_lod > 0.0 {
+            if let Some(bsdf) = surface_interaction.bsdf.as_mut() {
+                bsdf.blur_for_footprint(footprint_alpha(path_distance, settings));
+            }
+        }
+
+        let mut light_irradiance = if brute_force {
+            Vector3::zeros()
+        } else {
+            let highlight_clamp = settings.highlight_clamp.filter(|_| bounce == 0);
+            uniform_sample_light(
+                scene,
+                &surface_interaction,
+                sampler,
+                settings,
+                highlight_clamp,
+            )
+        };
 
         let wo = -ray.direction;
-        let bsdf_sample = surface_interaction
-            .bsdf
-            .as_ref()
-            .unwrap()
-            .sample_f(wo, BXDFTYPES::ALL);
+        let bsdf_sample = surface_interaction.bsdf.as_ref().unwrap().sample_f(
+            wo,
+            Point3::from_slice(&sampler.get_3d()),
+            BXDFTYPES::ALL,
+        );
+
+        // The lobe of the first non-specular bounce decides which AOV the rest of the path
+        // contributes to, specular bounces before it only mark the path as transmitted.
+        if path_lobe.is_none() {
+            if bsdf_sample.sampled_flags.contains(BXDFTYPES::SPECULAR) {
+                transmitted |= bsdf_sample
+                    .sampled_flags
+                    .intersects(BXDFTYPES::REFRACTION | BXDFTYPES::TRANSMISSION);
+            } else {
+                path_lobe = Some(Lobe::from_flags(bsdf_sample.sampled_flags, transmitted));
+            }
+        }
+
+        let direct = clamp_first_bounce(
+            bounce,
+            contribution.component_mul(&light_irradiance),
+            settings,
+        );
+        l += direct;
+        lobes.add(path_lobe, direct);
+        // light sampling only evaluates the non-specular part of the BSDF
+        add_light_path(&mut events, &[PathEvent::Diffuse, PathEvent::Light], direct);
 
         if bsdf_sample.pdf == 0.0 || bsdf_sample.f.is_zero() {
             break;
@@ -105,42 +294,293 @@ pub fn trace(
         // }
 
         specular_bounce = bsdf_sample.sampled_flags.contains(BXDFTYPES::SPECULAR);
+        regularize |= settings.regularize && !specular_bounce;
+        if track_events {
+            events.push(if specular_bounce {
+                PathEvent::Specular
+            } else {
+                PathEvent::Diffuse
+            });
+        }
+
+        // every transmission through the surface either enters or leaves the medium
+        if bsdf_sample
+            .sampled_flags
+            .intersects(BXDFTYPES::REFRACTION | BXDFTYPES::TRANSMISSION)
+        {
+            medium_absorption = match medium_absorption {
+                Some(_) => None,
+                None => Some(materials[0].get_absorption()),
+            };
+            subsurface = match subsurface {
+                Some(_) => None,
+                None => materials[0].get_subsurface(),
+            };
+        }
 
         ray = Ray {
             point: surface_interaction.point,
             direction: bsdf_sample.wi,
+            t_max: f64::INFINITY,
         };
 
         // russian roulette termination
-        if bounce > 3 {
-            let q = (1.0 - contribution.max()).max(0.05);
-            if rng.gen::<f64>() < q {
+        let q = termination_probability(bounce, contribution, settings);
+        if q > 0.0 {
+            if sampler.rng().gen::<f64>() < q {
                 break;
             }
 
             contribution /= 1.0 - q;
         }
+
+        max_throughput = max_throughput.max(contribution.y);
     }
 
     SampleResult {
-        radiance: l,
+        // the wireframe is drawn in black over the render
+        radiance: if on_wireframe { Vector3::zeros() } else { l },
         p_film: point_film,
         normal,
         albedo,
+        diffuse: lobes.diffuse,
+        glossy: lobes.glossy,
+        transmission: lobes.transmission,
+        hit_background,
+        shadow,
+        light_paths,
+        path_length,
+        filter_weight: 1.0,
+        max_throughput,
+    }
+}
+
+/// Fraction of the light reaching the point without occluders that is blocked, every light is
+/// sampled once.
+fn blocked_light_fraction(
+    scene: &Scene,
+    surface_interaction: &SurfaceInteraction,
+    sampler: &mut SobolSampler,
+) -> f64 {
+    let mut total = 0.0;
+    let mut blocked = 0.0;
+
+    for light in &scene.lights {
+        let sample = light.sample_irradiance(surface_interaction, sampler.get_3d());
+        if sample.pdf == 0.0 {
+            continue;
+        }
+
+        let cos = sample.wi.dot(&surface_interaction.shading_normal).abs();
+        let irradiance = sample.irradiance.y * cos / sample.pdf;
+        total += irradiance;
+
+        if !check_light_visible(surface_interaction, scene, &sample) {
+            blocked += irradiance;
+        }
+    }
+
+    if total > 0.0 {
+        blocked / total
+    } else {
+        0.0
+    }
+}
+
+/// Chance of ending the path with russian roulette after this bounce. Paths that carry little
+/// light (by luminance, the Y of the contribution) are more likely to end, paths before the
+/// start depth never do.
+fn termination_probability(bounce: u32, contribution: Vector3<f64>, settings: &Settings) -> f64 {
+    if bounce <= settings.rr_start_depth {
+        return 0.0;
+    }
+
+    (1.0 - contribution.y).max(settings.rr_min_probability)
+}
+
+/// Alpha that reflections are blurred to at `distance` along the path, see
+/// `Settings::specular_lod`.
+fn footprint_alpha(distance: f64, settings: &Settings) -> f64 {
+    (settings.specular_lod * distance).min(MAX_LOD_ALPHA)
+}
+
+/// Limits the luminance of light picked up at the first indirect bounce (bounce 1) to
+/// `Settings::first_bounce_clamp`, where tiny bright highlights cause most fireflies. Both the
+/// sampled lights and the lights hit by the path are clamped. The color is scaled, not clipped
+/// per channel. Other bounces are never clamped.
+fn clamp_first_bounce(bounce: u32, radiance: Vector3<f64>, settings: &Settings) -> Vector3<f64> {
+    match settings.first_bounce_clamp {
+        Some(max) if bounce == 1 && radiance.y > max => radiance * (max / radiance.y),
+        _ => radiance,
+    }
+}
+
+/// Scatters a ray that entered a subsurface object until it reaches the surface again. Returns
+/// the ray from the last scattering event towards the surface, or None when the light was
+/// absorbed. The albedo is used as survival probability so the throughput stays the same.
+fn random_walk(
+    mut ray: Ray,
+    scene: &Scene,
+    medium: SubsurfaceMedium,
+    sampler: &mut SobolSampler,
+) -> Option<Ray> {
+    for _ in 0..MAX_RANDOM_WALK_STEPS {
+        let rng = sampler.rng();
+        let scatter_distance = -(1.0 - rng.gen::<f64>()).ln() * medium.mean_free_path;
+
+        let (interaction, _) = check_intersect_scene(ray, scene)?;
+        if nalgebra::distance(&ray.point, &interaction.point) <= scatter_distance {
+            return Some(ray);
+        }
+
+        if rng.gen::<f64>() >= medium.albedo {
+            return None;
+        }
+
+        ray = Ray {
+            point: ray.point + ray.direction * scatter_distance,
+            direction: get_random_in_unit_sphere(rng).normalize(),
+            t_max: f64::INFINITY,
+        };
+    }
+
+    None
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Lobe {
+    Diffuse,
+    Glossy,
+    Transmission,
+}
+
+impl Lobe {
+    fn from_flags(flags: BXDFTYPES, transmitted: bool) -> Lobe {
+        if transmitted || flags.contains(BXDFTYPES::TRANSMISSION) {
+            Lobe::Transmission
+        } else if flags.contains(BXDFTYPES::GLOSSY) {
+            Lobe::Glossy
+        } else {
+            Lobe::Diffuse
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+struct LobeRadiance {
+    diffuse: Vector3<f64>,
+    glossy: Vector3<f64>,
+    transmission: Vector3<f64>,
+}
+
+impl Default for LobeRadiance {
+    fn default() -> Self {
+        LobeRadiance {
+            diffuse: Vector3::zeros(),
+            glossy: Vector3::zeros(),
+            transmission: Vector3::zeros(),
+        }
     }
 }
 
+impl LobeRadiance {
+    /// Radiance found before the path has a lobe (e.g. directly visible lights) only counts
+    /// towards the beauty pass.
+    fn add(&mut self, lobe: Option<Lobe>, radiance: Vector3<f64>) {
+        match lobe {
+            Some(Lobe::Diffuse) => self.diffuse += radiance,
+            Some(Lobe::Glossy) => self.glossy += radiance,
+            Some(Lobe::Transmission) => self.transmission += radiance,
+            None => {}
+        }
+    }
+}
+
+/// Direct light from one randomly picked light. `highlight_clamp` limits the glossy part, see
+/// `Settings::highlight_clamp`.
 fn uniform_sample_light(
     scene: &Scene,
     surface_interaction: &SurfaceInteraction,
     sampler: &mut SobolSampler,
+    settings: &Settings,
+    highlight_clamp: Option<f64>,
 ) -> Vector3<f64> {
-    let mut rng = thread_rng();
-    let bsdf_flags = BXDFTYPES::ALL & !BXDFTYPES::SPECULAR;
+    let light_index = scene.light_distribution.sample(sampler.get_light_sample());
+    let light_pdf = scene.light_distribution.pdf(light_index);
+
+    // the estimate is divided by the pdf of the pick, the clamp holds after that
+    estimate_direct(
+        scene,
+        &scene.lights[light_index],
+        surface_interaction,
+        sampler,
+        settings,
+        highlight_clamp.map(|max| max * light_pdf),
+    ) / light_pdf
+}
+
+/// Direct light from one light, averaging `settings.light_samples` light samples and
+/// `settings.bsdf_samples` BSDF samples combined with multiple importance sampling. More samples
+/// give smoother penumbras and reflections of lights. `settings.direct_strategy` can leave out
+/// either kind of sample, the other then gets the full weight.
+fn estimate_direct(
+    scene: &Scene,
+    light: &Arc<Light>,
+    surface_interaction: &SurfaceInteraction,
+    sampler: &mut SobolSampler,
+    settings: &Settings,
+    highlight_clamp: Option<f64>,
+) -> Vector3<f64> {
+    // BSDF samples never hit a delta light, those are always sampled directly
+    let (n_light_samples, n_bsdf_samples) = match settings.direct_strategy {
+        _ if light.is_delta() => (settings.light_samples.max(1), 0),
+        DirectStrategy::Mis => (settings.light_samples.max(1), settings.bsdf_samples),
+        DirectStrategy::Light => (settings.light_samples.max(1), 0),
+        DirectStrategy::Bsdf => (0, settings.bsdf_samples.max(1)),
+    };
 
     let mut direct_irradiance = Vector3::zeros();
 
-    let light = scene.lights.choose(&mut rng).unwrap();
+    for _ in 0..n_light_samples {
+        direct_irradiance += estimate_direct_light_sample(
+            scene,
+            light,
+            surface_interaction,
+            sampler,
+            n_light_samples,
+            n_bsdf_samples,
+            highlight_clamp,
+        ) / n_light_samples as f64;
+    }
+
+    for _ in 0..n_bsdf_samples {
+        direct_irradiance += estimate_direct_bsdf_sample(
+            scene,
+            light,
+            surface_interaction,
+            sampler,
+            n_light_samples,
+            n_bsdf_samples,
+            highlight_clamp,
+        ) / n_bsdf_samples as f64;
+    }
+
+    direct_irradiance
+}
+
+/// One light sample of `estimate_direct`, MIS weighted against the BSDF samples.
+fn estimate_direct_light_sample(
+    scene: &Scene,
+    light: &Arc<Light>,
+    surface_interaction: &SurfaceInteraction,
+    sampler: &mut SobolSampler,
+    n_light_samples: u32,
+    n_bsdf_samples: u32,
+    highlight_clamp: Option<f64>,
+) -> Vector3<f64> {
+    let bsdf_flags = BXDFTYPES::ALL & !BXDFTYPES::SPECULAR;
+
+    let mut direct_irradiance = Vector3::zeros();
 
     // Sample a random point on the light and calculate the irradiance at our intersection point.
     let u_light = sampler.get_3d();
@@ -168,8 +608,13 @@ fn uniform_sample_light(
 
             if !irradiance_sample.irradiance.is_zero() {
                 if light.is_delta() {
-                    direct_irradiance +=
-                        f.component_mul(&irradiance_sample.irradiance) / irradiance_sample.pdf;
+                    direct_irradiance += clamp_highlight(
+                        surface_interaction,
+                        irradiance_sample.wi,
+                        f,
+                        irradiance_sample.irradiance / irradiance_sample.pdf,
+                        highlight_clamp,
+                    );
                 } else {
                     let scattering_pdf = if let Some(bsdf) = surface_interaction.bsdf.as_ref() {
                         bsdf.pdf(surface_interaction.wo, irradiance_sample.wi, bsdf_flags)
@@ -177,77 +622,1179 @@ fn uniform_sample_light(
                         0.0
                     };
 
-                    let weight = power_heuristic(1, irradiance_sample.pdf, 1, scattering_pdf);
-                    direct_irradiance += f.component_mul(&irradiance_sample.irradiance) * weight
-                        / irradiance_sample.pdf;
+                    let weight = power_heuristic(
+                        n_light_samples as i32,
+                        irradiance_sample.pdf,
+                        n_bsdf_samples as i32,
+                        scattering_pdf,
+                    );
+                    direct_irradiance += clamp_highlight(
+                        surface_interaction,
+                        irradiance_sample.wi,
+                        f,
+                        irradiance_sample.irradiance * weight / irradiance_sample.pdf,
+                        highlight_clamp,
+                    );
                 }
             }
         }
     }
 
-    if !light.is_delta() {
-        let bsdf_sample = if let Some(bsdf) = surface_interaction.bsdf.as_ref() {
-            bsdf.sample_f(surface_interaction.wo, bsdf_flags)
-        } else {
-            BsdfSampleResult {
-                wi: Vector3::zeros(),
-                pdf: 0.0,
-                f: Vector3::zeros(),
-                sampled_flags: BXDFTYPES::NONE,
+    direct_irradiance
+}
+
+/// One BSDF sample of `estimate_direct`, MIS weighted against the light samples.
+fn estimate_direct_bsdf_sample(
+    scene: &Scene,
+    light: &Arc<Light>,
+    surface_interaction: &SurfaceInteraction,
+    sampler: &mut SobolSampler,
+    n_light_samples: u32,
+    n_bsdf_samples: u32,
+    highlight_clamp: Option<f64>,
+) -> Vector3<f64> {
+    let bsdf_flags = BXDFTYPES::ALL & !BXDFTYPES::SPECULAR;
+
+    let mut direct_irradiance = Vector3::zeros();
+
+    let bsdf_sample = if let Some(bsdf) = surface_interaction.bsdf.as_ref() {
+        bsdf.sample_f(
+            surface_interaction.wo,
+            Point3::from_slice(&sampler.get_3d()),
+            bsdf_flags,
+        )
+    } else {
+        BsdfSampleResult {
+            wi: Vector3::zeros(),
+            pdf: 0.0,
+            f: Vector3::zeros(),
+            sampled_flags: BXDFTYPES::NONE,
+        }
+    };
+
+    let f = bsdf_sample.f
+        * bsdf_sample
+            .wi
+            .dot(&surface_interaction.shading_normal)
+            .abs();
+
+    if !f.is_zero() && bsdf_sample.pdf > 0.0 {
+        let interaction = Interaction::from(surface_interaction);
+        let light_pdf = light.pdf_incidence(&interaction, bsdf_sample.wi);
+        if light_pdf == 0.0 {
+            return direct_irradiance;
+        }
+
+        let weight = power_heuristic(
+            n_bsdf_samples as i32,
+            bsdf_sample.pdf,
+            n_light_samples as i32,
+            light_pdf,
+        );
+
+        let ray = Ray {
+            point: surface_interaction.point + (bsdf_sample.wi * 1.0e-9),
+            direction: bsdf_sample.wi,
+            t_max: f64::INFINITY,
+        };
+
+        let mut light_irradiance = Vector3::zeros();
+
+        if let Some((object_interaction, object)) = check_intersect_scene(ray, scene) {
+            if let Some(found_light_arc) = object.get_light() {
+                if std::ptr::eq(light.as_ref(), found_light_arc.as_ref()) {
+                    if let Light::Area(light) = light.as_ref() {
+                        // we've hit OUR area light
+                        let interaction = Interaction::from(object_interaction);
+                        light_irradiance = light.irradiance_at_point(&interaction, -bsdf_sample.wi);
+                    }
+                }
             }
+        } else if matches!(
+            light.as_ref(),
+            Light::InfiniteArea(_) | Light::Sky(_) | Light::Portal(_)
+        ) {
+            // every ray that escapes the scene sees the environment map or the sky, through a
+            // portal only the rays that leave through it
+            light_irradiance = light.environment_emitting(ray);
+        } else {
+            // no hit, add emitting light if infinite area light
+            // let interaction = Interaction {
+            //     point: surface_interaction.point,
+            //     normal: surface_interaction.shading_normal,
+            // };
+            // light_irradiance = light.emitting(&interaction, -wi)
+        }
+
+        direct_irradiance += clamp_highlight(
+            surface_interaction,
+            bsdf_sample.wi,
+            f,
+            light_irradiance * weight / bsdf_sample.pdf,
+            highlight_clamp,
+        );
+    }
+
+    direct_irradiance
+}
+
+/// Light arriving from `wi` reflected by the BSDF value `f` (including the cosine), with the
+/// luminance of the glossy and specular part limited to `highlight_clamp`. The diffuse part is
+/// kept as it is. `incoming` is the light over the pdf of the sample, times its MIS weight.
+fn clamp_highlight(
+    surface_interaction: &SurfaceInteraction,
+    wi: Vector3<f64>,
+    f: Vector3<f64>,
+    incoming: Vector3<f64>,
+    highlight_clamp: Option<f64>,
+) -> Vector3<f64> {
+    let (max, bsdf) = match (highlight_clamp, surface_interaction.bsdf.as_ref()) {
+        (Some(max), Some(bsdf)) => (max, bsdf),
+        _ => return f.component_mul(&incoming),
+    };
+
+    let f_highlight = bsdf.f(surface_interaction.wo, wi, HIGHLIGHT_FLAGS)
+        * wi.dot(&surface_interaction.shading_normal).abs();
+    let highlight = f_highlight.component_mul(&incoming);
+    let highlight = if highlight.y > max {
+        highlight * (max / highlight.y)
+    } else {
+        highlight
+    };
+
+    (f - f_highlight).component_mul(&incoming) + highlight
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use approx::assert_relative_eq;
+    use bvh::bvh::BVH;
+    use image::{ImageBuffer, Rgb};
+    use nalgebra::{Matrix4, Point2, Point3, Vector3};
+    use tobj::Mesh;
+
+    use crate::light_path::LightPathExpression;
+    use crate::lights::area::AreaLight;
+    use crate::lights::distribution::LightDistribution;
+    use crate::lights::infinite_area::InfiniteAreaLight;
+    use crate::lights::point::PointLight;
+    use crate::lights::Light;
+    use crate::materials::glass::GlassMaterial;
+    use crate::materials::matte::MatteMaterial;
+    use crate::materials::mirror::MirrorMaterial;
+    use crate::materials::phong::PhongMaterial;
+    use crate::materials::shadow_catcher::ShadowCatcherMaterial;
+    use crate::materials::subsurface::SubsurfaceMaterial;
+    use crate::materials::unlit::UnlitMaterial;
+    use crate::materials::Material;
+    use crate::objects::cone::Cone;
+    use crate::objects::plane::Plane;
+    use crate::objects::rectangle::Rectangle;
+    use crate::objects::triangle::Triangle;
+    use crate::objects::{ArcObject, Object};
+    use crate::renderer::{DirectStrategy, Integrator, Ray, SampleResult, Settings};
+    use crate::sampler::SobolSampler;
+    use crate::scene::{split_unbounded_objects, Scene};
+    use crate::textures::mip_map::FilterMode;
+    use crate::tracer::{
+        clamp_first_bounce, footprint_alpha, termination_probability, trace, MAX_LOD_ALPHA,
+    };
+
+    fn build_scene(objects: Vec<ArcObject>, lights: Vec<Arc<Light>>) -> Scene {
+        let (mut objects, unbounded_objects) = split_unbounded_objects(objects);
+        let bvh = BVH::build(&mut objects);
+
+        Scene {
+            bg_color: Vector3::zeros(),
+            bg_lights_scene: false,
+            objects,
+            unbounded_objects,
+            light_distribution: LightDistribution::uniform(lights.len()),
+            lights,
+            bvh,
+            isolated: None,
+        }
+    }
+
+    fn lit_plane_scene(color: Vector3<f64>) -> Scene {
+        build_scene(
+            vec![ArcObject(Arc::new(Object::Plane(Plane::new(
+                Point3::origin(),
+                Vector3::y(),
+                vec![Material::Matte(MatteMaterial::new(color, 0.0))],
+            ))))],
+            vec![Arc::new(Light::Point(PointLight::new(
+                Point3::new(0.0, 2.0, 0.0),
+                Vector3::repeat(10.0),
+            )))],
+        )
+    }
+
+    fn settings() -> Settings {
+        Settings {
+            thread_count: 1,
+            integrator: Integrator::PathTracer,
+            depth_limit: 4,
+            rr_start_depth: 3,
+            rr_min_probability: 0.05,
+            light_samples: 1,
+            bsdf_samples: 1,
+            direct_strategy: DirectStrategy::Mis,
+            regularize: false,
+            min_alpha: 0.0,
+            specular_lod: 0.0,
+            pixel_spread_angle: 0.0,
+            first_bounce_clamp: None,
+            highlight_clamp: None,
+            max_gi_distance: None,
+            debug_throughput: false,
+            wireframe: false,
+            max_samples: 1,
+            clay: false,
+            light_path_expressions: vec![],
+            debug_pixel: None,
+            preview_stride: 1,
+        }
+    }
+
+    fn trace_down(scene: &Scene, settings: &Settings) -> SampleResult {
+        let mut sampler = SobolSampler::new(0);
+        let ray = Ray {
+            point: Point3::new(0.0, 1.0, 0.0),
+            direction: Vector3::new(0.0, -1.0, 0.0),
+            t_max: f64::INFINITY,
+        };
+
+        trace(ray, Point2::origin(), settings, scene, &mut sampler)
+    }
+
+    #[test]
+    fn test_diffuse_scene_only_fills_diffuse_aov() {
+        let scene = lit_plane_scene(Vector3::repeat(0.8));
+
+        let result = trace_down(&scene, &settings());
+
+        assert!(result.radiance.x > 0.0);
+        assert_relative_eq!(result.radiance, result.diffuse);
+        assert_relative_eq!(Vector3::zeros(), result.glossy);
+        assert_relative_eq!(Vector3::zeros(), result.transmission);
+    }
+
+    #[test]
+    fn test_clay_shades_colored_object_gray() {
+        let scene = lit_plane_scene(Vector3::new(0.9, 0.1, 0.1));
+        let clay_settings = Settings {
+            clay: true,
+            ..settings()
         };
 
-        let f = bsdf_sample.f
-            * bsdf_sample
-                .wi
-                .dot(&surface_interaction.shading_normal)
-                .abs();
+        let colored = trace_down(&scene, &settings());
+        let clay = trace_down(&scene, &clay_settings);
+
+        assert!(colored.radiance.x > colored.radiance.y);
+        assert!(clay.radiance.x > 0.0);
+        assert_relative_eq!(clay.radiance.x, clay.radiance.y);
+        assert_relative_eq!(clay.radiance.y, clay.radiance.z);
+    }
+
+    #[test]
+    fn test_clay_keeps_emission() {
+        let position = Point3::new(-0.5, 0.0, -0.5);
+        let side_a = Vector3::new(0.0, 0.0, 1.0);
+        let side_b = Vector3::new(1.0, 0.0, 0.0);
+        let intensity = Vector3::new(4.0, 2.0, 1.0);
+
+        let light = Arc::new(Light::Area(AreaLight::new(
+            ArcObject(Arc::new(Object::Rectangle(Rectangle::new(
+                position,
+                side_a,
+                side_b,
+                vec![],
+                None,
+            )))),
+            intensity,
+        )));
+        let light_rectangle = ArcObject(Arc::new(Object::Rectangle(Rectangle::new(
+            position,
+            side_a,
+            side_b,
+            vec![Material::Matte(MatteMaterial::new(
+                Vector3::repeat(0.9),
+                0.0,
+            ))],
+            Some(light.clone()),
+        ))));
+        let scene = build_scene(vec![light_rectangle], vec![light]);
+
+        let result = trace_down(
+            &scene,
+            &Settings {
+                clay: true,
+                ..settings()
+            },
+        );
+
+        assert!(result.radiance.x >= intensity.x);
+        assert!(result.radiance.x > result.radiance.y);
+        assert!(result.radiance.y > result.radiance.z);
+    }
+
+    fn slab_over_light(thickness: f64, material: Material) -> Scene {
+        let position = Point3::new(-0.5, 0.0, -0.5);
+        let side_a = Vector3::new(0.0, 0.0, 1.0);
+        let side_b = Vector3::new(1.0, 0.0, 0.0);
+        let intensity = Vector3::repeat(4.0);
+
+        let light = Arc::new(Light::Area(AreaLight::new(
+            ArcObject(Arc::new(Object::Rectangle(Rectangle::new(
+                position,
+                side_a,
+                side_b,
+                vec![],
+                None,
+            )))),
+            intensity,
+        )));
+        let light_rectangle = ArcObject(Arc::new(Object::Rectangle(Rectangle::new(
+            position,
+            side_a,
+            side_b,
+            vec![Material::Matte(MatteMaterial::new(Vector3::zeros(), 0.0))],
+            Some(light.clone()),
+        ))));
+
+        let top = ArcObject(Arc::new(Object::Plane(Plane::new(
+            Point3::new(0.0, 0.8, 0.0),
+            Vector3::y(),
+            vec![material.clone()],
+        ))));
+        let bottom = ArcObject(Arc::new(Object::Plane(Plane::new(
+            Point3::new(0.0, 0.8 - thickness, 0.0),
+            Vector3::y(),
+            vec![material],
+        ))));
+
+        build_scene(vec![light_rectangle, top, bottom], vec![light])
+    }
+
+    #[test]
+    fn test_hidden_object_still_casts_shadow() {
+        let mut hidden = Plane::new(
+            Point3::new(0.0, 0.5, 0.0),
+            Vector3::y(),
+            vec![Material::Matte(MatteMaterial::new(
+                Vector3::new(0.9, 0.1, 0.1),
+                0.0,
+            ))],
+        );
+        hidden.visible_to_camera = false;
+
+        let mut scene = lit_plane_scene(Vector3::repeat(0.8));
+        scene
+            .objects
+            .push(ArcObject(Arc::new(Object::Plane(hidden))));
+        let objects = scene
+            .objects
+            .into_iter()
+            .chain(scene.unbounded_objects)
+            .collect();
+        let scene = build_scene(objects, scene.lights);
+
+        let result = trace_down(&scene, &settings());
+
+        // the camera sees the plane below, which is in the shadow of the hidden plane
+        assert_relative_eq!(Vector3::repeat(0.8), result.albedo);
+        assert_relative_eq!(Vector3::zeros(), result.radiance);
+    }
+
+    #[test]
+    fn test_isolated_object_is_the_only_one_seen_by_camera_rays() {
+        // a red cone in front of a blue one, both open cones around the z axis
+        let scene = || {
+            let objects = [("front", 3.0, Vector3::x()), ("back", 0.0, Vector3::z())]
+                .into_iter()
+                .map(|(name, z, color)| {
+                    let mut cone = Cone::new(
+                        1.0,
+                        2.0,
+                        360.0,
+                        Matrix4::new_translation(&Vector3::new(0.0, 0.0, z)),
+                        vec![Material::Matte(MatteMaterial::new(color, 0.0))],
+                    );
+                    cone.name = Some(name.to_string());
+                    ArcObject(Arc::new(Object::Cone(cone)))
+                })
+                .collect();
+            let light = Arc::new(Light::Point(PointLight::new(
+                Point3::new(0.0, 0.0, 10.0),
+                Vector3::repeat(10.0),
+            )));
 
-        if !f.is_zero() && bsdf_sample.pdf > 0.0 {
-            let interaction = Interaction {
-                point: surface_interaction.point,
-                normal: surface_interaction.shading_normal,
+            build_scene(objects, vec![light])
+        };
+        let albedo = |scene: &Scene| {
+            let ray = Ray {
+                point: Point3::new(0.3, 0.0, 10.0),
+                direction: Vector3::new(0.0, 0.0, -1.0),
+                t_max: f64::INFINITY,
             };
-            let light_pdf = light.pdf_incidence(&interaction, bsdf_sample.wi);
-            if light_pdf == 0.0 {
-                return direct_irradiance;
-            }
+            let mut sampler = SobolSampler::new(0);
 
-            let weight = power_heuristic(1, bsdf_sample.pdf, 1, light_pdf);
+            trace(ray, Point2::origin(), &settings(), scene, &mut sampler).albedo
+        };
 
+        assert_relative_eq!(Vector3::x(), albedo(&scene()));
+
+        for keep_shadows in [false, true] {
+            let mut isolated = scene();
+            isolated.isolate("back", keep_shadows);
+
+            assert_relative_eq!(Vector3::z(), albedo(&isolated));
+            assert_eq!(keep_shadows, isolated.objects.len() == 2);
+        }
+    }
+
+    #[test]
+    fn test_back_of_a_double_sided_triangle_shades_like_the_front() {
+        // a triangle in the y = 0 plane with its normals pointing up
+        let mesh = Mesh {
+            positions: vec![-5.0, 0.0, -5.0, 0.0, 0.0, 5.0, 5.0, 0.0, -5.0],
+            normals: vec![0.0, 1.0, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 0.0],
+            indices: vec![0, 1, 2],
+            ..Mesh::default()
+        };
+        // the light and the camera ray are on the same side, above or below the triangle
+        let radiance = |side: f64, double_sided: bool| {
+            let mut triangle = Triangle::new(
+                Arc::new(mesh.clone()),
+                0,
+                1,
+                2,
+                vec![Material::Matte(MatteMaterial::new(
+                    Vector3::repeat(0.8),
+                    0.0,
+                ))],
+                None,
+            );
+            triangle.double_sided = double_sided;
+            let scene = build_scene(
+                vec![ArcObject(Arc::new(Object::Triangle(triangle)))],
+                vec![Arc::new(Light::Point(PointLight::new(
+                    Point3::new(0.0, 2.0 * side, 0.0),
+                    Vector3::repeat(10.0),
+                )))],
+            );
             let ray = Ray {
-                point: surface_interaction.point + (bsdf_sample.wi * 1.0e-9),
-                direction: bsdf_sample.wi,
+                point: Point3::new(0.0, side, 0.0),
+                direction: Vector3::new(0.0, -side, 0.0),
+                t_max: f64::INFINITY,
             };
+            let mut sampler = SobolSampler::new(0);
 
-            let mut light_irradiance = Vector3::zeros();
-
-            if let Some((object_interaction, object)) = check_intersect_scene(ray, scene) {
-                if let Some(found_light_arc) = object.get_light() {
-                    if std::ptr::eq(light.as_ref(), found_light_arc.as_ref()) {
-                        if let Light::Area(light) = light.as_ref() {
-                            // we've hit OUR area light
-                            let interaction = Interaction {
-                                point: object_interaction.point,
-                                normal: object_interaction.shading_normal,
-                            };
-                            light_irradiance =
-                                light.irradiance_at_point(&interaction, -bsdf_sample.wi);
-                        }
-                    }
+            trace(ray, Point2::origin(), &settings(), &scene, &mut sampler)
+        };
+
+        let front = radiance(1.0, false);
+        assert!(front.radiance.y > 0.0);
+        assert_relative_eq!(Vector3::zeros(), radiance(-1.0, false).radiance);
+
+        let back = radiance(-1.0, true);
+        assert_relative_eq!(front.radiance, back.radiance, epsilon = 1e-6);
+        assert_relative_eq!(-Vector3::y(), back.normal);
+    }
+
+    #[test]
+    fn test_camera_ray_into_empty_space_sees_background() {
+        let mut scene = lit_plane_scene(Vector3::repeat(0.8));
+        scene.bg_color = Vector3::new(0.2, 0.4, 0.6);
+        let mut sampler = SobolSampler::new(0);
+        let ray = Ray {
+            point: Point3::new(0.0, 1.0, 0.0),
+            direction: Vector3::y(),
+            t_max: f64::INFINITY,
+        };
+
+        let result = trace(ray, Point2::origin(), &settings(), &scene, &mut sampler);
+
+        assert_relative_eq!(Vector3::new(0.2, 0.4, 0.6), result.radiance);
+        assert!(result.hit_background);
+
+        // the plane below does not receive light from the background by default
+        let lit = trace_down(&scene, &settings());
+        scene.bg_lights_scene = true;
+        let lit_by_background = trace_down(&scene, &settings());
+        assert!(lit_by_background.radiance.x > lit.radiance.x);
+    }
+
+    fn glass() -> Material {
+        Material::Glass(GlassMaterial::new(
+            Vector3::repeat(1.0),
+            Vector3::repeat(2.0),
+        ))
+    }
+
+    #[test]
+    fn test_thick_glass_absorbs_more() {
+        let thin = trace_down(&slab_over_light(0.05, glass()), &settings());
+        let thick = trace_down(&slab_over_light(0.5, glass()), &settings());
+
+        assert!(thin.radiance.x > 0.0);
+        assert!(thick.radiance.x < thin.radiance.x);
+    }
+
+    #[test]
+    fn test_thin_subsurface_slab_transmits_more() {
+        let wax = Material::Subsurface(SubsurfaceMaterial::new(Vector3::repeat(1.0), 0.95, 0.1));
+        let transmitted = |thickness| {
+            let scene = slab_over_light(thickness, wax.clone());
+            let mut sampler = SobolSampler::new(0);
+            let ray = Ray {
+                point: Point3::new(0.0, 1.0, 0.0),
+                direction: -Vector3::y(),
+                t_max: f64::INFINITY,
+            };
+
+            (0..256)
+                .map(|i| {
+                    sampler.start_pixel_sample(i, 256);
+                    trace(ray, Point2::origin(), &settings(), &scene, &mut sampler)
+                        .radiance
+                        .x
+                })
+                .sum::<f64>()
+        };
+
+        let thin = transmitted(0.05);
+        let thick = transmitted(0.6);
+
+        assert!(thin > 0.0);
+        assert!(thick < thin * 0.5);
+    }
+
+    #[test]
+    fn test_light_importance_changes_selection_but_not_brightness() {
+        // returns how often the dim second light is picked and the average brightness
+        let render = |importance: &[f64]| {
+            let mut scene = lit_plane_scene(Vector3::repeat(0.8));
+            scene.lights.push(Arc::new(Light::Point(PointLight::new(
+                Point3::new(1.0, 0.5, 0.0),
+                Vector3::repeat(0.5),
+            ))));
+            scene.light_distribution = LightDistribution::new(importance);
+
+            let mut sampler = SobolSampler::new(0);
+            let samples = 4000;
+            let mut second_light_picks = 0;
+            let mut brightness = 0.0;
+
+            for _ in 0..samples {
+                if scene.light_distribution.sample(sampler.get_light_sample()) == 1 {
+                    second_light_picks += 1;
                 }
-            } else {
-                // no hit, add emitting light if infinite area light
-                // let interaction = Interaction {
-                //     point: surface_interaction.point,
-                //     normal: surface_interaction.shading_normal,
-                // };
-                // light_irradiance = light.emitting(&interaction, -wi)
+
+                let ray = Ray {
+                    point: Point3::new(0.0, 1.0, 0.0),
+                    direction: Vector3::new(0.0, -1.0, 0.0),
+                    t_max: f64::INFINITY,
+                };
+                brightness += trace(ray, Point2::origin(), &settings(), &scene, &mut sampler)
+                    .radiance
+                    .y;
             }
 
-            direct_irradiance += f.component_mul(&(light_irradiance * weight)) / bsdf_sample.pdf;
+            (second_light_picks, brightness / samples as f64)
+        };
+
+        let (uniform_picks, uniform_brightness) = render(&[1.0, 1.0]);
+        let (important_picks, important_brightness) = render(&[1.0, 8.0]);
+
+        assert!(important_picks > uniform_picks * 3 / 2);
+        assert_relative_eq!(
+            uniform_brightness,
+            important_brightness,
+            max_relative = 0.05
+        );
+    }
+
+    #[test]
+    fn test_light_path_expressions_split_direct_diffuse_from_specular_paths() {
+        let expressions = vec![
+            LightPathExpression::parse("direct_diffuse", "LDE").unwrap(),
+            LightPathExpression::parse("reflected", "LDSE").unwrap(),
+        ];
+        let settings = Settings {
+            light_path_expressions: expressions,
+            ..settings()
+        };
+        let mut scene = lit_plane_scene(Vector3::repeat(0.8));
+
+        let direct = trace_down(&scene, &settings);
+
+        assert!(direct.radiance.y > 0.0);
+        assert_relative_eq!(direct.radiance, direct.light_paths.0[0]);
+        assert_relative_eq!(Vector3::zeros(), direct.light_paths.0[1]);
+
+        // a mirror wall that reflects the camera ray down onto the lit floor
+        scene
+            .objects
+            .push(ArcObject(Arc::new(Object::Rectangle(Rectangle::new(
+                Point3::new(1.0, 0.0, -1.0),
+                Vector3::new(0.0, 2.0, 0.0),
+                Vector3::new(0.0, 0.0, 2.0),
+                vec![Material::Mirror(MirrorMaterial::new(Vector3::repeat(1.0)))],
+                None,
+            )))));
+        let objects = scene
+            .objects
+            .into_iter()
+            .chain(scene.unbounded_objects)
+            .collect();
+        let scene = build_scene(objects, scene.lights);
+        let ray = Ray {
+            point: Point3::new(0.0, 0.5, 0.0),
+            direction: Vector3::new(1.0, -0.2, 0.0).normalize(),
+            t_max: f64::INFINITY,
+        };
+
+        let reflected = trace(
+            ray,
+            Point2::origin(),
+            &settings,
+            &scene,
+            &mut SobolSampler::new(0),
+        );
+
+        assert!(reflected.radiance.y > 0.0);
+        assert_relative_eq!(Vector3::zeros(), reflected.light_paths.0[0]);
+        assert_relative_eq!(reflected.radiance, reflected.light_paths.0[1]);
+    }
+
+    #[test]
+    fn test_regularization_blurs_mirrors_after_a_diffuse_bounce() {
+        // a mirror floor under a matte ceiling, lit by a point light between them
+        let scene = build_scene(
+            vec![
+                ArcObject(Arc::new(Object::Plane(Plane::new(
+                    Point3::origin(),
+                    Vector3::y(),
+                    vec![Material::Mirror(MirrorMaterial::new(Vector3::repeat(1.0)))],
+                )))),
+                ArcObject(Arc::new(Object::Plane(Plane::new(
+                    Point3::new(0.0, 2.0, 0.0),
+                    -Vector3::y(),
+                    vec![Material::Matte(MatteMaterial::new(
+                        Vector3::repeat(0.8),
+                        0.0,
+                    ))],
+                )))),
+            ],
+            vec![Arc::new(Light::Point(PointLight::new(
+                Point3::new(0.0, 1.0, 0.0),
+                Vector3::repeat(10.0),
+            )))],
+        );
+        // light sampled at the first and at the second hit
+        let expressions = vec![
+            LightPathExpression::parse("first", "LDE").unwrap(),
+            LightPathExpression::parse("second", "LDDE").unwrap(),
+        ];
+
+        // sums the light sampled at each hit over a few paths starting in `direction`
+        let light_paths = |regularize: bool, direction: Vector3<f64>| {
+            let settings = Settings {
+                regularize,
+                light_path_expressions: expressions.clone(),
+                ..settings()
+            };
+            let ray = Ray {
+                point: Point3::new(0.0, 1.5, 0.0),
+                direction,
+                t_max: f64::INFINITY,
+            };
+
+            (0..16)
+                .map(|seed| {
+                    let mut sampler = SobolSampler::new(seed);
+                    let result = trace(ray, Point2::origin(), &settings, &scene, &mut sampler);
+                    (result.light_paths.0[0].y, result.light_paths.0[1].y)
+                })
+                .fold((0.0, 0.0), |(a, b), (first, second)| {
+                    (a + first, b + second)
+                })
+        };
+
+        // a mirror seen by the camera stays sharp and never reflects the point light
+        assert_eq!((0.0, 0.0), light_paths(true, -Vector3::y()));
+
+        // after the ceiling the mirror is glossy and picks up the light
+        assert_eq!(0.0, light_paths(false, Vector3::y()).1);
+        assert!(light_paths(true, Vector3::y()).1 > 0.0);
+    }
+
+    #[test]
+    fn test_paths_before_the_roulette_start_depth_never_terminate() {
+        let dark = Vector3::repeat(0.01);
+        let deep = Settings {
+            rr_start_depth: 6,
+            ..settings()
+        };
+
+        assert!(termination_probability(5, dark, &settings()) > 0.9);
+        for bounce in 0..=6 {
+            assert_eq!(0.0, termination_probability(bounce, dark, &deep));
         }
+        assert!(termination_probability(7, dark, &deep) > 0.9);
+        assert_eq!(
+            0.05,
+            termination_probability(7, Vector3::repeat(2.0), &deep)
+        );
     }
 
-    direct_irradiance
+    #[test]
+    fn test_first_bounce_clamp_only_limits_bounce_one() {
+        let firefly = Vector3::new(200.0, 400.0, 100.0);
+        let clamped = Settings {
+            first_bounce_clamp: Some(10.0),
+            ..settings()
+        };
+
+        assert_relative_eq!(
+            Vector3::new(5.0, 10.0, 2.5),
+            clamp_first_bounce(1, firefly, &clamped)
+        );
+        for bounce in [0, 2, 3] {
+            assert_eq!(firefly, clamp_first_bounce(bounce, firefly, &clamped));
+        }
+        assert_eq!(firefly, clamp_first_bounce(1, firefly, &settings()));
+    }
+
+    #[test]
+    fn test_first_bounce_clamp_limits_lights_hit_by_the_path() {
+        let scene = area_light_over_floor_scene();
+        let ray = Ray {
+            point: Point3::new(0.0, 0.5, 0.0),
+            direction: Vector3::new(0.0, -1.0, 0.0),
+            t_max: f64::INFINITY,
+        };
+        // brute force only finds the light when the bounce off the floor hits it
+        let settings = Settings {
+            integrator: Integrator::BruteForce,
+            depth_limit: 2,
+            first_bounce_clamp: Some(0.1),
+            ..settings()
+        };
+
+        let luminances: Vec<f64> = (0..256)
+            .map(|trial| {
+                let mut sampler = SobolSampler::new(0).for_bucket(trial);
+                trace(ray, Point2::origin(), &settings, &scene, &mut sampler)
+                    .radiance
+                    .y
+            })
+            .collect();
+
+        assert!(luminances.iter().any(|&luminance| luminance > 0.0));
+        assert!(luminances.iter().all(|&luminance| luminance <= 0.1 + 1e-9));
+    }
+
+    #[test]
+    fn test_footprint_grows_with_the_distance_up_to_the_maximum() {
+        let lod = Settings {
+            specular_lod: 0.02,
+            pixel_spread_angle: 0.0,
+            ..settings()
+        };
+
+        assert_eq!(0.0, footprint_alpha(0.0, &lod));
+        assert_relative_eq!(0.1, footprint_alpha(5.0, &lod));
+        assert!(footprint_alpha(10.0, &lod) > footprint_alpha(5.0, &lod));
+        assert_eq!(MAX_LOD_ALPHA, footprint_alpha(1000.0, &lod));
+    }
+
+    #[test]
+    fn test_shadow_catcher_reports_the_blocked_light() {
+        // returns the shadow caught below the camera with or without a blocker for the left light
+        let shadow = |blocked: bool| {
+            let mut objects = vec![ArcObject(Arc::new(Object::Plane(Plane::new(
+                Point3::origin(),
+                Vector3::y(),
+                vec![Material::ShadowCatcher(ShadowCatcherMaterial::new(
+                    Vector3::repeat(0.8),
+                ))],
+            ))))];
+            if blocked {
+                objects.push(ArcObject(Arc::new(Object::Rectangle(Rectangle::new(
+                    Point3::new(-0.7, 1.0, -0.2),
+                    Vector3::x() * 0.4,
+                    Vector3::z() * 0.4,
+                    vec![Material::Matte(MatteMaterial::new(
+                        Vector3::repeat(0.5),
+                        0.0,
+                    ))],
+                    None,
+                )))));
+            }
+            let lights = [-1.0, 1.0]
+                .map(|x| {
+                    Arc::new(Light::Point(PointLight::new(
+                        Point3::new(x, 2.0, 0.0),
+                        Vector3::repeat(10.0),
+                    )))
+                })
+                .to_vec();
+
+            let result = trace_down(&build_scene(objects, lights), &settings());
+            assert!(result.hit_background);
+
+            result.shadow
+        };
+
+        assert_relative_eq!(0.0, shadow(false));
+        assert_relative_eq!(0.5, shadow(true), epsilon = 1e-9);
+    }
+
+    #[test]
+    fn test_bounces_beyond_the_max_gi_distance_see_the_environment() {
+        // a mirror floor reflecting the camera ray up to an unlit ceiling far above
+        let mut scene = build_scene(
+            vec![
+                ArcObject(Arc::new(Object::Plane(Plane::new(
+                    Point3::origin(),
+                    Vector3::y(),
+                    vec![Material::Mirror(MirrorMaterial::new(Vector3::repeat(1.0)))],
+                )))),
+                ArcObject(Arc::new(Object::Plane(Plane::new(
+                    Point3::new(0.0, 100.0, 0.0),
+                    -Vector3::y(),
+                    vec![Material::Unlit(UnlitMaterial::new(Vector3::new(
+                        0.9, 0.1, 0.1,
+                    )))],
+                )))),
+            ],
+            vec![Arc::new(Light::Point(PointLight::new(
+                Point3::new(0.0, 2.0, 0.0),
+                Vector3::repeat(10.0),
+            )))],
+        );
+        scene.bg_color = Vector3::new(0.2, 0.4, 0.6);
+        scene.bg_lights_scene = true;
+
+        let far = trace_down(&scene, &settings());
+        assert_relative_eq!(Vector3::new(0.9, 0.1, 0.1), far.radiance);
+
+        let limited = Settings {
+            max_gi_distance: Some(10.0),
+            ..settings()
+        };
+        let near = trace_down(&scene, &limited);
+        assert_relative_eq!(Vector3::new(0.2, 0.4, 0.6), near.radiance);
+    }
+
+    #[test]
+    fn test_unlit_object_returns_its_color_regardless_of_lights() {
+        let color = Vector3::new(0.2, 0.5, 0.9);
+
+        for power in [0.0, 10.0, 1000.0] {
+            let scene = build_scene(
+                vec![ArcObject(Arc::new(Object::Plane(Plane::new(
+                    Point3::origin(),
+                    Vector3::y(),
+                    vec![Material::Unlit(UnlitMaterial::new(color))],
+                ))))],
+                vec![Arc::new(Light::Point(PointLight::new(
+                    Point3::new(0.0, 2.0, 0.0),
+                    Vector3::repeat(power),
+                )))],
+            );
+
+            let result = trace_down(&scene, &settings());
+
+            assert_eq!(color, result.radiance);
+            assert_eq!(1, result.path_length);
+        }
+    }
+
+    #[test]
+    fn test_more_light_samples_smooth_the_penumbra() {
+        let position = Point3::new(-0.5, 2.0, -0.5);
+        let light = Arc::new(Light::Area(AreaLight::new(
+            ArcObject(Arc::new(Object::Rectangle(Rectangle::new(
+                position,
+                Vector3::x(),
+                Vector3::z(),
+                vec![],
+                None,
+            )))),
+            Vector3::repeat(4.0),
+        )));
+        let black = vec![Material::Matte(MatteMaterial::new(Vector3::zeros(), 0.0))];
+        let objects = vec![
+            ArcObject(Arc::new(Object::Rectangle(Rectangle::new(
+                position,
+                Vector3::x(),
+                Vector3::z(),
+                black.clone(),
+                Some(light.clone()),
+            )))),
+            ArcObject(Arc::new(Object::Plane(Plane::new(
+                Point3::origin(),
+                Vector3::y(),
+                vec![Material::Matte(MatteMaterial::new(
+                    Vector3::repeat(0.8),
+                    0.0,
+                ))],
+            )))),
+            // hides the half of the light with negative x from the origin
+            ArcObject(Arc::new(Object::Rectangle(Rectangle::new(
+                Point3::new(-0.3, 1.0, -0.3),
+                Vector3::x() * 0.3,
+                Vector3::z() * 0.6,
+                black,
+                None,
+            )))),
+        ];
+        let scene = build_scene(objects, vec![light]);
+        let ray = Ray {
+            point: Point3::new(0.0, 0.5, 0.0),
+            direction: Vector3::new(0.0, -1.0, 0.0),
+            t_max: f64::INFINITY,
+        };
+
+        // mean and variance of the radiance at the origin over independent renders
+        let estimate = |light_samples: u32| {
+            let settings = Settings {
+                light_samples,
+                ..settings()
+            };
+            let radiance: Vec<f64> = (0..64)
+                .map(|trial| {
+                    let mut sampler = SobolSampler::new(0).for_bucket(trial);
+                    trace(ray, Point2::origin(), &settings, &scene, &mut sampler)
+                        .radiance
+                        .y
+                })
+                .collect();
+            let mean = radiance.iter().sum::<f64>() / radiance.len() as f64;
+            let variance =
+                radiance.iter().map(|r| (r - mean).powi(2)).sum::<f64>() / radiance.len() as f64;
+
+            (mean, variance)
+        };
+
+        let (single_mean, single_variance) = estimate(1);
+        let (many_mean, many_variance) = estimate(8);
+
+        assert!(single_mean > 0.0);
+        assert_relative_eq!(single_mean, many_mean, max_relative = 0.1);
+        assert!(many_variance < single_variance / 4.0);
+    }
+
+    /// A unit square light at height 2, centered above the origin and facing down, over a
+    /// diffuse floor.
+    fn area_light_over_floor_scene() -> Scene {
+        area_light_over_floor_scene_with(Material::Matte(MatteMaterial::new(
+            Vector3::repeat(0.8),
+            0.0,
+        )))
+    }
+
+    fn area_light_over_floor_scene_with(floor: Material) -> Scene {
+        let position = Point3::new(-0.5, 2.0, -0.5);
+        let light = Arc::new(Light::Area(AreaLight::new(
+            ArcObject(Arc::new(Object::Rectangle(Rectangle::new(
+                position,
+                Vector3::x(),
+                Vector3::z(),
+                vec![],
+                None,
+            )))),
+            Vector3::repeat(4.0),
+        )));
+        let objects = vec![
+            ArcObject(Arc::new(Object::Rectangle(Rectangle::new(
+                position,
+                Vector3::x(),
+                Vector3::z(),
+                vec![Material::Matte(MatteMaterial::new(Vector3::zeros(), 0.0))],
+                Some(light.clone()),
+            )))),
+            ArcObject(Arc::new(Object::Plane(Plane::new(
+                Point3::origin(),
+                Vector3::y(),
+                vec![floor],
+            )))),
+        ];
+        build_scene(objects, vec![light])
+    }
+
+    #[test]
+    fn test_area_light_direct_illumination_matches_the_analytic_irradiance() {
+        let scene = area_light_over_floor_scene();
+        let ray = Ray {
+            point: Point3::new(0.0, 0.5, 0.0),
+            direction: Vector3::new(0.0, -1.0, 0.0),
+            t_max: f64::INFINITY,
+        };
+
+        // irradiance below the center of a parallel square, summed over its four quarters
+        let a: f64 = 0.5 / 2.0;
+        let s = a / (1.0 + a * a).sqrt();
+        let irradiance = 4.0 * 4.0 * s * s.atan();
+        let expected = 0.8 / std::f64::consts::PI * irradiance;
+
+        // light sampling alone and combined with BSDF sampling through MIS, the light hit by a
+        // BSDF sample must not be counted twice
+        for bsdf_samples in [0, 1] {
+            let settings = Settings {
+                bsdf_samples,
+                ..settings()
+            };
+            let mean = (0..256)
+                .map(|trial| {
+                    let mut sampler = SobolSampler::new(0).for_bucket(trial);
+                    trace(ray, Point2::origin(), &settings, &scene, &mut sampler)
+                        .radiance
+                        .y
+                })
+                .sum::<f64>()
+                / 256.0;
+
+            assert_relative_eq!(expected, mean, max_relative = 0.05);
+        }
+    }
+
+    #[test]
+    fn test_brute_force_converges_to_the_same_mean_as_mis() {
+        let scene = area_light_over_floor_scene();
+        let ray = Ray {
+            point: Point3::new(0.0, 0.5, 0.0),
+            direction: Vector3::new(0.0, -1.0, 0.0),
+            t_max: f64::INFINITY,
+        };
+        let mean = |integrator, trials| {
+            let settings = Settings {
+                integrator,
+                ..settings()
+            };
+
+            (0..trials)
+                .map(|trial| {
+                    let mut sampler = SobolSampler::new(0).for_bucket(trial);
+                    trace(ray, Point2::origin(), &settings, &scene, &mut sampler)
+                        .radiance
+                        .y
+                })
+                .sum::<f64>()
+                / trials as f64
+        };
+
+        // only a few BSDF samples hit the light, brute force needs many more samples
+        let path_tracer = mean(Integrator::PathTracer, 256);
+        let brute_force = mean(Integrator::BruteForce, 8192);
+
+        assert!(brute_force > 0.0);
+        assert_relative_eq!(path_tracer, brute_force, max_relative = 0.1);
+    }
+
+    #[test]
+    fn test_single_strategies_converge_to_the_mis_mean() {
+        let ray = Ray {
+            point: Point3::new(0.0, 0.5, 0.0),
+            direction: Vector3::new(0.0, -1.0, 0.0),
+            t_max: f64::INFINITY,
+        };
+        let mean = |scene: &Scene, direct_strategy| {
+            let settings = Settings {
+                direct_strategy,
+                ..settings()
+            };
+
+            (0..256)
+                .map(|trial| {
+                    let mut sampler = SobolSampler::new(0).for_bucket(trial);
+                    trace(ray, Point2::origin(), &settings, scene, &mut sampler)
+                        .radiance
+                        .y
+                })
+                .sum::<f64>()
+                / 256.0
+        };
+
+        // light samples for a diffuse floor, BSDF samples for a glossy one
+        let diffuse = area_light_over_floor_scene();
+        let glossy = area_light_over_floor_scene_with(Material::Phong(PhongMaterial::new(
+            Vector3::zeros(),
+            Vector3::repeat(0.8),
+            50.0,
+        )));
+        // an environment map over a diffuse floor, reached by both kinds of samples
+        let environment = build_scene(
+            vec![ArcObject(Arc::new(Object::Plane(Plane::new(
+                Point3::origin(),
+                Vector3::y(),
+                vec![Material::Matte(MatteMaterial::new(
+                    Vector3::repeat(0.8),
+                    0.0,
+                ))],
+            ))))],
+            vec![Arc::new(Light::InfiniteArea(InfiniteAreaLight::new(
+                &Vector3::repeat(1.0),
+                ImageBuffer::from_pixel(4, 2, Rgb([1.0, 1.0, 1.0])),
+                Matrix4::identity(),
+                None,
+                FilterMode::Nearest,
+                4,
+            )))],
+        );
+        for (scene, strategy) in [
+            (&diffuse, DirectStrategy::Light),
+            (&glossy, DirectStrategy::Bsdf),
+            (&environment, DirectStrategy::Light),
+            (&environment, DirectStrategy::Bsdf),
+        ] {
+            let combined = mean(scene, DirectStrategy::Mis);
+
+            assert!(combined > 0.0);
+            assert_relative_eq!(combined, mean(scene, strategy), max_relative = 0.05);
+        }
+    }
+
+    #[test]
+    fn test_highlight_clamp_limits_the_glossy_highlight_and_keeps_the_diffuse() {
+        // the camera ray reflects straight into the point light, a very bright highlight
+        let floor = |specular: f64| {
+            build_scene(
+                vec![ArcObject(Arc::new(Object::Plane(Plane::new(
+                    Point3::origin(),
+                    Vector3::y(),
+                    vec![Material::Phong(PhongMaterial::new(
+                        Vector3::repeat(0.5),
+                        Vector3::repeat(specular),
+                        1000.0,
+                    ))],
+                ))))],
+                vec![Arc::new(Light::Point(PointLight::new(
+                    Point3::new(0.0, 2.0, 0.0),
+                    Vector3::repeat(10.0),
+                )))],
+            )
+        };
+        let clamped = Settings {
+            highlight_clamp: Some(1.0),
+            ..settings()
+        };
+
+        let glossy = floor(0.8);
+        let highlight = trace_down(&glossy, &settings()).radiance;
+        let limited = trace_down(&glossy, &clamped).radiance;
+
+        let diffuse = floor(0.0);
+        let diffuse_only = trace_down(&diffuse, &settings()).radiance;
+
+        assert!(highlight.y > 10.0);
+        assert_relative_eq!(diffuse_only.y + 1.0, limited.y, max_relative = 1e-9);
+        assert_relative_eq!(diffuse_only, trace_down(&diffuse, &clamped).radiance);
+    }
 }